@@ -0,0 +1,272 @@
+use std::{
+    collections::BTreeMap,
+    future,
+    io::{self, Write},
+    path::PathBuf,
+    str::FromStr,
+    sync::Arc,
+};
+
+use axum::body::Bytes;
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use flate2::write::GzEncoder;
+use futures::{stream, stream::BoxStream, StreamExt};
+use tar::{EntryType, Header};
+use tokio::fs;
+use tokio_util::io::{ReaderStream, SyncIoBridge};
+use zip::{write::SimpleFileOptions, CompressionMethod, ZipWriter};
+
+use crate::{
+    compression::{Codec, Compression},
+    domain::{in_scope, StorageDetails, INTERNAL_DIRECTORY},
+    index::Index,
+};
+
+/// Bytes of the archive buffered between the writer and the response.
+const BUFFER_SIZE: usize = 64 * 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    Zip,
+    TarGz,
+}
+
+impl FromStr for ArchiveFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "zip" => Ok(ArchiveFormat::Zip),
+            "tar.gz" => Ok(ArchiveFormat::TarGz),
+            _ => Err(format!(
+                "Unsupported archive format {}, expected zip or tar.gz",
+                s
+            )),
+        }
+    }
+}
+
+impl ArchiveFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ArchiveFormat::Zip => "zip",
+            ArchiveFormat::TarGz => "tar.gz",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ArchiveFormat::Zip => "application/zip",
+            ArchiveFormat::TarGz => "application/gzip",
+        }
+    }
+}
+
+/// A file or directory put into an archive.
+pub struct ArchiveEntry {
+    /// Path inside the archive, ending in a slash for directories.
+    pub name: String,
+    path: PathBuf,
+    codec: Option<Codec>,
+    modified_at: DateTime<Utc>,
+}
+
+impl ArchiveEntry {
+    pub fn is_directory(&self) -> bool {
+        self.name.ends_with('/')
+    }
+}
+
+/// Archives of files and directories, written on the fly while they are downloaded.
+pub struct Archives {
+    storage_root: PathBuf,
+    index: Arc<Index>,
+    compression: Arc<Compression>,
+}
+
+impl Archives {
+    pub fn new(
+        storage_details: &StorageDetails,
+        index: Arc<Index>,
+        compression: Arc<Compression>,
+    ) -> Self {
+        Self {
+            storage_root: PathBuf::from(&storage_details.path),
+            index,
+            compression,
+        }
+    }
+
+    /// Adds the file or directory at `relative_path` and everything below it to `entries`, named
+    /// after `name` in the archive, returning how many were found. Paths outside of `scope` are
+    /// left out, and the internal directory always is.
+    pub async fn collect(
+        &self,
+        relative_path: &str,
+        name: &str,
+        scope: Option<&[String]>,
+        entries: &mut BTreeMap<String, ArchiveEntry>,
+    ) -> Result<usize, io::Error> {
+        let codecs = self
+            .index
+            .codecs(relative_path)
+            .await
+            .map_err(io::Error::other)?;
+        let mut found = 0;
+        let mut pending = vec![(relative_path.to_string(), name.to_string())];
+        while let Some((relative_path, name)) = pending.pop() {
+            let path = self.storage_root.join(&relative_path);
+            let metadata = fs::symlink_metadata(&path).await?;
+            let modified_at = metadata.modified().map(DateTime::<Utc>::from)?;
+
+            if metadata.is_dir() {
+                let mut children = fs::read_dir(&path).await?;
+                while let Some(child) = children.next_entry().await? {
+                    let child_name = child.file_name().to_string_lossy().into_owned();
+                    if relative_path.is_empty() && child_name == INTERNAL_DIRECTORY {
+                        continue;
+                    }
+                    pending.push((join(&relative_path, &child_name), join(&name, &child_name)));
+                }
+                if !name.is_empty() && in_scope(&relative_path, scope) {
+                    found += 1;
+                    let name = format!("{}/", name);
+                    entries.insert(
+                        name.clone(),
+                        ArchiveEntry {
+                            name,
+                            path,
+                            codec: None,
+                            modified_at,
+                        },
+                    );
+                }
+            } else if metadata.is_file() && in_scope(&relative_path, scope) {
+                found += 1;
+                entries.insert(
+                    name.clone(),
+                    ArchiveEntry {
+                        name,
+                        path,
+                        codec: codecs.get(&relative_path).copied(),
+                        modified_at,
+                    },
+                );
+            }
+        }
+        Ok(found)
+    }
+
+    /// Streams an archive of `entries`. Files are read as they are written into the archive on
+    /// the blocking pool, the archive crates being synchronous like those unpacking uploads,
+    /// and a failure part way through ends the stream with an error rather than a truncated
+    /// archive.
+    pub fn stream(
+        &self,
+        entries: Vec<ArchiveEntry>,
+        format: ArchiveFormat,
+    ) -> BoxStream<'static, Result<Bytes, io::Error>> {
+        let (writer, reader) = tokio::io::duplex(BUFFER_SIZE);
+        let compression = self.compression.clone();
+        let writing = tokio::task::spawn_blocking(move || {
+            let writer = SyncIoBridge::new(writer);
+            let written = match format {
+                ArchiveFormat::Zip => write_zip(&compression, &entries, writer),
+                ArchiveFormat::TarGz => write_tar_gz(&compression, &entries, writer),
+            };
+            if let Err(e) = &written {
+                tracing::error!("Failed to write archive: {:?}", e);
+            }
+            written
+        });
+
+        let outcome = stream::once(async move {
+            match writing.await {
+                Ok(Ok(())) => None,
+                Ok(Err(e)) => Some(Err(e)),
+                Err(e) => Some(Err(io::Error::other(e))),
+            }
+        })
+        .filter_map(future::ready);
+        ReaderStream::new(reader).chain(outcome).boxed()
+    }
+}
+
+fn write_zip(
+    compression: &Compression,
+    entries: &[ArchiveEntry],
+    writer: SyncIoBridge<impl tokio::io::AsyncWrite + Unpin>,
+) -> Result<(), io::Error> {
+    let mut zip = ZipWriter::new_stream(writer);
+    for entry in entries {
+        let options = SimpleFileOptions::default()
+            .compression_method(CompressionMethod::Deflated)
+            .last_modified_time(zip_date_time(&entry.modified_at));
+        if entry.is_directory() {
+            zip.add_directory(
+                entry.name.as_str(),
+                options.compression_method(CompressionMethod::Stored),
+            )
+            .map_err(io::Error::other)?;
+            continue;
+        }
+
+        let mut content = compression.open_blocking(&entry.path, entry.codec)?;
+        let options = options.large_file(content.size() >= u32::MAX as u64);
+        zip.start_file(entry.name.as_str(), options)
+            .map_err(io::Error::other)?;
+        io::copy(&mut content, &mut zip)?;
+    }
+
+    // Dropping the writer afterwards ends the stream.
+    zip.finish().map_err(io::Error::other)?.into_inner().flush()
+}
+
+fn write_tar_gz(
+    compression: &Compression,
+    entries: &[ArchiveEntry],
+    writer: SyncIoBridge<impl tokio::io::AsyncWrite + Unpin>,
+) -> Result<(), io::Error> {
+    let mut tar = tar::Builder::new(GzEncoder::new(writer, flate2::Compression::default()));
+    for entry in entries {
+        let mut header = Header::new_gnu();
+        header.set_mtime(entry.modified_at.timestamp().max(0) as u64);
+        if entry.is_directory() {
+            header.set_entry_type(EntryType::Directory);
+            header.set_mode(0o755);
+            header.set_size(0);
+            tar.append_data(&mut header, &entry.name, io::empty())?;
+            continue;
+        }
+
+        let content = compression.open_blocking(&entry.path, entry.codec)?;
+        header.set_entry_type(EntryType::Regular);
+        header.set_mode(0o644);
+        // The size read at opening, so the header matches the data even if the file changes.
+        header.set_size(content.size());
+        tar.append_data(&mut header, &entry.name, content)?;
+    }
+
+    tar.into_inner()?.finish()?.flush()
+}
+
+/// The modification time of a zip entry, which cannot be before 1980.
+fn zip_date_time(date_time: &DateTime<Utc>) -> zip::DateTime {
+    zip::DateTime::from_date_and_time(
+        date_time.year().try_into().unwrap_or_default(),
+        date_time.month() as u8,
+        date_time.day() as u8,
+        date_time.hour() as u8,
+        date_time.minute() as u8,
+        date_time.second() as u8,
+    )
+    .unwrap_or_default()
+}
+
+fn join(parent: &str, name: &str) -> String {
+    if parent.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", parent, name)
+    }
+}
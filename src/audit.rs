@@ -0,0 +1,165 @@
+use std::{
+    io::{self, ErrorKind},
+    net::IpAddr,
+    path::PathBuf,
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    fs::{self, OpenOptions},
+    io::AsyncWriteExt,
+    sync::Mutex,
+};
+
+use crate::configuration::AuditSettings;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    Upload,
+    Overwrite,
+    Restore,
+    Delete,
+    Purge,
+    UpdateMetadata,
+    Move,
+    Copy,
+    CreateDirectory,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct AuditRecord {
+    pub timestamp: DateTime<Utc>,
+    pub action: AuditAction,
+    pub identity: String,
+    pub client_ip: Option<IpAddr>,
+    /// Path relative to the storage root.
+    pub path: String,
+    pub size: Option<u64>,
+    pub checksum: Option<String>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+pub struct AuditFilter {
+    pub path_prefix: Option<String>,
+    pub user: Option<String>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+}
+
+impl AuditFilter {
+    fn matches(&self, record: &AuditRecord) -> bool {
+        self.path_prefix
+            .as_ref()
+            .is_none_or(|prefix| record.path.starts_with(prefix.as_str()))
+            && self
+                .user
+                .as_ref()
+                .is_none_or(|user| &record.identity == user)
+            && self.from.is_none_or(|from| record.timestamp >= from)
+            && self.to.is_none_or(|to| record.timestamp <= to)
+    }
+}
+
+/// Append-only JSON-lines audit trail of file mutations, rotated by size.
+pub struct AuditLog {
+    settings: AuditSettings,
+    write_lock: Mutex<()>,
+}
+
+impl AuditLog {
+    pub fn new(settings: AuditSettings) -> Self {
+        Self {
+            settings,
+            write_lock: Mutex::new(()),
+        }
+    }
+
+    #[tracing::instrument(name = "Write audit records", skip(self, records))]
+    pub async fn record(&self, records: &[AuditRecord]) -> Result<(), io::Error> {
+        if records.is_empty() {
+            return Ok(());
+        }
+
+        let mut lines = vec![];
+        for record in records {
+            serde_json::to_writer(&mut lines, record)?;
+            lines.push(b'\n');
+        }
+
+        let _guard = self.write_lock.lock().await;
+        let path = PathBuf::from(&self.settings.path);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+
+        let current_size = match fs::metadata(&path).await {
+            Ok(metadata) => metadata.len(),
+            Err(e) if e.kind() == ErrorKind::NotFound => 0,
+            Err(e) => return Err(e),
+        };
+        if current_size > 0 && current_size + lines.len() as u64 > self.settings.max_file_bytes {
+            self.rotate().await?;
+        }
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await?;
+        file.write_all(&lines).await?;
+        file.sync_data().await
+    }
+
+    /// Returns the matching records from the rotated and active files, oldest first.
+    #[tracing::instrument(name = "Query audit records", skip(self))]
+    pub async fn query(&self, filter: &AuditFilter) -> Result<Vec<AuditRecord>, io::Error> {
+        let mut records = vec![];
+        for index in (0..self.settings.max_files.max(1)).rev() {
+            let contents = match fs::read_to_string(self.file_path(index)).await {
+                Ok(contents) => contents,
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+
+            for line in contents.lines().filter(|line| !line.is_empty()) {
+                let record: AuditRecord = serde_json::from_str(line)?;
+                if filter.matches(&record) {
+                    records.push(record);
+                }
+            }
+        }
+
+        Ok(records)
+    }
+
+    async fn rotate(&self) -> Result<(), io::Error> {
+        let oldest = self.settings.max_files.max(1) - 1;
+        remove_if_exists(self.file_path(oldest)).await?;
+
+        for index in (0..oldest).rev() {
+            match fs::rename(self.file_path(index), self.file_path(index + 1)).await {
+                Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Index 0 is the active file, higher indices are older rotations.
+    fn file_path(&self, index: usize) -> PathBuf {
+        match index {
+            0 => PathBuf::from(&self.settings.path),
+            index => PathBuf::from(format!("{}.{}", self.settings.path, index)),
+        }
+    }
+}
+
+async fn remove_if_exists(path: PathBuf) -> Result<(), io::Error> {
+    match fs::remove_file(path).await {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,224 @@
+use std::{path::PathBuf, process::ExitCode};
+
+use clap::{Parser, Subcommand};
+use crumbbox::client::{
+    plan_download, plan_sync, plan_upload, transfer_all, Client, ClientError, Direction, Profile,
+    Progress, Transfer,
+};
+
+/// Command-line client of a crumbbox server.
+#[derive(Parser)]
+#[command(name = "crumbbox-cli", version)]
+struct Cli {
+    /// Profile of the profile file to connect with.
+    #[arg(
+        long,
+        env = "CRUMBBOX_PROFILE",
+        default_value = "default",
+        global = true
+    )]
+    profile: String,
+    /// Profile file, `~/.config/crumbbox/profiles.yaml` by default.
+    #[arg(long, env = "CRUMBBOX_PROFILE_FILE", global = true)]
+    profile_file: Option<PathBuf>,
+    /// Transfers run at the same time, instead of the profile's.
+    #[arg(long, global = true)]
+    parallel: Option<usize>,
+    /// Times a failing request is retried, instead of the profile's.
+    #[arg(long, global = true)]
+    retries: Option<u32>,
+    /// Does not draw progress bars.
+    #[arg(long, short, global = true)]
+    quiet: bool,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Uploads a file, or a directory with everything below it, into a remote directory.
+    Upload {
+        local: PathBuf,
+        #[arg(default_value = "")]
+        remote_directory: String,
+    },
+    /// Downloads a remote file, or a remote directory with everything below it.
+    Download {
+        remote: String,
+        #[arg(default_value = ".")]
+        local: PathBuf,
+    },
+    /// Lists the files of a remote directory.
+    Ls {
+        #[arg(default_value = "")]
+        remote_directory: String,
+        /// Also lists the files of its subdirectories.
+        #[arg(long, short)]
+        recursive: bool,
+    },
+    /// Moves remote files or directories to the trash.
+    Rm {
+        #[arg(required = true)]
+        remote: Vec<String>,
+    },
+    /// Moves or renames a remote file or directory.
+    Mv { from: String, to: String },
+    /// Uploads the files of a local directory that are missing or different in a remote one.
+    Sync {
+        local_directory: PathBuf,
+        remote_directory: String,
+        /// Also removes the remote files that are not in the local directory.
+        #[arg(long)]
+        delete: bool,
+        /// Only prints what would change.
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    match run(Cli::parse()).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run(cli: Cli) -> Result<(), ClientError> {
+    let profile_file = cli
+        .profile_file
+        .or_else(Profile::default_path)
+        .ok_or_else(|| ClientError::Profile("No profile file, pass --profile-file".to_string()))?;
+    let mut profile = Profile::load(&profile_file, &cli.profile)?;
+    if let Some(parallel) = cli.parallel {
+        profile.parallel = parallel;
+    }
+    if let Some(retries) = cli.retries {
+        profile.retries = retries;
+    }
+    let client = Client::new(&profile)?;
+    let transfers = Transfers {
+        client: &client,
+        parallel: profile.parallel,
+        quiet: cli.quiet,
+    };
+
+    match cli.command {
+        Command::Upload {
+            local,
+            remote_directory,
+        } => {
+            let uploads = plan_upload(&local, &remote_directory)?;
+            transfers.run(Direction::Upload, uploads).await
+        }
+        Command::Download { remote, local } => {
+            let downloads = match client.stat(&remote).await? {
+                Some(file) => {
+                    let name = file.path.rsplit('/').next().unwrap_or_default();
+                    let local_path = match local.is_dir() {
+                        true => local.join(name),
+                        false => local,
+                    };
+                    vec![Transfer {
+                        local_path,
+                        remote_path: file.path.clone(),
+                        size: file.size,
+                    }]
+                }
+                None => {
+                    let files = client.list(&remote, true).await?;
+                    if files.is_empty() {
+                        return Err(ClientError::Invalid(format!(
+                            "No file or directory {}",
+                            remote
+                        )));
+                    }
+                    plan_download(files, &remote, &local)
+                }
+            };
+            transfers.run(Direction::Download, downloads).await
+        }
+        Command::Ls {
+            remote_directory,
+            recursive,
+        } => {
+            for file in client.list(&remote_directory, recursive).await? {
+                println!(
+                    "{:>12}  {}  {}",
+                    file.size,
+                    file.modified_at.format("%Y-%m-%d %H:%M"),
+                    file.path
+                );
+            }
+            Ok(())
+        }
+        Command::Rm { remote } => {
+            for path in remote {
+                client.remove(&path).await?;
+            }
+            Ok(())
+        }
+        Command::Mv { from, to } => client.rename(&from, &to).await,
+        Command::Sync {
+            local_directory,
+            remote_directory,
+            delete,
+            dry_run,
+        } => {
+            let remote_files = client.list(&remote_directory, true).await?;
+            let plan = plan_sync(&local_directory, &remote_directory, remote_files)?;
+            let removals = match delete {
+                true => plan.removals,
+                false => vec![],
+            };
+            if dry_run {
+                for upload in &plan.uploads {
+                    println!("upload {}", upload.remote_path);
+                }
+                for removal in &removals {
+                    println!("remove {}", removal);
+                }
+                return Ok(());
+            }
+            transfers.run(Direction::Upload, plan.uploads).await?;
+            for removal in removals {
+                client.remove(&removal).await?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Runs batches of transfers with progress bars, reporting the ones that failed.
+struct Transfers<'a> {
+    client: &'a Client,
+    parallel: usize,
+    quiet: bool,
+}
+
+impl Transfers<'_> {
+    async fn run(&self, direction: Direction, transfers: Vec<Transfer>) -> Result<(), ClientError> {
+        let count = transfers.len();
+        let progress = match self.quiet {
+            true => Progress::hidden(),
+            false => Progress::new(transfers.iter().map(|transfer| transfer.size).sum()),
+        };
+        let failures =
+            transfer_all(self.client, direction, transfers, self.parallel, &progress).await?;
+        progress.finish();
+
+        for (transfer, e) in &failures {
+            eprintln!("{}: {}", transfer.remote_path, e);
+        }
+        match failures.len() {
+            0 => Ok(()),
+            failed => Err(ClientError::Invalid(format!(
+                "{} of {} transfers failed",
+                failed, count
+            ))),
+        }
+    }
+}
@@ -0,0 +1,307 @@
+mod profile;
+mod progress;
+mod transfer;
+
+pub use profile::*;
+pub use progress::*;
+pub use transfer::*;
+
+use std::{io, path::Path, time::Duration};
+
+use futures::{Future, TryStreamExt};
+use reqwest::{
+    header,
+    multipart::{Form, Part},
+    Body, Method, RequestBuilder, Response, StatusCode, Url,
+};
+use tokio::io::AsyncWriteExt;
+use tokio_util::io::ReaderStream;
+
+use crate::index::FileRecord;
+
+/// Files a listing request returns at most, the server's own limit.
+const LIST_PAGE_SIZE: usize = 1000;
+/// Wait before the first retry, doubled for each one after it.
+const RETRY_DELAY: Duration = Duration::from_millis(250);
+
+#[derive(thiserror::Error, Debug)]
+pub enum ClientError {
+    #[error("{status}: {message}")]
+    Status { status: StatusCode, message: String },
+    #[error(transparent)]
+    Http(#[from] reqwest::Error),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error("{0}")]
+    Profile(String),
+    #[error("{0}")]
+    Invalid(String),
+}
+
+impl ClientError {
+    /// Whether trying again may succeed: the server could not be reached, failed or was busy.
+    fn is_transient(&self) -> bool {
+        match self {
+            ClientError::Status { status, .. } => {
+                status.is_server_error() || *status == StatusCode::TOO_MANY_REQUESTS
+            }
+            ClientError::Http(e) => e.is_connect() || e.is_timeout() || e.is_body(),
+            _ => false,
+        }
+    }
+}
+
+/// Talks to a crumbbox server with the credentials of a profile, retrying requests that fail
+/// on the way.
+pub struct Client {
+    http: reqwest::Client,
+    url: Url,
+    api_key: Option<String>,
+    retries: u32,
+}
+
+impl Client {
+    pub fn new(profile: &Profile) -> Result<Self, ClientError> {
+        let url = Url::parse(&profile.url)
+            .map_err(|e| ClientError::Profile(format!("Invalid url {}: {}", profile.url, e)))?;
+        if url.cannot_be_a_base() {
+            return Err(ClientError::Profile(format!("Invalid url {}", profile.url)));
+        }
+        Ok(Self {
+            http: reqwest::Client::new(),
+            url,
+            api_key: profile.api_key.clone(),
+            retries: profile.retries,
+        })
+    }
+
+    /// Lists the files under `prefix`, page after page.
+    pub async fn list(
+        &self,
+        prefix: &str,
+        recursive: bool,
+    ) -> Result<Vec<FileRecord>, ClientError> {
+        let mut files = vec![];
+        loop {
+            let mut query = vec![
+                ("prefix", prefix.to_string()),
+                ("recursive", recursive.to_string()),
+                ("limit", LIST_PAGE_SIZE.to_string()),
+            ];
+            if let Some(last) = files.last().map(|file: &FileRecord| file.path.clone()) {
+                query.push(("cursor", last));
+            }
+            let page = self
+                .send(|| {
+                    Ok(self
+                        .request(Method::GET, self.url(&["files"]))
+                        .query(&query))
+                })
+                .await?
+                .json::<Vec<FileRecord>>()
+                .await?;
+            let done = page.len() < LIST_PAGE_SIZE;
+            files.extend(page);
+            if done {
+                return Ok(files);
+            }
+        }
+    }
+
+    /// The indexed record of a file, `None` when there is no file at `remote_path`.
+    pub async fn stat(&self, remote_path: &str) -> Result<Option<FileRecord>, ClientError> {
+        let result = self
+            .send(|| {
+                Ok(self
+                    .request(Method::GET, self.url_of("files", remote_path)?)
+                    .query(&[("stat", "")]))
+            })
+            .await;
+        match result {
+            Ok(response) => Ok(Some(response.json().await?)),
+            Err(ClientError::Status {
+                status: StatusCode::NOT_FOUND,
+                ..
+            }) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Uploads a local file as `remote_path`, creating the directories on the way.
+    pub async fn upload(
+        &self,
+        local_path: &Path,
+        remote_path: &str,
+        progress: &FileProgress,
+    ) -> Result<(), ClientError> {
+        let (directory, file_name) = remote_path.rsplit_once('/').unwrap_or(("", remote_path));
+        self.send(|| {
+            let file = std::fs::File::open(local_path)?;
+            let size = file.metadata()?.len();
+            progress.restart();
+            let progress = progress.clone();
+            let body = ReaderStream::new(tokio::fs::File::from_std(file))
+                .inspect_ok(move |bytes| progress.advance(bytes.len() as u64));
+            let part = Part::stream_with_length(Body::wrap_stream(body), size)
+                .file_name(file_name.to_string());
+            let form = Form::new()
+                .text("relative_path", directory.to_string())
+                .part("file", part);
+            Ok(self
+                .request(Method::POST, self.url(&["upload"]))
+                .multipart(form))
+        })
+        .await?;
+        Ok(())
+    }
+
+    /// Downloads `remote_path` to a local file, replacing it only once the whole file arrived.
+    pub async fn download(
+        &self,
+        remote_path: &str,
+        local_path: &Path,
+        progress: &FileProgress,
+    ) -> Result<(), ClientError> {
+        if let Some(parent) = local_path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let mut partial_path = local_path.as_os_str().to_owned();
+        partial_path.push(".part");
+
+        let result = self
+            .retry(|| async {
+                progress.restart();
+                let response = self
+                    .checked(
+                        self.request(Method::GET, self.url_of("files", remote_path)?)
+                            .send(),
+                    )
+                    .await?;
+                let mut file = tokio::fs::File::create(&partial_path).await?;
+                let mut body = response.bytes_stream();
+                while let Some(bytes) = body.try_next().await? {
+                    file.write_all(&bytes).await?;
+                    progress.advance(bytes.len() as u64);
+                }
+                file.flush().await?;
+                Ok(())
+            })
+            .await;
+        match result {
+            Ok(()) => Ok(tokio::fs::rename(&partial_path, local_path).await?),
+            Err(e) => {
+                let _ = tokio::fs::remove_file(&partial_path).await;
+                Err(e)
+            }
+        }
+    }
+
+    /// Creates a directory whose parent exists, doing nothing when it exists already.
+    pub async fn create_directory(&self, remote_path: &str) -> Result<(), ClientError> {
+        let method = Method::from_bytes(b"MKCOL").expect("MKCOL is a valid method");
+        let result = self
+            .send(|| Ok(self.request(method.clone(), self.url_of("webdav", remote_path)?)))
+            .await;
+        match result {
+            Ok(_)
+            | Err(ClientError::Status {
+                status: StatusCode::METHOD_NOT_ALLOWED,
+                ..
+            }) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Moves a file or directory to the trash of the server.
+    pub async fn remove(&self, remote_path: &str) -> Result<(), ClientError> {
+        self.send(|| Ok(self.request(Method::DELETE, self.url_of("files", remote_path)?)))
+            .await?;
+        Ok(())
+    }
+
+    /// Moves a file or directory, over WebDAV since that is where the server moves things.
+    pub async fn rename(&self, from: &str, to: &str) -> Result<(), ClientError> {
+        let destination = self.url_of("webdav", to)?;
+        let method = Method::from_bytes(b"MOVE").expect("MOVE is a valid method");
+        self.send(|| {
+            Ok(self
+                .request(method.clone(), self.url_of("webdav", from)?)
+                .header("Destination", destination.as_str())
+                .header("Overwrite", "T"))
+        })
+        .await?;
+        Ok(())
+    }
+
+    /// Sends the request made by `request`, again after failures that may pass.
+    async fn send(
+        &self,
+        request: impl Fn() -> Result<RequestBuilder, ClientError>,
+    ) -> Result<Response, ClientError> {
+        self.retry(|| async { self.checked(request()?.send()).await })
+            .await
+    }
+
+    async fn retry<T, F, Fut>(&self, attempt: F) -> Result<T, ClientError>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<T, ClientError>>,
+    {
+        let mut retries = 0;
+        loop {
+            match attempt().await {
+                Err(e) if e.is_transient() && retries < self.retries => {
+                    tokio::time::sleep(RETRY_DELAY * 2u32.pow(retries)).await;
+                    retries += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// The response of a request, or the error the server answered with.
+    async fn checked(
+        &self,
+        response: impl Future<Output = Result<Response, reqwest::Error>>,
+    ) -> Result<Response, ClientError> {
+        let response = response.await?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+        let message = response.text().await.unwrap_or_default();
+        Err(ClientError::Status { status, message })
+    }
+
+    fn request(&self, method: Method, url: Url) -> RequestBuilder {
+        let request = self.http.request(method, url);
+        match &self.api_key {
+            Some(api_key) => request.header(header::AUTHORIZATION, format!("Bearer {}", api_key)),
+            None => request,
+        }
+    }
+
+    fn url(&self, segments: &[&str]) -> Url {
+        let mut url = self.url.clone();
+        url.path_segments_mut()
+            .expect("Checked to be a base")
+            .pop_if_empty()
+            .extend(segments);
+        url
+    }
+
+    /// The URL of a storage path below one of the routes of the server.
+    fn url_of(&self, route: &str, path: &str) -> Result<Url, ClientError> {
+        let path = path.trim_matches('/');
+        if path.is_empty() {
+            return Err(ClientError::Invalid(
+                "Expected a path below the storage root".to_string(),
+            ));
+        }
+        let mut url = self.url(&[route]);
+        url.path_segments_mut()
+            .expect("Checked to be a base")
+            .extend(path.split('/'));
+        Ok(url)
+    }
+}
@@ -0,0 +1,61 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use config::{Config, File, FileFormat};
+use serde::Deserialize;
+
+use crate::client::ClientError;
+
+/// A server and how to talk to it, one of the named sections of the profile file:
+///
+/// ```yaml
+/// default:
+///   url: https://files.example.com
+///   api_key: secret
+///   parallel: 8
+/// ```
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct Profile {
+    pub url: String,
+    pub api_key: Option<String>,
+    /// Transfers run at the same time.
+    pub parallel: usize,
+    /// Times a request failing on the way is sent again.
+    pub retries: u32,
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Self {
+            url: "http://127.0.0.1:8000".to_string(),
+            api_key: None,
+            parallel: 4,
+            retries: 3,
+        }
+    }
+}
+
+impl Profile {
+    /// Reads the profile `name` from a profile file. Names are case-insensitive.
+    pub fn load(path: &Path, name: &str) -> Result<Self, ClientError> {
+        let mut profiles = Config::builder()
+            .add_source(File::from(path).format(FileFormat::Yaml))
+            .build()
+            .and_then(Config::try_deserialize::<HashMap<String, Profile>>)
+            .map_err(|e| {
+                ClientError::Profile(format!("Failed to read {}: {}", path.display(), e))
+            })?;
+        profiles.remove(&name.to_lowercase()).ok_or_else(|| {
+            ClientError::Profile(format!("No profile {} in {}", name, path.display()))
+        })
+    }
+
+    /// `~/.config/crumbbox/profiles.yaml`.
+    pub fn default_path() -> Option<PathBuf> {
+        std::env::var_os("HOME")
+            .map(|home| PathBuf::from(home).join(".config/crumbbox/profiles.yaml"))
+    }
+}
@@ -0,0 +1,75 @@
+use indicatif::{MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+
+/// Progress bars of a batch of transfers: one for the bytes of them all, and one for each
+/// transfer under way.
+#[derive(Clone)]
+pub struct Progress {
+    bars: MultiProgress,
+    total: ProgressBar,
+}
+
+/// The bar of one transfer, which also moves the total.
+#[derive(Clone)]
+pub struct FileProgress {
+    bar: ProgressBar,
+    total: ProgressBar,
+}
+
+impl Progress {
+    pub fn new(total_bytes: u64) -> Self {
+        Self::with_draw_target(total_bytes, ProgressDrawTarget::stderr())
+    }
+
+    /// Progress kept track of without being drawn.
+    pub fn hidden() -> Self {
+        Self::with_draw_target(0, ProgressDrawTarget::hidden())
+    }
+
+    fn with_draw_target(total_bytes: u64, target: ProgressDrawTarget) -> Self {
+        let bars = MultiProgress::with_draw_target(target);
+        let total = bars.add(ProgressBar::new(total_bytes).with_style(style(
+            "[{elapsed_precise}] {wide_bar} {bytes}/{total_bytes} {binary_bytes_per_sec}",
+        )));
+        Self { bars, total }
+    }
+
+    pub fn file(&self, name: &str, size: u64) -> FileProgress {
+        let bar = self.bars.insert_before(
+            &self.total,
+            ProgressBar::new(size)
+                .with_style(style("{wide_msg} {bar:30} {bytes}/{total_bytes}"))
+                .with_message(name.to_string()),
+        );
+        FileProgress {
+            bar,
+            total: self.total.clone(),
+        }
+    }
+
+    pub fn finish(&self) {
+        self.total.finish_and_clear();
+    }
+}
+
+impl FileProgress {
+    pub fn advance(&self, bytes: u64) {
+        self.bar.inc(bytes);
+        self.total.inc(bytes);
+    }
+
+    /// Takes back what a failed attempt transferred before it is retried.
+    pub fn restart(&self) {
+        let transferred = self.bar.position();
+        self.bar.set_position(0);
+        self.total
+            .set_position(self.total.position().saturating_sub(transferred));
+    }
+
+    pub fn finish(&self) {
+        self.bar.finish_and_clear();
+    }
+}
+
+fn style(template: &str) -> ProgressStyle {
+    ProgressStyle::with_template(template).expect("Valid progress bar template")
+}
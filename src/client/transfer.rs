@@ -0,0 +1,231 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use futures::StreamExt;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    client::{Client, ClientError, Progress},
+    index::FileRecord,
+};
+
+/// A file to copy between the local disk and the storage.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Transfer {
+    pub local_path: PathBuf,
+    /// Path relative to the storage root.
+    pub remote_path: String,
+    pub size: u64,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Direction {
+    Upload,
+    Download,
+}
+
+/// What a sync changes to make a remote directory match a local one.
+#[derive(Debug, Default)]
+pub struct SyncPlan {
+    /// Files missing or different on the server.
+    pub uploads: Vec<Transfer>,
+    /// Files only on the server.
+    pub removals: Vec<String>,
+}
+
+/// Runs the transfers, `parallel` at a time, and returns the ones that failed with why. Uploads
+/// go into directories created beforehand, since the server only creates them below the
+/// directory of an upload.
+pub async fn transfer_all(
+    client: &Client,
+    direction: Direction,
+    transfers: Vec<Transfer>,
+    parallel: usize,
+    progress: &Progress,
+) -> Result<Vec<(Transfer, ClientError)>, ClientError> {
+    if let Direction::Upload = direction {
+        // Sorted, so parents come before their subdirectories.
+        let directories = transfers
+            .iter()
+            .flat_map(|transfer| {
+                transfer
+                    .remote_path
+                    .match_indices('/')
+                    .map(|(end, _)| &transfer.remote_path[..end])
+            })
+            .collect::<BTreeSet<_>>();
+        for directory in directories {
+            client.create_directory(directory).await?;
+        }
+    }
+
+    let failures = futures::stream::iter(transfers)
+        .map(|transfer| async move {
+            let file_progress = progress.file(&transfer.remote_path, transfer.size);
+            let result = match direction {
+                Direction::Upload => {
+                    client
+                        .upload(&transfer.local_path, &transfer.remote_path, &file_progress)
+                        .await
+                }
+                Direction::Download => {
+                    client
+                        .download(&transfer.remote_path, &transfer.local_path, &file_progress)
+                        .await
+                }
+            };
+            file_progress.finish();
+            result.err().map(|e| (transfer, e))
+        })
+        .buffer_unordered(parallel.max(1))
+        .filter_map(futures::future::ready)
+        .collect()
+        .await;
+    Ok(failures)
+}
+
+/// Uploads of a local file into `remote_directory`, or of a local directory into a directory
+/// of the same name below it.
+pub fn plan_upload(
+    local_path: &Path,
+    remote_directory: &str,
+) -> Result<Vec<Transfer>, ClientError> {
+    let name = local_path
+        .canonicalize()?
+        .file_name()
+        .and_then(|name| name.to_str())
+        .map(str::to_string)
+        .ok_or_else(|| ClientError::Invalid(format!("Cannot upload {}", local_path.display())))?;
+    let remote_path = join(remote_directory.trim_matches('/'), &name);
+
+    let metadata = fs::metadata(local_path)?;
+    if !metadata.is_dir() {
+        return Ok(vec![Transfer {
+            local_path: local_path.to_path_buf(),
+            remote_path,
+            size: metadata.len(),
+        }]);
+    }
+    Ok(local_files(local_path)?
+        .into_iter()
+        .map(|transfer| Transfer {
+            remote_path: join(&remote_path, &transfer.remote_path),
+            ..transfer
+        })
+        .collect())
+}
+
+/// Downloads of the files listed under the remote directory `remote_path` into a directory of
+/// the same name below `local_directory`.
+pub fn plan_download(
+    files: Vec<FileRecord>,
+    remote_path: &str,
+    local_directory: &Path,
+) -> Vec<Transfer> {
+    let remote_path = remote_path.trim_matches('/');
+    let parent = remote_path
+        .rsplit_once('/')
+        .map_or("", |(parent, _)| parent);
+    files
+        .into_iter()
+        .map(|file| {
+            let relative_path = match parent {
+                "" => file.path.as_str(),
+                parent => file.path[parent.len()..].trim_start_matches('/'),
+            };
+            Transfer {
+                local_path: relative_path
+                    .split('/')
+                    .fold(local_directory.to_path_buf(), |path, segment| {
+                        path.join(segment)
+                    }),
+                remote_path: file.path.clone(),
+                size: file.size,
+            }
+        })
+        .collect()
+}
+
+/// Compares the files of `local_directory` with the ones listed under `remote_directory`, by
+/// size and then checksum.
+pub fn plan_sync(
+    local_directory: &Path,
+    remote_directory: &str,
+    remote_files: Vec<FileRecord>,
+) -> Result<SyncPlan, ClientError> {
+    let remote_directory = remote_directory.trim_matches('/');
+    let mut remote_files = remote_files
+        .into_iter()
+        .map(|file| (file.path.clone(), file))
+        .collect::<HashMap<_, _>>();
+
+    let mut plan = SyncPlan::default();
+    for transfer in local_files(local_directory)? {
+        let remote_path = join(remote_directory, &transfer.remote_path);
+        let unchanged = match remote_files.remove(&remote_path) {
+            Some(remote) if remote.size == transfer.size => match &remote.checksum {
+                Some(checksum) => *checksum == checksum_of(&transfer.local_path)?,
+                None => false,
+            },
+            _ => false,
+        };
+        if !unchanged {
+            plan.uploads.push(Transfer {
+                remote_path,
+                ..transfer
+            });
+        }
+    }
+    plan.removals = remote_files.into_keys().collect();
+    plan.removals.sort();
+    Ok(plan)
+}
+
+/// The regular files below a local directory, with their `/`-separated path relative to it as
+/// remote path. Symbolic links are skipped.
+fn local_files(root: &Path) -> Result<Vec<Transfer>, ClientError> {
+    let mut files = vec![];
+    let mut directories = vec![(root.to_path_buf(), String::new())];
+    while let Some((directory, relative_directory)) = directories.pop() {
+        for entry in fs::read_dir(&directory)? {
+            let entry = entry?;
+            let name = entry.file_name().into_string().map_err(|name| {
+                ClientError::Invalid(format!(
+                    "Cannot upload {}, its name is not UTF-8",
+                    directory.join(name).display()
+                ))
+            })?;
+            let relative_path = join(&relative_directory, &name);
+            let file_type = entry.file_type()?;
+            if file_type.is_dir() {
+                directories.push((entry.path(), relative_path));
+            } else if file_type.is_file() {
+                files.push(Transfer {
+                    local_path: entry.path(),
+                    remote_path: relative_path,
+                    size: entry.metadata()?.len(),
+                });
+            }
+        }
+    }
+    files.sort_by(|a, b| a.remote_path.cmp(&b.remote_path));
+    Ok(files)
+}
+
+/// The hex SHA-256 of a local file, the checksum the server keeps.
+fn checksum_of(path: &Path) -> Result<String, ClientError> {
+    let mut file = fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+fn join(directory: &str, name: &str) -> String {
+    match directory {
+        "" => name.to_string(),
+        directory => format!("{}/{}", directory, name),
+    }
+}
@@ -0,0 +1,327 @@
+use std::{
+    io::{self, Cursor, Read},
+    ops::Range,
+    path::Path,
+    sync::Arc,
+};
+
+use async_compression::{
+    tokio::bufread::{ZstdDecoder, ZstdEncoder},
+    Level,
+};
+use axum::body::Bytes;
+use futures::{stream::BoxStream, StreamExt};
+use mime::Mime;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    fs,
+    io::{AsyncRead, AsyncReadExt, BufReader},
+    runtime::Handle,
+};
+use tokio_util::io::{ReaderStream, StreamReader, SyncIoBridge};
+
+use crate::{
+    configuration::CompressionSettings,
+    content_types,
+    encryption::{Content, Encryption},
+};
+
+const MAGIC: &[u8; 4] = b"CBXZ";
+/// Magic, codec and the size of the original content.
+const HEADER_LENGTH: u64 = 4 + 1 + 8;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Codec {
+    Zstd,
+}
+
+impl Codec {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Codec::Zstd => "zstd",
+        }
+    }
+
+    /// The codec named as by `as_str`, the way metadata records it.
+    pub fn parse(name: &str) -> Result<Self, io::Error> {
+        match name {
+            "zstd" => Ok(Codec::Zstd),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unknown codec {}", name),
+            )),
+        }
+    }
+
+    fn id(&self) -> u8 {
+        match self {
+            Codec::Zstd => 1,
+        }
+    }
+
+    fn from_id(id: u8) -> Result<Self, io::Error> {
+        match id {
+            1 => Ok(Codec::Zstd),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unknown codec {}", id),
+            )),
+        }
+    }
+}
+
+/// Transparent compression of stored files. Compressed files start with a header naming the
+/// codec and the original size, and are encrypted like any other file when encryption is
+/// enabled. Every read of stored content goes through here, so compressed and plain files look
+/// the same to callers.
+///
+/// Whether a file is compressed is never guessed from its content, which may well start like a
+/// header. Readers pass the codec recorded for the file: in the index for the files of the
+/// tree, in the id of versions and in the entry of trashed files.
+pub struct Compression {
+    settings: CompressionSettings,
+    skipped_types: Vec<Mime>,
+    encryption: Arc<Encryption>,
+}
+
+impl Compression {
+    pub fn new(
+        settings: CompressionSettings,
+        encryption: Arc<Encryption>,
+    ) -> Result<Self, mime::FromStrError> {
+        let skipped_types = settings
+            .skipped_mime_types
+            .iter()
+            .map(|mime| mime.parse())
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            settings,
+            skipped_types,
+            encryption,
+        })
+    }
+
+    /// Reads plain, unencrypted files only.
+    pub fn disabled() -> Self {
+        Self {
+            settings: CompressionSettings {
+                enabled: false,
+                ..CompressionSettings::default()
+            },
+            skipped_types: vec![],
+            encryption: Arc::new(Encryption::disabled()),
+        }
+    }
+
+    pub fn encryption(&self) -> &Encryption {
+        &self.encryption
+    }
+
+    /// Compresses a file written by an upload in place, if enabled for its content type and
+    /// the compressed form is small enough to be worth it. Returns the codec used.
+    #[tracing::instrument(name = "Compress file", skip(self, path))]
+    pub async fn compress(
+        &self,
+        path: &Path,
+        content_type: &Mime,
+        size: u64,
+    ) -> Result<Option<Codec>, io::Error> {
+        let skipped = self
+            .skipped_types
+            .iter()
+            .any(|pattern| content_types::matches(pattern, content_type));
+        if !self.settings.enabled || size < self.settings.min_size_bytes || skipped {
+            return Ok(None);
+        }
+
+        let content = self.encryption.open(path).await?.into_reader().await?;
+        let encoder =
+            ZstdEncoder::with_quality(BufReader::new(content), Level::Precise(self.settings.level));
+        let mut header = MAGIC.to_vec();
+        header.push(Codec::Zstd.id());
+        header.extend_from_slice(&size.to_be_bytes());
+        let mut compressed = AsyncReadExt::chain(Cursor::new(header), encoder);
+
+        let mut compressed_path = path.as_os_str().to_owned();
+        compressed_path.push(".zst");
+        let written = self
+            .encryption
+            .write(Path::new(&compressed_path), &mut compressed)
+            .await;
+        let beneficial = match written {
+            Ok(written) => {
+                (written - HEADER_LENGTH) as f64 <= size as f64 * self.settings.max_ratio
+            }
+            Err(_) => false,
+        };
+        if !beneficial {
+            let _ = fs::remove_file(&compressed_path).await;
+            return written.map(|_| None);
+        }
+
+        fs::rename(&compressed_path, path).await?;
+        Ok(Some(Codec::Zstd))
+    }
+
+    /// Opens a stored file for reading its original content, `codec` being the one recorded
+    /// for it.
+    pub async fn open(&self, path: &Path, codec: Option<Codec>) -> Result<Decoded, io::Error> {
+        let content = self.encryption.open(path).await?;
+        let size = match codec {
+            Some(codec) => parse_header(&content.head(HEADER_LENGTH).await?, codec)?,
+            None => content.size(),
+        };
+        Ok(Decoded {
+            content,
+            codec,
+            size,
+        })
+    }
+
+    /// Opens a stored file for reading its original content from blocking code running on the
+    /// runtime's blocking pool, `codec` being the one recorded for it. Compressed files are
+    /// decoded by the runtime.
+    pub fn open_blocking(
+        &self,
+        path: &Path,
+        codec: Option<Codec>,
+    ) -> Result<BlockingDecoded, io::Error> {
+        if codec.is_none() {
+            let content = self.encryption.open_blocking(path)?;
+            return Ok(BlockingDecoded {
+                size: content.size(),
+                codec: None,
+                reader: Box::new(content),
+            });
+        }
+
+        let handle = Handle::current();
+        let content = handle.block_on(self.open(path, codec))?;
+        let size = content.size();
+        let reader = handle.block_on(content.into_reader())?;
+        Ok(BlockingDecoded {
+            size,
+            codec,
+            reader: Box::new(SyncIoBridge::new_with_handle(reader, handle)),
+        })
+    }
+
+    /// Returns the size of the original content of a stored file, `codec` being the one
+    /// recorded for it.
+    pub async fn content_size(&self, path: &Path, codec: Option<Codec>) -> Result<u64, io::Error> {
+        Ok(self.open(path, codec).await?.size())
+    }
+}
+
+/// The original content of a stored file.
+pub struct Decoded {
+    content: Content,
+    codec: Option<Codec>,
+    size: u64,
+}
+
+impl Decoded {
+    /// Size of the original content in bytes.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Codec the file is stored with, `None` if it is not compressed.
+    pub fn codec(&self) -> Option<Codec> {
+        self.codec
+    }
+
+    /// Size of the file as stored by its codec.
+    pub fn encoded_size(&self) -> u64 {
+        match self.codec {
+            Some(_) => self.content.size() - HEADER_LENGTH,
+            None => self.content.size(),
+        }
+    }
+
+    /// Streams a range of the original content, which must be within its size. Ranges of
+    /// compressed files are found by decompressing everything before them.
+    pub async fn range(
+        self,
+        range: Range<u64>,
+    ) -> Result<BoxStream<'static, Result<Bytes, io::Error>>, io::Error> {
+        if self.codec.is_none() {
+            return self.content.range(range).await;
+        }
+
+        let mut decoder =
+            ZstdDecoder::new(BufReader::new(StreamReader::new(self.encoded().await?)));
+        tokio::io::copy(
+            &mut (&mut decoder).take(range.start),
+            &mut tokio::io::sink(),
+        )
+        .await?;
+        Ok(ReaderStream::new(decoder.take(range.end - range.start)).boxed())
+    }
+
+    /// Streams the file as stored by its codec, e.g. the zstd frame of a compressed file.
+    pub async fn encoded(self) -> Result<BoxStream<'static, Result<Bytes, io::Error>>, io::Error> {
+        let start = match self.codec {
+            Some(_) => HEADER_LENGTH,
+            None => 0,
+        };
+        let end = self.content.size();
+        self.content.range(start..end).await
+    }
+
+    pub async fn into_reader(self) -> Result<impl AsyncRead + Send + Unpin, io::Error> {
+        let size = self.size;
+        Ok(StreamReader::new(self.range(0..size).await?))
+    }
+
+    pub async fn read_to_end(self) -> Result<Vec<u8>, io::Error> {
+        let mut contents = Vec::with_capacity(self.size as usize);
+        self.into_reader().await?.read_to_end(&mut contents).await?;
+        Ok(contents)
+    }
+}
+
+/// The original content of a stored file, read from blocking code.
+pub struct BlockingDecoded {
+    size: u64,
+    codec: Option<Codec>,
+    reader: Box<dyn Read + Send>,
+}
+
+impl BlockingDecoded {
+    /// Size of the original content in bytes.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Codec the file is stored with, `None` if it is not compressed.
+    pub fn codec(&self) -> Option<Codec> {
+        self.codec
+    }
+}
+
+impl Read for BlockingDecoded {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+/// Parses the original size from the header of a file compressed with `codec`, failing if the
+/// header names another codec or is missing.
+fn parse_header(header: &[u8], codec: Codec) -> Result<u64, io::Error> {
+    if header.len() < HEADER_LENGTH as usize || &header[..4] != MAGIC {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Compressed file has no header",
+        ));
+    }
+    if Codec::from_id(header[4])? != codec {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Compressed file is not {}", codec.as_str()),
+        ));
+    }
+    Ok(u64::from_be_bytes(header[5..13].try_into().unwrap()))
+}
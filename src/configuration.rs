@@ -1,9 +1,44 @@
 use config::{Config, ConfigError, File};
 use serde::Deserialize;
 
+use crate::events::FileEventKind;
+
 #[derive(Deserialize)]
 pub struct Settings {
     pub application: ApplicationSettings,
+    #[serde(default)]
+    pub telemetry: TelemetrySettings,
+    pub audit: AuditSettings,
+    #[serde(default)]
+    pub auth: AuthSettings,
+    #[serde(default)]
+    pub versioning: VersioningSettings,
+    #[serde(default)]
+    pub trash: TrashSettings,
+    #[serde(default)]
+    pub webhooks: WebhooksSettings,
+    #[serde(default)]
+    pub events: EventsSettings,
+    #[serde(default)]
+    pub journal: JournalSettings,
+    #[serde(default)]
+    pub index: IndexSettings,
+    #[serde(default)]
+    pub content_types: ContentTypesSettings,
+    #[serde(default)]
+    pub scanning: ScanningSettings,
+    #[serde(default)]
+    pub thumbnails: ThumbnailsSettings,
+    #[serde(default)]
+    pub encryption: EncryptionSettings,
+    #[serde(default)]
+    pub compression: CompressionSettings,
+    #[serde(default)]
+    pub extraction: ExtractionSettings,
+    #[serde(default)]
+    pub webdav: WebDavSettings,
+    #[serde(default)]
+    pub s3: S3Settings,
 }
 
 #[derive(Deserialize)]
@@ -11,6 +46,383 @@ pub struct ApplicationSettings {
     pub port: u16,
     pub host: String,
     pub storage_path: String,
+    pub min_free_space_bytes: u64,
+}
+
+#[derive(Deserialize)]
+pub struct TelemetrySettings {
+    pub service_name: String,
+    #[serde(default)]
+    pub log_format: LogFormat,
+    /// OTLP/HTTP traces endpoint, e.g. `http://localhost:4318/v1/traces`. Spans are only
+    /// exported when this is set.
+    pub otlp_endpoint: Option<String>,
+}
+
+impl Default for TelemetrySettings {
+    fn default() -> Self {
+        Self {
+            service_name: String::from("crumbbox"),
+            log_format: LogFormat::default(),
+            otlp_endpoint: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Default, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human readable lines.
+    #[default]
+    Pretty,
+    /// Bunyan-style JSON objects, one per line.
+    Json,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct AuditSettings {
+    /// Path of the active JSON-lines audit file. Rotated files get a numeric suffix.
+    pub path: String,
+    pub max_file_bytes: u64,
+    pub max_files: usize,
+}
+
+#[derive(Deserialize, Default, Clone)]
+pub struct AuthSettings {
+    #[serde(default)]
+    pub api_keys: Vec<ApiKeySettings>,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct ApiKeySettings {
+    pub user: String,
+    pub key: String,
+    #[serde(default)]
+    pub admin: bool,
+    /// Paths the key can read and change, the whole tree when empty. Admin keys are never
+    /// limited.
+    #[serde(default)]
+    pub allowed_paths: Vec<String>,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct VersioningSettings {
+    /// Keep the previous content of files overwritten by uploads.
+    #[serde(default)]
+    pub enabled: bool,
+    /// Versions kept per file, newest first. Unlimited when unset.
+    pub max_versions: Option<usize>,
+    /// Versions older than this are removed. Unlimited when unset.
+    pub max_age_days: Option<u64>,
+    /// How often the versions of every file are pruned, at most every second, so the age limit
+    /// also applies to files not overwritten since.
+    pub prune_interval_seconds: u64,
+}
+
+impl Default for VersioningSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_versions: None,
+            max_age_days: None,
+            prune_interval_seconds: 3600,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct TrashSettings {
+    /// Deleted entries older than this are removed permanently.
+    pub retention_days: u64,
+    /// How often expired entries are looked for, at most every second.
+    pub purge_interval_seconds: u64,
+}
+
+impl Default for TrashSettings {
+    fn default() -> Self {
+        Self {
+            retention_days: 30,
+            purge_interval_seconds: 3600,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct EventsSettings {
+    /// Recent events kept for change feed subscribers resuming from a cursor.
+    pub buffer_size: usize,
+}
+
+impl Default for EventsSettings {
+    fn default() -> Self {
+        Self { buffer_size: 1000 }
+    }
+}
+
+#[derive(Deserialize, Default, Clone)]
+pub struct JournalSettings {
+    /// Change journal file. Defaults to `journal/journal.jsonl` in the internal directory of
+    /// the storage root.
+    pub path: Option<String>,
+}
+
+#[derive(Deserialize, Default, Clone)]
+pub struct IndexSettings {
+    /// SQLite metadata index. Defaults to `index/index.sqlite` in the internal directory of the
+    /// storage root.
+    pub path: Option<String>,
+    #[serde(default)]
+    pub full_text: FullTextSettings,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct FullTextSettings {
+    /// Extract the text of text-like files into the index for full-text search.
+    pub enabled: bool,
+    /// Larger files are left out of full-text search.
+    pub max_file_size_bytes: u64,
+}
+
+impl Default for FullTextSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            max_file_size_bytes: 10 * 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct WebhooksSettings {
+    #[serde(default)]
+    pub endpoints: Vec<WebhookEndpointSettings>,
+    /// Deliveries failing this many times are moved out of the outbox.
+    pub max_attempts: u32,
+    /// Delay after the first failed attempt, doubled after every further one.
+    pub initial_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+}
+
+impl Default for WebhooksSettings {
+    fn default() -> Self {
+        Self {
+            endpoints: vec![],
+            max_attempts: 10,
+            initial_backoff_ms: 1000,
+            max_backoff_ms: 3_600_000,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct WebhookEndpointSettings {
+    pub url: String,
+    /// Key of the HMAC-SHA256 signature sent with every delivery.
+    pub secret: String,
+    /// Event kinds to deliver. All of them when empty.
+    #[serde(default)]
+    pub events: Vec<FileEventKind>,
+    /// Globs matched against the relative path, `*` stays within a directory and `**` spans
+    /// several. All paths when empty.
+    #[serde(default)]
+    pub paths: Vec<String>,
+}
+
+/// MIME types, which may be `type/*`, and extensions, with or without a leading dot, that
+/// uploads to the storage root may have. Denied entries win, empty allow lists allow everything.
+#[derive(Deserialize, Default, Clone)]
+pub struct ContentTypesSettings {
+    #[serde(default)]
+    pub allowed_mime_types: Vec<String>,
+    #[serde(default)]
+    pub denied_mime_types: Vec<String>,
+    #[serde(default)]
+    pub allowed_extensions: Vec<String>,
+    #[serde(default)]
+    pub denied_extensions: Vec<String>,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct ScanningSettings {
+    /// Scan uploaded files before they are moved into place.
+    pub enabled: bool,
+    /// clamd to stream files to, `tcp://host:port` or `unix:///path/to/clamd.sock`.
+    pub clamd_address: String,
+    pub on_infected: InfectedAction,
+    pub timeout_seconds: u64,
+}
+
+impl Default for ScanningSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            clamd_address: String::from("tcp://127.0.0.1:3310"),
+            on_infected: InfectedAction::default(),
+            timeout_seconds: 60,
+        }
+    }
+}
+
+#[derive(Deserialize, Default, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum InfectedAction {
+    /// Delete infected files.
+    #[default]
+    Reject,
+    /// Keep infected files in the quarantine area for review.
+    Quarantine,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct ThumbnailsSettings {
+    /// Sizes in pixels thumbnails fit within, the only ones that can be requested.
+    pub sizes: Vec<u32>,
+    /// Generate every size as soon as an image is uploaded rather than on first request.
+    pub generate_on_upload: bool,
+    /// Images larger than this are never read to make thumbnails of.
+    pub max_source_size_bytes: u64,
+}
+
+impl Default for ThumbnailsSettings {
+    fn default() -> Self {
+        Self {
+            sizes: vec![128, 512],
+            generate_on_upload: true,
+            max_source_size_bytes: 32 * 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct EncryptionSettings {
+    /// Encrypt written files with the active key, and read every stored file as encrypted.
+    /// Files stay readable while their master key is configured. Enabling or disabling
+    /// encryption applies to the whole storage root: files written before enabling it are
+    /// unreadable until `crumbbox encrypt` has encrypted them.
+    pub enabled: bool,
+    /// Id of the master key new files are encrypted with.
+    pub active_key: Option<String>,
+    /// Keys wrapping the per-file data keys. Old keys must stay until rotation has moved every
+    /// file to the active one.
+    #[serde(default)]
+    pub master_keys: Vec<MasterKeySettings>,
+    /// Plaintext bytes per encrypted chunk, the granularity of range reads.
+    pub chunk_size_bytes: u32,
+}
+
+impl Default for EncryptionSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            active_key: None,
+            master_keys: vec![],
+            chunk_size_bytes: 65536,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct MasterKeySettings {
+    /// Up to 32 bytes, stored in the header of every file encrypted with the key.
+    pub id: String,
+    /// 32 bytes, hex-encoded.
+    pub key: String,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct CompressionSettings {
+    /// Compress uploaded files with zstd when it saves enough space.
+    pub enabled: bool,
+    /// zstd level, from 1 (fastest) to 22 (smallest).
+    pub level: i32,
+    /// Files smaller than this are stored as they are.
+    pub min_size_bytes: u64,
+    /// Largest compressed size worth keeping, as a fraction of the original size.
+    pub max_ratio: f64,
+    /// Content types that are compressed already, `type/*` for all subtypes.
+    pub skipped_mime_types: Vec<String>,
+}
+
+impl Default for CompressionSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            level: 3,
+            min_size_bytes: 1024,
+            max_ratio: 0.9,
+            skipped_mime_types: [
+                "image/jpeg",
+                "image/png",
+                "image/gif",
+                "image/webp",
+                "video/*",
+                "audio/*",
+                "application/zip",
+                "application/gzip",
+                "application/zstd",
+                "application/x-bzip2",
+                "application/x-xz",
+                "application/x-7z-compressed",
+                "application/vnd.rar",
+                "application/pdf",
+            ]
+            .map(String::from)
+            .to_vec(),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct ExtractionSettings {
+    /// Most entries, files and directories, an uploaded archive may hold.
+    pub max_entries: usize,
+    /// Most bytes extracted from one archive.
+    pub max_total_size_bytes: u64,
+    /// Most bytes extracted per byte of archive, against archives that unpack to far more
+    /// than they weigh.
+    pub max_ratio: u64,
+}
+
+impl Default for ExtractionSettings {
+    fn default() -> Self {
+        Self {
+            max_entries: 10000,
+            max_total_size_bytes: 1073741824,
+            max_ratio: 100,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct WebDavSettings {
+    /// Longest a lock is held without being refreshed. Locks asked for without a timeout, or
+    /// an infinite one, get this one.
+    pub max_lock_timeout_seconds: u64,
+}
+
+impl Default for WebDavSettings {
+    fn default() -> Self {
+        Self {
+            max_lock_timeout_seconds: 3600,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct S3Settings {
+    /// Furthest the time a request was signed at may be from the server's.
+    pub max_clock_skew_seconds: u64,
+    /// Multipart uploads neither completed nor aborted within this long are removed.
+    pub multipart_upload_expiry_hours: u64,
+}
+
+impl Default for S3Settings {
+    fn default() -> Self {
+        Self {
+            max_clock_skew_seconds: 900,
+            multipart_upload_expiry_hours: 24,
+        }
+    }
 }
 
 impl Settings {
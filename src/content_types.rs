@@ -0,0 +1,122 @@
+use std::path::Path;
+
+use mime::Mime;
+
+use crate::configuration::ContentTypesSettings;
+
+/// Leading bytes of a file looked at to detect its type.
+pub const SNIFF_LENGTH: usize = 8192;
+
+/// Content types of text besides `text/*`, trusted from the file name when the content is text.
+const TEXT_CONTENT_TYPES: [&str; 4] = [
+    "application/json",
+    "application/xml",
+    "application/toml",
+    "application/javascript",
+];
+
+/// Detects the content type of a file from its leading bytes. Binary formats are recognised by
+/// their magic bytes, text takes the type its name suggests if that is a text type.
+pub fn detect(head: &[u8], file_name: &str) -> Mime {
+    if let Some(kind) = infer::get(head) {
+        if let Ok(mime) = kind.mime_type().parse() {
+            return mime;
+        }
+    }
+
+    if !is_text(head) {
+        return mime::APPLICATION_OCTET_STREAM;
+    }
+    match mime_guess::from_path(file_name).first() {
+        Some(guess)
+            if guess.type_() == mime::TEXT || TEXT_CONTENT_TYPES.contains(&guess.essence_str()) =>
+        {
+            guess
+        }
+        _ => mime::TEXT_PLAIN,
+    }
+}
+
+/// Whether the content type matches a pattern, either a type or `type/*` for all of its
+/// subtypes.
+pub fn matches(pattern: &Mime, content_type: &Mime) -> bool {
+    pattern.type_() == content_type.type_()
+        && (pattern.subtype() == mime::STAR || pattern.subtype() == content_type.subtype())
+}
+
+/// UTF-8 without NUL bytes, allowing for a character cut off at the end of the sample.
+fn is_text(head: &[u8]) -> bool {
+    let valid = match std::str::from_utf8(head) {
+        Ok(_) => true,
+        Err(e) => e.error_len().is_none(),
+    };
+    valid && !head.contains(&0)
+}
+
+/// The content types and extensions uploads may have. Denied entries win over allowed ones, and
+/// empty allow lists allow everything.
+pub struct ContentPolicy {
+    allowed_types: Vec<Mime>,
+    denied_types: Vec<Mime>,
+    allowed_extensions: Vec<String>,
+    denied_extensions: Vec<String>,
+}
+
+impl ContentPolicy {
+    pub fn new(settings: &ContentTypesSettings) -> Result<Self, mime::FromStrError> {
+        let parse = |types: &[String]| {
+            types
+                .iter()
+                .map(|mime| mime.parse())
+                .collect::<Result<Vec<Mime>, _>>()
+        };
+        let normalise = |extensions: &[String]| {
+            extensions
+                .iter()
+                .map(|extension| extension.trim_start_matches('.').to_lowercase())
+                .collect()
+        };
+
+        Ok(Self {
+            allowed_types: parse(&settings.allowed_mime_types)?,
+            denied_types: parse(&settings.denied_mime_types)?,
+            allowed_extensions: normalise(&settings.allowed_extensions),
+            denied_extensions: normalise(&settings.denied_extensions),
+        })
+    }
+
+    pub fn check_extension(&self, file_name: &str) -> Result<(), String> {
+        let extension = Path::new(file_name)
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase());
+        let denied = extension
+            .as_ref()
+            .is_some_and(|extension| self.denied_extensions.contains(extension));
+        let allowed = self.allowed_extensions.is_empty()
+            || extension
+                .as_ref()
+                .is_some_and(|extension| self.allowed_extensions.contains(extension));
+
+        if denied || !allowed {
+            Err(format!("Files named {} are not allowed", file_name))
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn check_type(&self, file_name: &str, content_type: &Mime) -> Result<(), String> {
+        let matches = |pattern: &Mime| matches(pattern, content_type);
+        let denied = self.denied_types.iter().any(matches);
+        let allowed = self.allowed_types.is_empty() || self.allowed_types.iter().any(matches);
+
+        if denied || !allowed {
+            Err(format!(
+                "{} is {}, which is not allowed",
+                file_name,
+                content_type.essence_str()
+            ))
+        } else {
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,151 @@
+use std::{path::Path, sync::Arc};
+
+use axum::{
+    async_trait,
+    extract::{FromRequest, RequestParts},
+    http::{header, StatusCode},
+};
+
+use crate::configuration::AuthSettings;
+
+/// A path the caller's API key does not reach.
+#[derive(thiserror::Error, Debug)]
+#[error("{0} is outside the paths allowed to the API key")]
+pub struct OutOfScope(pub String);
+
+/// The caller of a request, resolved from a `Bearer` API key in the `Authorization` header, or
+/// from `Basic` credentials whose password is the key for clients that only speak those, such as
+/// WebDAV mounts. Requests without a key are anonymous, which is only allowed while no API key
+/// is configured: the allowed paths of keys would mean nothing otherwise.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Identity {
+    Anonymous,
+    User {
+        name: String,
+        admin: bool,
+        allowed_paths: Vec<String>,
+    },
+}
+
+impl Identity {
+    pub fn name(&self) -> &str {
+        match self {
+            Identity::Anonymous => "anonymous",
+            Identity::User { name, .. } => name,
+        }
+    }
+
+    pub fn is_admin(&self) -> bool {
+        matches!(self, Identity::User { admin: true, .. })
+    }
+
+    /// Paths the caller is limited to, or `None` for the whole tree.
+    pub fn allowed_paths(&self) -> Option<&[String]> {
+        match self {
+            Identity::User {
+                admin: false,
+                allowed_paths,
+                ..
+            } if !allowed_paths.is_empty() => Some(allowed_paths),
+            _ => None,
+        }
+    }
+
+    /// Whether the caller may read or change `path`.
+    pub fn can_access(&self, path: &str) -> bool {
+        in_scope(path, self.allowed_paths())
+    }
+
+    /// Fails for paths outside the allowed paths of the caller, for every route taking a path to
+    /// check before touching it.
+    pub fn check_scope(&self, path: &str) -> Result<(), OutOfScope> {
+        match self.can_access(path) {
+            true => Ok(()),
+            false => Err(OutOfScope(path.to_string())),
+        }
+    }
+
+    /// The caller of a request without an API key, `None` once keys are configured.
+    pub fn anonymous(auth: &AuthSettings) -> Option<Self> {
+        auth.api_keys.is_empty().then_some(Identity::Anonymous)
+    }
+
+    pub fn from_api_key(auth: &AuthSettings, key: &str) -> Option<Self> {
+        auth.api_keys
+            .iter()
+            .find(|api_key| api_key.key == key)
+            .map(|api_key| Identity::User {
+                name: api_key.user.clone(),
+                admin: api_key.admin,
+                allowed_paths: api_key
+                    .allowed_paths
+                    .iter()
+                    .map(|path| path.trim_matches('/').to_string())
+                    .collect(),
+            })
+    }
+}
+
+#[async_trait]
+impl<B: Send> FromRequest<B> for Identity {
+    type Rejection = (StatusCode, &'static str);
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let authorization = req
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok());
+        let key = match authorization {
+            Some(value) => match value.strip_prefix("Bearer ") {
+                Some(key) => Some(key.to_string()),
+                None => match value.strip_prefix("Basic ") {
+                    Some(credentials) => Some(
+                        basic_password(credentials)
+                            .ok_or((StatusCode::UNAUTHORIZED, "Invalid Basic credentials"))?,
+                    ),
+                    None => None,
+                },
+            },
+            None => None,
+        };
+
+        let auth = req
+            .extensions()
+            .get::<Arc<AuthSettings>>()
+            .expect("AuthSettings extension is missing");
+
+        match key {
+            Some(key) => Identity::from_api_key(auth, &key)
+                .ok_or((StatusCode::UNAUTHORIZED, "Unknown API key")),
+            None => Identity::anonymous(auth).ok_or((StatusCode::UNAUTHORIZED, "Missing API key")),
+        }
+    }
+}
+
+/// Whether `path` is at or below one of the `scope` paths, `None` or an empty path being the
+/// whole tree.
+pub fn in_scope(path: &str, scope: Option<&[String]>) -> bool {
+    scope.is_none_or(|scope| {
+        scope
+            .iter()
+            .any(|allowed| Path::new(path).starts_with(allowed))
+    })
+}
+
+/// Whether `path` is in scope, or a directory on the way to a path in scope. Callers may list
+/// those to find their way, but not change them.
+pub fn leads_into_scope(path: &str, scope: Option<&[String]>) -> bool {
+    scope.is_none_or(|scope| {
+        scope.iter().any(|allowed| {
+            Path::new(path).starts_with(allowed) || Path::new(allowed).starts_with(path)
+        })
+    })
+}
+
+/// The password of base64 encoded `user:password` credentials, the user name being ignored.
+fn basic_password(credentials: &str) -> Option<String> {
+    let decoded = String::from_utf8(base64::decode(credentials.trim()).ok()?).ok()?;
+    decoded
+        .split_once(':')
+        .map(|(_, password)| password.to_string())
+}
@@ -1,3 +1,7 @@
+mod identity;
 mod storage_details;
+mod write_locks;
 
+pub use identity::*;
 pub use storage_details::*;
+pub use write_locks::*;
@@ -1,3 +1,57 @@
+use std::{
+    fs::Metadata,
+    io,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use super::WriteLocks;
+
+/// Directory under the storage root holding crumbbox's own data, hidden from clients.
+pub const INTERNAL_DIRECTORY: &str = ".crumbbox";
+
 pub struct StorageDetails {
     pub path: String,
+    pub min_free_space_bytes: u64,
+    pub write_locks: WriteLocks,
+}
+
+impl StorageDetails {
+    pub fn space(&self) -> Result<StorageSpace, io::Error> {
+        Ok(StorageSpace {
+            total: fs2::total_space(&self.path)?,
+            free: fs2::available_space(&self.path)?,
+        })
+    }
+
+    /// Resolves a validated path relative to the storage root.
+    pub fn file_path(&self, relative_path: &str) -> PathBuf {
+        Path::new(&self.path).join(relative_path)
+    }
+
+    /// Resolves an area of the internal directory, e.g. `versions`.
+    pub fn internal_path(&self, area: &str) -> PathBuf {
+        Path::new(&self.path).join(INTERNAL_DIRECTORY).join(area)
+    }
+}
+
+/// The entity tag of a stored file, changing whenever it is written.
+pub fn etag(metadata: &Metadata) -> String {
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .unwrap_or_default();
+    format!("\"{:x}-{:x}\"", metadata.len(), modified.as_nanos())
+}
+
+pub struct StorageSpace {
+    pub total: u64,
+    pub free: u64,
+}
+
+impl StorageSpace {
+    pub fn used(&self) -> u64 {
+        self.total.saturating_sub(self.free)
+    }
 }
@@ -0,0 +1,36 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    path::Path,
+};
+
+use tokio::sync::{Mutex, MutexGuard};
+
+/// Number of locks paths are spread over.
+const STRIPES: usize = 64;
+
+/// Serialises writes to the same file: an upload holds the lock of a path from checking the file
+/// it replaces until its own file is in place, so concurrent conditional uploads cannot both pass
+/// their checks. Paths share a fixed set of locks.
+pub struct WriteLocks {
+    stripes: Vec<Mutex<()>>,
+}
+
+impl Default for WriteLocks {
+    fn default() -> Self {
+        Self {
+            stripes: (0..STRIPES).map(|_| Mutex::new(())).collect(),
+        }
+    }
+}
+
+impl WriteLocks {
+    /// Waits for the lock of `path`, held until the guard is dropped.
+    pub async fn lock(&self, path: &Path) -> MutexGuard<'_, ()> {
+        let mut hasher = DefaultHasher::new();
+        path.hash(&mut hasher);
+        self.stripes[(hasher.finish() % STRIPES as u64) as usize]
+            .lock()
+            .await
+    }
+}
@@ -0,0 +1,810 @@
+use std::{
+    collections::HashMap,
+    io::{self, ErrorKind, Read, Seek, SeekFrom, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use axum::body::Bytes;
+use chacha20poly1305::{
+    aead::{rand_core::RngCore, Aead, AeadCore, KeyInit, OsRng, Payload},
+    XChaCha20Poly1305, XNonce,
+};
+use futures::{stream::BoxStream, StreamExt};
+use serde::Serialize;
+use tokio::{
+    fs::{self, File},
+    io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWriteExt, BufWriter},
+};
+use tokio_util::io::{ReaderStream, StreamReader};
+use uuid::Uuid;
+
+use crate::configuration::EncryptionSettings;
+
+const MAGIC: &[u8; 4] = b"CBXE";
+const FORMAT_VERSION: u8 = 1;
+/// Key ids are stored zero-padded, so the header has the same length whatever the key.
+pub const MAX_KEY_ID_LENGTH: usize = 32;
+const KEY_LENGTH: usize = 32;
+const TAG_LENGTH: u64 = 16;
+const NONCE_LENGTH: usize = 24;
+/// Chunk nonces are the prefix followed by the chunk index and a flag marking the last chunk.
+const NONCE_PREFIX_LENGTH: usize = NONCE_LENGTH - 5;
+const WRAPPED_KEY_LENGTH: usize = NONCE_LENGTH + KEY_LENGTH + TAG_LENGTH as usize;
+/// Magic, format version, chunk size, key id, wrapped data key and nonce prefix.
+const HEADER_LENGTH: usize =
+    4 + 1 + 4 + MAX_KEY_ID_LENGTH + WRAPPED_KEY_LENGTH + NONCE_PREFIX_LENGTH;
+/// The part of the header authenticated along with the wrapped data key.
+const WRAPPED_KEY_OFFSET: usize = 4 + 1 + 4 + MAX_KEY_ID_LENGTH;
+
+#[derive(thiserror::Error, Debug)]
+pub enum EncryptionError {
+    #[error("Master key {0} is not 32 hex-encoded bytes")]
+    InvalidKey(String),
+    #[error("Master key id {0} is empty or longer than 32 bytes")]
+    InvalidKeyId(String),
+    #[error("Active key {0} is not one of the master keys")]
+    UnknownActiveKey(String),
+    #[error("Encryption is enabled without an active key")]
+    MissingActiveKey,
+    #[error("Chunk size must be positive")]
+    InvalidChunkSize,
+}
+
+#[derive(Serialize, Debug)]
+pub struct RotationReport {
+    /// Encrypted files whose data key was wrapped again with the active key.
+    pub rewrapped: usize,
+    /// Encrypted files already using the active key.
+    pub current: usize,
+    /// Encrypted files whose data key could not be unwrapped, such as those of a master key
+    /// no longer configured.
+    pub failed: usize,
+}
+
+#[derive(Serialize, Debug)]
+pub struct EncryptionReport {
+    /// Plaintext files encrypted with the active key.
+    pub encrypted: usize,
+    /// Files already encrypted.
+    pub current: usize,
+    /// Files encrypted with a master key no longer configured, left as they are.
+    pub failed: usize,
+}
+
+/// Encryption at rest of stored files. Every file gets a random data key, wrapped with a master
+/// key from the configuration and kept in the file's header, and its content is encrypted in
+/// chunks with XChaCha20-Poly1305 so ranges can be read without decrypting the whole file.
+///
+/// Whether files are encrypted is never guessed from their content: with encryption enabled
+/// every file is written with the header and read as encrypted, without it files are read as
+/// they are. Rotating a master key only wraps the data keys again.
+pub struct Encryption {
+    master_keys: HashMap<String, XChaCha20Poly1305>,
+    /// Key new files are encrypted with, `None` to write them in plaintext.
+    active_key: Option<String>,
+    chunk_size: u32,
+}
+
+impl Encryption {
+    pub fn new(settings: &EncryptionSettings) -> Result<Self, EncryptionError> {
+        let mut master_keys = HashMap::new();
+        for master_key in &settings.master_keys {
+            if master_key.id.is_empty() || master_key.id.len() > MAX_KEY_ID_LENGTH {
+                return Err(EncryptionError::InvalidKeyId(master_key.id.clone()));
+            }
+            let key = hex::decode(&master_key.key)
+                .ok()
+                .filter(|key| key.len() == KEY_LENGTH)
+                .ok_or_else(|| EncryptionError::InvalidKey(master_key.id.clone()))?;
+            master_keys.insert(
+                master_key.id.clone(),
+                XChaCha20Poly1305::new_from_slice(&key)
+                    .map_err(|_| EncryptionError::InvalidKey(master_key.id.clone()))?,
+            );
+        }
+
+        let active_key = match (&settings.active_key, settings.enabled) {
+            (Some(id), _) if !master_keys.contains_key(id) => {
+                return Err(EncryptionError::UnknownActiveKey(id.clone()))
+            }
+            (Some(id), true) => Some(id.clone()),
+            (None, true) => return Err(EncryptionError::MissingActiveKey),
+            (_, false) => None,
+        };
+        if settings.chunk_size_bytes == 0 {
+            return Err(EncryptionError::InvalidChunkSize);
+        }
+
+        Ok(Self {
+            master_keys,
+            active_key,
+            chunk_size: settings.chunk_size_bytes,
+        })
+    }
+
+    /// Reads plaintext files only, for stores without encryption configured.
+    pub fn disabled() -> Self {
+        Self {
+            master_keys: HashMap::new(),
+            active_key: None,
+            chunk_size: EncryptionSettings::default().chunk_size_bytes,
+        }
+    }
+
+    /// Whether new files are encrypted.
+    pub fn is_enabled(&self) -> bool {
+        self.active_key.is_some()
+    }
+
+    /// Writes everything `reader` yields to a new file at `path`, returning the number of
+    /// plaintext bytes written.
+    pub async fn write<R>(&self, path: &Path, reader: &mut R) -> Result<u64, io::Error>
+    where
+        R: AsyncRead + Unpin,
+    {
+        let mut file = BufWriter::new(File::create(path).await?);
+        let (header, mut encryptor) = match self.encryptor()? {
+            Some(encryptor) => encryptor,
+            None => return tokio::io::copy(reader, &mut file).await,
+        };
+        file.write_all(&header).await?;
+
+        let mut buffer = vec![0; self.chunk_size as usize];
+        let mut written = 0;
+        loop {
+            let read = reader.read(&mut buffer).await?;
+            if read == 0 {
+                break;
+            }
+            written += read as u64;
+            file.write_all(&encryptor.update(&buffer[..read])?).await?;
+        }
+        file.write_all(&encryptor.finish()?).await?;
+
+        file.flush().await?;
+        Ok(written)
+    }
+
+    /// Writes everything `reader` yields to a new file at `path` from blocking code, returning
+    /// the number of plaintext bytes written.
+    pub fn write_blocking(&self, path: &Path, reader: &mut impl Read) -> Result<u64, io::Error> {
+        let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+        let (header, mut encryptor) = match self.encryptor()? {
+            Some(encryptor) => encryptor,
+            None => {
+                let written = io::copy(reader, &mut file)?;
+                file.flush()?;
+                return Ok(written);
+            }
+        };
+        file.write_all(&header)?;
+
+        let mut buffer = vec![0; self.chunk_size as usize];
+        let mut written = 0;
+        loop {
+            let read = match reader.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            written += read as u64;
+            file.write_all(&encryptor.update(&buffer[..read])?)?;
+        }
+        file.write_all(&encryptor.finish()?)?;
+
+        file.flush()?;
+        Ok(written)
+    }
+
+    /// Opens a stored file for reading its plaintext.
+    pub async fn open(&self, path: &Path) -> Result<Content, io::Error> {
+        let mut file = File::open(path).await?;
+        let length = file.metadata().await?.len();
+        let decryptor = match self.is_enabled() {
+            true => {
+                let mut header = Vec::with_capacity(HEADER_LENGTH);
+                (&mut file)
+                    .take(HEADER_LENGTH as u64)
+                    .read_to_end(&mut header)
+                    .await?;
+                Some(self.decryptor(&header, length)?)
+            }
+            false => None,
+        };
+        Ok(Content {
+            file,
+            size: decryptor.as_ref().map_or(length, Decryptor::size),
+            decryptor,
+        })
+    }
+
+    /// Opens a stored file for reading its plaintext from blocking code.
+    pub fn open_blocking(&self, path: &Path) -> Result<BlockingContent, io::Error> {
+        let mut file = std::fs::File::open(path)?;
+        let length = file.metadata()?.len();
+        let decryptor = match self.is_enabled() {
+            true => {
+                let mut header = Vec::with_capacity(HEADER_LENGTH);
+                (&mut file)
+                    .take(HEADER_LENGTH as u64)
+                    .read_to_end(&mut header)?;
+                Some(self.decryptor(&header, length)?)
+            }
+            false => None,
+        };
+        Ok(BlockingContent {
+            file,
+            size: decryptor.as_ref().map_or(length, Decryptor::size),
+            decryptor,
+            index: 0,
+            buffer: vec![],
+            position: 0,
+        })
+    }
+
+    /// Wraps the data keys of every encrypted file under `root` with the active key, leaving
+    /// the content untouched. The header is rewritten in place, so files replaced meanwhile
+    /// are never overwritten with older content.
+    #[tracing::instrument(name = "Rotate encryption keys", skip(self))]
+    pub async fn rotate(&self, root: &Path) -> Result<RotationReport, io::Error> {
+        let active_key = self
+            .active_key
+            .as_deref()
+            .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "Encryption is not enabled"))?;
+        let mut report = RotationReport {
+            rewrapped: 0,
+            current: 0,
+            failed: 0,
+        };
+
+        let mut directories: Vec<PathBuf> = vec![root.to_path_buf()];
+        while let Some(directory) = directories.pop() {
+            let mut entries = fs::read_dir(&directory).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                let file_type = entry.file_type().await?;
+                if file_type.is_dir() {
+                    directories.push(entry.path());
+                } else if file_type.is_file() {
+                    match self.rewrap(&entry.path(), active_key).await {
+                        Ok(Some(true)) => report.rewrapped += 1,
+                        Ok(Some(false)) => report.current += 1,
+                        Ok(None) => {}
+                        // Files removed while walking the tree need no rotation.
+                        Err(e) if e.kind() == ErrorKind::NotFound => {}
+                        Err(e) if e.kind() == ErrorKind::InvalidData => {
+                            tracing::warn!("Failed to rewrap {:?}: {}", entry.path(), e);
+                            report.failed += 1;
+                        }
+                        Err(e) => return Err(e),
+                    }
+                }
+            }
+        }
+
+        tracing::info!(
+            "Rewrapped the data keys of {} files with {}",
+            report.rewrapped,
+            active_key
+        );
+        Ok(report)
+    }
+
+    /// Encrypts the files under `root` written in plaintext before encryption was enabled,
+    /// leaving out those `excluded` accepts, which are never read as encrypted. Files whose
+    /// data key unwraps are already encrypted, so an interrupted run can be resumed. Files are
+    /// encrypted next to themselves and renamed into place, with the server stopped.
+    #[tracing::instrument(name = "Encrypt plaintext files", skip(self, excluded))]
+    pub async fn encrypt_plaintext(
+        &self,
+        root: &Path,
+        excluded: impl Fn(&Path) -> bool,
+    ) -> Result<EncryptionReport, io::Error> {
+        if !self.is_enabled() {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "Encryption is not enabled",
+            ));
+        }
+        let mut report = EncryptionReport {
+            encrypted: 0,
+            current: 0,
+            failed: 0,
+        };
+
+        let mut directories: Vec<PathBuf> = vec![root.to_path_buf()];
+        while let Some(directory) = directories.pop() {
+            let mut entries = fs::read_dir(&directory).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                let path = entry.path();
+                let file_type = entry.file_type().await?;
+                if excluded(&path) {
+                    continue;
+                } else if file_type.is_dir() {
+                    directories.push(path);
+                } else if file_type.is_file() {
+                    match self.encrypt_in_place(&path).await {
+                        Ok(true) => report.encrypted += 1,
+                        Ok(false) => report.current += 1,
+                        Err(e) if e.kind() == ErrorKind::InvalidData => {
+                            tracing::warn!("Failed to encrypt {:?}: {}", path, e);
+                            report.failed += 1;
+                        }
+                        Err(e) => return Err(e),
+                    }
+                }
+            }
+        }
+
+        tracing::info!("Encrypted {} plaintext files", report.encrypted);
+        Ok(report)
+    }
+
+    /// Encrypts a plaintext file, returning whether it was one.
+    async fn encrypt_in_place(&self, path: &Path) -> Result<bool, io::Error> {
+        let mut file = File::open(path).await?;
+        let mut header = Vec::with_capacity(HEADER_LENGTH);
+        (&mut file)
+            .take(HEADER_LENGTH as u64)
+            .read_to_end(&mut header)
+            .await?;
+        // The data key is authenticated, so only encrypted files unwrap. Those that do not
+        // were encrypted with a master key no longer configured.
+        if let Ok(header) = Header::parse(&header) {
+            self.unwrap_key(&header)?;
+            return Ok(false);
+        }
+
+        let mut temporary_path = path.as_os_str().to_owned();
+        temporary_path.push(format!(".{}.encrypting", Uuid::new_v4().simple()));
+        file.seek(SeekFrom::Start(0)).await?;
+        if let Err(e) = self.write(Path::new(&temporary_path), &mut file).await {
+            let _ = fs::remove_file(&temporary_path).await;
+            return Err(e);
+        }
+        fs::rename(&temporary_path, path).await?;
+        Ok(true)
+    }
+
+    /// Wraps the data key of a file with `active_key`. Returns whether the file needed it, or
+    /// `None` for files without a header.
+    async fn rewrap(&self, path: &Path, active_key: &str) -> Result<Option<bool>, io::Error> {
+        let mut file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .await?;
+        let mut header = Vec::with_capacity(HEADER_LENGTH);
+        (&mut file)
+            .take(HEADER_LENGTH as u64)
+            .read_to_end(&mut header)
+            .await?;
+
+        // Rotation walks internal files never written encrypted too, such as the index and the
+        // descriptions of trash entries. Only here are files told apart by their start: a file
+        // mistaken for an encrypted one fails to unwrap and is left as is.
+        if !header.starts_with(MAGIC) {
+            return Ok(None);
+        }
+        let header = Header::parse(&header)?;
+        if header.key_id == active_key {
+            return Ok(Some(false));
+        }
+        let data_key = self.unwrap_key(&header)?;
+        let rewrapped = Header {
+            key_id: active_key.to_string(),
+            ..header
+        };
+        let encoded = self.wrap_key(&rewrapped, &data_key)?;
+
+        file.seek(SeekFrom::Start(0)).await?;
+        file.write_all(&encoded).await?;
+        file.sync_data().await?;
+        Ok(Some(true))
+    }
+
+    /// A header with a new data key, and the encryptor of the content following it.
+    fn encryptor(&self) -> Result<Option<(Vec<u8>, Encryptor)>, io::Error> {
+        let key_id = match &self.active_key {
+            Some(key_id) => key_id,
+            None => return Ok(None),
+        };
+
+        let data_key = XChaCha20Poly1305::generate_key(&mut OsRng);
+        let mut nonce_prefix = [0; NONCE_PREFIX_LENGTH];
+        OsRng.fill_bytes(&mut nonce_prefix);
+        let header = Header {
+            chunk_size: self.chunk_size,
+            key_id: key_id.clone(),
+            wrapped_key: vec![],
+            nonce_prefix,
+        };
+
+        let encoded = self.wrap_key(&header, &data_key)?;
+        Ok(Some((
+            encoded,
+            Encryptor {
+                cipher: XChaCha20Poly1305::new(&data_key),
+                nonce_prefix,
+                chunk_size: self.chunk_size as usize,
+                pending: vec![],
+                index: 0,
+            },
+        )))
+    }
+
+    /// The decryptor of a file starting with `header`.
+    fn decryptor(&self, header: &[u8], length: u64) -> Result<Decryptor, io::Error> {
+        let header = Header::parse(header)?;
+        let data_key = self.unwrap_key(&header)?;
+
+        let chunk_size = header.chunk_size as u64;
+        let body = length - HEADER_LENGTH as u64;
+        let chunks = chunk_count(body, chunk_size);
+        if body < chunks * TAG_LENGTH {
+            return Err(invalid_data("Encrypted file is truncated"));
+        }
+
+        Ok(Decryptor {
+            cipher: XChaCha20Poly1305::new_from_slice(&data_key)
+                .map_err(|_| invalid_data("Invalid data key"))?,
+            nonce_prefix: header.nonce_prefix,
+            chunk_size,
+            chunks,
+            body,
+        })
+    }
+
+    /// Encodes the header with the data key wrapped by its master key.
+    fn wrap_key(&self, header: &Header, data_key: &[u8]) -> Result<Vec<u8>, io::Error> {
+        let master_key = self.master_key(&header.key_id)?;
+        let mut encoded = header.encode_prefix();
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let wrapped = master_key
+            .encrypt(
+                &nonce,
+                Payload {
+                    msg: data_key,
+                    aad: &encoded,
+                },
+            )
+            .map_err(|_| io::Error::other("Failed to wrap data key"))?;
+
+        encoded.extend_from_slice(&nonce);
+        encoded.extend_from_slice(&wrapped);
+        encoded.extend_from_slice(&header.nonce_prefix);
+        Ok(encoded)
+    }
+
+    fn unwrap_key(&self, header: &Header) -> Result<Vec<u8>, io::Error> {
+        let (nonce, wrapped) = header.wrapped_key.split_at(NONCE_LENGTH);
+        self.master_key(&header.key_id)?
+            .decrypt(
+                XNonce::from_slice(nonce),
+                Payload {
+                    msg: wrapped,
+                    aad: &header.encode_prefix(),
+                },
+            )
+            .map_err(|_| invalid_data("Failed to unwrap data key"))
+    }
+
+    fn master_key(&self, key_id: &str) -> Result<&XChaCha20Poly1305, io::Error> {
+        self.master_keys
+            .get(key_id)
+            .ok_or_else(|| invalid_data(format!("Unknown master key {}", key_id)))
+    }
+}
+
+/// The plaintext of a stored file.
+pub struct Content {
+    file: File,
+    size: u64,
+    decryptor: Option<Decryptor>,
+}
+
+impl Content {
+    /// Size of the plaintext in bytes.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Reads up to `length` leading bytes of the plaintext, leaving the content to be read
+    /// again from its start.
+    pub async fn head(&self, length: u64) -> Result<Vec<u8>, io::Error> {
+        let content = Content {
+            file: self.file.try_clone().await?,
+            size: self.size,
+            decryptor: self.decryptor.clone(),
+        };
+        let mut head = vec![];
+        StreamReader::new(content.range(0..length.min(self.size)).await?)
+            .read_to_end(&mut head)
+            .await?;
+        Ok(head)
+    }
+
+    /// Streams a range of the plaintext, which must be within its size.
+    pub async fn range(
+        mut self,
+        range: Range<u64>,
+    ) -> Result<BoxStream<'static, Result<Bytes, io::Error>>, io::Error> {
+        let decryptor = match self.decryptor {
+            Some(decryptor) => decryptor,
+            None => {
+                self.file.seek(SeekFrom::Start(range.start)).await?;
+                let reader = self.file.take(range.end - range.start);
+                return Ok(ReaderStream::new(reader).boxed());
+            }
+        };
+
+        let first = range.start / decryptor.chunk_size;
+        self.file
+            .seek(SeekFrom::Start(decryptor.chunk_offset(first)))
+            .await?;
+        let skip = (range.start - first * decryptor.chunk_size) as usize;
+        let remaining = range.end - range.start;
+
+        let state = (self.file, decryptor, first, skip, remaining);
+        let stream = futures::stream::try_unfold(
+            state,
+            |(mut file, decryptor, index, skip, remaining)| async move {
+                if remaining == 0 {
+                    return Ok(None);
+                }
+                let mut ciphertext = vec![0; decryptor.ciphertext_length(index)];
+                file.read_exact(&mut ciphertext).await?;
+                let plaintext = Bytes::from(decryptor.open(index, &ciphertext)?);
+
+                let end = plaintext.len().min(skip + remaining as usize);
+                let bytes = plaintext.slice(skip.min(end)..end);
+                let remaining = remaining - bytes.len() as u64;
+                Ok(Some((bytes, (file, decryptor, index + 1, 0, remaining))))
+            },
+        );
+        Ok(stream.boxed())
+    }
+
+    /// Reads the whole plaintext.
+    pub async fn into_reader(self) -> Result<impl AsyncRead + Send + Unpin, io::Error> {
+        let size = self.size;
+        Ok(StreamReader::new(self.range(0..size).await?))
+    }
+
+    pub async fn read_to_end(self) -> Result<Vec<u8>, io::Error> {
+        let mut contents = Vec::with_capacity(self.size as usize);
+        self.into_reader().await?.read_to_end(&mut contents).await?;
+        Ok(contents)
+    }
+}
+
+/// The plaintext of a stored file, read from blocking code.
+pub struct BlockingContent {
+    file: std::fs::File,
+    size: u64,
+    decryptor: Option<Decryptor>,
+    /// Index of the next chunk to decrypt.
+    index: u64,
+    buffer: Vec<u8>,
+    position: usize,
+}
+
+impl BlockingContent {
+    /// Size of the plaintext in bytes.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+impl Read for BlockingContent {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let decryptor = match &self.decryptor {
+            Some(decryptor) => decryptor,
+            None => return self.file.read(buf),
+        };
+
+        while self.position == self.buffer.len() {
+            if self.index == decryptor.chunks {
+                return Ok(0);
+            }
+            let mut ciphertext = vec![0; decryptor.ciphertext_length(self.index)];
+            self.file.read_exact(&mut ciphertext)?;
+            self.buffer = decryptor.open(self.index, &ciphertext)?;
+            self.position = 0;
+            self.index += 1;
+        }
+
+        let read = buf.len().min(self.buffer.len() - self.position);
+        buf[..read].copy_from_slice(&self.buffer[self.position..self.position + read]);
+        self.position += read;
+        Ok(read)
+    }
+}
+
+impl Seek for BlockingContent {
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        let decryptor = match &self.decryptor {
+            Some(decryptor) => decryptor,
+            None => return self.file.seek(position),
+        };
+
+        let current = match self.buffer.is_empty() {
+            true => (self.index * decryptor.chunk_size).min(self.size),
+            false => (self.index - 1) * decryptor.chunk_size + self.position as u64,
+        };
+        let target = match position {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::Current(offset) => current.checked_add_signed(offset),
+            SeekFrom::End(offset) => self.size.checked_add_signed(offset),
+        }
+        .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "Invalid seek position"))?;
+
+        // The chunk holding the target is decrypted right away, unless the target is past the
+        // end of the content.
+        self.buffer = vec![];
+        self.position = 0;
+        self.index = match target < self.size {
+            true => target / decryptor.chunk_size,
+            false => decryptor.chunks,
+        };
+        self.file
+            .seek(SeekFrom::Start(decryptor.chunk_offset(self.index)))?;
+        let offset = target.saturating_sub(self.index * decryptor.chunk_size) as usize;
+        if target < self.size && offset > 0 {
+            let mut ciphertext = vec![0; decryptor.ciphertext_length(self.index)];
+            self.file.read_exact(&mut ciphertext)?;
+            self.buffer = decryptor.open(self.index, &ciphertext)?;
+            self.position = offset;
+            self.index += 1;
+        }
+        Ok(target)
+    }
+}
+
+struct Header {
+    chunk_size: u32,
+    key_id: String,
+    /// Nonce and ciphertext of the data key.
+    wrapped_key: Vec<u8>,
+    nonce_prefix: [u8; NONCE_PREFIX_LENGTH],
+}
+
+impl Header {
+    /// Parses the header of a file, failing if the file does not start with one.
+    fn parse(bytes: &[u8]) -> Result<Self, io::Error> {
+        if bytes.len() < HEADER_LENGTH || &bytes[..4] != MAGIC {
+            return Err(invalid_data("Encrypted file has no header"));
+        }
+        if bytes[4] != FORMAT_VERSION {
+            return Err(invalid_data(format!(
+                "Unsupported encryption format {}",
+                bytes[4]
+            )));
+        }
+
+        let chunk_size = u32::from_be_bytes(bytes[5..9].try_into().unwrap());
+        let key_id = &bytes[9..WRAPPED_KEY_OFFSET];
+        let key_id_length = key_id.iter().position(|&b| b == 0).unwrap_or(key_id.len());
+        let key_id = String::from_utf8(key_id[..key_id_length].to_vec())
+            .map_err(|_| invalid_data("Invalid master key id"))?;
+        let nonce_prefix_offset = WRAPPED_KEY_OFFSET + WRAPPED_KEY_LENGTH;
+        if chunk_size == 0 {
+            return Err(invalid_data("Invalid chunk size"));
+        }
+
+        Ok(Self {
+            chunk_size,
+            key_id,
+            wrapped_key: bytes[WRAPPED_KEY_OFFSET..nonce_prefix_offset].to_vec(),
+            nonce_prefix: bytes[nonce_prefix_offset..HEADER_LENGTH]
+                .try_into()
+                .unwrap(),
+        })
+    }
+
+    /// The header up to the wrapped data key, which authenticates it.
+    fn encode_prefix(&self) -> Vec<u8> {
+        let mut encoded = Vec::with_capacity(HEADER_LENGTH);
+        encoded.extend_from_slice(MAGIC);
+        encoded.push(FORMAT_VERSION);
+        encoded.extend_from_slice(&self.chunk_size.to_be_bytes());
+        let mut key_id = [0; MAX_KEY_ID_LENGTH];
+        key_id[..self.key_id.len()].copy_from_slice(self.key_id.as_bytes());
+        encoded.extend_from_slice(&key_id);
+        encoded
+    }
+}
+
+/// Seals content into chunks as it is read, by the async and blocking writers alike. A full
+/// chunk is only sealed once more content follows it, since the last chunk is sealed as such.
+struct Encryptor {
+    cipher: XChaCha20Poly1305,
+    nonce_prefix: [u8; NONCE_PREFIX_LENGTH],
+    chunk_size: usize,
+    /// Content read but not sealed yet.
+    pending: Vec<u8>,
+    index: u64,
+}
+
+impl Encryptor {
+    /// Adds content, returning the chunks it completed, sealed.
+    fn update(&mut self, plaintext: &[u8]) -> Result<Vec<u8>, io::Error> {
+        self.pending.extend_from_slice(plaintext);
+        let mut sealed = vec![];
+        while self.pending.len() > self.chunk_size {
+            let chunk = self.pending.drain(..self.chunk_size).collect::<Vec<_>>();
+            sealed.extend(self.seal(&chunk, false)?);
+        }
+        Ok(sealed)
+    }
+
+    /// Seals the last chunk, which is empty for empty content.
+    fn finish(mut self) -> Result<Vec<u8>, io::Error> {
+        let chunk = std::mem::take(&mut self.pending);
+        self.seal(&chunk, true)
+    }
+
+    fn seal(&mut self, plaintext: &[u8], last: bool) -> Result<Vec<u8>, io::Error> {
+        let nonce = chunk_nonce(&self.nonce_prefix, self.index, last)?;
+        self.index += 1;
+        self.cipher
+            .encrypt(&nonce, plaintext)
+            .map_err(|_| io::Error::other("Failed to encrypt chunk"))
+    }
+}
+
+#[derive(Clone)]
+struct Decryptor {
+    cipher: XChaCha20Poly1305,
+    nonce_prefix: [u8; NONCE_PREFIX_LENGTH],
+    chunk_size: u64,
+    chunks: u64,
+    /// Length of the file after the header.
+    body: u64,
+}
+
+impl Decryptor {
+    fn size(&self) -> u64 {
+        self.body - self.chunks * TAG_LENGTH
+    }
+
+    fn chunk_offset(&self, index: u64) -> u64 {
+        HEADER_LENGTH as u64 + index * (self.chunk_size + TAG_LENGTH)
+    }
+
+    fn ciphertext_length(&self, index: u64) -> usize {
+        let offset = index * (self.chunk_size + TAG_LENGTH);
+        (self.body - offset).min(self.chunk_size + TAG_LENGTH) as usize
+    }
+
+    /// Decrypts a chunk, failing if it was tampered with, reordered or is missing its end.
+    fn open(&self, index: u64, ciphertext: &[u8]) -> Result<Vec<u8>, io::Error> {
+        let nonce = chunk_nonce(&self.nonce_prefix, index, index + 1 == self.chunks)?;
+        self.cipher
+            .decrypt(&nonce, ciphertext)
+            .map_err(|_| invalid_data("Failed to decrypt chunk"))
+    }
+}
+
+/// Number of chunks in the part of a file after its header. Empty content still has one.
+fn chunk_count(body: u64, chunk_size: u64) -> u64 {
+    body.div_ceil(chunk_size + TAG_LENGTH).max(1)
+}
+
+fn chunk_nonce(
+    nonce_prefix: &[u8; NONCE_PREFIX_LENGTH],
+    index: u64,
+    last: bool,
+) -> Result<XNonce, io::Error> {
+    let index = u32::try_from(index).map_err(|_| invalid_data("Too many chunks"))?;
+    let mut nonce = [0; NONCE_LENGTH];
+    nonce[..NONCE_PREFIX_LENGTH].copy_from_slice(nonce_prefix);
+    nonce[NONCE_PREFIX_LENGTH..NONCE_LENGTH - 1].copy_from_slice(&index.to_be_bytes());
+    nonce[NONCE_LENGTH - 1] = last as u8;
+    Ok(XNonce::clone_from_slice(&nonce))
+}
+
+fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message.into())
+}
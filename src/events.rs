@@ -0,0 +1,267 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    io,
+    sync::Arc,
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{broadcast, Mutex};
+use uuid::Uuid;
+
+use crate::{
+    configuration::EventsSettings, index::Index, journal::Journal, versioning::VersionInfo,
+    webhooks::Webhooks,
+};
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FileEventKind {
+    UploadCompleted,
+    Deleted,
+    Moved,
+    VersionCreated,
+    /// A version or a trash entry was put back in place.
+    Restored,
+}
+
+impl FileEventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FileEventKind::UploadCompleted => "upload_completed",
+            FileEventKind::Deleted => "deleted",
+            FileEventKind::Moved => "moved",
+            FileEventKind::VersionCreated => "version_created",
+            FileEventKind::Restored => "restored",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FileEvent {
+    pub id: String,
+    /// Position in the change feed, assigned when the event is published.
+    #[serde(default)]
+    pub sequence: u64,
+    pub kind: FileEventKind,
+    pub timestamp: DateTime<Utc>,
+    /// Path relative to the storage root. For moves, the destination.
+    pub path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from_path: Option<String>,
+    /// Who made the change.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub identity: Option<String>,
+    /// Whether an upload or restore replaced existing content.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub overwritten: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub is_directory: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<String>,
+    /// Detected from the content of uploaded files.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    /// Codec an uploaded file is stored with, if it was compressed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub codec: Option<String>,
+    /// Codecs of the compressed files of restored content, by path relative to the storage
+    /// root.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub codecs: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+}
+
+impl FileEvent {
+    pub fn new(kind: FileEventKind, path: impl Into<String>) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
+            sequence: 0,
+            kind,
+            timestamp: Utc::now(),
+            path: path.into(),
+            from_path: None,
+            identity: None,
+            overwritten: None,
+            is_directory: None,
+            size: None,
+            checksum: None,
+            content_type: None,
+            codec: None,
+            codecs: BTreeMap::new(),
+            version: None,
+        }
+    }
+
+    /// Whether `accepts` holds for the path of the event or, for moves, the one it came from.
+    pub fn concerns(&self, accepts: impl Fn(&str) -> bool) -> bool {
+        accepts(&self.path) || self.from_path.as_deref().is_some_and(accepts)
+    }
+
+    /// Describes the previous content of `path` having been kept as `version`.
+    pub fn version_created(path: impl Into<String>, version: &VersionInfo) -> Self {
+        Self {
+            size: Some(version.size),
+            version: Some(version.id.clone()),
+            ..Self::new(FileEventKind::VersionCreated, path)
+        }
+    }
+}
+
+/// Hands file events to everything interested in them: the change journal, the metadata index,
+/// webhooks, and subscribers of the change feed. The most recent events are also kept in memory, so
+/// reconnecting subscribers usually catch up without reading the journal.
+pub struct Events {
+    settings: EventsSettings,
+    feed: Mutex<Feed>,
+    journal: Journal,
+    index: Arc<Index>,
+    sender: broadcast::Sender<FileEvent>,
+    webhooks: Arc<Webhooks>,
+}
+
+struct Feed {
+    next_sequence: u64,
+    recent: VecDeque<FileEvent>,
+    /// Events published while the journal could not be written, not sequenced yet.
+    unjournaled: Vec<FileEvent>,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum FeedError {
+    #[error("The cursor is ahead of the change journal")]
+    CursorExpired,
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+/// Events published after the subscriber's cursor, followed by the live ones.
+pub struct Subscription {
+    pub missed: Vec<FileEvent>,
+    pub live: broadcast::Receiver<FileEvent>,
+}
+
+impl Events {
+    /// Continues the sequence where the journal left off.
+    pub fn new(
+        settings: EventsSettings,
+        journal: Journal,
+        index: Arc<Index>,
+        webhooks: Arc<Webhooks>,
+    ) -> Self {
+        let (sender, _) = broadcast::channel(settings.buffer_size.max(1));
+        let next_sequence = journal.last_sequence() + 1;
+
+        Self {
+            settings,
+            feed: Mutex::new(Feed {
+                next_sequence,
+                recent: VecDeque::new(),
+                unjournaled: vec![],
+            }),
+            journal,
+            index,
+            sender,
+            webhooks,
+        }
+    }
+
+    /// Publishes events for changes that already happened, so failures are logged rather than
+    /// returned to the client.
+    pub async fn publish(&self, events: &[FileEvent]) {
+        let journaled = self.append(events).await;
+        if let Err(e) = self.index.apply(events).await {
+            tracing::error!("Failed to update metadata index: {:?}", e);
+        }
+        for event in &journaled {
+            if let Err(e) = self.webhooks.enqueue(event).await {
+                tracing::error!("Failed to queue webhook deliveries: {:?}", e);
+            }
+        }
+    }
+
+    /// Subscribes to the events following `cursor`, the sequence of the last event seen, or to
+    /// new events only without one.
+    pub async fn subscribe(&self, cursor: Option<u64>) -> Result<Subscription, FeedError> {
+        let feed = self.feed.lock().await;
+        let missed = match cursor {
+            None => vec![],
+            Some(cursor) if cursor >= feed.next_sequence => return Err(FeedError::CursorExpired),
+            Some(cursor) => {
+                let oldest = feed
+                    .recent
+                    .front()
+                    .map_or(feed.next_sequence, |event| event.sequence);
+                if cursor.saturating_add(1) >= oldest {
+                    feed.recent
+                        .iter()
+                        .filter(|event| event.sequence > cursor)
+                        .cloned()
+                        .collect()
+                } else {
+                    self.journal.read_after(cursor, usize::MAX).await?
+                }
+            }
+        };
+
+        // Subscribing while holding the lock means no event is missed or seen twice.
+        Ok(Subscription {
+            missed,
+            live: self.sender.subscribe(),
+        })
+    }
+
+    /// Returns up to `limit` journaled events following `cursor`.
+    pub async fn read_after(&self, cursor: u64, limit: usize) -> Result<Vec<FileEvent>, FeedError> {
+        if cursor >= self.feed.lock().await.next_sequence {
+            return Err(FeedError::CursorExpired);
+        }
+        Ok(self.journal.read_after(cursor, limit).await?)
+    }
+
+    /// Sequence of the last published event.
+    pub async fn last_sequence(&self) -> u64 {
+        self.feed.lock().await.next_sequence - 1
+    }
+
+    /// Sequences and journals the events, with any held back by an earlier failed write, and
+    /// returns the ones journaled. Events are held back rather than given up on, so the
+    /// sequence only moves past journaled events and `/delta` clients never skip any.
+    async fn append(&self, events: &[FileEvent]) -> Vec<FileEvent> {
+        let mut feed = self.feed.lock().await;
+        feed.unjournaled.extend_from_slice(events);
+        let events = feed
+            .unjournaled
+            .iter()
+            .zip(feed.next_sequence..)
+            .map(|(event, sequence)| FileEvent {
+                sequence,
+                ..event.clone()
+            })
+            .collect::<Vec<_>>();
+
+        if let Err(e) = self.journal.append(&events).await {
+            tracing::error!(
+                "Failed to write change journal, holding back {} events: {:?}",
+                events.len(),
+                e
+            );
+            return vec![];
+        }
+        feed.unjournaled.clear();
+        feed.next_sequence += events.len() as u64;
+        for event in &events {
+            if feed.recent.len() >= self.settings.buffer_size {
+                feed.recent.pop_front();
+            }
+            feed.recent.push_back(event.clone());
+            // Sending only fails when nobody is subscribed.
+            let _ = self.sender.send(event.clone());
+        }
+
+        events
+    }
+}
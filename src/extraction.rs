@@ -0,0 +1,349 @@
+use std::{
+    collections::HashSet,
+    io::{self, Read, Seek},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use flate2::read::GzDecoder;
+use mime::Mime;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    configuration::ExtractionSettings, content_types::SNIFF_LENGTH, encryption::Encryption,
+    validators::validate_file_name,
+};
+
+/// File type bits of a unix mode, and their value for symbolic links.
+const FILE_TYPE_MASK: u32 = 0o170000;
+const SYMLINK_TYPE: u32 = 0o120000;
+
+#[derive(thiserror::Error, Debug)]
+pub enum ExtractionError {
+    #[error("{0}")]
+    NotAnArchive(String),
+    #[error("{0}")]
+    InvalidArchive(String),
+    #[error("{0}")]
+    UnsafeEntry(String),
+    #[error("{0}")]
+    TooLarge(String),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+#[derive(Clone, Copy, Debug)]
+enum ArchiveKind {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+impl ArchiveKind {
+    fn of(content_type: &Mime) -> Option<Self> {
+        match content_type.essence_str() {
+            "application/zip" => Some(ArchiveKind::Zip),
+            "application/x-tar" => Some(ArchiveKind::Tar),
+            "application/gzip" => Some(ArchiveKind::TarGz),
+            _ => None,
+        }
+    }
+}
+
+/// A file unpacked from an archive into the staging directory.
+pub struct ExtractedFile {
+    /// Path of the file inside the archive, normalised to `/` separated components.
+    pub name: String,
+    pub staged_path: PathBuf,
+    pub size: u64,
+    pub checksum: String,
+    /// The leading bytes, to detect the content type from.
+    pub head: Vec<u8>,
+}
+
+/// The content of an archive, unpacked next to it.
+#[derive(Default)]
+pub struct Extracted {
+    pub files: Vec<ExtractedFile>,
+    /// Directories the archive holds, named like files, which may be empty.
+    pub directories: Vec<String>,
+}
+
+/// Unpacks uploaded zip and tar archives, refusing entries that would land outside of the
+/// target directory and archives that unpack to more than the configured limits.
+pub struct Extraction {
+    settings: ExtractionSettings,
+    encryption: Arc<Encryption>,
+}
+
+impl Extraction {
+    pub fn new(settings: ExtractionSettings, encryption: Arc<Encryption>) -> Self {
+        Self {
+            settings,
+            encryption,
+        }
+    }
+
+    /// Unpacks the staged archive at `archive_path`, writing its files next to it. Nothing is
+    /// cleaned up on failure, the staging directory is expected to be removed as a whole.
+    #[tracing::instrument(name = "Extract archive", skip(self))]
+    pub async fn extract(
+        &self,
+        archive_path: &Path,
+        archive_name: &str,
+        content_type: &Mime,
+    ) -> Result<Extracted, ExtractionError> {
+        let kind = ArchiveKind::of(content_type).ok_or_else(|| {
+            ExtractionError::NotAnArchive(format!(
+                "{} is {}, expected a zip or tar archive",
+                archive_name, content_type
+            ))
+        })?;
+        let mut extractor = Extractor {
+            settings: self.settings.clone(),
+            encryption: self.encryption.clone(),
+            archive_path: archive_path.to_path_buf(),
+            archive_name: archive_name.to_string(),
+            limit: 0,
+            remaining: 0,
+            entries: 0,
+            extracted: Extracted::default(),
+        };
+        tokio::task::spawn_blocking(move || {
+            extractor.run(kind)?;
+            Ok(extractor.extracted)
+        })
+        .await
+        .map_err(io::Error::other)?
+    }
+}
+
+/// The state of one extraction, run on the blocking pool since the archive crates are
+/// synchronous.
+struct Extractor {
+    settings: ExtractionSettings,
+    encryption: Arc<Encryption>,
+    archive_path: PathBuf,
+    archive_name: String,
+    /// Bytes the archive may unpack to, and how many of them are left.
+    limit: u64,
+    remaining: u64,
+    entries: usize,
+    extracted: Extracted,
+}
+
+impl Extractor {
+    fn run(&mut self, kind: ArchiveKind) -> Result<(), ExtractionError> {
+        let content = self.encryption.open_blocking(&self.archive_path)?;
+        self.limit = self
+            .settings
+            .max_total_size_bytes
+            .min(content.size().saturating_mul(self.settings.max_ratio));
+        self.remaining = self.limit;
+        match kind {
+            ArchiveKind::Zip => self.extract_zip(content)?,
+            ArchiveKind::Tar => self.extract_tar(content)?,
+            ArchiveKind::TarGz => self.extract_tar(GzDecoder::new(content))?,
+        }
+        self.check_conflicts()
+    }
+
+    fn extract_zip(&mut self, content: impl Read + Seek) -> Result<(), ExtractionError> {
+        let mut archive = zip::ZipArchive::new(content).map_err(|e| self.invalid(e))?;
+        for i in 0..archive.len() {
+            let mut file = archive.by_index(i).map_err(|e| self.invalid(e))?;
+            self.count_entry()?;
+            let name = entry_name(file.name())?;
+            if file
+                .unix_mode()
+                .is_some_and(|mode| mode & FILE_TYPE_MASK == SYMLINK_TYPE)
+            {
+                return Err(unsupported_entry(file.name()));
+            }
+
+            if file.is_dir() {
+                self.extracted.directories.push(name);
+            } else if file.size() > self.remaining {
+                return Err(self.too_large());
+            } else {
+                self.extract_file(name, &mut file)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn extract_tar(&mut self, content: impl Read) -> Result<(), ExtractionError> {
+        let mut archive = tar::Archive::new(content);
+        for entry in archive.entries().map_err(|e| self.invalid(e))? {
+            let mut entry = entry.map_err(|e| self.invalid(e))?;
+            let entry_type = entry.header().entry_type();
+            if entry_type.is_pax_global_extensions() {
+                continue;
+            }
+            self.count_entry()?;
+            let raw_name = String::from_utf8(entry.path_bytes().into_owned()).map_err(|_| {
+                ExtractionError::UnsafeEntry(format!(
+                    "{} has an entry whose name is not UTF-8",
+                    self.archive_name
+                ))
+            })?;
+            let name = entry_name(&raw_name)?;
+
+            if entry_type.is_dir() {
+                self.extracted.directories.push(name);
+            } else if entry_type.is_file() || entry_type.is_contiguous() {
+                self.extract_file(name, &mut entry)?;
+            } else {
+                return Err(unsupported_entry(&raw_name));
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes an entry to the staging directory, counting its bytes against the limits.
+    fn extract_file(
+        &mut self,
+        name: String,
+        reader: &mut impl Read,
+    ) -> Result<(), ExtractionError> {
+        let mut staged_path = self.archive_path.clone().into_os_string();
+        staged_path.push(format!(".{}", self.extracted.files.len()));
+        let staged_path = PathBuf::from(staged_path);
+
+        let mut entry_reader = EntryReader {
+            inner: reader,
+            remaining: self.remaining,
+            hasher: Sha256::new(),
+            head: Vec::with_capacity(SNIFF_LENGTH),
+            exceeded: false,
+            failed: false,
+        };
+        let written = self
+            .encryption
+            .write_blocking(&staged_path, &mut entry_reader);
+        match written {
+            Ok(size) => {
+                self.remaining -= size;
+                self.extracted.files.push(ExtractedFile {
+                    name,
+                    staged_path,
+                    size,
+                    checksum: hex::encode(entry_reader.hasher.finalize()),
+                    head: entry_reader.head,
+                });
+                Ok(())
+            }
+            Err(_) if entry_reader.exceeded => Err(self.too_large()),
+            Err(e) if entry_reader.failed => Err(self.invalid(e)),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn count_entry(&mut self) -> Result<(), ExtractionError> {
+        self.entries += 1;
+        if self.entries > self.settings.max_entries {
+            return Err(ExtractionError::TooLarge(format!(
+                "{} holds more than {} entries",
+                self.archive_name, self.settings.max_entries
+            )));
+        }
+        Ok(())
+    }
+
+    /// Refuses archives holding the same file twice, or a file where a directory is expected.
+    fn check_conflicts(&self) -> Result<(), ExtractionError> {
+        let mut files = HashSet::new();
+        for file in &self.extracted.files {
+            if !files.insert(file.name.as_str()) {
+                return Err(conflicting_entry(&file.name));
+            }
+        }
+        let directories = self
+            .extracted
+            .files
+            .iter()
+            .flat_map(|file| Path::new(&file.name).ancestors().skip(1))
+            .chain(self.extracted.directories.iter().map(Path::new));
+        for directory in directories {
+            if let Some(name) = directory.to_str().filter(|name| files.contains(name)) {
+                return Err(conflicting_entry(name));
+            }
+        }
+        Ok(())
+    }
+
+    fn invalid(&self, e: impl std::fmt::Display) -> ExtractionError {
+        ExtractionError::InvalidArchive(format!("Failed to read {}: {}", self.archive_name, e))
+    }
+
+    fn too_large(&self) -> ExtractionError {
+        ExtractionError::TooLarge(format!(
+            "{} unpacks to more than {} bytes",
+            self.archive_name, self.limit
+        ))
+    }
+}
+
+/// Reads an entry, hashing its bytes and keeping the first ones, and failing once it goes
+/// past the bytes the archive may still unpack to.
+struct EntryReader<'a, R> {
+    inner: &'a mut R,
+    remaining: u64,
+    hasher: Sha256,
+    head: Vec<u8>,
+    exceeded: bool,
+    /// Whether reading the archive failed, rather than writing the file.
+    failed: bool,
+}
+
+impl<R: Read> Read for EntryReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf).inspect_err(|_| self.failed = true)?;
+        if read as u64 > self.remaining {
+            self.exceeded = true;
+            return Err(io::Error::other("Archive unpacks to too many bytes"));
+        }
+        self.remaining -= read as u64;
+        self.hasher.update(&buf[..read]);
+        let missing = SNIFF_LENGTH - self.head.len();
+        self.head.extend_from_slice(&buf[..missing.min(read)]);
+        Ok(read)
+    }
+}
+
+/// Normalises the name of an entry to `/` separated components, refusing absolute names and
+/// any that would climb out of the directory the archive is extracted into.
+fn entry_name(raw_name: &str) -> Result<String, ExtractionError> {
+    let unsafe_entry = |reason: String| {
+        ExtractionError::UnsafeEntry(format!("Unsafe archive entry {}: {}", raw_name, reason))
+    };
+    if raw_name.starts_with('/') || raw_name.contains('\\') {
+        return Err(unsafe_entry("names must be relative and use /".to_string()));
+    }
+
+    let components = raw_name
+        .split('/')
+        .filter(|component| !component.is_empty() && *component != ".")
+        .collect::<Vec<_>>();
+    if components.is_empty() {
+        return Err(unsafe_entry("empty name".to_string()));
+    }
+    for component in &components {
+        validate_file_name(component).map_err(unsafe_entry)?;
+    }
+    Ok(components.join("/"))
+}
+
+fn unsupported_entry(raw_name: &str) -> ExtractionError {
+    ExtractionError::UnsafeEntry(format!(
+        "Unsafe archive entry {}: only files and directories are extracted",
+        raw_name
+    ))
+}
+
+fn conflicting_entry(name: &str) -> ExtractionError {
+    ExtractionError::UnsafeEntry(format!(
+        "Archive entry {} conflicts with another entry",
+        name
+    ))
+}
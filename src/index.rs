@@ -0,0 +1,986 @@
+use std::{
+    collections::BTreeMap,
+    io::{self, ErrorKind, Read},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, OnceLock},
+};
+
+use chrono::{DateTime, Utc};
+use mime::Mime;
+use rusqlite::{
+    functions::FunctionFlags, params, params_from_iter, Connection, OptionalExtension, ToSql,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+
+use crate::{
+    compression::{Codec, Compression},
+    configuration::{FullTextSettings, IndexSettings},
+    content_types::{self, SNIFF_LENGTH},
+    domain::{in_scope, StorageDetails, INTERNAL_DIRECTORY},
+    events::{FileEvent, FileEventKind},
+};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS files (
+        path TEXT PRIMARY KEY,
+        size INTEGER NOT NULL,
+        checksum TEXT,
+        content_type TEXT,
+        codec TEXT,
+        owner TEXT,
+        created_at TEXT NOT NULL,
+        modified_at TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS file_tags (
+        path TEXT NOT NULL,
+        tag TEXT NOT NULL,
+        PRIMARY KEY (path, tag)
+    );
+    CREATE TABLE IF NOT EXISTS file_metadata (
+        path TEXT NOT NULL,
+        key TEXT NOT NULL,
+        value TEXT NOT NULL,
+        PRIMARY KEY (path, key)
+    );
+    CREATE TABLE IF NOT EXISTS file_texts (
+        path TEXT PRIMARY KEY,
+        content TEXT NOT NULL
+    );
+    CREATE VIRTUAL TABLE IF NOT EXISTS file_contents USING fts5(
+        content,
+        content = 'file_texts',
+        tokenize = 'porter unicode61'
+    );
+    CREATE TRIGGER IF NOT EXISTS file_texts_insert AFTER INSERT ON file_texts BEGIN
+        INSERT INTO file_contents (rowid, content) VALUES (new.rowid, new.content);
+    END;
+    CREATE TRIGGER IF NOT EXISTS file_texts_delete AFTER DELETE ON file_texts BEGIN
+        INSERT INTO file_contents (file_contents, rowid, content)
+        VALUES ('delete', old.rowid, old.content);
+    END;
+    CREATE TRIGGER IF NOT EXISTS file_texts_update AFTER UPDATE ON file_texts BEGIN
+        INSERT INTO file_contents (file_contents, rowid, content)
+        VALUES ('delete', old.rowid, old.content);
+        INSERT INTO file_contents (rowid, content) VALUES (new.rowid, new.content);
+    END;
+";
+
+/// Tables with rows keyed by file path. `file_contents` follows `file_texts` through triggers.
+const FILE_TABLES: [&str; 4] = ["files", "file_tags", "file_metadata", "file_texts"];
+
+/// Content types extracted for full-text search besides `text/*`.
+const TEXT_CONTENT_TYPES: [&str; 3] = ["application/json", "application/xml", "application/toml"];
+
+const RECORD_COLUMNS: &str =
+    "path, size, checksum, content_type, owner, created_at, modified_at, codec";
+
+/// The last segment of the path, after its final slash.
+const NAME: &str = "substr(path, length(rtrim(path, replace(path, '/', ''))) + 1)";
+
+/// Matches a path and everything below it, with the path bound to `?1`.
+const SUBTREE: &str = "(path = ?1 OR substr(path, 1, length(?1) + 1) = ?1 || '/')";
+
+#[derive(thiserror::Error, Debug)]
+pub enum IndexError {
+    #[error(transparent)]
+    Sqlite(#[from] rusqlite::Error),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FileRecord {
+    /// Path relative to the storage root.
+    pub path: String,
+    pub size: u64,
+    pub checksum: Option<String>,
+    pub content_type: Option<String>,
+    /// Codec the file is stored with, `None` if it is stored as is.
+    pub codec: Option<String>,
+    pub owner: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub modified_at: DateTime<Utc>,
+    pub tags: Vec<String>,
+    pub metadata: BTreeMap<String, String>,
+}
+
+/// Changes to the custom metadata and tags of a file.
+#[derive(Deserialize, Default, Clone, Debug)]
+pub struct MetadataPatch {
+    /// Keys to set, or to remove when `null`.
+    #[serde(default)]
+    pub metadata: BTreeMap<String, Option<String>>,
+    #[serde(default)]
+    pub add_tags: Vec<String>,
+    #[serde(default)]
+    pub remove_tags: Vec<String>,
+}
+
+impl MetadataPatch {
+    pub fn is_empty(&self) -> bool {
+        self.metadata.is_empty() && self.add_tags.is_empty() && self.remove_tags.is_empty()
+    }
+}
+
+#[derive(Default, Debug)]
+pub struct ListFilter {
+    /// Directory relative to the storage root, the whole tree when empty.
+    pub prefix: String,
+    /// Include files in subdirectories of the prefix.
+    pub recursive: bool,
+    pub tag: Option<String>,
+    /// Metadata every listed file has, as key and value.
+    pub metadata: Vec<(String, String)>,
+    /// Paths the files must be at or below, any path when `None`.
+    pub scope: Option<Vec<String>>,
+    /// Path the listed files come after, to page through a listing.
+    pub after: Option<String>,
+    pub limit: usize,
+}
+
+#[derive(Debug)]
+pub enum NameMatch {
+    /// Case-insensitive substring of the file name.
+    Substring(String),
+    /// Glob the whole file name matches, case-sensitively.
+    Glob(String),
+}
+
+#[derive(Default, Debug)]
+pub struct SearchFilter {
+    pub name: Option<NameMatch>,
+    pub tag: Option<String>,
+    /// Metadata every file found has, as key and value.
+    pub metadata: Vec<(String, String)>,
+    pub min_size: Option<u64>,
+    pub max_size: Option<u64>,
+    pub modified_after: Option<DateTime<Utc>>,
+    pub modified_before: Option<DateTime<Utc>>,
+    /// Exact content type, or `type/*` for all of its subtypes.
+    pub content_type: Option<String>,
+    /// Paths the files must be at or below, any path when `None`.
+    pub scope: Option<Vec<String>>,
+    /// Path of the last file of the previous page.
+    pub after: Option<String>,
+    pub limit: usize,
+}
+
+#[derive(Serialize, Debug)]
+pub struct TextMatch {
+    pub path: String,
+    /// Excerpt of the text around the matched terms, which are wrapped in `<mark>` tags.
+    pub snippet: String,
+    /// Relevance, higher is better.
+    pub score: f64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ReconcileReport {
+    pub indexed: usize,
+    pub removed: usize,
+}
+
+/// SQLite index of the files under the storage root, kept up to date from published file
+/// events and rebuilt from the filesystem by `reconcile`. Queries run on the blocking pool.
+/// The text of files is extracted for full-text search in the background, after the change to
+/// the file is applied.
+pub struct Index {
+    connection: Arc<Mutex<Connection>>,
+    storage_root: PathBuf,
+    /// Size limit of the files whose text is extracted, `None` with full-text search disabled.
+    text_size_limit: Option<u64>,
+    compression: Arc<Compression>,
+    /// Files queued for text extraction, the task extracting them started on first use.
+    texts: OnceLock<UnboundedSender<TextJob>>,
+}
+
+impl Index {
+    pub fn from_settings(
+        settings: &IndexSettings,
+        storage_details: &StorageDetails,
+    ) -> Result<Self, IndexError> {
+        let path = settings.path.as_ref().map_or_else(
+            || storage_details.internal_path("index").join("index.sqlite"),
+            PathBuf::from,
+        );
+        Ok(Self::open(&path, &storage_details.path)?.with_full_text(&settings.full_text))
+    }
+
+    pub fn open(path: &Path, storage_root: impl Into<PathBuf>) -> Result<Self, IndexError> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let connection = Connection::open(path)?;
+        connection.pragma_update(None, "journal_mode", "WAL")?;
+        connection.execute_batch(SCHEMA)?;
+        // Indexes created before compression have no codec column yet.
+        let has_codec = connection
+            .query_row(
+                "SELECT 1 FROM pragma_table_info('files') WHERE name = 'codec'",
+                [],
+                |_| Ok(()),
+            )
+            .optional()?
+            .is_some();
+        if !has_codec {
+            connection.execute_batch("ALTER TABLE files ADD COLUMN codec TEXT")?;
+        }
+        // `in_scope(path, scope)` takes the scope as a JSON array, parsed once per query.
+        connection.create_scalar_function(
+            "in_scope",
+            2,
+            FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+            |context| {
+                let scope = context.get_or_create_aux(1, |scope| {
+                    serde_json::from_str::<Vec<String>>(scope.as_str()?)
+                        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)
+                })?;
+                Ok(in_scope(&context.get::<String>(0)?, Some(&scope)))
+            },
+        )?;
+
+        Ok(Self {
+            connection: Arc::new(Mutex::new(connection)),
+            storage_root: storage_root.into(),
+            text_size_limit: None,
+            compression: Arc::new(Compression::disabled()),
+            texts: OnceLock::new(),
+        })
+    }
+
+    pub fn with_full_text(self, settings: &FullTextSettings) -> Self {
+        Self {
+            text_size_limit: settings.enabled.then_some(settings.max_file_size_bytes),
+            ..self
+        }
+    }
+
+    /// Reads files through `compression`, for storage roots holding compressed or encrypted
+    /// files.
+    pub fn with_compression(self, compression: Arc<Compression>) -> Self {
+        Self {
+            compression,
+            ..self
+        }
+    }
+
+    pub fn full_text_enabled(&self) -> bool {
+        self.text_size_limit.is_some()
+    }
+
+    pub async fn get(&self, path: &str) -> Result<Option<FileRecord>, IndexError> {
+        let path = path.to_string();
+        self.call(move |connection, _| read_file(connection, &path))
+            .await
+    }
+
+    /// The codec a file is stored with, `None` if it is stored as is or not indexed.
+    pub async fn codec(&self, path: &str) -> Result<Option<Codec>, IndexError> {
+        let path = path.to_string();
+        self.call(move |connection, _| recorded_codec(connection, &path))
+            .await
+    }
+
+    /// The codecs of the compressed files at or below `path`, by path.
+    pub async fn codecs(&self, path: &str) -> Result<BTreeMap<String, Codec>, IndexError> {
+        let path = path.to_string();
+        self.call(move |connection, _| {
+            let mut statement = connection.prepare(&format!(
+                "SELECT path, codec FROM files WHERE codec IS NOT NULL AND {}",
+                SUBTREE
+            ))?;
+            let rows = statement
+                .query_map([&path], |row| {
+                    Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+                })?
+                .collect::<Result<Vec<_>, _>>()?;
+            let codecs = rows
+                .into_iter()
+                .map(|(path, codec)| Ok((path, Codec::parse(&codec)?)))
+                .collect::<Result<_, io::Error>>()?;
+            Ok(codecs)
+        })
+        .await
+    }
+
+    /// Applies a metadata patch, returning the updated file or `None` if it is not indexed.
+    pub async fn update_metadata(
+        &self,
+        path: &str,
+        patch: MetadataPatch,
+    ) -> Result<Option<FileRecord>, IndexError> {
+        let path = path.to_string();
+        self.call(move |connection, _| {
+            let transaction = connection.transaction()?;
+            if read_file(&transaction, &path)?.is_none() {
+                return Ok(None);
+            }
+
+            for (key, value) in &patch.metadata {
+                match value {
+                    Some(value) => transaction.execute(
+                        "INSERT INTO file_metadata (path, key, value) VALUES (?1, ?2, ?3)
+                         ON CONFLICT (path, key) DO UPDATE SET value = excluded.value",
+                        params![path, key, value],
+                    )?,
+                    None => transaction.execute(
+                        "DELETE FROM file_metadata WHERE path = ?1 AND key = ?2",
+                        params![path, key],
+                    )?,
+                };
+            }
+            for tag in &patch.add_tags {
+                transaction.execute(
+                    "INSERT OR IGNORE INTO file_tags (path, tag) VALUES (?1, ?2)",
+                    params![path, tag],
+                )?;
+            }
+            for tag in &patch.remove_tags {
+                transaction.execute(
+                    "DELETE FROM file_tags WHERE path = ?1 AND tag = ?2",
+                    params![path, tag],
+                )?;
+            }
+
+            let record = read_file(&transaction, &path)?;
+            transaction.commit()?;
+            Ok(record)
+        })
+        .await
+    }
+
+    /// Lists indexed files by path.
+    pub async fn list(&self, filter: ListFilter) -> Result<Vec<FileRecord>, IndexError> {
+        self.call(move |connection, _| {
+            let mut selection = Selection::default();
+            if filter.prefix.is_empty() {
+                if !filter.recursive {
+                    selection.filter("instr(path, '/') = 0".to_string());
+                }
+            } else {
+                // Measured by SQLite, whose `substr` counts characters rather than bytes.
+                let index = selection.bind(format!("{}/", filter.prefix));
+                selection.filter(format!("substr(path, 1, length(?{0})) = ?{0}", index));
+                if !filter.recursive {
+                    selection.filter(format!(
+                        "instr(substr(path, length(?{}) + 1), '/') = 0",
+                        index
+                    ));
+                }
+            }
+            if let Some(after) = filter.after {
+                let index = selection.bind(after);
+                selection.filter(format!("path > ?{}", index));
+            }
+            selection.scope(filter.scope);
+            selection.tag(filter.tag);
+            selection.metadata(filter.metadata);
+            selection.select(connection, filter.limit)
+        })
+        .await
+    }
+
+    /// Finds indexed files matching every given criterion, by path after `filter.after`.
+    pub async fn search(&self, filter: SearchFilter) -> Result<Vec<FileRecord>, IndexError> {
+        self.call(move |connection, _| {
+            let mut selection = Selection::default();
+            match filter.name {
+                Some(NameMatch::Substring(substring)) => {
+                    let index = selection.bind(substring);
+                    selection.filter(format!("instr(lower({}), lower(?{})) > 0", NAME, index));
+                }
+                Some(NameMatch::Glob(glob)) => {
+                    let index = selection.bind(glob);
+                    selection.filter(format!("{} GLOB ?{}", NAME, index));
+                }
+                None => {}
+            }
+            if let Some(min_size) = filter.min_size {
+                let index = selection.bind(min_size as i64);
+                selection.filter(format!("size >= ?{}", index));
+            }
+            if let Some(max_size) = filter.max_size {
+                let index = selection.bind(max_size as i64);
+                selection.filter(format!("size <= ?{}", index));
+            }
+            if let Some(modified_after) = filter.modified_after {
+                let index = selection.bind(modified_after);
+                selection.filter(format!("modified_at >= ?{}", index));
+            }
+            if let Some(modified_before) = filter.modified_before {
+                let index = selection.bind(modified_before);
+                selection.filter(format!("modified_at < ?{}", index));
+            }
+            match filter.content_type {
+                // A `type/*` pattern matches every subtype.
+                Some(content_type) if content_type.ends_with("/*") => {
+                    let index = selection.bind(content_type.trim_end_matches('*').to_string());
+                    selection.filter(format!(
+                        "substr(content_type, 1, length(?{0})) = ?{0}",
+                        index
+                    ));
+                }
+                Some(content_type) => {
+                    let index = selection.bind(content_type);
+                    selection.filter(format!("content_type = ?{}", index));
+                }
+                None => {}
+            }
+            if let Some(after) = filter.after {
+                let index = selection.bind(after);
+                selection.filter(format!("path > ?{}", index));
+            }
+            selection.scope(filter.scope);
+            selection.tag(filter.tag);
+            selection.metadata(filter.metadata);
+            selection.select(connection, filter.limit)
+        })
+        .await
+    }
+
+    /// Ranks the files whose text contains every word of `query`, best match first.
+    pub async fn search_text(
+        &self,
+        query: &str,
+        scope: Option<Vec<String>>,
+        offset: usize,
+        limit: usize,
+    ) -> Result<Vec<TextMatch>, IndexError> {
+        // Every word is quoted, so nothing in the query is taken for FTS5 syntax.
+        let phrases = query
+            .split_whitespace()
+            .map(|word| format!("\"{}\"", word.replace('"', "\"\"")))
+            .collect::<Vec<_>>()
+            .join(" ");
+        self.call(move |connection, _| {
+            let mut selection = Selection::default();
+            let match_index = selection.bind(phrases);
+            selection.filter(format!("file_contents MATCH ?{}", match_index));
+            selection.scope(scope);
+            let limit_index = selection.bind(limit as i64);
+            let offset_index = selection.bind(offset as i64);
+
+            let query = format!(
+                "SELECT path, snippet(file_contents, 0, '<mark>', '</mark>', '…', 16), rank
+                 FROM file_contents JOIN file_texts ON file_texts.rowid = file_contents.rowid
+                 {} ORDER BY rank LIMIT ?{} OFFSET ?{}",
+                selection.where_clause(),
+                limit_index,
+                offset_index
+            );
+            let matches = connection
+                .prepare(&query)?
+                .query_map(params_from_iter(selection.values.iter()), |row| {
+                    Ok(TextMatch {
+                        path: row.get(0)?,
+                        snippet: row.get(1)?,
+                        // BM25 ranks are lower for better matches.
+                        score: -row.get::<_, f64>(2)?,
+                    })
+                })?
+                .collect::<Result<_, _>>()?;
+            Ok(matches)
+        })
+        .await
+    }
+
+    /// Applies the changes described by published events.
+    pub async fn apply(&self, events: &[FileEvent]) -> Result<(), IndexError> {
+        let events = events.to_vec();
+        let compression = self.compression.clone();
+        let changed = self
+            .call(move |connection, storage_root| {
+                let transaction = connection.transaction()?;
+                let tree = Tree {
+                    root: storage_root,
+                    compression: &compression,
+                };
+                let mut changed = BTreeMap::new();
+                for event in &events {
+                    apply_event(&transaction, &tree, event, &mut changed)?;
+                }
+                transaction.commit()?;
+                Ok(changed)
+            })
+            .await?;
+        self.extract_texts(changed);
+        Ok(())
+    }
+
+    /// Rebuilds the index from the files under the storage root, keeping the owners, creation
+    /// times, tags and metadata of files already indexed.
+    #[tracing::instrument(name = "Reconcile metadata index", skip(self))]
+    pub async fn reconcile(&self) -> Result<ReconcileReport, IndexError> {
+        let compression = self.compression.clone();
+        let (report, seen) = self
+            .call(move |connection, storage_root| {
+                let transaction = connection.transaction()?;
+                let mut seen = BTreeMap::new();
+                let tree = Tree {
+                    root: storage_root,
+                    compression: &compression,
+                };
+                // Files already indexed keep their codec, new ones are taken as stored as is.
+                let codec_of = |path: &str| recorded_codec(&transaction, path);
+                index_tree(&transaction, &tree, "", None, &codec_of, &mut seen)?;
+
+                let indexed = transaction
+                    .prepare("SELECT path FROM files")?
+                    .query_map([], |row| row.get::<_, String>(0))?
+                    .collect::<Result<Vec<_>, _>>()?;
+                let mut removed = 0;
+                for path in indexed.iter().filter(|path| !seen.contains_key(*path)) {
+                    for table in FILE_TABLES {
+                        transaction
+                            .execute(&format!("DELETE FROM {} WHERE path = ?1", table), [path])?;
+                    }
+                    removed += 1;
+                }
+
+                transaction.commit()?;
+                let report = ReconcileReport {
+                    indexed: seen.len(),
+                    removed,
+                };
+                Ok((report, seen))
+            })
+            .await?;
+        self.extract_texts(seen);
+        Ok(report)
+    }
+
+    /// Queues the extraction of the text of changed files, given with their codec, if
+    /// full-text search is enabled.
+    fn extract_texts(&self, changed: BTreeMap<String, Option<Codec>>) {
+        let limit = match self.text_size_limit {
+            Some(limit) => limit,
+            None => return,
+        };
+        let texts = self.texts.get_or_init(|| {
+            let (sender, receiver) = mpsc::unbounded_channel();
+            let extraction = Arc::new(TextExtraction {
+                connection: self.connection.clone(),
+                root: self.storage_root.clone(),
+                limit,
+                compression: self.compression.clone(),
+            });
+            tokio::spawn(extract_texts(extraction, receiver));
+            sender
+        });
+        for (path, codec) in changed {
+            if is_text_like(&path) {
+                let _ = texts.send(TextJob { path, codec });
+            }
+        }
+    }
+
+    async fn call<T, F>(&self, f: F) -> Result<T, IndexError>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection, &Path) -> Result<T, IndexError> + Send + 'static,
+    {
+        let connection = self.connection.clone();
+        let storage_root = self.storage_root.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut connection = connection.lock().unwrap();
+            f(&mut connection, &storage_root)
+        })
+        .await
+        .map_err(io::Error::other)?
+    }
+}
+
+/// The storage root being indexed.
+struct Tree<'a> {
+    root: &'a Path,
+    compression: &'a Compression,
+}
+
+/// Applies an event, recording the files it changed and their codec in `changed`.
+fn apply_event(
+    connection: &Connection,
+    tree: &Tree,
+    event: &FileEvent,
+    changed: &mut BTreeMap<String, Option<Codec>>,
+) -> Result<(), IndexError> {
+    match event.kind {
+        FileEventKind::UploadCompleted => {
+            upsert(
+                connection,
+                &Entry {
+                    path: &event.path,
+                    size: event.size.unwrap_or_default(),
+                    checksum: event.checksum.as_deref(),
+                    content_type: event.content_type.as_deref(),
+                    codec: event.codec.as_deref(),
+                    owner: event.identity.as_deref(),
+                    modified_at: event.timestamp,
+                },
+            )?;
+            let codec = event.codec.as_deref().map(Codec::parse).transpose()?;
+            changed.insert(event.path.clone(), codec);
+        }
+        FileEventKind::Deleted => {
+            for table in FILE_TABLES {
+                connection.execute(
+                    &format!("DELETE FROM {} WHERE {}", table, SUBTREE),
+                    [&event.path],
+                )?;
+            }
+        }
+        FileEventKind::Moved => {
+            if let Some(from_path) = &event.from_path {
+                for table in FILE_TABLES {
+                    connection.execute(
+                        &format!(
+                            "UPDATE {} SET path = ?2 || substr(path, length(?1) + 1) WHERE {}",
+                            table, SUBTREE
+                        ),
+                        params![from_path, event.path],
+                    )?;
+                }
+            }
+        }
+        // Restored content may be a whole directory, and its checksum is not in the event.
+        FileEventKind::Restored => {
+            let codec_of = |path: &str| {
+                let codec = event.codecs.get(path).map(|codec| Codec::parse(codec));
+                Ok(codec.transpose()?)
+            };
+            index_tree(
+                connection,
+                tree,
+                &event.path,
+                event.identity.as_deref(),
+                &codec_of,
+                changed,
+            )?;
+        }
+        FileEventKind::VersionCreated => {}
+    }
+
+    Ok(())
+}
+
+/// Indexes the file or directory at `relative_path`, recording every file and its codec in
+/// `seen`. Files are read with the codec given for their path by `codec_of`.
+fn index_tree(
+    connection: &Connection,
+    tree: &Tree,
+    relative_path: &str,
+    owner: Option<&str>,
+    codec_of: &dyn Fn(&str) -> Result<Option<Codec>, IndexError>,
+    seen: &mut BTreeMap<String, Option<Codec>>,
+) -> Result<(), IndexError> {
+    let path = tree.root.join(relative_path);
+    let metadata = std::fs::metadata(&path)?;
+
+    if metadata.is_dir() {
+        for entry in std::fs::read_dir(&path)? {
+            let name = entry?.file_name().to_string_lossy().into_owned();
+            if relative_path.is_empty() && name == INTERNAL_DIRECTORY {
+                continue;
+            }
+            let child = if relative_path.is_empty() {
+                name
+            } else {
+                format!("{}/{}", relative_path, name)
+            };
+            index_tree(connection, tree, &child, owner, codec_of, seen)?;
+        }
+    } else if metadata.is_file() {
+        let modified_at = metadata.modified().map(DateTime::<Utc>::from)?;
+        let codec = codec_of(relative_path)?;
+        let content = tree.compression.open_blocking(&path, codec)?;
+        let size = content.size();
+        let (checksum, content_type) = fingerprint(content, relative_path)?;
+        upsert(
+            connection,
+            &Entry {
+                path: relative_path,
+                size,
+                checksum: Some(&checksum),
+                content_type: Some(content_type.essence_str()),
+                codec: codec.as_ref().map(Codec::as_str),
+                owner,
+                modified_at,
+            },
+        )?;
+        seen.insert(relative_path.to_string(), codec);
+    }
+
+    Ok(())
+}
+
+/// A file as written to the `files` table.
+struct Entry<'a> {
+    path: &'a str,
+    size: u64,
+    checksum: Option<&'a str>,
+    content_type: Option<&'a str>,
+    codec: Option<&'a str>,
+    owner: Option<&'a str>,
+    modified_at: DateTime<Utc>,
+}
+
+/// Inserts or updates a file, keeping the owner and creation time of an existing entry. The
+/// text of the previous content is dropped, until that of the new one is extracted.
+fn upsert(connection: &Connection, entry: &Entry) -> Result<(), IndexError> {
+    connection.execute(
+        "INSERT INTO files
+             (path, size, checksum, content_type, codec, owner, created_at, modified_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?7)
+         ON CONFLICT (path) DO UPDATE SET
+             size = excluded.size,
+             checksum = excluded.checksum,
+             content_type = excluded.content_type,
+             codec = excluded.codec,
+             owner = coalesce(files.owner, excluded.owner),
+             modified_at = excluded.modified_at",
+        params![
+            entry.path,
+            entry.size,
+            entry.checksum,
+            entry
+                .content_type
+                .map(str::to_string)
+                .or_else(|| mime_guess::from_path(entry.path)
+                    .first()
+                    .map(|mime| mime.essence_str().to_string())),
+            entry.codec,
+            entry.owner,
+            entry.modified_at
+        ],
+    )?;
+    connection.execute("DELETE FROM file_texts WHERE path = ?1", [entry.path])?;
+    Ok(())
+}
+
+/// A file whose text is to be extracted, with the codec it is stored with.
+struct TextJob {
+    path: String,
+    codec: Option<Codec>,
+}
+
+/// What the background extraction of texts needs from the index.
+struct TextExtraction {
+    connection: Arc<Mutex<Connection>>,
+    root: PathBuf,
+    limit: u64,
+    compression: Arc<Compression>,
+}
+
+impl TextExtraction {
+    /// Stores the text of a file for full-text search, if it is text small enough. PDFs and
+    /// other binary documents are left out. The file is read before locking the index, which
+    /// is only held for storing the text.
+    fn extract(&self, job: &TextJob) -> Result<(), IndexError> {
+        let mut content = self
+            .compression
+            .open_blocking(&self.root.join(&job.path), job.codec)?;
+        if content.size() > self.limit {
+            return Ok(());
+        }
+        let mut text = vec![];
+        content.read_to_end(&mut text)?;
+        let text = match String::from_utf8(text) {
+            Ok(text) => text,
+            Err(_) => return Ok(()),
+        };
+
+        // Files removed since they were queued are left out.
+        self.connection.lock().unwrap().execute(
+            "INSERT INTO file_texts (path, content)
+             SELECT ?1, ?2 WHERE EXISTS (SELECT 1 FROM files WHERE path = ?1)
+             ON CONFLICT (path) DO UPDATE SET content = excluded.content",
+            params![job.path, text],
+        )?;
+        Ok(())
+    }
+}
+
+/// Extracts the texts of queued files one at a time, in the order their changes were applied,
+/// so the text of a file overwritten meanwhile ends up being that of its last content.
+async fn extract_texts(extraction: Arc<TextExtraction>, mut jobs: UnboundedReceiver<TextJob>) {
+    while let Some(job) = jobs.recv().await {
+        let extraction = extraction.clone();
+        let extracted = tokio::task::spawn_blocking(move || {
+            let extracted = extraction.extract(&job);
+            (job.path, extracted)
+        })
+        .await;
+        match extracted {
+            Ok((_, Ok(()))) => {}
+            // Removed since it was queued.
+            Ok((_, Err(IndexError::Io(e)))) if e.kind() == ErrorKind::NotFound => {}
+            Ok((path, Err(e))) => tracing::error!("Failed to extract text of {}: {:?}", path, e),
+            Err(e) => tracing::error!("Failed to extract text: {:?}", e),
+        }
+    }
+}
+
+/// Whether the file is a text format, going by its name. Files of unknown type, such as logs
+/// without an extension, count as text if their content is UTF-8.
+fn is_text_like(path: &str) -> bool {
+    match mime_guess::from_path(path).first() {
+        Some(mime) => {
+            mime.type_() == mime_guess::mime::TEXT
+                || TEXT_CONTENT_TYPES.contains(&mime.essence_str())
+        }
+        None => true,
+    }
+}
+
+/// Reads the content of a file for its SHA-256 checksum and detected content type.
+fn fingerprint(mut content: impl Read, relative_path: &str) -> Result<(String, Mime), io::Error> {
+    let mut head = Vec::with_capacity(SNIFF_LENGTH);
+    (&mut content)
+        .take(SNIFF_LENGTH as u64)
+        .read_to_end(&mut head)?;
+
+    let mut hasher = Sha256::new();
+    hasher.update(&head);
+    io::copy(&mut content, &mut hasher)?;
+    Ok((
+        hex::encode(hasher.finalize()),
+        content_types::detect(&head, relative_path),
+    ))
+}
+
+fn read_record(row: &rusqlite::Row) -> Result<FileRecord, rusqlite::Error> {
+    Ok(FileRecord {
+        path: row.get(0)?,
+        size: row.get(1)?,
+        checksum: row.get(2)?,
+        content_type: row.get(3)?,
+        owner: row.get(4)?,
+        created_at: row.get(5)?,
+        modified_at: row.get(6)?,
+        codec: row.get(7)?,
+        tags: vec![],
+        metadata: BTreeMap::new(),
+    })
+}
+
+fn recorded_codec(connection: &Connection, path: &str) -> Result<Option<Codec>, IndexError> {
+    let codec = connection
+        .query_row("SELECT codec FROM files WHERE path = ?1", [path], |row| {
+            row.get::<_, Option<String>>(0)
+        })
+        .optional()?
+        .flatten();
+    Ok(codec.as_deref().map(Codec::parse).transpose()?)
+}
+
+fn read_file(connection: &Connection, path: &str) -> Result<Option<FileRecord>, IndexError> {
+    let record = connection
+        .query_row(
+            &format!("SELECT {} FROM files WHERE path = ?1", RECORD_COLUMNS),
+            [path],
+            read_record,
+        )
+        .optional()?;
+
+    match record {
+        Some(mut record) => {
+            read_details(connection, &mut record)?;
+            Ok(Some(record))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Conditions on the `files` table and the values bound to their numbered parameters.
+#[derive(Default)]
+struct Selection {
+    conditions: Vec<String>,
+    values: Vec<Box<dyn ToSql + Send>>,
+}
+
+impl Selection {
+    /// Binds a value, returning the number of its parameter.
+    fn bind(&mut self, value: impl ToSql + Send + 'static) -> usize {
+        self.values.push(Box::new(value));
+        self.values.len()
+    }
+
+    fn filter(&mut self, condition: String) {
+        self.conditions.push(condition);
+    }
+
+    /// Keeps the files in `scope`, the allowed paths of the caller.
+    fn scope(&mut self, scope: Option<Vec<String>>) {
+        if let Some(scope) = scope {
+            let index = self.bind(serde_json::to_string(&scope).expect("Paths serialize"));
+            self.filter(format!("in_scope(path, ?{})", index));
+        }
+    }
+
+    fn tag(&mut self, tag: Option<String>) {
+        if let Some(tag) = tag {
+            let index = self.bind(tag);
+            self.filter(format!(
+                "EXISTS (SELECT 1 FROM file_tags t WHERE t.path = files.path AND t.tag = ?{})",
+                index
+            ));
+        }
+    }
+
+    fn metadata(&mut self, metadata: Vec<(String, String)>) {
+        for (key, value) in metadata {
+            let key_index = self.bind(key);
+            let value_index = self.bind(value);
+            self.filter(format!(
+                "EXISTS (SELECT 1 FROM file_metadata m WHERE m.path = files.path \
+                 AND m.key = ?{} AND m.value = ?{})",
+                key_index, value_index
+            ));
+        }
+    }
+
+    fn where_clause(&self) -> String {
+        if self.conditions.is_empty() {
+            String::new()
+        } else {
+            format!("WHERE {}", self.conditions.join(" AND "))
+        }
+    }
+
+    /// Reads up to `limit` matching files by path.
+    fn select(
+        mut self,
+        connection: &Connection,
+        limit: usize,
+    ) -> Result<Vec<FileRecord>, IndexError> {
+        let limit_index = self.bind(limit as i64);
+        let query = format!(
+            "SELECT {} FROM files {} ORDER BY path LIMIT ?{}",
+            RECORD_COLUMNS,
+            self.where_clause(),
+            limit_index
+        );
+        let mut records = connection
+            .prepare(&query)?
+            .query_map(params_from_iter(self.values.iter()), read_record)?
+            .collect::<Result<Vec<_>, _>>()?;
+        for record in &mut records {
+            read_details(connection, record)?;
+        }
+        Ok(records)
+    }
+}
+
+/// Fills in the tags and custom metadata of a record.
+fn read_details(connection: &Connection, record: &mut FileRecord) -> Result<(), IndexError> {
+    record.tags = connection
+        .prepare("SELECT tag FROM file_tags WHERE path = ?1 ORDER BY tag")?
+        .query_map([&record.path], |row| row.get(0))?
+        .collect::<Result<_, _>>()?;
+    record.metadata = connection
+        .prepare("SELECT key, value FROM file_metadata WHERE path = ?1")?
+        .query_map([&record.path], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<Result<_, _>>()?;
+    Ok(())
+}
@@ -0,0 +1,173 @@
+use std::{
+    io::{self, ErrorKind, SeekFrom},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use tokio::{
+    fs::{self, File, OpenOptions},
+    io::{AsyncBufReadExt, AsyncSeekExt, AsyncWriteExt, BufReader},
+};
+
+use crate::events::FileEvent;
+
+/// Events between two checkpoints of the offset index.
+const CHECKPOINT_INTERVAL: u64 = 1000;
+
+/// Append-only JSON-lines record of every published file event, in sequence order. Writes are
+/// serialised by the caller.
+pub struct Journal {
+    path: PathBuf,
+    state: Mutex<JournalState>,
+}
+
+struct JournalState {
+    last_sequence: u64,
+    /// Sequence and byte offset of the first event of a batch, every `CHECKPOINT_INTERVAL`
+    /// events or so, for reads to seek close to their cursor.
+    checkpoints: Vec<(u64, u64)>,
+    /// Events appended since the last checkpoint.
+    since_checkpoint: u64,
+}
+
+impl JournalState {
+    fn record(&mut self, first_sequence: u64, offset: u64, last_sequence: u64, count: u64) {
+        if self.checkpoints.is_empty() || self.since_checkpoint >= CHECKPOINT_INTERVAL {
+            self.checkpoints.push((first_sequence, offset));
+            self.since_checkpoint = 0;
+        }
+        self.since_checkpoint += count;
+        self.last_sequence = last_sequence;
+    }
+}
+
+impl Journal {
+    /// Opens the journal at `path`, reading it once to build its offset index.
+    pub async fn open(path: impl Into<PathBuf>) -> Result<Self, io::Error> {
+        let path = path.into();
+        let mut state = JournalState {
+            last_sequence: 0,
+            checkpoints: vec![],
+            since_checkpoint: 0,
+        };
+
+        if let Some(mut lines) = Lines::open(&path, 0).await? {
+            while let Some((offset, event)) = lines.next().await? {
+                state.record(event.sequence, offset, event.sequence, 1);
+            }
+        }
+
+        Ok(Self {
+            path,
+            state: Mutex::new(state),
+        })
+    }
+
+    /// Sequence of the last journaled event, or 0 for an empty journal.
+    pub fn last_sequence(&self) -> u64 {
+        self.state.lock().unwrap().last_sequence
+    }
+
+    /// Appends the events, or nothing at all when writing fails.
+    pub async fn append(&self, events: &[FileEvent]) -> Result<(), io::Error> {
+        let (first, last) = match (events.first(), events.last()) {
+            (Some(first), Some(last)) => (first.sequence, last.sequence),
+            _ => return Ok(()),
+        };
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+
+        let mut lines = vec![];
+        for event in events {
+            serde_json::to_writer(&mut lines, event)?;
+            lines.push(b'\n');
+        }
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+        let offset = file.metadata().await?.len();
+        let written = match file.write_all(&lines).await {
+            Ok(()) => file.sync_data().await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = written {
+            // A partial line would break every read after it.
+            let _ = file.set_len(offset).await;
+            return Err(e);
+        }
+
+        self.state
+            .lock()
+            .unwrap()
+            .record(first, offset, last, events.len() as u64);
+        Ok(())
+    }
+
+    /// Returns up to `limit` events following the `cursor` sequence, reading from the last
+    /// checkpoint before it.
+    pub async fn read_after(&self, cursor: u64, limit: usize) -> Result<Vec<FileEvent>, io::Error> {
+        let offset = {
+            let state = self.state.lock().unwrap();
+            let checkpoint = state
+                .checkpoints
+                .partition_point(|(sequence, _)| *sequence <= cursor.saturating_add(1));
+            match checkpoint {
+                0 => 0,
+                checkpoint => state.checkpoints[checkpoint - 1].1,
+            }
+        };
+
+        let mut events = vec![];
+        if let Some(mut lines) = Lines::open(&self.path, offset).await? {
+            while events.len() < limit {
+                match lines.next().await? {
+                    Some((_, event)) if event.sequence > cursor => events.push(event),
+                    Some(_) => {}
+                    None => break,
+                }
+            }
+        }
+        Ok(events)
+    }
+}
+
+/// The events of the journal from a byte offset on, with the offset of each.
+struct Lines {
+    reader: BufReader<File>,
+    offset: u64,
+    line: String,
+}
+
+impl Lines {
+    async fn open(path: &Path, offset: u64) -> Result<Option<Self>, io::Error> {
+        let mut file = match File::open(path).await {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        file.seek(SeekFrom::Start(offset)).await?;
+        Ok(Some(Self {
+            reader: BufReader::new(file),
+            offset,
+            line: String::new(),
+        }))
+    }
+
+    async fn next(&mut self) -> Result<Option<(u64, FileEvent)>, io::Error> {
+        self.line.clear();
+        let read = self.reader.read_line(&mut self.line).await?;
+        // Every complete line ends with a newline, anything after the last one is still being
+        // written.
+        if !self.line.ends_with('\n') {
+            return Ok(None);
+        }
+        let offset = self.offset;
+        self.offset += read as u64;
+        let event = serde_json::from_str(&self.line).map_err(io::Error::from)?;
+        Ok(Some((offset, event)))
+    }
+}
@@ -1,6 +1,24 @@
+pub mod archives;
+pub mod audit;
+pub mod client;
+pub mod compression;
 pub mod configuration;
+pub mod content_types;
 pub mod domain;
+pub mod encryption;
+pub mod events;
+pub mod extraction;
+pub mod index;
+pub mod journal;
+pub mod metrics;
 pub mod routes;
+pub mod s3;
+pub mod scanning;
 pub mod startup;
 pub mod telemetry;
+pub mod thumbnails;
+pub mod trash;
 pub mod validators;
+pub mod versioning;
+pub mod webdav;
+pub mod webhooks;
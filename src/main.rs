@@ -1,8 +1,7 @@
 use crumbbox::{
     configuration::Settings,
-    domain::StorageDetails,
-    startup::app,
-    telemetry::{get_subscriber, init_subscriber},
+    startup::{app, encrypt, reindex},
+    telemetry::{get_otlp_tracer, get_subscriber, init_subscriber},
 };
 use std::net::{SocketAddr, TcpListener};
 
@@ -10,9 +9,42 @@ use std::net::{SocketAddr, TcpListener};
 async fn main() {
     let config = Settings::get_configuration().expect("Failed to load configuration");
 
-    let subscriber = get_subscriber("info".to_string(), std::io::stdout);
+    let tracer = get_otlp_tracer(&config.telemetry).expect("Failed to create OTLP tracer");
+    let subscriber = get_subscriber(
+        "info".to_string(),
+        std::io::stdout,
+        &config.telemetry,
+        tracer,
+    );
     init_subscriber(subscriber);
 
+    // `crumbbox reindex` rebuilds the metadata index from the storage root instead of serving.
+    if std::env::args().nth(1).as_deref() == Some("reindex") {
+        let report = reindex(config)
+            .await
+            .expect("Failed to reconcile metadata index");
+        tracing::info!(
+            "Indexed {} files, removed {} stale entries",
+            report.indexed,
+            report.removed
+        );
+        return;
+    }
+
+    // `crumbbox encrypt` encrypts the files written before encryption was enabled.
+    if std::env::args().nth(1).as_deref() == Some("encrypt") {
+        let report = encrypt(config)
+            .await
+            .expect("Failed to encrypt plaintext files");
+        tracing::info!(
+            "Encrypted {} files, {} were already encrypted and {} failed",
+            report.encrypted,
+            report.current,
+            report.failed
+        );
+        return;
+    }
+
     let address = format!("{}:{}", config.application.host, config.application.port)
         .parse::<SocketAddr>()
         .expect("Failed to parse address");
@@ -21,8 +53,7 @@ async fn main() {
 
     tracing::info!("Listening on {}", listener.local_addr().unwrap());
 
-    let storage_details = StorageDetails {
-        path: config.application.storage_path,
-    };
-    app(listener, storage_details).await;
+    app(listener, config).await;
+
+    opentelemetry::global::shutdown_tracer_provider();
 }
@@ -0,0 +1,119 @@
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts, Registry,
+    TextEncoder,
+};
+
+use crate::domain::StorageDetails;
+
+pub struct Metrics {
+    registry: Registry,
+    pub http_requests_total: IntCounterVec,
+    pub http_request_duration_seconds: HistogramVec,
+    pub uploaded_bytes_total: IntCounter,
+    pub downloaded_bytes_total: IntCounter,
+    active_uploads: IntGauge,
+    pub cleanup_failures_total: IntCounter,
+    pub validation_rejections_total: IntCounterVec,
+    storage_used_bytes: IntGauge,
+    storage_free_bytes: IntGauge,
+}
+
+impl Metrics {
+    pub fn new() -> Result<Self, prometheus::Error> {
+        let registry = Registry::new_custom(Some("crumbbox".to_string()), None)?;
+
+        let http_requests_total = IntCounterVec::new(
+            Opts::new("http_requests_total", "Number of handled HTTP requests"),
+            &["method", "route", "status"],
+        )?;
+        let http_request_duration_seconds = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "Latency of handled HTTP requests in seconds",
+            ),
+            &["method", "route", "status"],
+        )?;
+        let uploaded_bytes_total =
+            IntCounter::new("uploaded_bytes_total", "Number of bytes written by uploads")?;
+        let downloaded_bytes_total = IntCounter::new(
+            "downloaded_bytes_total",
+            "Number of bytes served by downloads",
+        )?;
+        let active_uploads = IntGauge::new("active_uploads", "Number of uploads in progress")?;
+        let cleanup_failures_total = IntCounter::new(
+            "cleanup_failures_total",
+            "Number of failed uploads whose files could not be removed",
+        )?;
+        let validation_rejections_total = IntCounterVec::new(
+            Opts::new(
+                "validation_rejections_total",
+                "Number of requests rejected by validation",
+            ),
+            &["reason"],
+        )?;
+        let storage_used_bytes = IntGauge::new(
+            "storage_used_bytes",
+            "Used bytes on the volume holding the storage path",
+        )?;
+        let storage_free_bytes = IntGauge::new(
+            "storage_free_bytes",
+            "Free bytes on the volume holding the storage path",
+        )?;
+
+        registry.register(Box::new(http_requests_total.clone()))?;
+        registry.register(Box::new(http_request_duration_seconds.clone()))?;
+        registry.register(Box::new(uploaded_bytes_total.clone()))?;
+        registry.register(Box::new(downloaded_bytes_total.clone()))?;
+        registry.register(Box::new(active_uploads.clone()))?;
+        registry.register(Box::new(cleanup_failures_total.clone()))?;
+        registry.register(Box::new(validation_rejections_total.clone()))?;
+        registry.register(Box::new(storage_used_bytes.clone()))?;
+        registry.register(Box::new(storage_free_bytes.clone()))?;
+
+        Ok(Self {
+            registry,
+            http_requests_total,
+            http_request_duration_seconds,
+            uploaded_bytes_total,
+            downloaded_bytes_total,
+            active_uploads,
+            cleanup_failures_total,
+            validation_rejections_total,
+            storage_used_bytes,
+            storage_free_bytes,
+        })
+    }
+
+    /// Refreshes the storage gauges and encodes every metric in the Prometheus text format.
+    pub fn render(&self, storage_details: &StorageDetails) -> Result<String, anyhow::Error> {
+        let space = storage_details.space()?;
+        self.storage_used_bytes.set(space.used() as i64);
+        self.storage_free_bytes.set(space.free as i64);
+
+        let mut buffer = vec![];
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(String::from_utf8(buffer)?)
+    }
+
+    /// Counts an upload as active until the returned guard is dropped, so uploads abandoned
+    /// midway are no longer counted either.
+    pub fn start_upload(&self) -> ActiveUpload {
+        self.active_uploads.inc();
+        ActiveUpload(self.active_uploads.clone())
+    }
+
+    pub fn record_validation_rejection(&self, reason: &str) {
+        self.validation_rejections_total
+            .with_label_values(&[reason])
+            .inc();
+    }
+}
+
+/// An upload in progress, counted by the `active_uploads` gauge while it lives.
+pub struct ActiveUpload(IntGauge);
+
+impl Drop for ActiveUpload {
+    fn drop(&mut self) {
+        self.0.dec();
+    }
+}
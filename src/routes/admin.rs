@@ -0,0 +1,83 @@
+use std::{path::Path, sync::Arc};
+
+use axum::{
+    extract::Query,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+
+use crate::{
+    audit::{AuditFilter, AuditLog},
+    configuration::AuthSettings,
+    domain::{Identity, StorageDetails},
+    encryption::Encryption,
+    scanning::Scanning,
+};
+
+#[tracing::instrument(name = "Query the audit log", skip(audit_log, auth))]
+pub async fn audit_log(
+    Query(filter): Query<AuditFilter>,
+    identity: Identity,
+    audit_log: Extension<Arc<AuditLog>>,
+    auth: Extension<Arc<AuthSettings>>,
+) -> Response {
+    if !auth.api_keys.is_empty() && !identity.is_admin() {
+        return (StatusCode::FORBIDDEN, "Admin access required").into_response();
+    }
+
+    match audit_log.query(&filter).await {
+        Ok(records) => Json(records).into_response(),
+        Err(e) => {
+            tracing::error!("{:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+#[tracing::instrument(name = "List quarantined files", skip(scanning, auth))]
+pub async fn quarantine(
+    identity: Identity,
+    scanning: Extension<Arc<Scanning>>,
+    auth: Extension<Arc<AuthSettings>>,
+) -> Response {
+    if !auth.api_keys.is_empty() && !identity.is_admin() {
+        return (StatusCode::FORBIDDEN, "Admin access required").into_response();
+    }
+
+    match scanning.quarantined().await {
+        Ok(entries) => Json(entries).into_response(),
+        Err(e) => {
+            tracing::error!("{:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Wraps the data keys of every encrypted file with the active master key, so older keys can be
+/// removed from the configuration afterwards.
+#[tracing::instrument(
+    name = "Rotate encryption keys",
+    skip(encryption, storage_details, auth)
+)]
+pub async fn rotate_keys(
+    identity: Identity,
+    encryption: Extension<Arc<Encryption>>,
+    storage_details: Extension<Arc<StorageDetails>>,
+    auth: Extension<Arc<AuthSettings>>,
+) -> Response {
+    if !auth.api_keys.is_empty() && !identity.is_admin() {
+        return (StatusCode::FORBIDDEN, "Admin access required").into_response();
+    }
+    if !encryption.is_enabled() {
+        return (StatusCode::CONFLICT, "Encryption is not enabled").into_response();
+    }
+
+    match encryption.rotate(Path::new(&storage_details.path)).await {
+        Ok(report) => Json(report).into_response(),
+        Err(e) => {
+            tracing::error!("{:?}", e);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
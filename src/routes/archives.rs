@@ -0,0 +1,173 @@
+use std::{collections::BTreeMap, io::ErrorKind, sync::Arc};
+
+use axum::{
+    body::StreamBody,
+    extract::{Path, Query},
+    http::header,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use futures::TryStreamExt;
+use serde::Deserialize;
+
+use crate::{
+    archives::{ArchiveEntry, ArchiveFormat, Archives},
+    domain::Identity,
+    metrics::Metrics,
+    routes::FilesError,
+    validators::validate_relative_path,
+};
+
+/// Name of archives of the whole storage root or of several paths.
+const DEFAULT_ARCHIVE_NAME: &str = "archive";
+
+#[derive(Deserialize, Debug)]
+pub struct ArchiveQuery {
+    /// `zip` or `tar.gz`, `zip` when absent.
+    format: Option<String>,
+}
+
+impl ArchiveQuery {
+    fn format(&self) -> Result<ArchiveFormat, FilesError> {
+        match &self.format {
+            Some(format) => format.parse().map_err(FilesError::ValidationError),
+            None => Ok(ArchiveFormat::Zip),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ArchiveRequest {
+    /// Files and directories to archive, named by their paths in the archive.
+    paths: Vec<String>,
+}
+
+/// Streams a directory, or a single file, as an archive named after it.
+#[tracing::instrument(
+    name = "Get archive request handler",
+    skip(identity, archives, metrics)
+)]
+pub async fn get_archive(
+    Path(path): Path<String>,
+    Query(query): Query<ArchiveQuery>,
+    identity: Identity,
+    archives: Extension<Arc<Archives>>,
+    metrics: Extension<Arc<Metrics>>,
+) -> Result<Response, FilesError> {
+    let format = query.format()?;
+    let relative_path = path.trim_matches('/');
+    if !relative_path.is_empty() {
+        validate_relative_path(relative_path).map_err(FilesError::ValidationError)?;
+    }
+    let name = relative_path.rsplit('/').next().unwrap_or_default();
+
+    let mut entries = BTreeMap::new();
+    collect(&archives, relative_path, name, &identity, &mut entries).await?;
+    let archive_name = match name {
+        "" => DEFAULT_ARCHIVE_NAME,
+        name => name,
+    };
+    Ok(stream_archive(
+        &archives,
+        entries,
+        format,
+        archive_name,
+        &metrics,
+    ))
+}
+
+/// Streams the posted files and directories as one archive.
+#[tracing::instrument(
+    name = "Post archive request handler",
+    skip(identity, archives, metrics)
+)]
+pub async fn post_archive(
+    Query(query): Query<ArchiveQuery>,
+    identity: Identity,
+    archives: Extension<Arc<Archives>>,
+    metrics: Extension<Arc<Metrics>>,
+    Json(request): Json<ArchiveRequest>,
+) -> Result<Response, FilesError> {
+    let format = query.format()?;
+    if request.paths.is_empty() {
+        return Err(FilesError::ValidationError(
+            "Expected at least one path".to_string(),
+        ));
+    }
+
+    let mut entries = BTreeMap::new();
+    for path in &request.paths {
+        let relative_path = path.trim_matches('/');
+        if relative_path.is_empty() {
+            return Err(FilesError::ValidationError(
+                "Expected a file path".to_string(),
+            ));
+        }
+        validate_relative_path(relative_path).map_err(FilesError::ValidationError)?;
+        collect(
+            &archives,
+            relative_path,
+            relative_path,
+            &identity,
+            &mut entries,
+        )
+        .await?;
+    }
+    Ok(stream_archive(
+        &archives,
+        entries,
+        format,
+        DEFAULT_ARCHIVE_NAME,
+        &metrics,
+    ))
+}
+
+/// Collects the entries below a path the caller can see any of.
+async fn collect(
+    archives: &Archives,
+    relative_path: &str,
+    name: &str,
+    identity: &Identity,
+    entries: &mut BTreeMap<String, ArchiveEntry>,
+) -> Result<(), FilesError> {
+    match archives
+        .collect(relative_path, name, identity.allowed_paths(), entries)
+        .await
+    {
+        Ok(0) => Err(FilesError::NotFound(relative_path.to_string())),
+        Ok(_) => Ok(()),
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            Err(FilesError::NotFound(relative_path.to_string()))
+        }
+        Err(e) => Err(anyhow::Error::new(e)
+            .context("Failed to collect archive entries")
+            .into()),
+    }
+}
+
+fn stream_archive(
+    archives: &Archives,
+    entries: BTreeMap<String, ArchiveEntry>,
+    format: ArchiveFormat,
+    archive_name: &str,
+    metrics: &Metrics,
+) -> Response {
+    let content_disposition = format!(
+        "attachment; filename=\"{}.{}\"",
+        archive_name.replace(['"', '\\'], "_"),
+        format.extension()
+    );
+    let downloaded_bytes = metrics.downloaded_bytes_total.clone();
+    let stream = archives
+        .stream(entries.into_values().collect(), format)
+        .inspect_ok(move |bytes| downloaded_bytes.inc_by(bytes.len() as u64));
+
+    (
+        [
+            (header::CONTENT_TYPE, format.content_type().to_string()),
+            (header::CONTENT_DISPOSITION, content_disposition),
+        ],
+        StreamBody::new(stream),
+    )
+        .into_response()
+}
@@ -0,0 +1,151 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::Query,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    domain::Identity,
+    events::{Events, FeedError, FileEvent, FileEventKind},
+};
+
+const DEFAULT_LIMIT: usize = 1000;
+
+#[derive(thiserror::Error, Debug)]
+pub enum DeltaError {
+    #[error("The cursor is ahead of the change journal, resynchronise and start without one")]
+    CursorExpired,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl IntoResponse for DeltaError {
+    fn into_response(self) -> Response {
+        let status = match self {
+            DeltaError::CursorExpired => StatusCode::GONE,
+            DeltaError::UnexpectedError(ref e) => {
+                tracing::error!("{:?}", e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        };
+
+        (status, self.to_string()).into_response()
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct DeltaQuery {
+    /// Cursor returned by the previous call. Without one only the current cursor is returned.
+    cursor: Option<u64>,
+    /// Journal entries to read at most, capped at 1000.
+    limit: Option<usize>,
+}
+
+#[derive(Serialize)]
+pub struct Delta {
+    pub changes: Vec<Change>,
+    /// Pass to the next call to continue after these changes.
+    pub cursor: u64,
+    pub has_more: bool,
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    Created,
+    Modified,
+    Deleted,
+    Moved,
+}
+
+#[derive(Serialize)]
+pub struct Change {
+    pub kind: ChangeKind,
+    pub path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from_path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub is_directory: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<String>,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl Change {
+    /// The change to the tree an event describes, if any.
+    fn from_event(event: FileEvent) -> Option<Self> {
+        let kind = match event.kind {
+            FileEventKind::UploadCompleted | FileEventKind::Restored => {
+                if event.overwritten == Some(true) {
+                    ChangeKind::Modified
+                } else {
+                    ChangeKind::Created
+                }
+            }
+            FileEventKind::Deleted => ChangeKind::Deleted,
+            FileEventKind::Moved => ChangeKind::Moved,
+            FileEventKind::VersionCreated => return None,
+        };
+
+        Some(Self {
+            kind,
+            path: event.path,
+            from_path: event.from_path,
+            is_directory: event.is_directory,
+            size: event.size,
+            checksum: event.checksum,
+            timestamp: event.timestamp,
+        })
+    }
+}
+
+/// Lists the changes to the tree since a cursor, read from the change journal, leaving out
+/// the ones to paths the caller cannot access.
+#[tracing::instrument(name = "Delta request handler", skip(identity, events))]
+pub async fn delta(
+    Query(query): Query<DeltaQuery>,
+    identity: Identity,
+    events: Extension<Arc<Events>>,
+) -> Result<Json<Delta>, DeltaError> {
+    let cursor = match query.cursor {
+        Some(cursor) => cursor,
+        None => {
+            return Ok(Json(Delta {
+                changes: vec![],
+                cursor: events.last_sequence().await,
+                has_more: false,
+            }))
+        }
+    };
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, DEFAULT_LIMIT);
+
+    // One more than asked for tells whether there is more to come.
+    let mut journaled = match events.read_after(cursor, limit + 1).await {
+        Ok(journaled) => journaled,
+        Err(FeedError::CursorExpired) => return Err(DeltaError::CursorExpired),
+        Err(FeedError::Io(e)) => {
+            return Err(anyhow::Error::new(e)
+                .context("Failed to read change journal")
+                .into())
+        }
+    };
+    let has_more = journaled.len() > limit;
+    journaled.truncate(limit);
+
+    Ok(Json(Delta {
+        cursor: journaled.last().map_or(cursor, |event| event.sequence),
+        changes: journaled
+            .into_iter()
+            .filter(|event| event.concerns(|path| identity.can_access(path)))
+            .filter_map(Change::from_event)
+            .collect(),
+        has_more,
+    }))
+}
@@ -0,0 +1,128 @@
+use std::{
+    convert::Infallible,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use axum::{
+    extract::Query,
+    http::{HeaderMap, StatusCode},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
+    Extension,
+};
+use futures::{stream, Stream, StreamExt};
+use serde::Deserialize;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::{
+    domain::Identity,
+    events::{Events, FeedError, FileEvent},
+    validators::validate_relative_path,
+};
+
+const LAST_EVENT_ID_HEADER: &str = "last-event-id";
+
+#[derive(thiserror::Error, Debug)]
+pub enum ChangeFeedError {
+    #[error("{0}")]
+    ValidationError(String),
+    #[error("The cursor is ahead of the change journal, resynchronise and subscribe without one")]
+    CursorExpired,
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl IntoResponse for ChangeFeedError {
+    fn into_response(self) -> Response {
+        let status = match self {
+            ChangeFeedError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            ChangeFeedError::CursorExpired => StatusCode::GONE,
+            ChangeFeedError::UnexpectedError(ref e) => {
+                tracing::error!("{:?}", e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        };
+
+        (status, self.to_string()).into_response()
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ChangeFeedQuery {
+    /// Only events for paths under this directory are sent.
+    prefix: Option<String>,
+    /// Sequence of the last event seen. The `Last-Event-ID` header takes precedence.
+    cursor: Option<u64>,
+}
+
+/// Streams file events as Server-Sent Events, each with its sequence as the event id so
+/// reconnecting clients resume where they left off. Callers only see events for paths they can
+/// access.
+#[tracing::instrument(name = "Change feed request handler", skip(headers, identity, events))]
+pub async fn change_feed(
+    Query(query): Query<ChangeFeedQuery>,
+    headers: HeaderMap,
+    identity: Identity,
+    events: Extension<Arc<Events>>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, ChangeFeedError> {
+    let prefix = query
+        .prefix
+        .as_deref()
+        .unwrap_or_default()
+        .trim_start_matches('/');
+    validate_relative_path(prefix).map_err(ChangeFeedError::ValidationError)?;
+    let prefix = PathBuf::from(prefix);
+
+    let cursor = match headers.get(LAST_EVENT_ID_HEADER) {
+        Some(value) => Some(
+            value
+                .to_str()
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .ok_or_else(|| {
+                    ChangeFeedError::ValidationError("Invalid Last-Event-ID".to_string())
+                })?,
+        ),
+        None => query.cursor,
+    };
+
+    let subscription = match events.subscribe(cursor).await {
+        Ok(subscription) => subscription,
+        Err(FeedError::CursorExpired) => return Err(ChangeFeedError::CursorExpired),
+        Err(FeedError::Io(e)) => {
+            return Err(anyhow::Error::new(e)
+                .context("Failed to read change journal")
+                .into())
+        }
+    };
+
+    let missed = stream::iter(subscription.missed);
+    // A subscriber falling too far behind is disconnected, it resumes from its cursor.
+    let live = stream::unfold(subscription.live, |mut receiver| async move {
+        match receiver.recv().await {
+            Ok(event) => Some((event, receiver)),
+            Err(RecvError::Lagged(_)) | Err(RecvError::Closed) => None,
+        }
+    });
+    let stream = missed
+        .chain(live)
+        .filter(move |event| {
+            std::future::ready(
+                event.concerns(|path| Path::new(path).starts_with(&prefix))
+                    && event.concerns(|path| identity.can_access(path)),
+            )
+        })
+        .map(|event| Ok(to_sse_event(&event)));
+
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
+fn to_sse_event(event: &FileEvent) -> Event {
+    Event::default()
+        .id(event.sequence.to_string())
+        .event(event.kind.as_str())
+        .data(serde_json::to_string(event).expect("File events serialise to JSON"))
+}
@@ -0,0 +1,698 @@
+use std::{
+    collections::HashMap, io::ErrorKind, net::SocketAddr, ops::Range, path::Path as FsPath,
+    sync::Arc,
+};
+
+use anyhow::Context;
+use axum::{
+    body::StreamBody,
+    extract::{BodyStream, ConnectInfo, Path, Query},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use chrono::Utc;
+use futures::TryStreamExt;
+use serde::Deserialize;
+
+use crate::{
+    audit::{AuditAction, AuditLog, AuditRecord},
+    compression::{Codec, Compression},
+    content_types::ContentPolicy,
+    domain::{etag, Identity, OutOfScope, StorageDetails},
+    events::{Events, FileEvent, FileEventKind},
+    index::{FileRecord, Index, ListFilter, MetadataPatch},
+    metrics::Metrics,
+    routes::{
+        upload::{
+            store_body, BodyOptions, Preconditions, UploadContext, UploadError, UploadStages,
+        },
+        webdav::IF,
+    },
+    scanning::Scanning,
+    trash::{Trash, TrashEntry},
+    validators::{validate_metadata_key, validate_metadata_patch, validate_relative_path},
+    versioning::Versioning,
+    webdav::{submitted_tokens, LockError, Locks},
+};
+
+const DEFAULT_LIST_LIMIT: usize = 1000;
+
+#[derive(thiserror::Error, Debug)]
+pub enum FilesError {
+    #[error("{0} was not found")]
+    NotFound(String),
+    #[error("{0}")]
+    Forbidden(String),
+    #[error("{0}")]
+    Conflict(String),
+    #[error("{0}")]
+    UnsupportedMediaType(String),
+    #[error("{0}")]
+    PreconditionFailed(String),
+    #[error("{0}")]
+    ValidationError(String),
+    #[error(transparent)]
+    Lock(#[from] LockError),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl IntoResponse for FilesError {
+    fn into_response(self) -> Response {
+        let status = match self {
+            FilesError::NotFound(_) => StatusCode::NOT_FOUND,
+            FilesError::Forbidden(_) => StatusCode::FORBIDDEN,
+            FilesError::Conflict(_) => StatusCode::CONFLICT,
+            FilesError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            FilesError::PreconditionFailed(_) => StatusCode::PRECONDITION_FAILED,
+            FilesError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            FilesError::Lock(_) => StatusCode::LOCKED,
+            FilesError::UnexpectedError(ref e) => {
+                tracing::error!("{:?}", e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        };
+
+        (status, self.to_string()).into_response()
+    }
+}
+
+impl From<OutOfScope> for FilesError {
+    fn from(e: OutOfScope) -> Self {
+        FilesError::Forbidden(e.to_string())
+    }
+}
+
+impl From<UploadError> for FilesError {
+    fn from(e: UploadError) -> Self {
+        match e {
+            UploadError::ValidationError { message, .. } => FilesError::ValidationError(message),
+            UploadError::Forbidden(message) => FilesError::Forbidden(message),
+            UploadError::PreconditionFailed(message) => FilesError::PreconditionFailed(message),
+            UploadError::Lock(e) => FilesError::Lock(e),
+            UploadError::UnexpectedError(e) => FilesError::UnexpectedError(e),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct FileQuery {
+    /// Present (with any value) to list the versions of a file instead of downloading it.
+    versions: Option<String>,
+    version: Option<String>,
+    /// Present (with any value) to return the indexed metadata of a file instead of its content.
+    stat: Option<String>,
+}
+
+/// Lists indexed files under `prefix`, filtered by `tag` and by `metadata.<key>` values, in path
+/// order. `cursor` takes the path a previous page ended on to list the files after it.
+#[tracing::instrument(name = "List files request handler", skip(index))]
+pub async fn list_files(
+    Query(query): Query<HashMap<String, String>>,
+    identity: Identity,
+    index: Extension<Arc<Index>>,
+) -> Result<Json<Vec<FileRecord>>, FilesError> {
+    let mut filter = ListFilter {
+        scope: identity.allowed_paths().map(<[String]>::to_vec),
+        limit: DEFAULT_LIST_LIMIT,
+        ..ListFilter::default()
+    };
+    for (name, value) in query {
+        match name.as_str() {
+            "prefix" => {
+                let prefix = value.trim_matches('/');
+                if !prefix.is_empty() {
+                    validate_relative_path(prefix).map_err(FilesError::ValidationError)?;
+                }
+                filter.prefix = prefix.to_string();
+            }
+            "recursive" => {
+                filter.recursive = value.parse().map_err(|_| {
+                    FilesError::ValidationError(format!("Invalid recursive flag: {}", value))
+                })?
+            }
+            "tag" => filter.tag = Some(value),
+            "cursor" => filter.after = Some(value),
+            "limit" => {
+                let limit: usize = value.parse().map_err(|_| {
+                    FilesError::ValidationError(format!("Invalid limit: {}", value))
+                })?;
+                filter.limit = limit.clamp(1, DEFAULT_LIST_LIMIT);
+            }
+            _ => match name.strip_prefix("metadata.") {
+                Some(key) => {
+                    validate_metadata_key(key).map_err(FilesError::ValidationError)?;
+                    filter.metadata.push((key.to_string(), value));
+                }
+                None => {
+                    return Err(FilesError::ValidationError(format!(
+                        "Unexpected query parameter: {}",
+                        name
+                    )))
+                }
+            },
+        }
+    }
+
+    let files = index.list(filter).await.context("Failed to list files")?;
+    Ok(Json(files))
+}
+
+/// A byte range requested with the `Range` header.
+enum ByteRange {
+    /// No range, or one that is ignored, such as several ranges at once.
+    Whole,
+    Partial(Range<u64>),
+    Unsatisfiable,
+}
+
+#[tracing::instrument(
+    name = "Get file request handler",
+    skip(headers, storage_details, versioning, metrics, index, compression)
+)]
+// Every extractor is an argument of its own.
+#[allow(clippy::too_many_arguments)]
+pub async fn get_file(
+    Path(path): Path<String>,
+    Query(query): Query<FileQuery>,
+    headers: HeaderMap,
+    identity: Identity,
+    storage_details: Extension<Arc<StorageDetails>>,
+    versioning: Extension<Arc<Versioning>>,
+    metrics: Extension<Arc<Metrics>>,
+    index: Extension<Arc<Index>>,
+    compression: Extension<Arc<Compression>>,
+) -> Result<Response, FilesError> {
+    let relative_path = parse_relative_path(&path)?;
+    identity.check_scope(relative_path)?;
+
+    if query.stat.is_some() {
+        let record = index
+            .get(relative_path)
+            .await
+            .context("Failed to read metadata index")?
+            .ok_or_else(|| FilesError::NotFound(relative_path.to_string()))?;
+        return Ok(Json(record).into_response());
+    }
+
+    if query.versions.is_some() {
+        let versions = versioning
+            .list(relative_path)
+            .await
+            .context("Failed to list versions")?;
+        return Ok(Json(versions).into_response());
+    }
+
+    let (file_path, codec) = match &query.version {
+        Some(id) => versioning
+            .version_path(relative_path, id)
+            .ok_or_else(|| FilesError::NotFound(format!("Version {}", id)))?,
+        None => (
+            storage_details.file_path(relative_path),
+            index
+                .codec(relative_path)
+                .await
+                .context("Failed to read metadata index")?,
+        ),
+    };
+
+    stream_file(
+        &file_path,
+        codec,
+        relative_path,
+        &headers,
+        &metrics,
+        &compression,
+    )
+    .await
+}
+
+/// Stores the raw request body as the file at `path`, for clients that would rather not build a
+/// multipart form.
+#[tracing::instrument(
+    name = "Put file request handler",
+    skip(
+        headers,
+        identity,
+        client_addr,
+        storage_details,
+        metrics,
+        audit_log,
+        versioning,
+        events,
+        compression,
+        content_policy,
+        scanning,
+        locks,
+        body
+    )
+)]
+// Every extractor is an argument of its own.
+#[allow(clippy::too_many_arguments)]
+pub async fn put_file(
+    Path(path): Path<String>,
+    headers: HeaderMap,
+    identity: Identity,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    storage_details: Extension<Arc<StorageDetails>>,
+    metrics: Extension<Arc<Metrics>>,
+    audit_log: Extension<Arc<AuditLog>>,
+    versioning: Extension<Arc<Versioning>>,
+    events: Extension<Arc<Events>>,
+    compression: Extension<Arc<Compression>>,
+    content_policy: Extension<Arc<ContentPolicy>>,
+    scanning: Extension<Arc<Scanning>>,
+    locks: Extension<Arc<Locks>>,
+    body: BodyStream,
+) -> Result<Response, FilesError> {
+    let relative_path = parse_relative_path(&path)?;
+    identity.check_scope(relative_path)?;
+    let options = parse_body_options(&headers)?;
+    let file_path = storage_details.file_path(relative_path);
+    if tokio::fs::metadata(&file_path)
+        .await
+        .is_ok_and(|metadata| metadata.is_dir())
+    {
+        return Err(FilesError::Conflict(format!(
+            "{} is a directory",
+            relative_path
+        )));
+    }
+
+    let context = UploadContext {
+        storage_details: &storage_details,
+        metrics: &metrics,
+        audit_log: &audit_log,
+        versioning: &versioning,
+        events: &events,
+        locks: &locks,
+        stages: UploadStages {
+            extraction: None,
+            compression: &compression,
+            content_policy: &content_policy,
+            scanning: &scanning,
+        },
+    };
+    let overwritten = store_body(
+        body,
+        relative_path,
+        &options,
+        &identity,
+        client_addr,
+        context,
+    )
+    .await?;
+
+    let status = match overwritten {
+        true => StatusCode::NO_CONTENT,
+        false => StatusCode::CREATED,
+    };
+    let metadata = tokio::fs::metadata(&file_path)
+        .await
+        .context("Failed to read stored file")?;
+    Ok((status, [(header::ETAG, etag(&metadata))]).into_response())
+}
+
+#[tracing::instrument(
+    name = "File action request handler",
+    skip(identity, client_addr, versioning, audit_log, events)
+)]
+pub async fn post_file(
+    Path(path): Path<String>,
+    Query(query): Query<FileQuery>,
+    identity: Identity,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    versioning: Extension<Arc<Versioning>>,
+    audit_log: Extension<Arc<AuditLog>>,
+    events: Extension<Arc<Events>>,
+) -> Result<StatusCode, FilesError> {
+    let relative_path = match path.strip_suffix("/restore") {
+        Some(relative_path) => parse_relative_path(relative_path)?,
+        None => return Err(FilesError::NotFound(path)),
+    };
+    identity.check_scope(relative_path)?;
+    let id = query
+        .version
+        .ok_or_else(|| FilesError::ValidationError("Expected a version to restore".to_string()))?;
+
+    let (restored, replaced) = match versioning.restore(relative_path, &id).await {
+        Ok(restored) => restored,
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            return Err(FilesError::NotFound(format!("Version {}", id)))
+        }
+        Err(e) => return Err(anyhow::Error::new(e).context("Failed to restore").into()),
+    };
+
+    audit_log
+        .record(&[AuditRecord {
+            timestamp: Utc::now(),
+            action: AuditAction::Restore,
+            identity: identity.name().to_string(),
+            client_ip: Some(client_addr.ip()),
+            path: relative_path.to_string(),
+            size: Some(restored.size),
+            checksum: None,
+        }])
+        .await
+        .context("Failed to write audit log")?;
+
+    let mut published = vec![];
+    if let Some(version) = &replaced {
+        published.push(FileEvent::version_created(relative_path, version));
+    }
+    published.push(FileEvent {
+        identity: Some(identity.name().to_string()),
+        overwritten: Some(replaced.is_some()),
+        size: Some(restored.size),
+        codecs: restored
+            .codec
+            .map(|codec| (relative_path.to_string(), codec.as_str().to_string()))
+            .into_iter()
+            .collect(),
+        version: Some(id),
+        ..FileEvent::new(FileEventKind::Restored, relative_path)
+    });
+    events.publish(&published).await;
+
+    Ok(StatusCode::OK)
+}
+
+/// Updates the custom metadata and tags of a file at `<path>/metadata`.
+#[tracing::instrument(
+    name = "Patch file metadata request handler",
+    skip(identity, client_addr, index, audit_log)
+)]
+pub async fn patch_file(
+    Path(path): Path<String>,
+    identity: Identity,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    index: Extension<Arc<Index>>,
+    audit_log: Extension<Arc<AuditLog>>,
+    Json(patch): Json<MetadataPatch>,
+) -> Result<Json<FileRecord>, FilesError> {
+    let relative_path = match path.strip_suffix("/metadata") {
+        Some(relative_path) => parse_relative_path(relative_path)?,
+        None => return Err(FilesError::NotFound(path)),
+    };
+    identity.check_scope(relative_path)?;
+    validate_metadata_patch(&patch).map_err(FilesError::ValidationError)?;
+
+    let record = index
+        .update_metadata(relative_path, patch)
+        .await
+        .context("Failed to update metadata")?
+        .ok_or_else(|| FilesError::NotFound(relative_path.to_string()))?;
+
+    audit_log
+        .record(&[AuditRecord {
+            timestamp: Utc::now(),
+            action: AuditAction::UpdateMetadata,
+            identity: identity.name().to_string(),
+            client_ip: Some(client_addr.ip()),
+            path: relative_path.to_string(),
+            size: None,
+            checksum: None,
+        }])
+        .await
+        .context("Failed to write audit log")?;
+
+    Ok(Json(record))
+}
+
+#[tracing::instrument(
+    name = "Delete file request handler",
+    skip(headers, identity, client_addr, trash, locks, audit_log, events)
+)]
+// Every extractor is an argument of its own.
+#[allow(clippy::too_many_arguments)]
+pub async fn delete_file(
+    Path(path): Path<String>,
+    headers: HeaderMap,
+    identity: Identity,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    trash: Extension<Arc<Trash>>,
+    locks: Extension<Arc<Locks>>,
+    audit_log: Extension<Arc<AuditLog>>,
+    events: Extension<Arc<Events>>,
+) -> Result<Json<TrashEntry>, FilesError> {
+    let relative_path = parse_relative_path(&path)?;
+    identity.check_scope(relative_path)?;
+    let lock_tokens = submitted_tokens(headers.get(IF).and_then(|value| value.to_str().ok()));
+    let entry = move_to_trash(
+        relative_path,
+        &identity,
+        client_addr,
+        &trash,
+        &locks,
+        &lock_tokens,
+        &audit_log,
+        &events,
+    )
+    .await?;
+    Ok(Json(entry))
+}
+
+/// Moves a file or directory to the trash, recording and publishing its deletion. The WebDAV
+/// locks on it have to be held, and are released once it is gone.
+#[allow(clippy::too_many_arguments)]
+pub(super) async fn move_to_trash(
+    relative_path: &str,
+    identity: &Identity,
+    client_addr: SocketAddr,
+    trash: &Trash,
+    locks: &Locks,
+    lock_tokens: &[String],
+    audit_log: &AuditLog,
+    events: &Events,
+) -> Result<TrashEntry, FilesError> {
+    locks.check(relative_path, lock_tokens)?;
+    let entry = match trash.delete(relative_path, identity.name()).await {
+        Ok(entry) => entry,
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            return Err(FilesError::NotFound(relative_path.to_string()))
+        }
+        Err(e) => {
+            return Err(anyhow::Error::new(e)
+                .context("Failed to move to trash")
+                .into())
+        }
+    };
+    locks.release(relative_path);
+
+    audit_log
+        .record(&[AuditRecord {
+            timestamp: Utc::now(),
+            action: AuditAction::Delete,
+            identity: identity.name().to_string(),
+            client_ip: Some(client_addr.ip()),
+            path: relative_path.to_string(),
+            size: entry.size,
+            checksum: None,
+        }])
+        .await
+        .context("Failed to write audit log")?;
+
+    events
+        .publish(&[FileEvent {
+            identity: Some(identity.name().to_string()),
+            is_directory: Some(entry.is_directory),
+            size: entry.size,
+            ..FileEvent::new(FileEventKind::Deleted, relative_path)
+        }])
+        .await;
+
+    Ok(entry)
+}
+
+/// Strips the leading slash of a wildcard capture and validates the remaining path.
+fn parse_relative_path(path: &str) -> Result<&str, FilesError> {
+    let relative_path = path.trim_start_matches('/');
+    if relative_path.is_empty() {
+        return Err(FilesError::ValidationError(
+            "Expected a file path".to_string(),
+        ));
+    }
+
+    validate_relative_path(relative_path).map_err(FilesError::ValidationError)?;
+    Ok(relative_path)
+}
+
+/// Reads the `Content-Length`, `Content-Type`, `If-Match` and `If-None-Match` headers of a raw
+/// upload.
+pub(super) fn parse_body_options(headers: &HeaderMap) -> Result<BodyOptions, FilesError> {
+    let header_value = |name: header::HeaderName| {
+        headers
+            .get(&name)
+            .map(|value| {
+                value
+                    .to_str()
+                    .map_err(|_| FilesError::ValidationError(format!("Invalid {} header", name)))
+            })
+            .transpose()
+    };
+    let entity_tags = |value: Option<&str>| {
+        value.map(|value| {
+            value
+                .split(',')
+                .map(str::trim)
+                .filter(|tag| !tag.is_empty())
+                .map(str::to_string)
+                .collect::<Vec<_>>()
+        })
+    };
+
+    let expected_size = header_value(header::CONTENT_LENGTH)?
+        .map(|length| {
+            length.parse().map_err(|_| {
+                FilesError::ValidationError(format!("Invalid Content-Length: {}", length))
+            })
+        })
+        .transpose()?;
+    let declared_type = header_value(header::CONTENT_TYPE)?
+        .map(|content_type| {
+            content_type.parse().map_err(|_| {
+                FilesError::ValidationError(format!("Invalid Content-Type: {}", content_type))
+            })
+        })
+        .transpose()?;
+    Ok(BodyOptions {
+        expected_size,
+        declared_type,
+        preconditions: Preconditions {
+            if_match: entity_tags(header_value(header::IF_MATCH)?),
+            if_none_match: entity_tags(header_value(header::IF_NONE_MATCH)?),
+        },
+        lock_tokens: submitted_tokens(header_value(IF)?),
+        ..BodyOptions::default()
+    })
+}
+
+/// Streams the original content of a file, or the part of it asked for by a `Range` header.
+/// Compressed files are sent as stored to clients accepting their codec as content encoding.
+pub(super) async fn stream_file(
+    file_path: &FsPath,
+    codec: Option<Codec>,
+    relative_path: &str,
+    request_headers: &HeaderMap,
+    metrics: &Metrics,
+    compression: &Compression,
+) -> Result<Response, FilesError> {
+    let metadata = match tokio::fs::metadata(file_path).await {
+        Ok(metadata) if metadata.is_file() => metadata,
+        Ok(_) => return Err(FilesError::NotFound(relative_path.to_string())),
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            return Err(FilesError::NotFound(relative_path.to_string()))
+        }
+        Err(e) => return Err(anyhow::Error::new(e).context("Failed to open file").into()),
+    };
+    let content = compression
+        .open(file_path, codec)
+        .await
+        .context("Failed to open file")?;
+    let size = content.size();
+    let range = request_headers
+        .get(header::RANGE)
+        .and_then(|range| range.to_str().ok());
+
+    let mut headers = HeaderMap::new();
+    headers.insert(header::ACCEPT_RANGES, "bytes".parse().unwrap());
+    headers.insert(header::ETAG, etag(&metadata).parse().unwrap());
+    if let Ok(modified) = metadata.modified() {
+        let last_modified = httpdate::fmt_http_date(modified);
+        headers.insert(header::LAST_MODIFIED, last_modified.parse().unwrap());
+    }
+    if let Some(codec) = content.codec() {
+        headers.insert(header::VARY, "Accept-Encoding".parse().unwrap());
+        // Ranges are of the original content, so they are always served decoded.
+        if range.is_none() && accepts_encoding(request_headers, codec) {
+            // The encoded content is a representation of its own, and so has its own entity tag.
+            let etag = etag(&metadata);
+            let encoded_etag = format!("{}-{}\"", etag.trim_end_matches('"'), codec.as_str());
+            headers.insert(header::ETAG, encoded_etag.parse().unwrap());
+            headers.insert(header::CONTENT_ENCODING, codec.as_str().parse().unwrap());
+            headers.insert(header::CONTENT_LENGTH, content.encoded_size().into());
+            let downloaded_bytes = metrics.downloaded_bytes_total.clone();
+            let stream = content
+                .encoded()
+                .await
+                .context("Failed to read file")?
+                .inspect_ok(move |bytes| downloaded_bytes.inc_by(bytes.len() as u64));
+            return Ok((StatusCode::OK, headers, StreamBody::new(stream)).into_response());
+        }
+    }
+
+    let (status, range) = match range.map_or(ByteRange::Whole, |range| parse_range(range, size)) {
+        ByteRange::Whole => (StatusCode::OK, 0..size),
+        ByteRange::Partial(range) => (StatusCode::PARTIAL_CONTENT, range),
+        ByteRange::Unsatisfiable => {
+            return Ok((
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                [(header::CONTENT_RANGE, format!("bytes */{}", size))],
+            )
+                .into_response())
+        }
+    };
+
+    headers.insert(header::CONTENT_LENGTH, (range.end - range.start).into());
+    if status == StatusCode::PARTIAL_CONTENT {
+        let content_range = format!("bytes {}-{}/{}", range.start, range.end - 1, size);
+        headers.insert(header::CONTENT_RANGE, content_range.parse().unwrap());
+    }
+
+    let downloaded_bytes = metrics.downloaded_bytes_total.clone();
+    let stream = content
+        .range(range)
+        .await
+        .context("Failed to read file")?
+        .inspect_ok(move |bytes| downloaded_bytes.inc_by(bytes.len() as u64));
+
+    Ok((status, headers, StreamBody::new(stream)).into_response())
+}
+
+/// Whether an `Accept-Encoding` header accepts `codec`, by name or through `*`.
+fn accepts_encoding(headers: &HeaderMap, codec: Codec) -> bool {
+    let mut accepted = None;
+    for value in headers.get_all(header::ACCEPT_ENCODING) {
+        for coding in value.to_str().unwrap_or_default().split(',') {
+            let mut parameters = coding.split(';').map(str::trim);
+            let name = parameters.next().unwrap_or_default();
+            let quality = parameters
+                .find_map(|parameter| parameter.strip_prefix("q="))
+                .and_then(|quality| quality.parse::<f32>().ok())
+                .unwrap_or(1.0);
+            if name.eq_ignore_ascii_case(codec.as_str()) {
+                return quality > 0.0;
+            }
+            if name == "*" {
+                accepted = Some(quality > 0.0);
+            }
+        }
+    }
+    accepted.unwrap_or(false)
+}
+
+/// Parses a `Range` header of a single byte range, e.g. `bytes=0-499`, `bytes=500-` or
+/// `bytes=-500` for the last 500 bytes.
+fn parse_range(range: &str, size: u64) -> ByteRange {
+    let (start, end) = match range
+        .strip_prefix("bytes=")
+        .filter(|range| !range.contains(','))
+        .and_then(|range| range.trim().split_once('-'))
+    {
+        Some(bounds) => bounds,
+        None => return ByteRange::Whole,
+    };
+
+    let range = match (start.parse::<u64>(), end.parse::<u64>()) {
+        (Ok(start), Ok(end)) if start <= end => start..end.saturating_add(1).min(size),
+        (Ok(start), Err(_)) if end.is_empty() => start..size,
+        (Err(_), Ok(suffix)) if start.is_empty() && suffix > 0 => size.saturating_sub(suffix)..size,
+        (Err(_), Ok(_)) if start.is_empty() => return ByteRange::Unsatisfiable,
+        _ => return ByteRange::Whole,
+    };
+    if range.start >= size {
+        return ByteRange::Unsatisfiable;
+    }
+    ByteRange::Partial(range)
+}
@@ -0,0 +1,23 @@
+use std::sync::Arc;
+
+use axum::{
+    http::{header, StatusCode},
+    response::IntoResponse,
+    Extension,
+};
+
+use crate::{domain::StorageDetails, metrics::Metrics};
+
+#[tracing::instrument(name = "Render prometheus metrics", skip(metrics, storage_details))]
+pub async fn metrics(
+    metrics: Extension<Arc<Metrics>>,
+    storage_details: Extension<Arc<StorageDetails>>,
+) -> impl IntoResponse {
+    match metrics.render(&storage_details) {
+        Ok(body) => Ok(([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], body)),
+        Err(e) => {
+            tracing::error!("{:?}", e);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
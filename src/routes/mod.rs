@@ -1,5 +1,29 @@
+mod admin;
+mod archives;
+mod delta;
+mod events;
+mod files;
 mod health_check;
+mod metrics;
+mod ready;
+mod s3;
+mod search;
+mod thumbnails;
+mod trash;
 mod upload;
+mod webdav;
 
+pub use admin::*;
+pub use archives::*;
+pub use delta::*;
+pub use events::*;
+pub use files::*;
 pub use health_check::*;
+pub use metrics::*;
+pub use ready::*;
+pub use s3::*;
+pub use search::*;
+pub use thumbnails::*;
+pub use trash::*;
 pub use upload::*;
+pub use webdav::*;
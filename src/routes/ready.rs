@@ -0,0 +1,94 @@
+use std::{path::Path, sync::Arc};
+
+use axum::{http::StatusCode, response::IntoResponse, Extension, Json};
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::domain::StorageDetails;
+
+#[derive(Serialize)]
+pub struct ReadinessReport {
+    pub ready: bool,
+    pub checks: ReadinessChecks,
+}
+
+#[derive(Serialize)]
+pub struct ReadinessChecks {
+    pub storage_exists: CheckResult,
+    pub storage_writable: CheckResult,
+    pub free_space: CheckResult,
+}
+
+#[derive(Serialize)]
+pub struct CheckResult {
+    pub ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+}
+
+impl CheckResult {
+    fn passed() -> Self {
+        Self {
+            ok: true,
+            detail: None,
+        }
+    }
+
+    fn failed(detail: impl Into<String>) -> Self {
+        Self {
+            ok: false,
+            detail: Some(detail.into()),
+        }
+    }
+}
+
+#[tracing::instrument(name = "Check the readiness of the storage", skip(storage_details))]
+pub async fn ready(storage_details: Extension<Arc<StorageDetails>>) -> impl IntoResponse {
+    let checks = ReadinessChecks {
+        storage_exists: check_storage_exists(&storage_details.path).await,
+        storage_writable: check_storage_writable(&storage_details.path).await,
+        free_space: check_free_space(&storage_details),
+    };
+    let ready = checks.storage_exists.ok && checks.storage_writable.ok && checks.free_space.ok;
+
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        tracing::warn!("Storage is not ready");
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    (status, Json(ReadinessReport { ready, checks }))
+}
+
+async fn check_storage_exists(path: &str) -> CheckResult {
+    match tokio::fs::metadata(path).await {
+        Ok(metadata) if metadata.is_dir() => CheckResult::passed(),
+        Ok(_) => CheckResult::failed(format!("{} is not a directory", path)),
+        Err(e) => CheckResult::failed(format!("{}: {}", path, e)),
+    }
+}
+
+async fn check_storage_writable(path: &str) -> CheckResult {
+    let probe_path = Path::new(path).join(format!(".ready-probe-{}", Uuid::new_v4()));
+
+    if let Err(e) = tokio::fs::write(&probe_path, b"probe").await {
+        return CheckResult::failed(format!("Failed to write probe file: {}", e));
+    }
+
+    match tokio::fs::remove_file(&probe_path).await {
+        Ok(_) => CheckResult::passed(),
+        Err(e) => CheckResult::failed(format!("Failed to remove probe file: {}", e)),
+    }
+}
+
+fn check_free_space(storage_details: &StorageDetails) -> CheckResult {
+    match storage_details.space() {
+        Ok(space) if space.free >= storage_details.min_free_space_bytes => CheckResult::passed(),
+        Ok(space) => CheckResult::failed(format!(
+            "{} bytes free, {} required",
+            space.free, storage_details.min_free_space_bytes
+        )),
+        Err(e) => CheckResult::failed(format!("Failed to determine free space: {}", e)),
+    }
+}
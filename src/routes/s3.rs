@@ -0,0 +1,897 @@
+use std::{
+    fs::Metadata,
+    io,
+    io::ErrorKind,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Context;
+use axum::{
+    body::{Body, Bytes},
+    extract::{ConnectInfo, Path},
+    http::{header, HeaderMap, HeaderValue, Method, Request, StatusCode},
+    response::{IntoResponse, Response},
+    Extension,
+};
+use chrono::Utc;
+use futures::{stream::BoxStream, StreamExt, TryStreamExt};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    audit::AuditLog,
+    compression::{Codec, Compression},
+    content_types::ContentPolicy,
+    domain::{
+        etag, in_scope, leads_into_scope, Identity, OutOfScope, StorageDetails, INTERNAL_DIRECTORY,
+    },
+    events::Events,
+    index::Index,
+    metrics::Metrics,
+    routes::{
+        files::{move_to_trash, parse_body_options, stream_file},
+        upload::{store_body, BodyOptions, UploadContext, UploadStages},
+        FilesError, UploadError,
+    },
+    s3::{
+        decode_chunks, paginate, query_pairs, xml, Authenticator, ChunkSigner, MultipartError,
+        MultipartUploads, Payload, SignatureError, UploadInfo, MAX_KEYS, PREFIX,
+    },
+    scanning::Scanning,
+    trash::Trash,
+    validators::validate_relative_path,
+    versioning::Versioning,
+    webdav::{LockError, Locks},
+};
+
+const XML_CONTENT_TYPE: &str = "application/xml";
+const DECODED_CONTENT_LENGTH: &str = "x-amz-decoded-content-length";
+const COPY_SOURCE: &str = "x-amz-copy-source";
+/// Largest `CompleteMultipartUpload` body read, enough for every part an upload can have.
+const MAX_XML_BODY_SIZE: usize = 2 * 1024 * 1024;
+/// Query parameters of the supported operations. Any other asks for a subresource, such as
+/// `?acl` or `?tagging`, which is not.
+const KNOWN_PARAMETERS: [&str; 13] = [
+    "list-type",
+    "prefix",
+    "delimiter",
+    "max-keys",
+    "marker",
+    "continuation-token",
+    "start-after",
+    "encoding-type",
+    "fetch-owner",
+    "location",
+    "uploads",
+    "uploadId",
+    "partNumber",
+];
+
+#[derive(thiserror::Error, Debug)]
+pub enum S3Error {
+    #[error("The bucket {0} does not exist")]
+    NoSuchBucket(String),
+    #[error("The key {0} does not exist")]
+    NoSuchKey(String),
+    #[error(transparent)]
+    Multipart(#[from] MultipartError),
+    #[error("{0}")]
+    MalformedXml(String),
+    #[error("{0}")]
+    InvalidArgument(String),
+    #[error("{0}")]
+    ContentSha256Mismatch(String),
+    #[error("{0}")]
+    IncompleteBody(String),
+    #[error("{0}")]
+    PreconditionFailed(String),
+    #[error("{0}")]
+    AccessDenied(String),
+    #[error(transparent)]
+    Signature(#[from] SignatureError),
+    #[error(transparent)]
+    Lock(#[from] LockError),
+    #[error("{0}")]
+    NotImplemented(String),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl IntoResponse for S3Error {
+    fn into_response(self) -> Response {
+        let (status, code) = match self {
+            S3Error::NoSuchBucket(_) => (StatusCode::NOT_FOUND, "NoSuchBucket"),
+            S3Error::NoSuchKey(_) => (StatusCode::NOT_FOUND, "NoSuchKey"),
+            S3Error::Multipart(MultipartError::NoSuchUpload(_)) => {
+                (StatusCode::NOT_FOUND, "NoSuchUpload")
+            }
+            S3Error::Multipart(MultipartError::InvalidPart(_)) => {
+                (StatusCode::BAD_REQUEST, "InvalidPart")
+            }
+            S3Error::Multipart(MultipartError::InvalidPartOrder) => {
+                (StatusCode::BAD_REQUEST, "InvalidPartOrder")
+            }
+            S3Error::Multipart(MultipartError::ChecksumMismatch)
+            | S3Error::ContentSha256Mismatch(_) => {
+                (StatusCode::BAD_REQUEST, "XAmzContentSHA256Mismatch")
+            }
+            S3Error::MalformedXml(_) => (StatusCode::BAD_REQUEST, "MalformedXML"),
+            S3Error::InvalidArgument(_) => (StatusCode::BAD_REQUEST, "InvalidArgument"),
+            S3Error::IncompleteBody(_) => (StatusCode::BAD_REQUEST, "IncompleteBody"),
+            S3Error::PreconditionFailed(_) => {
+                (StatusCode::PRECONDITION_FAILED, "PreconditionFailed")
+            }
+            S3Error::AccessDenied(_)
+            | S3Error::Signature(SignatureError::Anonymous)
+            | S3Error::Signature(SignatureError::Expired) => {
+                (StatusCode::FORBIDDEN, "AccessDenied")
+            }
+            S3Error::Signature(SignatureError::Malformed(_)) => {
+                (StatusCode::BAD_REQUEST, "AuthorizationHeaderMalformed")
+            }
+            S3Error::Signature(SignatureError::UnknownAccessKey(_)) => {
+                (StatusCode::FORBIDDEN, "InvalidAccessKeyId")
+            }
+            S3Error::Signature(SignatureError::Mismatch) => {
+                (StatusCode::FORBIDDEN, "SignatureDoesNotMatch")
+            }
+            S3Error::Signature(SignatureError::TimeSkewed) => {
+                (StatusCode::FORBIDDEN, "RequestTimeTooSkewed")
+            }
+            S3Error::Lock(_) => (StatusCode::LOCKED, "Locked"),
+            S3Error::NotImplemented(_) => (StatusCode::NOT_IMPLEMENTED, "NotImplemented"),
+            S3Error::Multipart(MultipartError::Io(ref e)) => {
+                tracing::error!("{:?}", e);
+                (StatusCode::INTERNAL_SERVER_ERROR, "InternalError")
+            }
+            S3Error::UnexpectedError(ref e) => {
+                tracing::error!("{:?}", e);
+                (StatusCode::INTERNAL_SERVER_ERROR, "InternalError")
+            }
+        };
+
+        (
+            status,
+            [(header::CONTENT_TYPE, XML_CONTENT_TYPE)],
+            xml::error(code, &self.to_string()),
+        )
+            .into_response()
+    }
+}
+
+impl From<FilesError> for S3Error {
+    fn from(e: FilesError) -> Self {
+        match e {
+            FilesError::NotFound(path) => S3Error::NoSuchKey(path),
+            FilesError::Forbidden(message) => S3Error::AccessDenied(message),
+            FilesError::PreconditionFailed(message) => S3Error::PreconditionFailed(message),
+            FilesError::Conflict(message)
+            | FilesError::UnsupportedMediaType(message)
+            | FilesError::ValidationError(message) => S3Error::InvalidArgument(message),
+            FilesError::Lock(e) => S3Error::Lock(e),
+            FilesError::UnexpectedError(e) => S3Error::UnexpectedError(e),
+        }
+    }
+}
+
+impl From<OutOfScope> for S3Error {
+    fn from(e: OutOfScope) -> Self {
+        S3Error::AccessDenied(e.to_string())
+    }
+}
+
+impl From<UploadError> for S3Error {
+    fn from(e: UploadError) -> Self {
+        match e {
+            UploadError::ValidationError {
+                reason: "checksum_mismatch",
+                message,
+            } => S3Error::ContentSha256Mismatch(message),
+            UploadError::ValidationError { message, .. } => S3Error::InvalidArgument(message),
+            UploadError::Forbidden(message) => S3Error::AccessDenied(message),
+            UploadError::PreconditionFailed(message) => S3Error::PreconditionFailed(message),
+            UploadError::Lock(e) => S3Error::Lock(e),
+            UploadError::UnexpectedError(e) => S3Error::UnexpectedError(e),
+        }
+    }
+}
+
+/// Serves an S3-compatible API below `/s3`, path-style, buckets being the top-level directories
+/// of the storage root.
+#[tracing::instrument(
+    name = "S3 request handler",
+    skip(
+        client_addr,
+        authenticator,
+        uploads,
+        storage_details,
+        metrics,
+        audit_log,
+        versioning,
+        trash,
+        events,
+        index,
+        compression,
+        content_policy,
+        scanning,
+        locks,
+        request
+    )
+)]
+// Every extractor is an argument of its own.
+#[allow(clippy::too_many_arguments)]
+pub async fn s3(
+    path: Option<Path<String>>,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    authenticator: Extension<Arc<Authenticator>>,
+    uploads: Extension<Arc<MultipartUploads>>,
+    storage_details: Extension<Arc<StorageDetails>>,
+    metrics: Extension<Arc<Metrics>>,
+    audit_log: Extension<Arc<AuditLog>>,
+    versioning: Extension<Arc<Versioning>>,
+    trash: Extension<Arc<Trash>>,
+    events: Extension<Arc<Events>>,
+    index: Extension<Arc<Index>>,
+    compression: Extension<Arc<Compression>>,
+    content_policy: Extension<Arc<ContentPolicy>>,
+    scanning: Extension<Arc<Scanning>>,
+    locks: Extension<Arc<Locks>>,
+    request: Request<Body>,
+) -> Result<Response, S3Error> {
+    let (parts, body) = request.into_parts();
+    let signed = authenticator.authenticate(&parts.method, &parts.uri, &parts.headers)?;
+
+    let path = path
+        .as_ref()
+        .map_or("", |Path(path)| path.trim_start_matches('/'));
+    let (bucket, key) = path.split_once('/').unwrap_or((path, ""));
+    if !bucket.is_empty() {
+        validate_relative_path(path).map_err(S3Error::InvalidArgument)?;
+    }
+    if !key.is_empty()
+        && key
+            .split('/')
+            .any(|segment| segment.is_empty() || segment == ".")
+    {
+        return Err(S3Error::InvalidArgument(format!(
+            "Unsupported key: {}",
+            key
+        )));
+    }
+    let query = query_pairs(parts.uri.query());
+    if let Some((name, _)) = query.iter().find(|(name, _)| {
+        !KNOWN_PARAMETERS.contains(&name.as_str())
+            && !name.starts_with("X-Amz-")
+            && !name.starts_with("response-")
+            && name != "x-id"
+    }) {
+        return Err(S3Error::NotImplemented(format!(
+            "The {} subresource is not supported",
+            name
+        )));
+    }
+
+    let mut request = S3Request {
+        bucket: bucket.to_string(),
+        key: key.to_string(),
+        query,
+        headers: parts.headers,
+        identity: signed.identity,
+        payload: signed.payload,
+        chunk_signer: signed.chunk_signer,
+        body_error: Arc::default(),
+        client_addr,
+        uploads: uploads.0,
+        storage_details: storage_details.0,
+        metrics: metrics.0,
+        audit_log: audit_log.0,
+        versioning: versioning.0,
+        trash: trash.0,
+        events: events.0,
+        index: index.0,
+        compression: compression.0,
+        content_policy: content_policy.0,
+        scanning: scanning.0,
+        locks: locks.0,
+    };
+    // Buckets on the way to the allowed paths can be listed, no object outside them touched.
+    match key.is_empty() {
+        true if leads_into_scope(bucket, request.identity.allowed_paths()) => {}
+        _ => request.identity.check_scope(&request.path_of(key))?,
+    }
+    let upload_id = request.parameter("uploadId").map(str::to_string);
+    match (&parts.method, bucket.is_empty(), key.is_empty()) {
+        (&Method::GET, true, _) => request.list_buckets().await,
+        (&Method::GET, false, true) if request.parameter("location").is_some() => {
+            request.require_bucket().await?;
+            Ok(xml_response(StatusCode::OK, xml::location_constraint()))
+        }
+        (&Method::GET, false, true) => request.list_objects().await,
+        (&Method::HEAD, false, true) => {
+            request.require_bucket().await?;
+            Ok(StatusCode::OK.into_response())
+        }
+        (&Method::GET | &Method::HEAD, false, false) if upload_id.is_none() => {
+            request.get_object().await
+        }
+        (&Method::PUT, false, false) => match upload_id {
+            Some(upload_id) => request.upload_part(&upload_id, body).await,
+            None => request.put_object(body).await,
+        },
+        (&Method::POST, false, false) if request.parameter("uploads").is_some() => {
+            request.create_multipart_upload().await
+        }
+        (&Method::POST, false, false) if upload_id.is_some() => {
+            request
+                .complete_multipart_upload(&upload_id.unwrap_or_default(), body)
+                .await
+        }
+        (&Method::DELETE, false, false) => match upload_id {
+            Some(upload_id) => request.abort_multipart_upload(&upload_id).await,
+            None => request.delete_object().await,
+        },
+        (method, _, _) => Err(S3Error::NotImplemented(format!(
+            "{} {} is not supported",
+            method,
+            parts.uri.path()
+        ))),
+    }
+}
+
+/// An S3 request on a bucket or an object, with what its operations act on.
+struct S3Request {
+    bucket: String,
+    key: String,
+    query: Vec<(String, String)>,
+    headers: HeaderMap,
+    identity: Identity,
+    payload: Payload,
+    chunk_signer: Option<ChunkSigner>,
+    /// What ended an `aws-chunked` body early, which the upload stages only see as a failed read.
+    body_error: Arc<Mutex<Option<(ErrorKind, String)>>>,
+    client_addr: SocketAddr,
+    uploads: Arc<MultipartUploads>,
+    storage_details: Arc<StorageDetails>,
+    metrics: Arc<Metrics>,
+    audit_log: Arc<AuditLog>,
+    versioning: Arc<Versioning>,
+    trash: Arc<Trash>,
+    events: Arc<Events>,
+    index: Arc<Index>,
+    compression: Arc<Compression>,
+    content_policy: Arc<ContentPolicy>,
+    scanning: Arc<Scanning>,
+    locks: Arc<Locks>,
+}
+
+impl S3Request {
+    async fn list_buckets(&self) -> Result<Response, S3Error> {
+        let scope = self.identity.allowed_paths();
+        let mut buckets = vec![];
+        let mut entries = tokio::fs::read_dir(&self.storage_details.path)
+            .await
+            .context("Failed to read storage root")?;
+        while let Some(entry) = entries
+            .next_entry()
+            .await
+            .context("Failed to read storage root")?
+        {
+            let Ok(name) = entry.file_name().into_string() else {
+                continue;
+            };
+            let file_type = entry.file_type().await.context("Failed to read entry")?;
+            if !file_type.is_dir() || name == INTERNAL_DIRECTORY || !leads_into_scope(&name, scope)
+            {
+                continue;
+            }
+            let metadata = entry.metadata().await.context("Failed to read entry")?;
+            let modified_at = metadata.modified().context("No modification time")?;
+            buckets.push(xml::Bucket {
+                name,
+                created_at: metadata.created().unwrap_or(modified_at),
+            });
+        }
+        buckets.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(xml_response(
+            StatusCode::OK,
+            xml::list_buckets(self.identity.name(), &buckets),
+        ))
+    }
+
+    /// Lists the objects of the bucket, `ListObjectsV2` with `list-type=2` and the original
+    /// `ListObjects` otherwise.
+    async fn list_objects(&self) -> Result<Response, S3Error> {
+        self.require_bucket().await?;
+        let prefix = self.parameter("prefix").unwrap_or_default().to_string();
+        let delimiter = self.parameter("delimiter").map(str::to_string);
+        let max_keys = match self.parameter("max-keys") {
+            Some(max_keys) => max_keys
+                .parse::<usize>()
+                .map_err(|_| S3Error::InvalidArgument(format!("Invalid max-keys: {}", max_keys)))?
+                .min(MAX_KEYS),
+            None => MAX_KEYS,
+        };
+        let version = match self.parameter("list-type") {
+            Some("2") => xml::ListVersion::V2 {
+                continuation_token: self.parameter("continuation-token").map(str::to_string),
+                start_after: self.parameter("start-after").map(str::to_string),
+            },
+            Some(list_type) => {
+                return Err(S3Error::InvalidArgument(format!(
+                    "Invalid list-type: {}",
+                    list_type
+                )))
+            }
+            None => xml::ListVersion::V1 {
+                marker: self.parameter("marker").map(str::to_string),
+            },
+        };
+        let start_after = match &version {
+            xml::ListVersion::V1 { marker } => marker.clone(),
+            xml::ListVersion::V2 {
+                continuation_token: Some(token),
+                ..
+            } => Some(xml::continuation_key(token).ok_or_else(|| {
+                S3Error::InvalidArgument("Invalid continuation-token".to_string())
+            })?),
+            xml::ListVersion::V2 { start_after, .. } => start_after.clone(),
+        };
+
+        let entries = self.walk(&prefix).await?;
+        let page = paginate(
+            entries,
+            &prefix,
+            delimiter.as_deref(),
+            start_after.as_deref(),
+            max_keys,
+        );
+        let mut objects = vec![];
+        for (key, metadata) in page.objects {
+            let path = self.path_of(&key);
+            let codec = self
+                .index
+                .codec(&path)
+                .await
+                .context("Failed to read metadata index")?;
+            let file_path = self.storage_details.file_path(&path);
+            let size = match self.compression.content_size(&file_path, codec).await {
+                Ok(size) => size,
+                // Removed since the bucket was walked.
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(anyhow::Error::new(e).context("Failed to read file").into()),
+            };
+            objects.push(xml::Object {
+                key,
+                size,
+                modified_at: metadata.modified().context("No modification time")?,
+                etag: etag(&metadata),
+            });
+        }
+
+        let listing = xml::Listing {
+            objects,
+            common_prefixes: page.common_prefixes,
+            is_truncated: page.is_truncated,
+            last: page.last,
+        };
+        let parameters = xml::ListParameters {
+            bucket: self.bucket.clone(),
+            prefix,
+            delimiter,
+            max_keys,
+            url_encoded: self.parameter("encoding-type") == Some("url"),
+            version,
+        };
+        Ok(xml_response(
+            StatusCode::OK,
+            xml::list_objects(&parameters, &listing),
+        ))
+    }
+
+    async fn get_object(&self) -> Result<Response, S3Error> {
+        self.require_bucket().await?;
+        let path = self.path_of(&self.key);
+        let file_path = self.storage_details.file_path(&path);
+        let record = self
+            .index
+            .get(&path)
+            .await
+            .context("Failed to read metadata index")?;
+        let codec = record
+            .as_ref()
+            .and_then(|record| record.codec.as_deref())
+            .map(Codec::parse)
+            .transpose()
+            .context("Failed to read metadata index")?;
+        let mut response = stream_file(
+            &file_path,
+            codec,
+            &self.key,
+            &self.headers,
+            &self.metrics,
+            &self.compression,
+        )
+        .await?;
+
+        let content_type = record
+            .and_then(|record| record.content_type)
+            .unwrap_or_else(|| mime::APPLICATION_OCTET_STREAM.to_string());
+        if let Ok(content_type) = HeaderValue::from_str(&content_type) {
+            response
+                .headers_mut()
+                .insert(header::CONTENT_TYPE, content_type);
+        }
+        Ok(response)
+    }
+
+    async fn put_object(&mut self, body: Body) -> Result<Response, S3Error> {
+        if self.headers.contains_key(COPY_SOURCE) {
+            return Err(S3Error::NotImplemented(
+                "CopyObject is not supported".to_string(),
+            ));
+        }
+        self.require_bucket().await?;
+        self.require_writable().await?;
+
+        let mut options = parse_body_options(&self.headers)?;
+        options.declared_type = options.declared_type.filter(|declared_type| {
+            !matches!(
+                declared_type.essence_str(),
+                "application/octet-stream" | "binary/octet-stream"
+            )
+        });
+        let body = self.body_stream(body, &mut options)?;
+        if let Err(e) = self.store(body, &options).await {
+            return Err(self.body_failure(e));
+        }
+
+        let metadata =
+            tokio::fs::metadata(self.storage_details.file_path(&self.path_of(&self.key)))
+                .await
+                .context("Failed to read stored file")?;
+        Ok((StatusCode::OK, [(header::ETAG, etag(&metadata))]).into_response())
+    }
+
+    async fn delete_object(&self) -> Result<Response, S3Error> {
+        self.require_bucket().await?;
+        let path = self.path_of(&self.key);
+        // Deleting what is not there succeeds, and directories are not objects.
+        if self.metadata(&path).await?.is_some_and(|m| m.is_file()) {
+            move_to_trash(
+                &path,
+                &self.identity,
+                self.client_addr,
+                &self.trash,
+                &self.locks,
+                &[],
+                &self.audit_log,
+                &self.events,
+            )
+            .await?;
+        }
+        Ok(StatusCode::NO_CONTENT.into_response())
+    }
+
+    async fn create_multipart_upload(&self) -> Result<Response, S3Error> {
+        self.require_bucket().await?;
+        self.require_writable().await?;
+        let content_type = self
+            .headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .filter(|content_type| {
+                !matches!(
+                    *content_type,
+                    "application/octet-stream" | "binary/octet-stream"
+                )
+            })
+            .map(str::to_string);
+
+        let upload_id = self
+            .uploads
+            .create(&UploadInfo {
+                bucket: self.bucket.clone(),
+                key: self.key.clone(),
+                initiator: self.identity.name().to_string(),
+                content_type,
+                initiated_at: Utc::now(),
+            })
+            .await
+            .context("Failed to create multipart upload")?;
+        Ok(xml_response(
+            StatusCode::OK,
+            xml::initiate_multipart_upload(&self.bucket, &self.key, &upload_id),
+        ))
+    }
+
+    async fn upload_part(&mut self, upload_id: &str, body: Body) -> Result<Response, S3Error> {
+        self.require_upload(upload_id).await?;
+        let part_number = self
+            .parameter("partNumber")
+            .and_then(|number| number.parse().ok())
+            .ok_or_else(|| S3Error::InvalidArgument("Invalid partNumber".to_string()))?;
+
+        let mut options = BodyOptions::default();
+        let body = self.body_stream(body, &mut options)?;
+        let etag = self
+            .uploads
+            .write_part(
+                upload_id,
+                part_number,
+                body,
+                options.expected_checksum.as_deref(),
+            )
+            .await
+            .map_err(|e| self.body_failure(e.into()))?;
+        Ok((StatusCode::OK, [(header::ETAG, etag)]).into_response())
+    }
+
+    async fn complete_multipart_upload(
+        &mut self,
+        upload_id: &str,
+        body: Body,
+    ) -> Result<Response, S3Error> {
+        let info = self.require_upload(upload_id).await?;
+        let body = self.read_xml_body(body).await?;
+        let parts = xml::parse_complete_multipart_upload(&body).map_err(S3Error::MalformedXml)?;
+        let paths = self.uploads.parts(upload_id, &parts).await?;
+        self.require_writable().await?;
+
+        let options = BodyOptions {
+            declared_type: info
+                .content_type
+                .and_then(|content_type| content_type.parse().ok()),
+            create_parents: true,
+            ..BodyOptions::default()
+        };
+        self.store(self.uploads.read_parts(paths).boxed(), &options)
+            .await?;
+        self.uploads.remove(upload_id).await?;
+
+        let path = self.path_of(&self.key);
+        let metadata = tokio::fs::metadata(self.storage_details.file_path(&path))
+            .await
+            .context("Failed to read stored file")?;
+        Ok(xml_response(
+            StatusCode::OK,
+            xml::complete_multipart_upload(
+                &format!("{}/{}", PREFIX, path),
+                &self.bucket,
+                &self.key,
+                &etag(&metadata),
+            ),
+        ))
+    }
+
+    async fn abort_multipart_upload(&self, upload_id: &str) -> Result<Response, S3Error> {
+        self.require_upload(upload_id).await?;
+        self.uploads.remove(upload_id).await?;
+        Ok(StatusCode::NO_CONTENT.into_response())
+    }
+
+    /// Stores a body as the object, going through the stages of every upload.
+    async fn store(
+        &self,
+        body: BoxStream<'static, Result<Bytes, io::Error>>,
+        options: &BodyOptions,
+    ) -> Result<(), S3Error> {
+        let context = UploadContext {
+            storage_details: &self.storage_details,
+            metrics: &self.metrics,
+            audit_log: &self.audit_log,
+            versioning: &self.versioning,
+            events: &self.events,
+            locks: &self.locks,
+            stages: UploadStages {
+                extraction: None,
+                compression: &self.compression,
+                content_policy: &self.content_policy,
+                scanning: &self.scanning,
+            },
+        };
+        store_body(
+            body,
+            &self.path_of(&self.key),
+            options,
+            &self.identity,
+            self.client_addr,
+            context,
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// The content of the request body, decoded from `aws-chunked` when sent so, with the size
+    /// and checksum it was announced with.
+    fn body_stream(
+        &mut self,
+        body: Body,
+        options: &mut BodyOptions,
+    ) -> Result<BoxStream<'static, Result<Bytes, io::Error>>, S3Error> {
+        options.create_parents = true;
+        if let Payload::Sha256(checksum) = &self.payload {
+            options.expected_checksum = Some(checksum.clone());
+        }
+        if !self.payload.is_chunked() {
+            return Ok(body.map_err(io::Error::other).boxed());
+        }
+
+        options.expected_size = self
+            .headers
+            .get(DECODED_CONTENT_LENGTH)
+            .map(|length| {
+                length
+                    .to_str()
+                    .ok()
+                    .and_then(|length| length.parse().ok())
+                    .ok_or_else(|| {
+                        S3Error::InvalidArgument(format!("Invalid {}", DECODED_CONTENT_LENGTH))
+                    })
+            })
+            .transpose()?;
+        let body_error = self.body_error.clone();
+        Ok(decode_chunks(body, self.chunk_signer.take())
+            .inspect_err(move |e| {
+                *body_error.lock().expect("Body error lock poisoned") =
+                    Some((e.kind(), e.to_string()))
+            })
+            .boxed())
+    }
+
+    /// The error to answer a failed write with, the one of the chunked body when it ended it.
+    fn body_failure(&self, e: S3Error) -> S3Error {
+        match self
+            .body_error
+            .lock()
+            .expect("Body error lock poisoned")
+            .take()
+        {
+            Some((ErrorKind::PermissionDenied, _)) => SignatureError::Mismatch.into(),
+            Some((_, message)) => S3Error::IncompleteBody(message),
+            None => e,
+        }
+    }
+
+    /// Walks the bucket for the files below `prefix` the caller may see, sorted by key.
+    async fn walk(&self, prefix: &str) -> Result<Vec<(String, Metadata)>, S3Error> {
+        let scope = self.identity.allowed_paths();
+        let mut files = vec![];
+        let mut directories = vec![String::new()];
+        while let Some(directory) = directories.pop() {
+            let directory_path = self.storage_details.file_path(&self.path_of(&directory));
+            let mut entries = match tokio::fs::read_dir(&directory_path).await {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => {
+                    return Err(anyhow::Error::new(e)
+                        .context("Failed to read directory")
+                        .into())
+                }
+            };
+            while let Some(entry) = entries
+                .next_entry()
+                .await
+                .context("Failed to read directory")?
+            {
+                let Ok(name) = entry.file_name().into_string() else {
+                    continue;
+                };
+                let key = match directory.as_str() {
+                    "" => name.clone(),
+                    directory => format!("{}/{}", directory, name),
+                };
+                if name == INTERNAL_DIRECTORY {
+                    continue;
+                }
+                // Symbolic links are neither files nor directories here, so they are skipped.
+                let file_type = entry.file_type().await.context("Failed to read entry")?;
+                let path = self.path_of(&key);
+                if file_type.is_dir() && leads_into_scope(&path, scope) {
+                    let directory_prefix = format!("{}/", key);
+                    if directory_prefix.starts_with(prefix) || prefix.starts_with(&directory_prefix)
+                    {
+                        directories.push(key);
+                    }
+                } else if file_type.is_file() && key.starts_with(prefix) && in_scope(&path, scope) {
+                    let metadata = entry.metadata().await.context("Failed to read entry")?;
+                    files.push((key, metadata));
+                }
+            }
+        }
+        files.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(files)
+    }
+
+    async fn require_bucket(&self) -> Result<(), S3Error> {
+        match self.metadata(&self.bucket).await? {
+            Some(metadata) if metadata.is_dir() => Ok(()),
+            _ => Err(S3Error::NoSuchBucket(self.bucket.clone())),
+        }
+    }
+
+    /// An object can be written where there is no directory, and no file on the way to it.
+    async fn require_writable(&self) -> Result<(), S3Error> {
+        let path = self.path_of(&self.key);
+        if self.metadata(&path).await?.is_some_and(|m| m.is_dir()) {
+            return Err(S3Error::InvalidArgument(format!(
+                "{} is a directory",
+                self.key
+            )));
+        }
+        let mut ancestor = path.as_str();
+        while let Some((parent, _)) = ancestor.rsplit_once('/') {
+            if self.metadata(parent).await?.is_some_and(|m| m.is_file()) {
+                return Err(S3Error::InvalidArgument(format!(
+                    "{} is an object, not a directory",
+                    parent
+                )));
+            }
+            ancestor = parent;
+        }
+        Ok(())
+    }
+
+    /// The multipart upload `upload_id`, which must be of this object and the caller's.
+    async fn require_upload(&self, upload_id: &str) -> Result<UploadInfo, S3Error> {
+        let info = self.uploads.info(upload_id).await?;
+        if info.bucket != self.bucket || info.key != self.key {
+            return Err(MultipartError::NoSuchUpload(upload_id.to_string()).into());
+        }
+        if info.initiator != self.identity.name() {
+            return Err(S3Error::AccessDenied(format!(
+                "The multipart upload {} was started by someone else",
+                upload_id
+            )));
+        }
+        Ok(info)
+    }
+
+    /// Reads a small XML body, checking it against the checksum it was signed with.
+    async fn read_xml_body(&self, mut body: Body) -> Result<Vec<u8>, S3Error> {
+        let mut bytes = vec![];
+        while let Some(chunk) = body.next().await {
+            let chunk = chunk.context("Failed to read request body")?;
+            if bytes.len() + chunk.len() > MAX_XML_BODY_SIZE {
+                return Err(S3Error::InvalidArgument(
+                    "Request body is too large".to_string(),
+                ));
+            }
+            bytes.extend_from_slice(&chunk);
+        }
+        if let Payload::Sha256(checksum) = &self.payload {
+            if hex::encode(Sha256::digest(&bytes)) != *checksum {
+                return Err(S3Error::ContentSha256Mismatch(
+                    "The body does not have the checksum it was sent with".to_string(),
+                ));
+            }
+        }
+        Ok(bytes)
+    }
+
+    async fn metadata(&self, path: &str) -> Result<Option<Metadata>, S3Error> {
+        match tokio::fs::metadata(self.storage_details.file_path(path)).await {
+            Ok(metadata) => Ok(Some(metadata)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(anyhow::Error::new(e)
+                .context("Failed to read metadata")
+                .into()),
+        }
+    }
+
+    /// The storage path of a key of the bucket.
+    fn path_of(&self, key: &str) -> String {
+        match key {
+            "" => self.bucket.clone(),
+            key => format!("{}/{}", self.bucket, key),
+        }
+    }
+
+    fn parameter(&self, name: &str) -> Option<&str> {
+        crate::s3::query_value(&self.query, name)
+    }
+}
+
+fn xml_response(status: StatusCode, body: String) -> Response {
+    (status, [(header::CONTENT_TYPE, XML_CONTENT_TYPE)], body).into_response()
+}
@@ -0,0 +1,141 @@
+use std::{collections::HashMap, str::FromStr, sync::Arc};
+
+use anyhow::Context;
+use axum::{extract::Query, Extension, Json};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    domain::Identity,
+    index::{FileRecord, Index, NameMatch, SearchFilter, TextMatch},
+    routes::FilesError,
+    validators::validate_metadata_key,
+};
+
+const DEFAULT_LIMIT: usize = 100;
+const MAX_LIMIT: usize = 1000;
+
+#[derive(Serialize)]
+pub struct SearchResults {
+    pub files: Vec<FileRecord>,
+    /// Pass back to get the next page, absent on the last one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cursor: Option<String>,
+    pub has_more: bool,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TextSearchQuery {
+    q: String,
+    offset: Option<usize>,
+    limit: Option<usize>,
+}
+
+#[derive(Serialize)]
+pub struct TextSearchResults {
+    pub matches: Vec<TextMatch>,
+    pub has_more: bool,
+}
+
+/// Searches the indexed files the caller may see. `q` matches file names, as a glob when it
+/// contains `*`, `?` or `[` and as a case-insensitive substring otherwise. `tag`,
+/// `metadata.<key>`, `min_size`, `max_size`, `modified_after`, `modified_before` (RFC 3339) and
+/// `content_type` (`type/*` for any subtype) narrow the results down.
+#[tracing::instrument(name = "Search request handler", skip(index))]
+pub async fn search(
+    Query(query): Query<HashMap<String, String>>,
+    identity: Identity,
+    index: Extension<Arc<Index>>,
+) -> Result<Json<SearchResults>, FilesError> {
+    let mut filter = SearchFilter {
+        scope: identity.allowed_paths().map(<[String]>::to_vec),
+        limit: DEFAULT_LIMIT,
+        ..SearchFilter::default()
+    };
+    for (name, value) in query {
+        match name.as_str() {
+            "q" if value.contains(['*', '?', '[']) => filter.name = Some(NameMatch::Glob(value)),
+            "q" => filter.name = Some(NameMatch::Substring(value)),
+            "tag" => filter.tag = Some(value),
+            "min_size" => filter.min_size = Some(parse(&name, &value)?),
+            "max_size" => filter.max_size = Some(parse(&name, &value)?),
+            "modified_after" => filter.modified_after = Some(parse_time(&name, &value)?),
+            "modified_before" => filter.modified_before = Some(parse_time(&name, &value)?),
+            "content_type" => filter.content_type = Some(value),
+            "cursor" => filter.after = Some(value),
+            "limit" => filter.limit = parse::<usize>(&name, &value)?.clamp(1, MAX_LIMIT),
+            _ => match name.strip_prefix("metadata.") {
+                Some(key) => {
+                    validate_metadata_key(key).map_err(FilesError::ValidationError)?;
+                    filter.metadata.push((key.to_string(), value));
+                }
+                None => {
+                    return Err(FilesError::ValidationError(format!(
+                        "Unexpected query parameter: {}",
+                        name
+                    )))
+                }
+            },
+        }
+    }
+
+    // One more than asked for tells whether there is more to come.
+    let limit = filter.limit;
+    filter.limit += 1;
+    let mut files = index.search(filter).await.context("Failed to search")?;
+    let has_more = files.len() > limit;
+    files.truncate(limit);
+
+    Ok(Json(SearchResults {
+        cursor: has_more
+            .then(|| files.last().map(|file| file.path.clone()))
+            .flatten(),
+        files,
+        has_more,
+    }))
+}
+
+/// Ranks the text files the caller may see by how well their content matches every word of `q`.
+#[tracing::instrument(name = "Full-text search request handler", skip(index))]
+pub async fn search_text(
+    Query(query): Query<TextSearchQuery>,
+    identity: Identity,
+    index: Extension<Arc<Index>>,
+) -> Result<Json<TextSearchResults>, FilesError> {
+    if !index.full_text_enabled() {
+        return Err(FilesError::NotFound("Full-text search".to_string()));
+    }
+    if query.q.trim().is_empty() {
+        return Err(FilesError::ValidationError(
+            "Expected words to search for".to_string(),
+        ));
+    }
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+
+    // One more than asked for tells whether there is more to come.
+    let mut matches = index
+        .search_text(
+            &query.q,
+            identity.allowed_paths().map(<[String]>::to_vec),
+            query.offset.unwrap_or_default(),
+            limit + 1,
+        )
+        .await
+        .context("Failed to search text")?;
+    let has_more = matches.len() > limit;
+    matches.truncate(limit);
+
+    Ok(Json(TextSearchResults { matches, has_more }))
+}
+
+fn parse<T: FromStr>(name: &str, value: &str) -> Result<T, FilesError> {
+    value
+        .parse()
+        .map_err(|_| FilesError::ValidationError(format!("Invalid {}: {}", name, value)))
+}
+
+fn parse_time(name: &str, value: &str) -> Result<DateTime<Utc>, FilesError> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|time| time.with_timezone(&Utc))
+        .map_err(|_| FilesError::ValidationError(format!("Invalid {}: {}", name, value)))
+}
@@ -0,0 +1,71 @@
+use std::{io::ErrorKind, sync::Arc};
+
+use axum::{
+    extract::{Path, Query},
+    http::header,
+    response::{IntoResponse, Response},
+    Extension,
+};
+use serde::Deserialize;
+
+use crate::{
+    domain::Identity,
+    routes::FilesError,
+    thumbnails::{ThumbnailError, Thumbnails},
+    validators::validate_relative_path,
+};
+
+#[derive(Deserialize, Debug)]
+pub struct ThumbnailQuery {
+    /// One of the configured sizes, the smallest when absent.
+    size: Option<u32>,
+}
+
+/// Serves the thumbnail of an image, generating it on a cache miss.
+#[tracing::instrument(name = "Get thumbnail request handler", skip(identity, thumbnails))]
+pub async fn get_thumbnail(
+    Path(path): Path<String>,
+    Query(query): Query<ThumbnailQuery>,
+    identity: Identity,
+    thumbnails: Extension<Arc<Thumbnails>>,
+) -> Result<Response, FilesError> {
+    let relative_path = path.trim_start_matches('/');
+    validate_relative_path(relative_path).map_err(FilesError::ValidationError)?;
+    identity.check_scope(relative_path)?;
+    let size = match query.size {
+        Some(size) if thumbnails.sizes().contains(&size) => size,
+        Some(size) => {
+            return Err(FilesError::ValidationError(format!(
+                "Unsupported thumbnail size {}, expected one of {:?}",
+                size,
+                thumbnails.sizes()
+            )))
+        }
+        None => thumbnails
+            .sizes()
+            .iter()
+            .copied()
+            .min()
+            .ok_or_else(|| FilesError::NotFound("Thumbnail size".to_string()))?,
+    };
+
+    let thumbnail = match thumbnails.get(relative_path, size).await {
+        Ok(thumbnail) => thumbnail,
+        Err(ThumbnailError::Io(e)) if e.kind() == ErrorKind::NotFound => {
+            return Err(FilesError::NotFound(relative_path.to_string()))
+        }
+        Err(e @ (ThumbnailError::Unsupported(_) | ThumbnailError::TooLarge(_))) => {
+            return Err(FilesError::UnsupportedMediaType(e.to_string()))
+        }
+        Err(ThumbnailError::Io(e)) => {
+            return Err(anyhow::Error::new(e)
+                .context("Failed to generate thumbnail")
+                .into())
+        }
+    };
+    Ok((
+        [(header::CONTENT_TYPE, thumbnail.content_type)],
+        thumbnail.contents,
+    )
+        .into_response())
+}
@@ -0,0 +1,141 @@
+use std::{io::ErrorKind, net::SocketAddr, sync::Arc};
+
+use anyhow::Context;
+use axum::{
+    extract::{ConnectInfo, Path},
+    Extension, Json,
+};
+use chrono::Utc;
+
+use crate::{
+    audit::{AuditAction, AuditLog, AuditRecord},
+    configuration::AuthSettings,
+    domain::Identity,
+    events::{Events, FileEvent, FileEventKind},
+    routes::FilesError,
+    trash::{Trash, TrashEntry},
+};
+
+/// Lists the entries deleted from paths the caller can access.
+#[tracing::instrument(name = "List trash request handler", skip(identity, trash))]
+pub async fn list_trash(
+    identity: Identity,
+    trash: Extension<Arc<Trash>>,
+) -> Result<Json<Vec<TrashEntry>>, FilesError> {
+    let mut entries = trash.list().await.context("Failed to list trash")?;
+    entries.retain(|entry| identity.can_access(&entry.original_path));
+    Ok(Json(entries))
+}
+
+#[tracing::instrument(
+    name = "Restore trash entry request handler",
+    skip(identity, client_addr, trash, audit_log, events)
+)]
+pub async fn restore_trash_entry(
+    Path(id): Path<String>,
+    identity: Identity,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    trash: Extension<Arc<Trash>>,
+    audit_log: Extension<Arc<AuditLog>>,
+    events: Extension<Arc<Events>>,
+) -> Result<Json<TrashEntry>, FilesError> {
+    match trash.entry(&id).await {
+        Ok(entry) => identity.check_scope(&entry.original_path)?,
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            return Err(FilesError::NotFound(format!("Trash entry {}", id)))
+        }
+        Err(e) => return Err(anyhow::Error::new(e).context("Failed to read entry").into()),
+    }
+    let entry = match trash.restore(&id).await {
+        Ok(entry) => entry,
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            return Err(FilesError::NotFound(format!("Trash entry {}", id)))
+        }
+        Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+            return Err(FilesError::Conflict(e.to_string()))
+        }
+        Err(e) => return Err(anyhow::Error::new(e).context("Failed to restore").into()),
+    };
+
+    record(
+        &audit_log,
+        AuditAction::Restore,
+        &identity,
+        client_addr,
+        &entry,
+    )
+    .await?;
+    events
+        .publish(&[FileEvent {
+            identity: Some(identity.name().to_string()),
+            overwritten: Some(false),
+            is_directory: Some(entry.is_directory),
+            size: entry.size,
+            codecs: entry
+                .codecs
+                .iter()
+                .map(|(path, codec)| (path.clone(), codec.as_str().to_string()))
+                .collect(),
+            ..FileEvent::new(FileEventKind::Restored, entry.original_path.clone())
+        }])
+        .await;
+    Ok(Json(entry))
+}
+
+/// Removes an entry for good, which only admins may do once API keys are configured.
+#[tracing::instrument(
+    name = "Purge trash entry request handler",
+    skip(identity, client_addr, trash, audit_log, auth)
+)]
+pub async fn purge_trash_entry(
+    Path(id): Path<String>,
+    identity: Identity,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    trash: Extension<Arc<Trash>>,
+    audit_log: Extension<Arc<AuditLog>>,
+    auth: Extension<Arc<AuthSettings>>,
+) -> Result<Json<TrashEntry>, FilesError> {
+    if !auth.api_keys.is_empty() && !identity.is_admin() {
+        return Err(FilesError::Forbidden("Admin access required".to_string()));
+    }
+
+    let entry = match trash.purge(&id).await {
+        Ok(entry) => entry,
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            return Err(FilesError::NotFound(format!("Trash entry {}", id)))
+        }
+        Err(e) => return Err(anyhow::Error::new(e).context("Failed to purge").into()),
+    };
+
+    record(
+        &audit_log,
+        AuditAction::Purge,
+        &identity,
+        client_addr,
+        &entry,
+    )
+    .await?;
+    Ok(Json(entry))
+}
+
+async fn record(
+    audit_log: &AuditLog,
+    action: AuditAction,
+    identity: &Identity,
+    client_addr: SocketAddr,
+    entry: &TrashEntry,
+) -> Result<(), FilesError> {
+    audit_log
+        .record(&[AuditRecord {
+            timestamp: Utc::now(),
+            action,
+            identity: identity.name().to_string(),
+            client_ip: Some(client_addr.ip()),
+            path: entry.original_path.clone(),
+            size: entry.size,
+            checksum: None,
+        }])
+        .await
+        .context("Failed to write audit log")?;
+    Ok(())
+}
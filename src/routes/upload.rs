@@ -1,31 +1,218 @@
 use anyhow::Context;
 use axum::{
     body::Bytes,
-    extract::{multipart::Field, Multipart},
-    http::StatusCode,
+    extract::{multipart::Field, ConnectInfo, Multipart, Query},
+    http::{HeaderMap, StatusCode},
     response::IntoResponse,
     BoxError, Extension,
 };
+use chrono::Utc;
 use futures::{Stream, TryStreamExt};
-use std::{io, sync::Arc};
-use tokio::{fs::File, io::BufWriter};
+use mime::Mime;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::{
+    io::{self, ErrorKind},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 use tokio_util::io::StreamReader;
+use uuid::Uuid;
 
-use crate::{domain::StorageDetails, validators::validate_file_name};
+use crate::{
+    audit::{AuditAction, AuditLog, AuditRecord},
+    compression::{Codec, Compression},
+    content_types::{self, ContentPolicy, SNIFF_LENGTH},
+    domain::{etag, Identity, OutOfScope, StorageDetails, WriteLocks},
+    encryption::Encryption,
+    events::{Events, FileEvent, FileEventKind},
+    extraction::{Extraction, ExtractionError},
+    index::{Index, MetadataPatch},
+    metrics::Metrics,
+    routes::webdav::IF,
+    scanning::{ScanError, Scanning},
+    validators::{
+        validate_file_name, validate_metadata_key, validate_metadata_value, validate_relative_path,
+        validate_tag,
+    },
+    versioning::{VersionInfo, Versioning},
+    webdav::{submitted_tokens, LockError, Locks},
+};
 
 #[derive(thiserror::Error, Debug)]
 pub enum UploadError {
+    #[error("{message}")]
+    ValidationError {
+        reason: &'static str,
+        message: String,
+    },
+    #[error("{0}")]
+    Forbidden(String),
     #[error("{0}")]
-    ValidationError(String),
+    PreconditionFailed(String),
+    #[error(transparent)]
+    Lock(#[from] LockError),
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
 
+impl From<OutOfScope> for UploadError {
+    fn from(e: OutOfScope) -> Self {
+        UploadError::Forbidden(e.to_string())
+    }
+}
+
+impl UploadError {
+    fn validation(reason: &'static str, message: impl Into<String>) -> Self {
+        UploadError::ValidationError {
+            reason,
+            message: message.into(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct UploadQuery {
+    /// Unpacks uploaded zip and tar archives into the target directory instead of storing
+    /// them.
+    #[serde(default)]
+    extract: bool,
+}
+
+/// A file being written by an upload, removed again if the upload fails.
+struct PendingFile {
+    relative_path: String,
+    file_path: String,
+    previous_version: Option<VersionInfo>,
+}
+
+/// A file received by an upload, waiting in the staging directory.
+struct StagedFile {
+    relative_path: String,
+    file_path: String,
+    staged_path: PathBuf,
+    size: u64,
+    checksum: String,
+    content_type: String,
+    codec: Option<Codec>,
+}
+
+/// The stages every received file goes through before it is moved into place.
+pub(super) struct UploadStages<'a> {
+    /// Set when uploaded archives are unpacked, their entries then going through the other
+    /// stages in their place.
+    pub extraction: Option<&'a Extraction>,
+    pub compression: &'a Compression,
+    pub content_policy: &'a ContentPolicy,
+    pub scanning: &'a Scanning,
+}
+
+/// What uploads other than the multipart form are stored with.
+pub(super) struct UploadContext<'a> {
+    pub storage_details: &'a StorageDetails,
+    pub metrics: &'a Metrics,
+    pub audit_log: &'a AuditLog,
+    pub versioning: &'a Versioning,
+    pub events: &'a Events,
+    pub locks: &'a Locks,
+    pub stages: UploadStages<'a>,
+}
+
+/// Conditions on the file a body upload replaces, from `If-Match` and `If-None-Match` headers.
+/// Each holds the entity tags listed, `*` standing for any existing file.
+#[derive(Default, Debug)]
+pub(super) struct Preconditions {
+    pub if_match: Option<Vec<String>>,
+    pub if_none_match: Option<Vec<String>>,
+}
+
+impl Preconditions {
+    /// Checks the conditions against the file currently at `file_path`.
+    async fn check(&self, file_path: &Path, relative_path: &str) -> Result<(), UploadError> {
+        if self.if_match.is_none() && self.if_none_match.is_none() {
+            return Ok(());
+        }
+        let current = match tokio::fs::metadata(file_path).await {
+            Ok(metadata) if metadata.is_file() => Some(etag(&metadata)),
+            Ok(_) => None,
+            Err(e) if e.kind() == ErrorKind::NotFound => None,
+            Err(e) => return Err(anyhow::Error::new(e).context("Failed to read file").into()),
+        };
+        let matches = |tags: &[String]| {
+            current
+                .as_ref()
+                .is_some_and(|current| tags.iter().any(|tag| tag == "*" || tag == current))
+        };
+
+        if self.if_match.as_deref().is_some_and(|tags| !matches(tags)) {
+            return Err(UploadError::PreconditionFailed(format!(
+                "{} does not match If-Match",
+                relative_path
+            )));
+        }
+        if self.if_none_match.as_deref().is_some_and(matches) {
+            return Err(UploadError::PreconditionFailed(format!(
+                "{} matches If-None-Match",
+                relative_path
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// What a request tells about the body it uploads.
+#[derive(Default, Debug)]
+pub(super) struct BodyOptions {
+    /// The `Content-Length` of the body, which the received bytes must add up to.
+    pub expected_size: Option<u64>,
+    /// The hex SHA-256 the client computed over the body, which the received bytes must have.
+    pub expected_checksum: Option<String>,
+    /// The `Content-Type` of the body, checked against the content policy and recorded when
+    /// the content itself does not give its type away.
+    pub declared_type: Option<Mime>,
+    pub preconditions: Preconditions,
+    /// Creates the missing directories leading to the file, rather than requiring them.
+    pub create_parents: bool,
+    /// Tokens of the WebDAV locks held on the file, submitted in the `If` header.
+    pub lock_tokens: Vec<String>,
+}
+
+/// What an upload writes into the storage, undone again if it fails.
+#[derive(Default)]
+struct PendingChanges {
+    files: Vec<PendingFile>,
+    /// Directories created for extracted files, in the order they were created.
+    directories: Vec<PathBuf>,
+}
+
+/// The files of an upload waiting in the staging directory, and the directories extracted
+/// archives hold, relative to the target directory.
+#[derive(Default)]
+struct Staged {
+    files: Vec<StagedFile>,
+    directories: Vec<String>,
+}
+
+/// A file written by an upload, with its path relative to the storage root.
+struct UploadedFile {
+    path: String,
+    size: u64,
+    checksum: String,
+    content_type: String,
+    codec: Option<Codec>,
+    overwritten: bool,
+    previous_version: Option<VersionInfo>,
+}
+
 impl IntoResponse for UploadError {
     fn into_response(self) -> axum::response::Response {
         let status = match self {
             UploadError::UnexpectedError(_) => StatusCode::INTERNAL_SERVER_ERROR,
-            UploadError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            UploadError::ValidationError { .. } => StatusCode::BAD_REQUEST,
+            UploadError::Forbidden(_) => StatusCode::FORBIDDEN,
+            UploadError::PreconditionFailed(_) => StatusCode::PRECONDITION_FAILED,
+            UploadError::Lock(_) => StatusCode::LOCKED,
         };
 
         (status, self.to_string()).into_response()
@@ -34,24 +221,180 @@ impl IntoResponse for UploadError {
 
 #[tracing::instrument(
     name = "Upload multipart form request handler",
-    skip(multipart, storage_details)
+    skip(
+        multipart,
+        query,
+        headers,
+        storage_details,
+        metrics,
+        audit_log,
+        versioning,
+        events,
+        index,
+        extraction,
+        compression,
+        content_policy,
+        scanning,
+        locks
+    )
 )]
+// Every extractor is an argument of its own.
+#[allow(clippy::too_many_arguments)]
 pub async fn upload(
     multipart: Multipart,
+    Query(query): Query<UploadQuery>,
+    headers: HeaderMap,
+    identity: Identity,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
     storage_details: Extension<Arc<StorageDetails>>,
+    metrics: Extension<Arc<Metrics>>,
+    audit_log: Extension<Arc<AuditLog>>,
+    versioning: Extension<Arc<Versioning>>,
+    events: Extension<Arc<Events>>,
+    index: Extension<Arc<Index>>,
+    extraction: Extension<Arc<Extraction>>,
+    compression: Extension<Arc<Compression>>,
+    content_policy: Extension<Arc<ContentPolicy>>,
+    scanning: Extension<Arc<Scanning>>,
+    locks: Extension<Arc<Locks>>,
 ) -> Result<(), UploadError> {
-    let mut uploaded_file_paths = vec![];
-    match handle_upload_process(multipart, storage_details, &mut uploaded_file_paths).await {
-        Ok(_) => Ok(()),
+    let _active_upload = metrics.start_upload();
+    let mut pending = PendingChanges::default();
+    let lock_tokens = submitted_tokens(headers.get(IF).and_then(|value| value.to_str().ok()));
+    let result = match handle_upload_process(
+        multipart,
+        &identity,
+        storage_details,
+        &metrics,
+        &versioning,
+        &locks,
+        &lock_tokens,
+        UploadStages {
+            extraction: query.extract.then_some(extraction.as_ref()),
+            compression: &compression,
+            content_policy: &content_policy,
+            scanning: &scanning,
+        },
+        &mut pending,
+    )
+    .await
+    {
+        Ok((uploaded_files, patch)) => {
+            let paths = uploaded_files
+                .iter()
+                .map(|file| file.path.clone())
+                .collect::<Vec<_>>();
+            let recorded =
+                record_upload(uploaded_files, &identity, client_addr, &audit_log, &events).await;
+            if recorded.is_ok() && !patch.is_empty() {
+                apply_metadata(&index, &paths, &patch).await;
+            }
+            recorded
+        }
+        Err(e) => Err(e),
+    };
+
+    finish_upload(result, &pending, &versioning, &metrics).await
+}
+
+/// Stores a request body as the file at `relative_path`, with the same stages, records and
+/// cleanup as multipart uploads. Returns whether an existing file was replaced.
+pub(super) async fn store_body<S, E>(
+    body: S,
+    relative_path: &str,
+    options: &BodyOptions,
+    identity: &Identity,
+    client_addr: SocketAddr,
+    context: UploadContext<'_>,
+) -> Result<bool, UploadError>
+where
+    S: Stream<Item = Result<Bytes, E>>,
+    E: Into<BoxError>,
+{
+    let _active_upload = context.metrics.start_upload();
+    let mut pending = PendingChanges::default();
+    let result =
+        match handle_body_upload(body, relative_path, options, &context, &mut pending).await {
+            Ok(uploaded_file) => {
+                let overwritten = uploaded_file.overwritten;
+                record_upload(
+                    vec![uploaded_file],
+                    identity,
+                    client_addr,
+                    context.audit_log,
+                    context.events,
+                )
+                .await
+                .map(|()| overwritten)
+            }
+            Err(e) => Err(e),
+        };
+
+    finish_upload(result, &pending, context.versioning, context.metrics).await
+}
+
+/// Records the files of a successful upload in the audit log, then publishes their events.
+async fn record_upload(
+    uploaded_files: Vec<UploadedFile>,
+    identity: &Identity,
+    client_addr: SocketAddr,
+    audit_log: &AuditLog,
+    events: &Events,
+) -> Result<(), UploadError> {
+    let timestamp = Utc::now();
+    let records = uploaded_files
+        .iter()
+        .map(|file| AuditRecord {
+            timestamp,
+            action: if file.overwritten {
+                AuditAction::Overwrite
+            } else {
+                AuditAction::Upload
+            },
+            identity: identity.name().to_string(),
+            client_ip: Some(client_addr.ip()),
+            path: file.path.clone(),
+            size: Some(file.size),
+            checksum: Some(file.checksum.clone()),
+        })
+        .collect::<Vec<_>>();
+    audit_log
+        .record(&records)
+        .await
+        .context("Failed to write audit log")?;
+
+    events.publish(&file_events(uploaded_files, identity)).await;
+    Ok(())
+}
+
+/// Logs and counts a failed upload and undoes what it wrote.
+async fn finish_upload<T>(
+    result: Result<T, UploadError>,
+    pending: &PendingChanges,
+    versioning: &Versioning,
+    metrics: &Metrics,
+) -> Result<T, UploadError> {
+    match result {
+        Ok(value) => Ok(value),
         Err(e) => {
             match &e {
-                UploadError::ValidationError(e) => tracing::warn!("{}", e),
+                UploadError::ValidationError { reason, message } => {
+                    tracing::warn!("{}", message);
+                    metrics.record_validation_rejection(reason);
+                }
+                UploadError::Forbidden(message) | UploadError::PreconditionFailed(message) => {
+                    tracing::warn!("{}", message)
+                }
+                UploadError::Lock(e) => tracing::warn!("{}", e),
                 UploadError::UnexpectedError(e) => tracing::error!("{:?}", e),
             }
 
-            cleanup_failed_files(&uploaded_file_paths)
-                .await
-                .context("Cleanup failed")?;
+            if let Err(cleanup_error) = cleanup_failed_files(pending, versioning).await {
+                metrics.cleanup_failures_total.inc();
+                return Err(UploadError::UnexpectedError(
+                    anyhow::Error::new(cleanup_error).context("Cleanup failed"),
+                ));
+            }
             Err(e)
         }
     }
@@ -59,64 +402,599 @@ pub async fn upload(
 
 #[tracing::instrument(
     name = "Handle upload process",
-    skip(multipart, storage_details, uploaded_file_paths)
+    skip(
+        multipart,
+        identity,
+        storage_details,
+        metrics,
+        versioning,
+        locks,
+        stages,
+        pending
+    )
 )]
+#[allow(clippy::too_many_arguments)]
 async fn handle_upload_process(
     mut multipart: Multipart,
+    identity: &Identity,
     storage_details: Extension<Arc<StorageDetails>>,
-    uploaded_file_paths: &mut Vec<String>,
-) -> Result<(), UploadError> {
+    metrics: &Metrics,
+    versioning: &Versioning,
+    locks: &Locks,
+    lock_tokens: &[String],
+    stages: UploadStages<'_>,
+    pending: &mut PendingChanges,
+) -> Result<(Vec<UploadedFile>, MetadataPatch), UploadError> {
+    let mut uploaded_files = vec![];
+    let mut patch = MetadataPatch::default();
     if let Some(path_field) = get_multipart_field(&mut multipart).await? {
         if path_field.name().context("No field name")? != "relative_path" {
-            return Err(UploadError::ValidationError(
-                "Expected field name 'relative_path'".to_string(),
+            return Err(UploadError::validation(
+                "missing_relative_path",
+                "Expected field name 'relative_path'",
             ));
         };
 
         let relative_path = path_field.text().await.context("Failed to get text")?;
+        let relative_path = relative_path.trim_start_matches('/');
+        validate_relative_path(relative_path)
+            .map_err(|e| UploadError::validation("invalid_relative_path", e))?;
+        identity.check_scope(relative_path)?;
         let base_path = format!("{}/{}", storage_details.path, relative_path);
-        let mut uploaded_files = false;
 
-        while let Some(field) = get_multipart_field(&mut multipart).await? {
-            uploaded_files = true;
-            let file_name = field.file_name().context("Failed to get file name")?;
-            validate_file_name(file_name).map_err(UploadError::ValidationError)?;
+        let staging_directory = create_staging_directory(&storage_details).await?;
+        let staged = stage_files(
+            &mut multipart,
+            &staging_directory,
+            relative_path,
+            &base_path,
+            metrics,
+            &stages,
+            &mut patch,
+        )
+        .await;
+        let committed = match staged {
+            Ok(staged) => match check_locks(&staged, locks, lock_tokens) {
+                Ok(()) => {
+                    let base_path = Path::new(&base_path);
+                    commit_files(
+                        staged,
+                        base_path,
+                        versioning,
+                        &storage_details.write_locks,
+                        &Preconditions::default(),
+                        pending,
+                        &mut uploaded_files,
+                    )
+                    .await
+                }
+                Err(e) => Err(e),
+            },
+            Err(e) => Err(e),
+        };
+        remove_staging_directory(&staging_directory).await;
+        committed?;
+
+        if uploaded_files.is_empty() {
+            return Err(UploadError::validation(
+                "no_files",
+                "No files were in the multipart form",
+            ));
+        }
+    }
+    Ok((uploaded_files, patch))
+}
 
-            let file_path = format!("{}/{}", base_path, file_name);
-            uploaded_file_paths.push(file_path.clone());
-            stream_to_file(&file_path, field)
+#[tracing::instrument(name = "Handle body upload", skip(body, context, pending))]
+async fn handle_body_upload<S, E>(
+    body: S,
+    relative_path: &str,
+    options: &BodyOptions,
+    context: &UploadContext<'_>,
+    pending: &mut PendingChanges,
+) -> Result<UploadedFile, UploadError>
+where
+    S: Stream<Item = Result<Bytes, E>>,
+    E: Into<BoxError>,
+{
+    validate_relative_path(relative_path)
+        .map_err(|e| UploadError::validation("invalid_relative_path", e))?;
+    let file_name = relative_path.rsplit('/').next().unwrap_or_default();
+    let file_path = context.storage_details.file_path(relative_path);
+    let base_path = match options.create_parents {
+        true => Path::new(&context.storage_details.path),
+        false => file_path.parent().context("No parent directory")?,
+    };
+    // Checked up front to spare receiving a body that cannot be stored, and again while the file
+    // is replaced.
+    context.locks.check(relative_path, &options.lock_tokens)?;
+    options
+        .preconditions
+        .check(&file_path, relative_path)
+        .await?;
+    if let Some(declared_type) = &options.declared_type {
+        context
+            .stages
+            .content_policy
+            .check_type(file_name, declared_type)
+            .map_err(|e| UploadError::validation("disallowed_content_type", e))?;
+    }
+
+    let staging_directory = create_staging_directory(context.storage_details).await?;
+    let staged_path = staging_directory.join("0");
+    let staged = match receive_file(
+        body,
+        &staged_path,
+        file_name,
+        context.metrics,
+        &context.stages,
+    )
+    .await
+    {
+        Ok((size, _, _))
+            if options
+                .expected_size
+                .is_some_and(|expected| expected != size) =>
+        {
+            Err(UploadError::validation(
+                "incomplete_body",
+                format!(
+                    "Received {} bytes instead of the {} announced by Content-Length",
+                    size,
+                    options.expected_size.unwrap_or_default()
+                ),
+            ))
+        }
+        Ok((_, checksum, _))
+            if options
+                .expected_checksum
+                .as_ref()
+                .is_some_and(|expected| *expected != checksum) =>
+        {
+            Err(UploadError::validation(
+                "checksum_mismatch",
+                "The body does not have the checksum it was sent with",
+            ))
+        }
+        Ok((size, checksum, mut content_type)) => {
+            if content_type == mime::APPLICATION_OCTET_STREAM {
+                if let Some(declared_type) = &options.declared_type {
+                    content_type = declared_type.clone();
+                }
+            }
+            let mut staged = Staged::default();
+            staged.files.push(StagedFile {
+                relative_path: relative_path.to_string(),
+                file_path: file_path.to_string_lossy().into_owned(),
+                staged_path,
+                size,
+                checksum,
+                content_type: content_type.essence_str().to_string(),
+                codec: None,
+            });
+            scan_and_compress(&mut staged, &context.stages)
                 .await
-                .context("Failed to save file")?;
+                .map(|()| staged)
         }
+        Err(e) => Err(e),
+    };
+    let mut uploaded_files = vec![];
+    let committed = match staged {
+        Ok(staged) => match check_locks(&staged, context.locks, &options.lock_tokens) {
+            Ok(()) => {
+                commit_files(
+                    staged,
+                    base_path,
+                    context.versioning,
+                    &context.storage_details.write_locks,
+                    &options.preconditions,
+                    pending,
+                    &mut uploaded_files,
+                )
+                .await
+            }
+            Err(e) => Err(e),
+        },
+        Err(e) => Err(e),
+    };
+    remove_staging_directory(&staging_directory).await;
+    committed?;
 
-        if !uploaded_files {
-            return Err(UploadError::ValidationError(
-                "No files were in the multipart form".to_string(),
-            ));
+    uploaded_files
+        .pop()
+        .context("No file was uploaded")
+        .map_err(UploadError::from)
+}
+
+/// Creates a staging directory of its own for an upload. Files are written there and only moved
+/// into place once every file of the upload has been received and scanned.
+async fn create_staging_directory(
+    storage_details: &StorageDetails,
+) -> Result<PathBuf, UploadError> {
+    let staging_directory = storage_details
+        .internal_path("staging")
+        .join(Uuid::new_v4().simple().to_string());
+    tokio::fs::create_dir_all(&staging_directory)
+        .await
+        .context("Failed to create staging directory")?;
+    Ok(staging_directory)
+}
+
+/// Removes a staging directory once the upload is over. Anything left in it belongs to a failed
+/// upload.
+async fn remove_staging_directory(staging_directory: &Path) {
+    if let Err(e) = tokio::fs::remove_dir_all(staging_directory).await {
+        tracing::warn!("Failed to remove staging directory: {:?}", e);
+    }
+}
+
+/// Receives the files of the form into the staging directory, checking their names and
+/// content, then scans them. Archives are replaced by their entries when extracting.
+async fn stage_files(
+    multipart: &mut Multipart,
+    staging_directory: &Path,
+    relative_path: &str,
+    base_path: &str,
+    metrics: &Metrics,
+    stages: &UploadStages<'_>,
+    patch: &mut MetadataPatch,
+) -> Result<Staged, UploadError> {
+    let mut staged = Staged::default();
+    let staged_files = &mut staged.files;
+    while let Some(field) = get_multipart_field(multipart).await? {
+        let file_name = match field.file_name() {
+            Some(file_name) => file_name.to_string(),
+            None => {
+                read_metadata_field(field, patch).await?;
+                continue;
+            }
+        };
+        let staged_path = staging_directory.join(staged_files.len().to_string());
+        let (size, checksum, content_type) =
+            receive_file(field, &staged_path, &file_name, metrics, stages).await?;
+
+        if let Some(extraction) = stages.extraction {
+            let extracted = extraction
+                .extract(&staged_path, &file_name, &content_type)
+                .await
+                .map_err(|e| match e {
+                    ExtractionError::NotAnArchive(message) => {
+                        UploadError::validation("not_an_archive", message)
+                    }
+                    ExtractionError::InvalidArchive(message) => {
+                        UploadError::validation("invalid_archive", message)
+                    }
+                    ExtractionError::UnsafeEntry(message) => {
+                        UploadError::validation("unsafe_archive_entry", message)
+                    }
+                    ExtractionError::TooLarge(message) => {
+                        UploadError::validation("archive_too_large", message)
+                    }
+                    ExtractionError::Io(e) => anyhow::Error::new(e)
+                        .context("Failed to extract archive")
+                        .into(),
+                })?;
+            tokio::fs::remove_file(&staged_path)
+                .await
+                .context("Failed to remove extracted archive")?;
+
+            for file in extracted.files {
+                let entry_path = Path::new(relative_path).join(&file.name);
+                let entry_path = entry_path.to_string_lossy();
+                validate_relative_path(&entry_path)
+                    .map_err(|e| UploadError::validation("unsafe_archive_entry", e))?;
+                let entry_file_name = file.name.rsplit('/').next().unwrap_or_default();
+                stages
+                    .content_policy
+                    .check_extension(entry_file_name)
+                    .map_err(|e| UploadError::validation("disallowed_extension", e))?;
+                let content_type = content_types::detect(&file.head, entry_file_name);
+                stages
+                    .content_policy
+                    .check_type(entry_file_name, &content_type)
+                    .map_err(|e| UploadError::validation("disallowed_content_type", e))?;
+
+                staged_files.push(StagedFile {
+                    relative_path: entry_path.into_owned(),
+                    file_path: format!("{}/{}", base_path, file.name),
+                    staged_path: file.staged_path,
+                    size: file.size,
+                    checksum: file.checksum,
+                    content_type: content_type.essence_str().to_string(),
+                    codec: None,
+                });
+            }
+            for directory in extracted.directories {
+                validate_relative_path(
+                    &Path::new(relative_path).join(&directory).to_string_lossy(),
+                )
+                .map_err(|e| UploadError::validation("unsafe_archive_entry", e))?;
+                staged.directories.push(directory);
+            }
+            continue;
         }
+
+        staged_files.push(StagedFile {
+            relative_path: Path::new(relative_path)
+                .join(&file_name)
+                .to_string_lossy()
+                .into_owned(),
+            file_path: format!("{}/{}", base_path, file_name),
+            staged_path,
+            size,
+            checksum,
+            content_type: content_type.essence_str().to_string(),
+            codec: None,
+        });
     }
-    Ok(())
+
+    scan_and_compress(&mut staged, stages).await?;
+    Ok(staged)
 }
 
-async fn stream_to_file<S, E>(path: &str, stream: S) -> Result<(), io::Error>
+/// Checks the name of a file and writes it to `staged_path`, then checks its content type.
+/// Returns its size, checksum and content type.
+async fn receive_file<S, E>(
+    stream: S,
+    staged_path: &Path,
+    file_name: &str,
+    metrics: &Metrics,
+    stages: &UploadStages<'_>,
+) -> Result<(u64, String, Mime), UploadError>
 where
     S: Stream<Item = Result<Bytes, E>>,
     E: Into<BoxError>,
 {
-    // Convert the stream into an `AsyncRead`.
-    let body_with_io_error = stream.map_err(|err| io::Error::new(io::ErrorKind::Other, err));
-    let body_reader = StreamReader::new(body_with_io_error);
-    futures::pin_mut!(body_reader);
+    validate_file_name(file_name).map_err(|e| UploadError::validation("invalid_file_name", e))?;
+    stages
+        .content_policy
+        .check_extension(file_name)
+        .map_err(|e| UploadError::validation("disallowed_extension", e))?;
+
+    let (size, checksum, head) =
+        stream_to_file(staged_path, stream, stages.compression.encryption())
+            .await
+            .context("Failed to save file")?;
+    metrics.uploaded_bytes_total.inc_by(size);
 
-    // Create the file. `File` implements `AsyncWrite`.
-    let mut file = BufWriter::new(File::create(path).await?);
+    let content_type = content_types::detect(&head, file_name);
+    stages
+        .content_policy
+        .check_type(file_name, &content_type)
+        .map_err(|e| UploadError::validation("disallowed_content_type", e))?;
+    Ok((size, checksum, content_type))
+}
+
+/// Scans the staged files, then compresses them.
+async fn scan_and_compress(
+    staged: &mut Staged,
+    stages: &UploadStages<'_>,
+) -> Result<(), UploadError> {
+    for staged_file in &staged.files {
+        match stages
+            .scanning
+            .check(&staged_file.staged_path, &staged_file.relative_path)
+            .await
+        {
+            Ok(()) => {}
+            Err(e @ ScanError::Infected { .. }) => {
+                return Err(UploadError::validation("infected_file", e.to_string()))
+            }
+            Err(ScanError::Io(e)) => {
+                return Err(anyhow::Error::new(e).context("Failed to scan file").into())
+            }
+        }
+    }
 
-    // Copy the body into the file.
-    tokio::io::copy(&mut body_reader, &mut file).await?;
+    // Compressed last, so that scanners and the content policy see the files as sent.
+    for staged_file in &mut staged.files {
+        let content_type = staged_file
+            .content_type
+            .parse()
+            .unwrap_or(mime::APPLICATION_OCTET_STREAM);
+        staged_file.codec = stages
+            .compression
+            .compress(&staged_file.staged_path, &content_type, staged_file.size)
+            .await
+            .context("Failed to compress file")?;
+    }
+    Ok(())
+}
 
+/// Checks the WebDAV locks on every staged file had their tokens submitted, right before the
+/// files are moved into place.
+fn check_locks(staged: &Staged, locks: &Locks, lock_tokens: &[String]) -> Result<(), UploadError> {
+    for staged_file in &staged.files {
+        locks.check(&staged_file.relative_path, lock_tokens)?;
+    }
     Ok(())
 }
 
+/// Moves staged files into place, keeping the previous versions of files they replace, and
+/// creates the directories of extracted archives. Each file is moved while holding the write lock
+/// of its path, once the file it replaces has met the preconditions.
+async fn commit_files(
+    staged: Staged,
+    base_path: &Path,
+    versioning: &Versioning,
+    write_locks: &WriteLocks,
+    preconditions: &Preconditions,
+    pending: &mut PendingChanges,
+    uploaded_files: &mut Vec<UploadedFile>,
+) -> Result<(), UploadError> {
+    for directory in &staged.directories {
+        create_directories(
+            base_path,
+            &base_path.join(directory),
+            &mut pending.directories,
+        )
+        .await
+        .context("Failed to create directory")?;
+    }
+    for staged_file in staged.files {
+        let file_path = Path::new(&staged_file.file_path);
+        if let Some(parent) = file_path.parent() {
+            create_directories(base_path, parent, &mut pending.directories)
+                .await
+                .context("Failed to create directory")?;
+        }
+        let _write_lock = write_locks.lock(file_path).await;
+        preconditions
+            .check(file_path, &staged_file.relative_path)
+            .await?;
+        let overwritten = tokio::fs::metadata(&staged_file.file_path).await.is_ok();
+        let previous_version = versioning
+            .preserve(&staged_file.relative_path)
+            .await
+            .context("Failed to preserve previous version")?;
+        pending.files.push(PendingFile {
+            relative_path: staged_file.relative_path.clone(),
+            file_path: staged_file.file_path.clone(),
+            previous_version: previous_version.clone(),
+        });
+        if let Err(e) = tokio::fs::rename(&staged_file.staged_path, &staged_file.file_path).await {
+            // Nothing was moved into place, so only a preserved version needs putting back.
+            if previous_version.is_none() {
+                pending.files.pop();
+            }
+            return Err(anyhow::Error::new(e)
+                .context("Failed to move file into place")
+                .into());
+        }
+
+        uploaded_files.push(UploadedFile {
+            path: staged_file.relative_path,
+            size: staged_file.size,
+            checksum: staged_file.checksum,
+            content_type: staged_file.content_type,
+            codec: staged_file.codec,
+            overwritten,
+            previous_version,
+        });
+    }
+    Ok(())
+}
+
+/// Creates `directory` and its missing parents below `base_path`, which is expected to exist,
+/// recording the ones created.
+async fn create_directories(
+    base_path: &Path,
+    directory: &Path,
+    created: &mut Vec<PathBuf>,
+) -> Result<(), io::Error> {
+    let mut missing = vec![];
+    let mut current = directory;
+    while current != base_path {
+        match tokio::fs::metadata(current).await {
+            Ok(_) => break,
+            Err(e) if e.kind() == ErrorKind::NotFound => missing.push(current.to_path_buf()),
+            Err(e) => return Err(e),
+        }
+        match current.parent() {
+            Some(parent) => current = parent,
+            None => break,
+        }
+    }
+
+    for directory in missing.into_iter().rev() {
+        match tokio::fs::create_dir(&directory).await {
+            Ok(()) => created.push(directory),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// Reads a form field other than a file into the metadata given to every uploaded file: a `tag`
+/// field adds a tag and a `metadata.<key>` field sets a key.
+async fn read_metadata_field(
+    field: Field<'_>,
+    patch: &mut MetadataPatch,
+) -> Result<(), UploadError> {
+    let name = field.name().context("No field name")?.to_string();
+    let value = field.text().await.context("Failed to get text")?;
+
+    if name == "tag" {
+        validate_tag(&value).map_err(|e| UploadError::validation("invalid_tag", e))?;
+        patch.add_tags.push(value);
+    } else if let Some(key) = name.strip_prefix("metadata.") {
+        validate_metadata_key(key)
+            .and_then(|_| validate_metadata_value(&value))
+            .map_err(|e| UploadError::validation("invalid_metadata", e))?;
+        patch.metadata.insert(key.to_string(), Some(value));
+    } else {
+        return Err(UploadError::validation(
+            "unexpected_field",
+            format!("Unexpected field '{}', files need a file name", name),
+        ));
+    }
+    Ok(())
+}
+
+/// Gives uploaded files the metadata sent with them. The files are already saved, so failures
+/// are logged rather than failing the upload.
+async fn apply_metadata(index: &Index, paths: &[String], patch: &MetadataPatch) {
+    for path in paths {
+        if let Err(e) = index.update_metadata(path, patch.clone()).await {
+            tracing::error!("Failed to save metadata of {}: {:?}", path, e);
+        }
+    }
+}
+
+/// The events describing a completed upload: the versions it created, then the new files.
+fn file_events(uploaded_files: Vec<UploadedFile>, identity: &Identity) -> Vec<FileEvent> {
+    let mut events = vec![];
+    for file in uploaded_files {
+        if let Some(version) = &file.previous_version {
+            events.push(FileEvent::version_created(&file.path, version));
+        }
+        events.push(FileEvent {
+            identity: Some(identity.name().to_string()),
+            overwritten: Some(file.overwritten),
+            size: Some(file.size),
+            checksum: Some(file.checksum),
+            content_type: Some(file.content_type),
+            codec: file.codec.map(|codec| codec.as_str().to_string()),
+            ..FileEvent::new(FileEventKind::UploadCompleted, file.path)
+        });
+    }
+    events
+}
+
+/// Writes the stream to `path`, encrypted if enabled, returning the number of bytes received,
+/// their SHA-256 checksum and the leading bytes to detect the content type from.
+async fn stream_to_file<S, E>(
+    path: &Path,
+    stream: S,
+    encryption: &Encryption,
+) -> Result<(u64, String, Vec<u8>), io::Error>
+where
+    S: Stream<Item = Result<Bytes, E>>,
+    E: Into<BoxError>,
+{
+    let mut hasher = Sha256::new();
+    let mut head = Vec::with_capacity(SNIFF_LENGTH);
+    let written_bytes = {
+        // Convert the stream into an `AsyncRead`, hashing every chunk and keeping the first
+        // bytes on the way through.
+        let body_with_io_error = stream
+            .inspect_ok(|bytes| {
+                hasher.update(bytes);
+                let missing = SNIFF_LENGTH - head.len();
+                head.extend_from_slice(&bytes[..missing.min(bytes.len())]);
+            })
+            .map_err(io::Error::other);
+        let body_reader = StreamReader::new(body_with_io_error);
+        futures::pin_mut!(body_reader);
+
+        // Copy the body into the file.
+        encryption.write(path, &mut body_reader).await?
+    };
+
+    Ok((written_bytes, hex::encode(hasher.finalize()), head))
+}
+
 //This clippy lint is currently disabled here due to a bug https://github.com/rust-lang/rust-clippy/issues/5787
 #[allow(clippy::needless_lifetimes)]
 async fn get_multipart_field<'a>(
@@ -128,9 +1006,29 @@ async fn get_multipart_field<'a>(
     }
 }
 
-async fn cleanup_failed_files(uploaded_file_paths: &[String]) -> Result<(), io::Error> {
-    for file_path in uploaded_file_paths {
-        tokio::fs::remove_file(file_path).await?;
+/// Removes the files written by a failed upload, putting back any content they replaced, then
+/// the directories it created.
+async fn cleanup_failed_files(
+    pending: &PendingChanges,
+    versioning: &Versioning,
+) -> Result<(), io::Error> {
+    for pending_file in &pending.files {
+        match &pending_file.previous_version {
+            Some(version) => {
+                versioning
+                    .revert(&pending_file.relative_path, version)
+                    .await?
+            }
+            None => tokio::fs::remove_file(&pending_file.file_path).await?,
+        }
+    }
+    // Deepest first, leaving any another upload has written into meanwhile.
+    for directory in pending.directories.iter().rev() {
+        match tokio::fs::remove_dir(directory).await {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::DirectoryNotEmpty => {}
+            Err(e) => return Err(e),
+        }
     }
 
     Ok(())
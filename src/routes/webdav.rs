@@ -0,0 +1,851 @@
+use std::{fs::Metadata, io::ErrorKind, net::SocketAddr, sync::Arc};
+
+use anyhow::Context;
+use axum::{
+    body::Body,
+    body::Bytes,
+    extract::{ConnectInfo, Path},
+    http::{header, HeaderMap, HeaderName, HeaderValue, Request, StatusCode},
+    response::{IntoResponse, Response},
+    Extension,
+};
+use chrono::Utc;
+use futures::StreamExt;
+
+use crate::{
+    audit::{AuditAction, AuditLog, AuditRecord},
+    compression::Compression,
+    content_types::ContentPolicy,
+    domain::{etag, leads_into_scope, Identity, OutOfScope, StorageDetails, INTERNAL_DIRECTORY},
+    events::{Events, FileEvent, FileEventKind},
+    index::Index,
+    metrics::Metrics,
+    routes::{
+        files::{move_to_trash, stream_file},
+        upload::{store_body, BodyOptions, UploadContext, UploadStages},
+        FilesError, UploadError,
+    },
+    scanning::Scanning,
+    trash::Trash,
+    validators::validate_relative_path,
+    versioning::Versioning,
+    webdav::{
+        destination_path, is_within, lock_discovery, multistatus, parent, parse_timeout,
+        precondition_error, submitted_tokens, Depth, LockError, LockInfo, LockRequest, Locks,
+        PropFind, Resource,
+    },
+};
+
+const ALLOWED_METHODS: &str =
+    "OPTIONS, GET, HEAD, PUT, DELETE, PROPFIND, MKCOL, COPY, MOVE, LOCK, UNLOCK";
+/// Largest `PROPFIND` or `LOCK` body read.
+const MAX_XML_BODY_SIZE: usize = 64 * 1024;
+const XML_CONTENT_TYPE: &str = "application/xml; charset=utf-8";
+
+const DAV: HeaderName = HeaderName::from_static("dav");
+const DEPTH: HeaderName = HeaderName::from_static("depth");
+const DESTINATION: HeaderName = HeaderName::from_static("destination");
+pub(super) const IF: HeaderName = HeaderName::from_static("if");
+const LOCK_TOKEN: HeaderName = HeaderName::from_static("lock-token");
+const MS_AUTHOR_VIA: HeaderName = HeaderName::from_static("ms-author-via");
+const OVERWRITE: HeaderName = HeaderName::from_static("overwrite");
+const TIMEOUT: HeaderName = HeaderName::from_static("timeout");
+
+#[derive(thiserror::Error, Debug)]
+pub enum WebDavError {
+    #[error("{0} was not found")]
+    NotFound(String),
+    #[error("{0}")]
+    MethodNotAllowed(String),
+    #[error("{0}")]
+    Forbidden(String),
+    #[error("{0}")]
+    Conflict(String),
+    #[error("{0}")]
+    PreconditionFailed(String),
+    #[error("{0}")]
+    UnsupportedMediaType(String),
+    #[error("{0}")]
+    ValidationError(String),
+    #[error(transparent)]
+    Lock(#[from] LockError),
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl IntoResponse for WebDavError {
+    fn into_response(self) -> Response {
+        let status = match self {
+            WebDavError::NotFound(_) => StatusCode::NOT_FOUND,
+            WebDavError::MethodNotAllowed(_) => StatusCode::METHOD_NOT_ALLOWED,
+            WebDavError::Forbidden(_) | WebDavError::Lock(LockError::NotOwner(_)) => {
+                StatusCode::FORBIDDEN
+            }
+            WebDavError::Conflict(_) | WebDavError::Lock(LockError::NoSuchLock(_)) => {
+                StatusCode::CONFLICT
+            }
+            WebDavError::PreconditionFailed(_) => StatusCode::PRECONDITION_FAILED,
+            WebDavError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            WebDavError::ValidationError(_) => StatusCode::BAD_REQUEST,
+            WebDavError::Lock(LockError::Locked(_)) => StatusCode::LOCKED,
+            WebDavError::UnexpectedError(ref e) => {
+                tracing::error!("{:?}", e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        };
+
+        let mut response = (status, self.to_string()).into_response();
+        if status == StatusCode::METHOD_NOT_ALLOWED {
+            response
+                .headers_mut()
+                .insert(header::ALLOW, HeaderValue::from_static(ALLOWED_METHODS));
+        }
+        response
+    }
+}
+
+impl From<FilesError> for WebDavError {
+    fn from(e: FilesError) -> Self {
+        match e {
+            FilesError::NotFound(path) => WebDavError::NotFound(path),
+            FilesError::Forbidden(message) => WebDavError::Forbidden(message),
+            FilesError::Conflict(message) => WebDavError::Conflict(message),
+            FilesError::UnsupportedMediaType(message) => WebDavError::UnsupportedMediaType(message),
+            FilesError::PreconditionFailed(message) => WebDavError::PreconditionFailed(message),
+            FilesError::ValidationError(message) => WebDavError::ValidationError(message),
+            FilesError::Lock(e) => WebDavError::Lock(e),
+            FilesError::UnexpectedError(e) => WebDavError::UnexpectedError(e),
+        }
+    }
+}
+
+impl From<OutOfScope> for WebDavError {
+    fn from(e: OutOfScope) -> Self {
+        WebDavError::Forbidden(e.to_string())
+    }
+}
+
+impl From<UploadError> for WebDavError {
+    fn from(e: UploadError) -> Self {
+        match e {
+            UploadError::ValidationError { message, .. } => WebDavError::ValidationError(message),
+            UploadError::Forbidden(message) => WebDavError::Forbidden(message),
+            UploadError::PreconditionFailed(message) => WebDavError::PreconditionFailed(message),
+            UploadError::Lock(e) => WebDavError::Lock(e),
+            UploadError::UnexpectedError(e) => WebDavError::UnexpectedError(e),
+        }
+    }
+}
+
+/// Serves the storage root over WebDAV, class 1 and 2, below `/webdav`.
+#[tracing::instrument(
+    name = "WebDAV request handler",
+    skip(
+        identity,
+        client_addr,
+        storage_details,
+        metrics,
+        audit_log,
+        versioning,
+        trash,
+        events,
+        index,
+        compression,
+        content_policy,
+        scanning,
+        locks,
+        request
+    )
+)]
+// Every extractor is an argument of its own.
+#[allow(clippy::too_many_arguments)]
+pub async fn webdav(
+    path: Option<Path<String>>,
+    identity: Identity,
+    ConnectInfo(client_addr): ConnectInfo<SocketAddr>,
+    storage_details: Extension<Arc<StorageDetails>>,
+    metrics: Extension<Arc<Metrics>>,
+    audit_log: Extension<Arc<AuditLog>>,
+    versioning: Extension<Arc<Versioning>>,
+    trash: Extension<Arc<Trash>>,
+    events: Extension<Arc<Events>>,
+    index: Extension<Arc<Index>>,
+    compression: Extension<Arc<Compression>>,
+    content_policy: Extension<Arc<ContentPolicy>>,
+    scanning: Extension<Arc<Scanning>>,
+    locks: Extension<Arc<Locks>>,
+    request: Request<Body>,
+) -> Result<Response, WebDavError> {
+    let (parts, body) = request.into_parts();
+    let relative_path = path
+        .as_ref()
+        .map_or("", |Path(path)| path.trim_matches('/'))
+        .to_string();
+    if !relative_path.is_empty() {
+        validate_relative_path(&relative_path).map_err(WebDavError::ValidationError)?;
+    }
+    // Directories on the way to the allowed paths can be listed, nothing else outside them.
+    match parts.method.as_str() {
+        "OPTIONS" | "PROPFIND" if leads_into_scope(&relative_path, identity.allowed_paths()) => {}
+        _ => identity.check_scope(&relative_path)?,
+    }
+
+    let request = DavRequest {
+        path: relative_path,
+        headers: parts.headers,
+        identity,
+        client_addr,
+        storage_details: storage_details.0,
+        metrics: metrics.0,
+        audit_log: audit_log.0,
+        versioning: versioning.0,
+        trash: trash.0,
+        events: events.0,
+        index: index.0,
+        compression: compression.0,
+        content_policy: content_policy.0,
+        scanning: scanning.0,
+        locks: locks.0,
+    };
+    match parts.method.as_str() {
+        "OPTIONS" => Ok(options()),
+        "GET" | "HEAD" => request.get().await,
+        "PUT" => request.put(body).await,
+        "DELETE" => request.delete().await,
+        "PROPFIND" => request.propfind(body).await,
+        "MKCOL" => request.mkcol(body).await,
+        "COPY" => request.copy_or_move(false).await,
+        "MOVE" => request.copy_or_move(true).await,
+        "LOCK" => request.lock(body).await,
+        "UNLOCK" => request.unlock(),
+        method => Err(WebDavError::MethodNotAllowed(format!(
+            "{} is not supported",
+            method
+        ))),
+    }
+}
+
+fn options() -> Response {
+    (
+        StatusCode::OK,
+        [
+            (DAV, "1, 2"),
+            (header::ALLOW, ALLOWED_METHODS),
+            (MS_AUTHOR_VIA, "DAV"),
+        ],
+    )
+        .into_response()
+}
+
+/// A WebDAV request on a storage path, with what its methods act on.
+struct DavRequest {
+    /// Path relative to the storage root, empty for the root.
+    path: String,
+    headers: HeaderMap,
+    identity: Identity,
+    client_addr: SocketAddr,
+    storage_details: Arc<StorageDetails>,
+    metrics: Arc<Metrics>,
+    audit_log: Arc<AuditLog>,
+    versioning: Arc<Versioning>,
+    trash: Arc<Trash>,
+    events: Arc<Events>,
+    index: Arc<Index>,
+    compression: Arc<Compression>,
+    content_policy: Arc<ContentPolicy>,
+    scanning: Arc<Scanning>,
+    locks: Arc<Locks>,
+}
+
+impl DavRequest {
+    async fn get(&self) -> Result<Response, WebDavError> {
+        let metadata = self.require(&self.path).await?;
+        if metadata.is_dir() {
+            return Err(WebDavError::MethodNotAllowed(
+                "Collections are listed with PROPFIND".to_string(),
+            ));
+        }
+
+        let file_path = self.storage_details.file_path(&self.path);
+        let codec = self
+            .index
+            .codec(&self.path)
+            .await
+            .context("Failed to read metadata index")?;
+        Ok(stream_file(
+            &file_path,
+            codec,
+            &self.path,
+            &self.headers,
+            &self.metrics,
+            &self.compression,
+        )
+        .await?)
+    }
+
+    async fn put(&self, body: Body) -> Result<Response, WebDavError> {
+        if self.path.is_empty() {
+            return Err(WebDavError::MethodNotAllowed(
+                "The root is a collection".to_string(),
+            ));
+        }
+        if self.metadata(&self.path).await?.is_some_and(|m| m.is_dir()) {
+            return Err(WebDavError::MethodNotAllowed(format!(
+                "{} is a collection",
+                self.path
+            )));
+        }
+        self.require_parent(&self.path).await?;
+
+        let context = UploadContext {
+            storage_details: &self.storage_details,
+            metrics: &self.metrics,
+            audit_log: &self.audit_log,
+            versioning: &self.versioning,
+            events: &self.events,
+            locks: &self.locks,
+            stages: UploadStages {
+                extraction: None,
+                compression: &self.compression,
+                content_policy: &self.content_policy,
+                scanning: &self.scanning,
+            },
+        };
+        let options = BodyOptions {
+            lock_tokens: submitted_tokens(self.header(&IF)),
+            ..BodyOptions::default()
+        };
+        let overwritten = store_body(
+            body,
+            &self.path,
+            &options,
+            &self.identity,
+            self.client_addr,
+            context,
+        )
+        .await?;
+        Ok(match overwritten {
+            true => StatusCode::NO_CONTENT,
+            false => StatusCode::CREATED,
+        }
+        .into_response())
+    }
+
+    async fn delete(&self) -> Result<Response, WebDavError> {
+        if self.path.is_empty() {
+            return Err(WebDavError::Forbidden(
+                "The root cannot be deleted".to_string(),
+            ));
+        }
+        self.trash_path(&self.path).await?;
+        Ok(StatusCode::NO_CONTENT.into_response())
+    }
+
+    async fn propfind(&self, body: Body) -> Result<Response, WebDavError> {
+        let depth = self.depth()?;
+        if depth == Depth::Infinity {
+            return Ok((
+                StatusCode::FORBIDDEN,
+                [(header::CONTENT_TYPE, XML_CONTENT_TYPE)],
+                precondition_error("propfind-finite-depth"),
+            )
+                .into_response());
+        }
+        let request =
+            PropFind::parse(&read_xml_body(body).await?).map_err(WebDavError::ValidationError)?;
+
+        let scope = self.identity.allowed_paths();
+        let metadata = self.require(&self.path).await?;
+        let mut responses = vec![self
+            .resource(&self.path, &metadata)
+            .await?
+            .response(&request)];
+
+        if depth == Depth::One && metadata.is_dir() {
+            let mut children = tokio::fs::read_dir(self.storage_details.file_path(&self.path))
+                .await
+                .context("Failed to list collection")?;
+            let mut names = vec![];
+            while let Some(child) = children
+                .next_entry()
+                .await
+                .context("Failed to list collection")?
+            {
+                names.push(child.file_name().to_string_lossy().into_owned());
+            }
+            names.sort();
+
+            for name in names {
+                if self.path.is_empty() && name == INTERNAL_DIRECTORY {
+                    continue;
+                }
+                let child_path = join(&self.path, &name);
+                if !leads_into_scope(&child_path, scope) {
+                    continue;
+                }
+                // Children removed meanwhile, and links, are left out.
+                let metadata =
+                    match tokio::fs::symlink_metadata(self.storage_details.file_path(&child_path))
+                        .await
+                    {
+                        Ok(metadata) if metadata.is_file() || metadata.is_dir() => metadata,
+                        Ok(_) => continue,
+                        Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                        Err(e) => {
+                            return Err(anyhow::Error::new(e)
+                                .context("Failed to read collection member")
+                                .into())
+                        }
+                    };
+                match self.resource(&child_path, &metadata).await {
+                    Ok(resource) => responses.push(resource.response(&request)),
+                    Err(WebDavError::NotFound(_)) => continue,
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+
+        Ok((
+            StatusCode::MULTI_STATUS,
+            [(header::CONTENT_TYPE, XML_CONTENT_TYPE)],
+            multistatus(&responses),
+        )
+            .into_response())
+    }
+
+    async fn mkcol(&self, body: Body) -> Result<Response, WebDavError> {
+        if !read_xml_body(body).await?.is_empty() {
+            return Err(WebDavError::UnsupportedMediaType(
+                "MKCOL bodies are not supported".to_string(),
+            ));
+        }
+        if self.path.is_empty() || self.metadata(&self.path).await?.is_some() {
+            return Err(WebDavError::MethodNotAllowed(format!(
+                "{} already exists",
+                self.path
+            )));
+        }
+        self.require_parent(&self.path).await?;
+        self.check_locks(&self.path)?;
+
+        match tokio::fs::create_dir(self.storage_details.file_path(&self.path)).await {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                return Err(WebDavError::MethodNotAllowed(format!(
+                    "{} already exists",
+                    self.path
+                )))
+            }
+            Err(e) => {
+                return Err(anyhow::Error::new(e)
+                    .context("Failed to create collection")
+                    .into())
+            }
+        }
+        self.audit(AuditAction::CreateDirectory, &self.path, None)
+            .await?;
+        Ok(StatusCode::CREATED.into_response())
+    }
+
+    /// Copies or moves the resource to the `Destination`, replacing what is there unless
+    /// `Overwrite` is `F`. Replaced resources go to the trash.
+    async fn copy_or_move(&self, is_move: bool) -> Result<Response, WebDavError> {
+        let destination = self
+            .header(&DESTINATION)
+            .and_then(destination_path)
+            .ok_or_else(|| {
+                WebDavError::ValidationError("Expected a Destination below /webdav".to_string())
+            })?;
+        if self.path.is_empty() || destination.is_empty() {
+            return Err(WebDavError::Forbidden(
+                "The root cannot be copied, moved or replaced".to_string(),
+            ));
+        }
+        validate_relative_path(&destination).map_err(WebDavError::ValidationError)?;
+        self.identity.check_scope(&destination)?;
+        if is_within(&destination, &self.path) || is_within(&self.path, &destination) {
+            return Err(WebDavError::Forbidden(format!(
+                "{} and {} overlap",
+                self.path, destination
+            )));
+        }
+        let overwrite = match self.header(&OVERWRITE) {
+            None | Some("T") => true,
+            Some("F") => false,
+            Some(value) => {
+                return Err(WebDavError::ValidationError(format!(
+                    "Invalid Overwrite header: {}",
+                    value
+                )))
+            }
+        };
+
+        let metadata = self.require(&self.path).await?;
+        let depth = self.depth()?;
+        let deep = match (metadata.is_dir(), depth) {
+            (false, _) | (true, Depth::Infinity) => true,
+            (true, Depth::Zero) if !is_move => false,
+            (true, _) => {
+                return Err(WebDavError::ValidationError(format!(
+                    "Invalid depth for collections: {:?}",
+                    depth
+                )))
+            }
+        };
+        if is_move {
+            self.check_locks(&self.path)?;
+        }
+        self.check_locks(&destination)?;
+        self.require_parent(&destination).await?;
+
+        let replaced = self.metadata(&destination).await?.is_some();
+        if replaced {
+            if !overwrite {
+                return Err(WebDavError::PreconditionFailed(format!(
+                    "{} already exists",
+                    destination
+                )));
+            }
+            self.trash_path(&destination).await?;
+        }
+
+        if is_move {
+            self.move_to(&destination, metadata.is_dir()).await?;
+        } else {
+            self.copy_to(&destination, deep).await?;
+        }
+        Ok(match replaced {
+            true => StatusCode::NO_CONTENT,
+            false => StatusCode::CREATED,
+        }
+        .into_response())
+    }
+
+    async fn move_to(&self, destination: &str, is_directory: bool) -> Result<(), WebDavError> {
+        tokio::fs::rename(
+            self.storage_details.file_path(&self.path),
+            self.storage_details.file_path(destination),
+        )
+        .await
+        .context("Failed to move")?;
+        self.locks.release(&self.path);
+
+        self.audit(AuditAction::Move, &self.path, None).await?;
+        self.events
+            .publish(&[FileEvent {
+                identity: Some(self.identity.name().to_string()),
+                from_path: Some(self.path.clone()),
+                is_directory: Some(is_directory),
+                ..FileEvent::new(FileEventKind::Moved, destination)
+            }])
+            .await;
+        Ok(())
+    }
+
+    /// Copies the resource, and everything below it for deep copies of collections. The files
+    /// are published like uploads, with the checksums of their sources.
+    async fn copy_to(&self, destination: &str, deep: bool) -> Result<(), WebDavError> {
+        let mut copied = vec![];
+        if let Err(e) = self.copy_tree(destination, deep, &mut copied).await {
+            let path = self.storage_details.file_path(destination);
+            let removed = match tokio::fs::metadata(&path).await {
+                Ok(metadata) if metadata.is_dir() => tokio::fs::remove_dir_all(&path).await,
+                Ok(_) => tokio::fs::remove_file(&path).await,
+                Err(_) => Ok(()),
+            };
+            if let Err(e) = removed {
+                tracing::error!("Failed to remove partial copy {}: {:?}", destination, e);
+            }
+            return Err(e);
+        }
+
+        let mut records = vec![];
+        let mut published = vec![];
+        for (source, copy) in copied {
+            let record = self
+                .index
+                .get(&source)
+                .await
+                .context("Failed to read metadata index")?;
+            let size = match &record {
+                Some(record) => record.size,
+                // Files missing from the index are taken as stored as is.
+                None => self
+                    .compression
+                    .content_size(&self.storage_details.file_path(&copy), None)
+                    .await
+                    .context("Failed to read copy")?,
+            };
+            records.push(AuditRecord {
+                timestamp: Utc::now(),
+                action: AuditAction::Copy,
+                identity: self.identity.name().to_string(),
+                client_ip: Some(self.client_addr.ip()),
+                path: copy.clone(),
+                size: Some(size),
+                checksum: record.as_ref().and_then(|record| record.checksum.clone()),
+            });
+            published.push(FileEvent {
+                identity: Some(self.identity.name().to_string()),
+                overwritten: Some(false),
+                size: Some(size),
+                checksum: record.as_ref().and_then(|record| record.checksum.clone()),
+                content_type: record
+                    .as_ref()
+                    .and_then(|record| record.content_type.clone()),
+                codec: record.and_then(|record| record.codec),
+                ..FileEvent::new(FileEventKind::UploadCompleted, copy)
+            });
+        }
+        self.audit_log
+            .record(&records)
+            .await
+            .context("Failed to write audit log")?;
+        self.events.publish(&published).await;
+        Ok(())
+    }
+
+    /// Copies the files and directories below the resource, recording the copied files as
+    /// `(source, copy)` pairs.
+    async fn copy_tree(
+        &self,
+        destination: &str,
+        deep: bool,
+        copied: &mut Vec<(String, String)>,
+    ) -> Result<(), WebDavError> {
+        let mut pending = vec![(self.path.clone(), destination.to_string())];
+        while let Some((source, copy)) = pending.pop() {
+            let source_path = self.storage_details.file_path(&source);
+            let copy_path = self.storage_details.file_path(&copy);
+            let metadata = tokio::fs::symlink_metadata(&source_path)
+                .await
+                .context("Failed to read copy source")?;
+
+            if metadata.is_file() {
+                tokio::fs::copy(&source_path, &copy_path)
+                    .await
+                    .context("Failed to copy file")?;
+                copied.push((source, copy));
+            } else if metadata.is_dir() {
+                tokio::fs::create_dir(&copy_path)
+                    .await
+                    .context("Failed to create collection")?;
+                if !deep {
+                    continue;
+                }
+                let mut children = tokio::fs::read_dir(&source_path)
+                    .await
+                    .context("Failed to list collection")?;
+                while let Some(child) = children
+                    .next_entry()
+                    .await
+                    .context("Failed to list collection")?
+                {
+                    let name = child.file_name().to_string_lossy().into_owned();
+                    pending.push((join(&source, &name), join(&copy, &name)));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Takes a lock, or refreshes one when the request has no body. Unmapped paths can be
+    /// locked before they are written.
+    async fn lock(&self, body: Body) -> Result<Response, WebDavError> {
+        let timeout = parse_timeout(self.header(&TIMEOUT));
+        let body = read_xml_body(body).await?;
+        if body.is_empty() {
+            let tokens = submitted_tokens(self.header(&IF));
+            let lock = self
+                .locks
+                .refresh(&self.path, &tokens, timeout)
+                .ok_or_else(|| {
+                    WebDavError::PreconditionFailed("No lock to refresh was submitted".to_string())
+                })?;
+            return Ok((
+                StatusCode::OK,
+                [(header::CONTENT_TYPE, XML_CONTENT_TYPE)],
+                lock_discovery(&lock),
+            )
+                .into_response());
+        }
+
+        let info = LockInfo::parse(&body).map_err(WebDavError::ValidationError)?;
+        let deep = match self.depth()? {
+            Depth::Zero => false,
+            Depth::Infinity => true,
+            Depth::One => {
+                return Err(WebDavError::ValidationError(
+                    "Locks have a depth of 0 or infinity".to_string(),
+                ))
+            }
+        };
+        if self.metadata(&self.path).await?.is_none() {
+            self.require_parent(&self.path).await?;
+        }
+
+        let lock = self.locks.lock(LockRequest {
+            path: self.path.clone(),
+            exclusive: info.exclusive,
+            deep,
+            owner: info.owner,
+            principal: self.identity.name().to_string(),
+            timeout,
+        })?;
+        Ok((
+            StatusCode::OK,
+            [
+                (header::CONTENT_TYPE, XML_CONTENT_TYPE.to_string()),
+                (LOCK_TOKEN, format!("<{}>", lock.token)),
+            ],
+            lock_discovery(&lock),
+        )
+            .into_response())
+    }
+
+    fn unlock(&self) -> Result<Response, WebDavError> {
+        let token = self
+            .header(&LOCK_TOKEN)
+            .and_then(|token| token.trim().strip_prefix('<')?.strip_suffix('>'))
+            .ok_or_else(|| WebDavError::ValidationError("Expected a Lock-Token".to_string()))?;
+        self.locks.unlock(&self.path, token, self.identity.name())?;
+        Ok(StatusCode::NO_CONTENT.into_response())
+    }
+
+    /// Moves a file or collection to the trash, which releases the locks on it.
+    async fn trash_path(&self, path: &str) -> Result<(), WebDavError> {
+        move_to_trash(
+            path,
+            &self.identity,
+            self.client_addr,
+            &self.trash,
+            &self.locks,
+            &submitted_tokens(self.header(&IF)),
+            &self.audit_log,
+            &self.events,
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn resource(&self, path: &str, metadata: &Metadata) -> Result<Resource, WebDavError> {
+        let size = match metadata.is_file() {
+            true => match self
+                .compression
+                .content_size(
+                    &self.storage_details.file_path(path),
+                    self.index
+                        .codec(path)
+                        .await
+                        .context("Failed to read metadata index")?,
+                )
+                .await
+            {
+                Ok(size) => size,
+                Err(e) if e.kind() == ErrorKind::NotFound => {
+                    return Err(WebDavError::NotFound(path.to_string()))
+                }
+                Err(e) => return Err(anyhow::Error::new(e).context("Failed to read file").into()),
+            },
+            false => 0,
+        };
+        let modified_at = metadata.modified().context("No modification time")?;
+        Ok(Resource {
+            path: path.to_string(),
+            is_collection: metadata.is_dir(),
+            size,
+            modified_at,
+            created_at: metadata.created().unwrap_or(modified_at),
+            etag: etag(metadata),
+            locks: self.locks.discover(path),
+        })
+    }
+
+    /// Checks the locks guarding a change to `path` were submitted in the `If` header.
+    fn check_locks(&self, path: &str) -> Result<(), WebDavError> {
+        let tokens = submitted_tokens(self.header(&IF));
+        Ok(self.locks.check(path, &tokens)?)
+    }
+
+    /// The collection `path` would be a member of has to exist.
+    async fn require_parent(&self, path: &str) -> Result<(), WebDavError> {
+        let parent = parent(path).unwrap_or_default();
+        match self.metadata(parent).await? {
+            Some(metadata) if metadata.is_dir() => Ok(()),
+            _ => Err(WebDavError::Conflict(format!(
+                "The collection {} does not exist",
+                parent
+            ))),
+        }
+    }
+
+    async fn require(&self, path: &str) -> Result<Metadata, WebDavError> {
+        self.metadata(path)
+            .await?
+            .ok_or_else(|| WebDavError::NotFound(path.to_string()))
+    }
+
+    async fn metadata(&self, path: &str) -> Result<Option<Metadata>, WebDavError> {
+        match tokio::fs::metadata(self.storage_details.file_path(path)).await {
+            Ok(metadata) => Ok(Some(metadata)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(anyhow::Error::new(e)
+                .context("Failed to read metadata")
+                .into()),
+        }
+    }
+
+    fn depth(&self) -> Result<Depth, WebDavError> {
+        Depth::parse(self.header(&DEPTH)).map_err(WebDavError::ValidationError)
+    }
+
+    fn header(&self, name: &HeaderName) -> Option<&str> {
+        self.headers.get(name).and_then(|value| value.to_str().ok())
+    }
+
+    async fn audit(
+        &self,
+        action: AuditAction,
+        path: &str,
+        size: Option<u64>,
+    ) -> Result<(), WebDavError> {
+        self.audit_log
+            .record(&[AuditRecord {
+                timestamp: Utc::now(),
+                action,
+                identity: self.identity.name().to_string(),
+                client_ip: Some(self.client_addr.ip()),
+                path: path.to_string(),
+                size,
+                checksum: None,
+            }])
+            .await
+            .context("Failed to write audit log")?;
+        Ok(())
+    }
+}
+
+/// Reads a request body expected to hold a small XML document.
+async fn read_xml_body(mut body: Body) -> Result<Vec<u8>, WebDavError> {
+    let mut bytes = vec![];
+    while let Some(chunk) = body.next().await {
+        let chunk: Bytes = chunk.context("Failed to read request body")?;
+        if bytes.len() + chunk.len() > MAX_XML_BODY_SIZE {
+            return Err(WebDavError::ValidationError(
+                "Request body is too large".to_string(),
+            ));
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    if bytes.iter().all(u8::is_ascii_whitespace) {
+        bytes.clear();
+    }
+    Ok(bytes)
+}
+
+fn join(parent: &str, name: &str) -> String {
+    match parent {
+        "" => name.to_string(),
+        parent => format!("{}/{}", parent, name),
+    }
+}
@@ -0,0 +1,130 @@
+use std::io::{self, ErrorKind};
+
+use axum::{body::Bytes, BoxError};
+use futures::{Stream, StreamExt};
+
+use crate::s3::ChunkSigner;
+
+/// Longest chunk header line, size and extensions, read before giving up on a body.
+const MAX_LINE_LENGTH: usize = 4096;
+/// Largest chunk held in memory, well above the 64 KiB to 1 MiB clients send.
+const MAX_CHUNK_SIZE: usize = 16 * 1024 * 1024;
+
+/// Decodes an `aws-chunked` body into its content, checking the signature of every chunk when
+/// they are signed. Trailing headers, the checksums clients add, are skipped. Chunks failing
+/// their signature end the stream with a `PermissionDenied` error.
+pub fn decode_chunks<S, E>(
+    body: S,
+    signer: Option<ChunkSigner>,
+) -> impl Stream<Item = Result<Bytes, io::Error>>
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+    E: Into<BoxError>,
+{
+    let decoder = ChunkDecoder {
+        body,
+        buffer: vec![],
+        signer,
+        done: false,
+    };
+    futures::stream::try_unfold(decoder, |mut decoder| async move {
+        Ok(decoder.next_chunk().await?.map(|chunk| (chunk, decoder)))
+    })
+}
+
+struct ChunkDecoder<S> {
+    body: S,
+    buffer: Vec<u8>,
+    signer: Option<ChunkSigner>,
+    done: bool,
+}
+
+impl<S, E> ChunkDecoder<S>
+where
+    S: Stream<Item = Result<Bytes, E>> + Unpin,
+    E: Into<BoxError>,
+{
+    async fn next_chunk(&mut self) -> Result<Option<Bytes>, io::Error> {
+        if self.done {
+            return Ok(None);
+        }
+        let line = self.read_line().await?;
+        let (size, extensions) = line.split_once(';').unwrap_or((&line, ""));
+        let size = usize::from_str_radix(size.trim(), 16)
+            .ok()
+            .filter(|size| *size <= MAX_CHUNK_SIZE)
+            .ok_or_else(|| invalid(format!("Invalid chunk size: {}", size)))?;
+        let signature = extensions
+            .split(';')
+            .find_map(|extension| extension.trim().strip_prefix("chunk-signature="))
+            .map(str::to_string);
+
+        let chunk = match size {
+            0 => vec![],
+            size => self.read_chunk(size).await?,
+        };
+        if let Some(signer) = &mut self.signer {
+            let signature = signature.ok_or_else(|| forged("Unsigned chunk"))?;
+            if !signer.verify(&chunk, &signature) {
+                return Err(forged("Chunk signature does not match"));
+            }
+        }
+
+        if size == 0 {
+            self.done = true;
+            return Ok(None);
+        }
+        Ok(Some(Bytes::from(chunk)))
+    }
+
+    /// Reads a chunk's data and the line break after it.
+    async fn read_chunk(&mut self, size: usize) -> Result<Vec<u8>, io::Error> {
+        while self.buffer.len() < size + 2 {
+            self.fill().await?;
+        }
+        let rest = self.buffer.split_off(size + 2);
+        let mut chunk = std::mem::replace(&mut self.buffer, rest);
+        if !chunk.ends_with(b"\r\n") {
+            return Err(invalid("Chunk is longer than its size".to_string()));
+        }
+        chunk.truncate(size);
+        Ok(chunk)
+    }
+
+    async fn read_line(&mut self) -> Result<String, io::Error> {
+        loop {
+            if let Some(end) = self.buffer.windows(2).position(|window| window == b"\r\n") {
+                let line = String::from_utf8_lossy(&self.buffer[..end]).into_owned();
+                self.buffer.drain(..end + 2);
+                return Ok(line);
+            }
+            if self.buffer.len() > MAX_LINE_LENGTH {
+                return Err(invalid("Chunk header is too long".to_string()));
+            }
+            self.fill().await?;
+        }
+    }
+
+    async fn fill(&mut self) -> Result<(), io::Error> {
+        match self.body.next().await {
+            Some(Ok(bytes)) => {
+                self.buffer.extend_from_slice(&bytes);
+                Ok(())
+            }
+            Some(Err(e)) => Err(io::Error::other(e)),
+            None => Err(io::Error::new(
+                ErrorKind::UnexpectedEof,
+                "Body ended within a chunk",
+            )),
+        }
+    }
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message)
+}
+
+/// A chunk whose signature does not check out, told apart from malformed bodies by its kind.
+fn forged(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::PermissionDenied, message)
+}
@@ -0,0 +1,80 @@
+mod chunked;
+mod multipart;
+mod signature;
+pub mod xml;
+
+pub use chunked::*;
+pub use multipart::*;
+pub use signature::*;
+
+/// Where the S3-compatible API is served, with buckets as the first path segment.
+pub const PREFIX: &str = "/s3";
+/// Most keys a listing returns.
+pub const MAX_KEYS: usize = 1000;
+
+/// Entries of a listing page, their keys in order.
+pub struct Page<T> {
+    pub objects: Vec<(String, T)>,
+    pub common_prefixes: Vec<String>,
+    pub is_truncated: bool,
+    /// The last key or common prefix of the page.
+    pub last: Option<String>,
+}
+
+/// Picks the page of `entries`, sorted by key, that starts after `start_after` and holds at
+/// most `max_keys` keys under `prefix`. Keys with the delimiter past the prefix are rolled up
+/// into a common prefix, which counts as one key.
+pub fn paginate<T>(
+    entries: Vec<(String, T)>,
+    prefix: &str,
+    delimiter: Option<&str>,
+    start_after: Option<&str>,
+    max_keys: usize,
+) -> Page<T> {
+    let delimiter = delimiter.filter(|delimiter| !delimiter.is_empty());
+    let mut page = Page {
+        objects: vec![],
+        common_prefixes: vec![],
+        is_truncated: false,
+        last: None,
+    };
+    let mut count = 0;
+    for (key, value) in entries {
+        if !key.starts_with(prefix) {
+            continue;
+        }
+        if let Some(start_after) = start_after {
+            // A page ending on a common prefix goes on after every key it rolled up.
+            let rolled_up = delimiter.is_some_and(|delimiter| start_after.ends_with(delimiter))
+                && key.starts_with(start_after);
+            if key.as_str() <= start_after || rolled_up {
+                continue;
+            }
+        }
+        let common_prefix = delimiter.and_then(|delimiter| {
+            key[prefix.len()..]
+                .find(delimiter)
+                .map(|end| key[..prefix.len() + end + delimiter.len()].to_string())
+        });
+        if common_prefix.is_some() && page.common_prefixes.last() == common_prefix.as_ref() {
+            continue;
+        }
+        if count == max_keys {
+            page.is_truncated = true;
+            break;
+        }
+        count += 1;
+
+        match common_prefix {
+            Some(common_prefix) => {
+                page.last = Some(common_prefix.clone());
+                page.common_prefixes.push(common_prefix);
+            }
+            None => {
+                page.last = Some(key.clone());
+                page.objects.push((key, value));
+            }
+        }
+    }
+    page
+}
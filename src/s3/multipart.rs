@@ -0,0 +1,249 @@
+use std::{
+    io::{self, ErrorKind},
+    path::PathBuf,
+    sync::Arc,
+};
+
+use axum::{body::Bytes, BoxError};
+use chrono::{DateTime, Utc};
+use futures::{Stream, StreamExt, TryStreamExt};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio_util::io::{ReaderStream, StreamReader};
+use uuid::Uuid;
+
+use crate::{configuration::S3Settings, domain::StorageDetails, encryption::Encryption};
+
+/// Highest part number S3 accepts.
+pub const MAX_PART_NUMBER: u32 = 10_000;
+/// Description of an upload, written in plaintext.
+pub const INFO_FILE: &str = "upload.json";
+
+#[derive(thiserror::Error, Debug)]
+pub enum MultipartError {
+    #[error("The multipart upload {0} does not exist")]
+    NoSuchUpload(String),
+    #[error("{0}")]
+    InvalidPart(String),
+    #[error("Parts must be listed in ascending order of their number")]
+    InvalidPartOrder,
+    #[error("The part does not have the checksum it was sent with")]
+    ChecksumMismatch,
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+/// What a multipart upload stores its file as.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct UploadInfo {
+    pub bucket: String,
+    pub key: String,
+    pub initiator: String,
+    pub content_type: Option<String>,
+    pub initiated_at: DateTime<Utc>,
+}
+
+/// A part listed by `CompleteMultipartUpload`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct CompletedPart {
+    pub number: u32,
+    pub etag: String,
+}
+
+/// Parts of multipart uploads, written to the internal directory as they arrive, until the
+/// upload is completed into a file of the storage or aborted. Parts are stored encrypted like
+/// any other file.
+pub struct MultipartUploads {
+    root: PathBuf,
+    encryption: Arc<Encryption>,
+    expiry: chrono::Duration,
+}
+
+impl MultipartUploads {
+    pub fn new(
+        settings: &S3Settings,
+        storage_details: &StorageDetails,
+        encryption: Arc<Encryption>,
+    ) -> Self {
+        Self {
+            root: storage_details.internal_path("s3-uploads"),
+            encryption,
+            expiry: chrono::Duration::hours(settings.multipart_upload_expiry_hours as i64),
+        }
+    }
+
+    /// Starts an upload, removing the expired ones on the way.
+    pub async fn create(&self, info: &UploadInfo) -> Result<String, io::Error> {
+        self.remove_expired().await;
+        let id = Uuid::new_v4().simple().to_string();
+        let directory = self.root.join(&id);
+        tokio::fs::create_dir_all(&directory).await?;
+        let info = serde_json::to_vec(info).map_err(io::Error::other)?;
+        tokio::fs::write(directory.join(INFO_FILE), info).await?;
+        Ok(id)
+    }
+
+    pub async fn info(&self, id: &str) -> Result<UploadInfo, MultipartError> {
+        let path = self.directory(id)?.join(INFO_FILE);
+        let info = match tokio::fs::read(&path).await {
+            Ok(info) => info,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                return Err(MultipartError::NoSuchUpload(id.to_string()))
+            }
+            Err(e) => return Err(e.into()),
+        };
+        Ok(serde_json::from_slice(&info).map_err(io::Error::other)?)
+    }
+
+    /// Stores a part, replacing any earlier one with its number, and returns its entity tag.
+    /// A part not matching the SHA-256 it was signed with is dropped.
+    pub async fn write_part<S, E>(
+        &self,
+        id: &str,
+        number: u32,
+        body: S,
+        expected_checksum: Option<&str>,
+    ) -> Result<String, MultipartError>
+    where
+        S: Stream<Item = Result<Bytes, E>>,
+        E: Into<BoxError>,
+    {
+        if !(1..=MAX_PART_NUMBER).contains(&number) {
+            return Err(MultipartError::InvalidPart(format!(
+                "Part numbers go from 1 to {}",
+                MAX_PART_NUMBER
+            )));
+        }
+        let directory = self.directory(id)?;
+        let written_path = directory.join(format!("{}.{}", number, Uuid::new_v4().simple()));
+
+        let mut hasher = Sha256::new();
+        let body = body
+            .inspect_ok(|bytes| hasher.update(bytes))
+            .map_err(io::Error::other);
+        let reader = StreamReader::new(body);
+        futures::pin_mut!(reader);
+        if let Err(e) = self.encryption.write(&written_path, &mut reader).await {
+            let _ = tokio::fs::remove_file(&written_path).await;
+            return Err(e.into());
+        }
+
+        // The entity tag is the name the part is kept under, so a part and its tag are replaced
+        // together.
+        let etag = hex::encode(hasher.finalize());
+        if expected_checksum.is_some_and(|checksum| !checksum.eq_ignore_ascii_case(&etag)) {
+            let _ = tokio::fs::remove_file(&written_path).await;
+            return Err(MultipartError::ChecksumMismatch);
+        }
+        let part_path = directory.join(format!("{}.{}", number, etag));
+        tokio::fs::rename(&written_path, &part_path).await?;
+        let mut entries = tokio::fs::read_dir(&directory).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            if entry.path() != part_path && part_number(&name) == Some(number) {
+                tokio::fs::remove_file(entry.path()).await?;
+            }
+        }
+        Ok(format!("\"{}\"", etag))
+    }
+
+    /// The stored parts listed by a completion, checked against their entity tags.
+    pub async fn parts(
+        &self,
+        id: &str,
+        completed: &[CompletedPart],
+    ) -> Result<Vec<PathBuf>, MultipartError> {
+        if completed.is_empty() {
+            return Err(MultipartError::InvalidPart(
+                "At least one part must be listed".to_string(),
+            ));
+        }
+        if completed
+            .windows(2)
+            .any(|parts| parts[0].number >= parts[1].number)
+        {
+            return Err(MultipartError::InvalidPartOrder);
+        }
+
+        let directory = self.directory(id)?;
+        let mut paths = vec![];
+        for part in completed {
+            let not_uploaded = || {
+                MultipartError::InvalidPart(format!(
+                    "Part {} with entity tag {} was not uploaded",
+                    part.number, part.etag
+                ))
+            };
+            let etag = part.etag.trim_matches('"');
+            if etag.len() != 64 || !etag.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return Err(not_uploaded());
+            }
+            let path = directory.join(format!("{}.{}", part.number, etag.to_ascii_lowercase()));
+            match tokio::fs::metadata(&path).await {
+                Ok(_) => paths.push(path),
+                Err(e) if e.kind() == ErrorKind::NotFound => return Err(not_uploaded()),
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(paths)
+    }
+
+    /// The content of the parts one after another.
+    pub fn read_parts(
+        &self,
+        paths: Vec<PathBuf>,
+    ) -> impl Stream<Item = Result<Bytes, io::Error>> + Send {
+        let encryption = self.encryption.clone();
+        futures::stream::iter(paths)
+            .then(move |path| {
+                let encryption = encryption.clone();
+                async move { encryption.open(&path).await?.into_reader().await }
+            })
+            .map_ok(ReaderStream::new)
+            .try_flatten()
+    }
+
+    pub async fn remove(&self, id: &str) -> Result<(), MultipartError> {
+        match tokio::fs::remove_dir_all(self.directory(id)?).await {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                Err(MultipartError::NoSuchUpload(id.to_string()))
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn remove_expired(&self) {
+        let Ok(mut entries) = tokio::fs::read_dir(&self.root).await else {
+            return;
+        };
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let id = entry.file_name().to_string_lossy().into_owned();
+            let expired = match self.info(&id).await {
+                Ok(info) => Utc::now() - info.initiated_at > self.expiry,
+                // Left behind by an upload that failed to start.
+                Err(MultipartError::NoSuchUpload(_)) => true,
+                Err(_) => false,
+            };
+            if expired {
+                if let Err(e) = tokio::fs::remove_dir_all(entry.path()).await {
+                    tracing::warn!("Failed to remove expired multipart upload {}: {:?}", id, e);
+                }
+            }
+        }
+    }
+
+    /// The directory of an upload, refusing ids that are not ours.
+    fn directory(&self, id: &str) -> Result<PathBuf, MultipartError> {
+        match Uuid::try_parse(id) {
+            Ok(uuid) if uuid.simple().to_string() == id => Ok(self.root.join(id)),
+            _ => Err(MultipartError::NoSuchUpload(id.to_string())),
+        }
+    }
+}
+
+/// The number of a part kept as `<number>.<entity tag>`, or being written as `<number>.<id>`.
+fn part_number(name: &str) -> Option<u32> {
+    name.split_once('.')?.0.parse().ok()
+}
@@ -0,0 +1,462 @@
+use std::sync::Arc;
+
+use axum::http::{HeaderMap, Method, Uri};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use hmac::{Hmac, Mac};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    configuration::{AuthSettings, S3Settings},
+    domain::Identity,
+};
+
+const ALGORITHM: &str = "AWS4-HMAC-SHA256";
+const CHUNK_ALGORITHM: &str = "AWS4-HMAC-SHA256-PAYLOAD";
+const TIMESTAMP_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+/// Longest a presigned URL may be valid for, a week.
+const MAX_PRESIGNED_EXPIRY_SECONDS: i64 = 7 * 24 * 3600;
+/// Hash of an empty payload.
+const EMPTY_SHA256: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+/// Characters escaped by the URI encoding of canonical requests, everything but the unreserved
+/// ones.
+pub const URI_ENCODE: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+const PATH_ENCODE: &AsciiSet = &URI_ENCODE.remove(b'/');
+
+const CONTENT_SHA256: &str = "x-amz-content-sha256";
+const DATE: &str = "x-amz-date";
+
+#[derive(thiserror::Error, Debug)]
+pub enum SignatureError {
+    #[error("{0}")]
+    Malformed(String),
+    #[error("Anonymous requests are not allowed once API keys are configured")]
+    Anonymous,
+    #[error("The access key {0} does not exist")]
+    UnknownAccessKey(String),
+    #[error("The request signature does not match the one calculated")]
+    Mismatch,
+    #[error("The request was signed too far from the server time")]
+    TimeSkewed,
+    #[error("The presigned URL has expired")]
+    Expired,
+}
+
+/// How the payload of a request was signed, from its `x-amz-content-sha256` header.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Payload {
+    Unsigned,
+    /// The hex SHA-256 of the whole body.
+    Sha256(String),
+    /// An `aws-chunked` body whose chunks are signed one after another.
+    StreamingSigned,
+    /// An `aws-chunked` body with unsigned chunks, followed by trailing checksum headers.
+    StreamingUnsignedTrailer,
+}
+
+impl Payload {
+    fn parse(value: Option<&str>) -> Result<Self, SignatureError> {
+        match value {
+            None | Some("UNSIGNED-PAYLOAD") => Ok(Payload::Unsigned),
+            Some("STREAMING-AWS4-HMAC-SHA256-PAYLOAD") => Ok(Payload::StreamingSigned),
+            Some("STREAMING-UNSIGNED-PAYLOAD-TRAILER") => Ok(Payload::StreamingUnsignedTrailer),
+            Some(hash) if hash.len() == 64 && hash.bytes().all(|b| b.is_ascii_hexdigit()) => {
+                Ok(Payload::Sha256(hash.to_ascii_lowercase()))
+            }
+            Some(value) => Err(SignatureError::Malformed(format!(
+                "Unsupported {}: {}",
+                CONTENT_SHA256, value
+            ))),
+        }
+    }
+
+    fn canonical(&self, header: Option<&str>) -> String {
+        match self {
+            Payload::Sha256(hash) => hash.clone(),
+            _ => header.unwrap_or("UNSIGNED-PAYLOAD").to_string(),
+        }
+    }
+
+    pub fn is_chunked(&self) -> bool {
+        matches!(
+            self,
+            Payload::StreamingSigned | Payload::StreamingUnsignedTrailer
+        )
+    }
+}
+
+/// The caller of a request whose signature checked out, and how its payload is to be read.
+pub struct Signed {
+    pub identity: Identity,
+    pub payload: Payload,
+    /// Checks the chunks of a `STREAMING-AWS4-HMAC-SHA256-PAYLOAD` body.
+    pub chunk_signer: Option<ChunkSigner>,
+}
+
+/// Checks the signature of each chunk of a signed `aws-chunked` body, chained from the one of
+/// the request.
+pub struct ChunkSigner {
+    key: Vec<u8>,
+    timestamp: String,
+    scope: String,
+    previous: String,
+}
+
+impl ChunkSigner {
+    pub fn verify(&mut self, chunk: &[u8], signature: &str) -> bool {
+        let string_to_sign = format!(
+            "{}\n{}\n{}\n{}\n{}\n{}",
+            CHUNK_ALGORITHM,
+            self.timestamp,
+            self.scope,
+            self.previous,
+            EMPTY_SHA256,
+            hex::encode(Sha256::digest(chunk))
+        );
+        if !verify(&self.key, &string_to_sign, signature) {
+            return false;
+        }
+        self.previous = signature.to_string();
+        true
+    }
+}
+
+/// Authenticates S3 requests signed with AWS Signature Version 4, in the `Authorization` header
+/// or the query of a presigned URL. Access key ids are the user names of the configured API
+/// keys, and secret keys the keys themselves. Unsigned requests are anonymous.
+pub struct Authenticator {
+    auth: Arc<AuthSettings>,
+    max_clock_skew: chrono::Duration,
+}
+
+/// The parts of a signature, from the header or the query.
+struct Credentials<'a> {
+    access_key: &'a str,
+    date: &'a str,
+    region: &'a str,
+    service: &'a str,
+    signed_headers: Vec<&'a str>,
+    signature: String,
+    timestamp: String,
+}
+
+impl Authenticator {
+    pub fn new(settings: &S3Settings, auth: Arc<AuthSettings>) -> Self {
+        Self {
+            auth,
+            max_clock_skew: chrono::Duration::seconds(settings.max_clock_skew_seconds as i64),
+        }
+    }
+
+    pub fn authenticate(
+        &self,
+        method: &Method,
+        uri: &Uri,
+        headers: &HeaderMap,
+    ) -> Result<Signed, SignatureError> {
+        let content_sha256 = header(headers, CONTENT_SHA256);
+        let payload = Payload::parse(content_sha256)?;
+        let query = query_pairs(uri.query());
+        let authorization = header(headers, "authorization");
+
+        let (credentials, presigned) = match authorization {
+            Some(authorization) if authorization.starts_with(ALGORITHM) => {
+                (header_credentials(authorization, headers)?, false)
+            }
+            _ if query.iter().any(|(name, _)| name == "X-Amz-Algorithm") => {
+                (query_credentials(&query)?, true)
+            }
+            _ => {
+                return Ok(Signed {
+                    identity: Identity::anonymous(&self.auth).ok_or(SignatureError::Anonymous)?,
+                    payload,
+                    chunk_signer: None,
+                })
+            }
+        };
+
+        let signed_at = NaiveDateTime::parse_from_str(&credentials.timestamp, TIMESTAMP_FORMAT)
+            .map_err(|_| {
+                SignatureError::Malformed(format!("Invalid timestamp: {}", credentials.timestamp))
+            })?;
+        let signed_at = DateTime::<Utc>::from_utc(signed_at, Utc);
+        if !credentials.timestamp.starts_with(credentials.date) {
+            return Err(SignatureError::Malformed(
+                "The credential date does not match the timestamp".to_string(),
+            ));
+        }
+        self.check_time(signed_at, &query, presigned)?;
+
+        let canonical_request = format!(
+            "{}\n{}\n{}\n{}\n{}\n{}",
+            method.as_str(),
+            canonical_path(uri.path()),
+            canonical_query(&query, presigned),
+            canonical_headers(headers, &credentials.signed_headers)?,
+            credentials.signed_headers.join(";"),
+            match presigned {
+                true => "UNSIGNED-PAYLOAD".to_string(),
+                false => payload.canonical(content_sha256),
+            }
+        );
+        let scope = format!(
+            "{}/{}/{}/aws4_request",
+            credentials.date, credentials.region, credentials.service
+        );
+        let string_to_sign = format!(
+            "{}\n{}\n{}\n{}",
+            ALGORITHM,
+            credentials.timestamp,
+            scope,
+            hex::encode(Sha256::digest(canonical_request.as_bytes()))
+        );
+
+        let mut known = false;
+        for api_key in self
+            .auth
+            .api_keys
+            .iter()
+            .filter(|api_key| api_key.user == credentials.access_key)
+        {
+            known = true;
+            let key = signing_key(
+                &api_key.key,
+                credentials.date,
+                credentials.region,
+                credentials.service,
+            );
+            if verify(&key, &string_to_sign, &credentials.signature) {
+                let identity = Identity::from_api_key(&self.auth, &api_key.key)
+                    .expect("The API key was just found");
+                let chunk_signer = (payload == Payload::StreamingSigned).then(|| ChunkSigner {
+                    key,
+                    timestamp: credentials.timestamp.clone(),
+                    scope: scope.clone(),
+                    previous: credentials.signature.clone(),
+                });
+                return Ok(Signed {
+                    identity,
+                    payload,
+                    chunk_signer,
+                });
+            }
+        }
+        match known {
+            true => Err(SignatureError::Mismatch),
+            false => Err(SignatureError::UnknownAccessKey(
+                credentials.access_key.to_string(),
+            )),
+        }
+    }
+
+    fn check_time(
+        &self,
+        signed_at: DateTime<Utc>,
+        query: &[(String, String)],
+        presigned: bool,
+    ) -> Result<(), SignatureError> {
+        let now = Utc::now();
+        let skew = self.max_clock_skew;
+        if signed_at - now > skew {
+            return Err(SignatureError::TimeSkewed);
+        }
+        if !presigned {
+            return match now - signed_at > skew {
+                true => Err(SignatureError::TimeSkewed),
+                false => Ok(()),
+            };
+        }
+
+        let expires = query_value(query, "X-Amz-Expires")
+            .and_then(|expires| expires.parse::<i64>().ok())
+            .filter(|expires| (0..=MAX_PRESIGNED_EXPIRY_SECONDS).contains(expires))
+            .ok_or_else(|| SignatureError::Malformed("Invalid X-Amz-Expires".to_string()))?;
+        match now > signed_at + chrono::Duration::seconds(expires) {
+            true => Err(SignatureError::Expired),
+            false => Ok(()),
+        }
+    }
+}
+
+/// Reads `AWS4-HMAC-SHA256 Credential=..., SignedHeaders=..., Signature=...`.
+fn header_credentials<'a>(
+    authorization: &'a str,
+    headers: &HeaderMap,
+) -> Result<Credentials<'a>, SignatureError> {
+    let fields = authorization[ALGORITHM.len()..]
+        .split(',')
+        .filter_map(|field| field.trim().split_once('='))
+        .collect::<Vec<_>>();
+    let field = |name: &str| {
+        fields
+            .iter()
+            .find(|(field, _)| *field == name)
+            .map(|(_, value)| *value)
+            .ok_or_else(|| {
+                SignatureError::Malformed(format!("The Authorization header has no {}", name))
+            })
+    };
+    let timestamp = header(headers, DATE)
+        .ok_or_else(|| SignatureError::Malformed(format!("Missing {} header", DATE)))?;
+    credentials(
+        field("Credential")?,
+        field("SignedHeaders")?,
+        field("Signature")?,
+        timestamp,
+    )
+}
+
+/// Reads the `X-Amz-*` parameters of a presigned URL.
+fn query_credentials(query: &[(String, String)]) -> Result<Credentials<'_>, SignatureError> {
+    let parameter = |name: &str| {
+        query_value(query, name)
+            .ok_or_else(|| SignatureError::Malformed(format!("The query has no {}", name)))
+    };
+    if parameter("X-Amz-Algorithm")? != ALGORITHM {
+        return Err(SignatureError::Malformed(format!(
+            "Only {} is supported",
+            ALGORITHM
+        )));
+    }
+    credentials(
+        parameter("X-Amz-Credential")?,
+        parameter("X-Amz-SignedHeaders")?,
+        parameter("X-Amz-Signature")?,
+        parameter("X-Amz-Date")?,
+    )
+}
+
+/// Splits a credential, `<access key>/<date>/<region>/s3/aws4_request`.
+fn credentials<'a>(
+    credential: &'a str,
+    signed_headers: &'a str,
+    signature: &str,
+    timestamp: &str,
+) -> Result<Credentials<'a>, SignatureError> {
+    let parts = credential.split('/').collect::<Vec<_>>();
+    let [access_key, date, region, service, "aws4_request"] = parts[..] else {
+        return Err(SignatureError::Malformed(format!(
+            "Invalid credential: {}",
+            credential
+        )));
+    };
+    if service != "s3" {
+        return Err(SignatureError::Malformed(format!(
+            "Invalid service in credential: {}",
+            service
+        )));
+    }
+    Ok(Credentials {
+        access_key,
+        date,
+        region,
+        service,
+        signed_headers: signed_headers.split(';').collect(),
+        signature: signature.to_ascii_lowercase(),
+        timestamp: timestamp.to_string(),
+    })
+}
+
+/// The path as signed, each segment URI encoded once.
+fn canonical_path(path: &str) -> String {
+    let decoded = percent_decode_str(path).decode_utf8_lossy();
+    utf8_percent_encode(&decoded, PATH_ENCODE).to_string()
+}
+
+/// The query as signed, sorted and URI encoded, without the signature of presigned URLs.
+fn canonical_query(query: &[(String, String)], presigned: bool) -> String {
+    let mut pairs = query
+        .iter()
+        .filter(|(name, _)| !(presigned && name == "X-Amz-Signature"))
+        .map(|(name, value)| {
+            (
+                utf8_percent_encode(name, URI_ENCODE).to_string(),
+                utf8_percent_encode(value, URI_ENCODE).to_string(),
+            )
+        })
+        .collect::<Vec<_>>();
+    pairs.sort();
+    pairs
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+/// The signed headers as `name:value` lines, values trimmed with inner spaces folded.
+fn canonical_headers(headers: &HeaderMap, signed: &[&str]) -> Result<String, SignatureError> {
+    let mut canonical = String::new();
+    for name in signed {
+        let values = headers
+            .get_all(*name)
+            .iter()
+            .map(|value| {
+                let value = String::from_utf8_lossy(value.as_bytes());
+                value.split_whitespace().collect::<Vec<_>>().join(" ")
+            })
+            .collect::<Vec<_>>();
+        if values.is_empty() {
+            return Err(SignatureError::Malformed(format!(
+                "The signed header {} is missing",
+                name
+            )));
+        }
+        canonical.push_str(&format!("{}:{}\n", name, values.join(",")));
+    }
+    Ok(canonical)
+}
+
+/// Decodes the pairs of a query, keeping `+` as is since S3 clients encode spaces as `%20`.
+pub fn query_pairs(query: Option<&str>) -> Vec<(String, String)> {
+    query
+        .unwrap_or_default()
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (
+                percent_decode_str(name).decode_utf8_lossy().into_owned(),
+                percent_decode_str(value).decode_utf8_lossy().into_owned(),
+            )
+        })
+        .collect()
+}
+
+pub fn query_value<'a>(query: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    query
+        .iter()
+        .find(|(parameter, _)| parameter == name)
+        .map(|(_, value)| value.as_str())
+}
+
+fn header<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).and_then(|value| value.to_str().ok())
+}
+
+fn signing_key(secret: &str, date: &str, region: &str, service: &str) -> Vec<u8> {
+    let mut key = format!("AWS4{}", secret).into_bytes();
+    for part in [date, region, service, "aws4_request"] {
+        key = hmac(&key, part.as_bytes());
+    }
+    key
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any size");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Compares a hex signature with the expected one in constant time.
+fn verify(key: &[u8], string_to_sign: &str, signature: &str) -> bool {
+    let Ok(signature) = hex::decode(signature) else {
+        return false;
+    };
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any size");
+    mac.update(string_to_sign.as_bytes());
+    mac.verify_slice(&signature).is_ok()
+}
@@ -0,0 +1,260 @@
+use std::time::SystemTime;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use percent_encoding::{utf8_percent_encode, AsciiSet};
+use quick_xml::{escape::escape, events::Event, Reader};
+
+use crate::s3::{CompletedPart, URI_ENCODE};
+
+const NAMESPACE: &str = "http://s3.amazonaws.com/doc/2006-03-01/";
+const DECLARATION: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\"?>";
+/// Characters escaped in keys of listings asked for with `encoding-type=url`.
+const KEY_ENCODE: &AsciiSet = &URI_ENCODE.remove(b'/');
+
+/// A bucket of `ListBuckets`.
+pub struct Bucket {
+    pub name: String,
+    pub created_at: SystemTime,
+}
+
+/// A file of an object listing.
+pub struct Object {
+    pub key: String,
+    pub size: u64,
+    pub modified_at: SystemTime,
+    pub etag: String,
+}
+
+/// A page of `ListObjects` or `ListObjectsV2`.
+#[derive(Default)]
+pub struct Listing {
+    pub objects: Vec<Object>,
+    pub common_prefixes: Vec<String>,
+    pub is_truncated: bool,
+    /// The last key or common prefix of the page, where the next one starts after.
+    pub last: Option<String>,
+}
+
+/// What a listing was asked for with, echoed back in its result.
+pub struct ListParameters {
+    pub bucket: String,
+    pub prefix: String,
+    pub delimiter: Option<String>,
+    pub max_keys: usize,
+    pub url_encoded: bool,
+    pub version: ListVersion,
+}
+
+pub enum ListVersion {
+    V1 {
+        marker: Option<String>,
+    },
+    V2 {
+        continuation_token: Option<String>,
+        start_after: Option<String>,
+    },
+}
+
+pub fn error(code: &str, message: &str) -> String {
+    format!(
+        "{}<Error><Code>{}</Code><Message>{}</Message></Error>",
+        DECLARATION,
+        code,
+        escape(message)
+    )
+}
+
+pub fn list_buckets(owner: &str, buckets: &[Bucket]) -> String {
+    let buckets = buckets
+        .iter()
+        .map(|bucket| {
+            format!(
+                "<Bucket><Name>{}</Name><CreationDate>{}</CreationDate></Bucket>",
+                escape(&bucket.name),
+                timestamp(bucket.created_at)
+            )
+        })
+        .collect::<String>();
+    format!(
+        "{}<ListAllMyBucketsResult xmlns=\"{}\">{}<Buckets>{}</Buckets></ListAllMyBucketsResult>",
+        DECLARATION,
+        NAMESPACE,
+        self::owner(owner),
+        buckets
+    )
+}
+
+pub fn list_objects(parameters: &ListParameters, listing: &Listing) -> String {
+    let key = |key: &str| match parameters.url_encoded {
+        true => utf8_percent_encode(key, KEY_ENCODE).to_string(),
+        false => escape(key).into_owned(),
+    };
+    let element = |name: &str, value: &str| format!("<{0}>{1}</{0}>", name, key(value));
+
+    let mut result = format!(
+        "{}<ListBucketResult xmlns=\"{}\"><Name>{}</Name>",
+        DECLARATION,
+        NAMESPACE,
+        escape(&parameters.bucket)
+    );
+    result.push_str(&element("Prefix", &parameters.prefix));
+    if let Some(delimiter) = &parameters.delimiter {
+        result.push_str(&element("Delimiter", delimiter));
+    }
+    result.push_str(&format!(
+        "<MaxKeys>{}</MaxKeys><IsTruncated>{}</IsTruncated>",
+        parameters.max_keys, listing.is_truncated
+    ));
+    if parameters.url_encoded {
+        result.push_str("<EncodingType>url</EncodingType>");
+    }
+    let next = listing.last.as_deref().filter(|_| listing.is_truncated);
+    match &parameters.version {
+        ListVersion::V1 { marker } => {
+            result.push_str(&element("Marker", marker.as_deref().unwrap_or_default()));
+            // Clients only get the next marker with a delimiter, they take the last key
+            // otherwise.
+            if let Some(next) = next.filter(|_| parameters.delimiter.is_some()) {
+                result.push_str(&element("NextMarker", next));
+            }
+        }
+        ListVersion::V2 {
+            continuation_token,
+            start_after,
+        } => {
+            result.push_str(&format!(
+                "<KeyCount>{}</KeyCount>",
+                listing.objects.len() + listing.common_prefixes.len()
+            ));
+            if let Some(token) = continuation_token {
+                result.push_str(&format!(
+                    "<ContinuationToken>{}</ContinuationToken>",
+                    escape(token)
+                ));
+            }
+            if let Some(next) = next {
+                result.push_str(&format!(
+                    "<NextContinuationToken>{}</NextContinuationToken>",
+                    base64::encode_config(next, base64::URL_SAFE_NO_PAD)
+                ));
+            }
+            if let Some(start_after) = start_after {
+                result.push_str(&element("StartAfter", start_after));
+            }
+        }
+    }
+
+    for object in &listing.objects {
+        result.push_str(&format!(
+            "<Contents>{}<LastModified>{}</LastModified><ETag>{}</ETag><Size>{}</Size>\
+             <StorageClass>STANDARD</StorageClass></Contents>",
+            element("Key", &object.key),
+            timestamp(object.modified_at),
+            escape(&object.etag),
+            object.size
+        ));
+    }
+    for prefix in &listing.common_prefixes {
+        result.push_str(&format!(
+            "<CommonPrefixes>{}</CommonPrefixes>",
+            element("Prefix", prefix)
+        ));
+    }
+    result.push_str("</ListBucketResult>");
+    result
+}
+
+/// The key a `ListObjectsV2` continuation token stands for.
+pub fn continuation_key(token: &str) -> Option<String> {
+    let key = base64::decode_config(token, base64::URL_SAFE_NO_PAD).ok()?;
+    String::from_utf8(key).ok()
+}
+
+pub fn location_constraint() -> String {
+    format!(
+        "{}<LocationConstraint xmlns=\"{}\"/>",
+        DECLARATION, NAMESPACE
+    )
+}
+
+pub fn initiate_multipart_upload(bucket: &str, key: &str, upload_id: &str) -> String {
+    format!(
+        "{}<InitiateMultipartUploadResult xmlns=\"{}\"><Bucket>{}</Bucket><Key>{}</Key>\
+         <UploadId>{}</UploadId></InitiateMultipartUploadResult>",
+        DECLARATION,
+        NAMESPACE,
+        escape(bucket),
+        escape(key),
+        upload_id
+    )
+}
+
+pub fn complete_multipart_upload(location: &str, bucket: &str, key: &str, etag: &str) -> String {
+    format!(
+        "{}<CompleteMultipartUploadResult xmlns=\"{}\"><Location>{}</Location>\
+         <Bucket>{}</Bucket><Key>{}</Key><ETag>{}</ETag></CompleteMultipartUploadResult>",
+        DECLARATION,
+        NAMESPACE,
+        escape(location),
+        escape(bucket),
+        escape(key),
+        escape(etag)
+    )
+}
+
+/// Reads the parts listed by a `CompleteMultipartUpload` body.
+pub fn parse_complete_multipart_upload(body: &[u8]) -> Result<Vec<CompletedPart>, String> {
+    let mut reader = Reader::from_reader(body);
+    reader.trim_text(true);
+    let mut buffer = vec![];
+    let mut parts = vec![];
+    let mut number = None;
+    let mut etag = None;
+    let mut current = None;
+    loop {
+        match reader.read_event_into(&mut buffer) {
+            Ok(Event::Start(start)) => {
+                current = Some(String::from_utf8_lossy(start.local_name().as_ref()).into_owned())
+            }
+            Ok(Event::Text(text)) => {
+                let text = text.unescape().map_err(|e| e.to_string())?;
+                match current.as_deref() {
+                    Some("PartNumber") => {
+                        number = Some(
+                            text.trim()
+                                .parse::<u32>()
+                                .map_err(|_| format!("Invalid part number: {}", text))?,
+                        )
+                    }
+                    Some("ETag") => etag = Some(text.trim().to_string()),
+                    _ => {}
+                }
+            }
+            Ok(Event::End(end)) => {
+                current = None;
+                if end.local_name().as_ref() == b"Part" {
+                    match (number.take(), etag.take()) {
+                        (Some(number), Some(etag)) => parts.push(CompletedPart { number, etag }),
+                        _ => return Err("Parts need a PartNumber and an ETag".to_string()),
+                    }
+                }
+            }
+            Ok(Event::Eof) => break,
+            Ok(_) => {}
+            Err(e) => return Err(e.to_string()),
+        }
+        buffer.clear();
+    }
+    Ok(parts)
+}
+
+fn owner(name: &str) -> String {
+    format!(
+        "<Owner><ID>{0}</ID><DisplayName>{0}</DisplayName></Owner>",
+        escape(name)
+    )
+}
+
+fn timestamp(time: SystemTime) -> String {
+    DateTime::<Utc>::from(time).to_rfc3339_opts(SecondsFormat::Millis, true)
+}
@@ -0,0 +1,259 @@
+use std::{
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    fs,
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::TcpStream,
+};
+use uuid::Uuid;
+
+use crate::{
+    configuration::{InfectedAction, ScanningSettings},
+    domain::StorageDetails,
+    encryption::Encryption,
+};
+
+/// Bytes sent to clamd per INSTREAM chunk, well below its default `StreamMaxLength`.
+const CHUNK_SIZE: usize = 64 * 1024;
+/// Description of a quarantined file, written in plaintext.
+pub const ENTRY_FILE_NAME: &str = "entry.json";
+const CONTENT_NAME: &str = "content";
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Verdict {
+    Clean,
+    /// Infected, with the name of the signature found.
+    Infected(String),
+}
+
+/// Scans file content for malware.
+#[async_trait]
+pub trait Scanner: Send + Sync {
+    async fn scan(
+        &self,
+        content: &mut (dyn AsyncRead + Send + Unpin),
+    ) -> Result<Verdict, io::Error>;
+}
+
+/// A clamd-compatible daemon, reached at `tcp://host:port` or `unix:///path/to/socket`.
+pub struct Clamd {
+    address: String,
+    timeout: Duration,
+}
+
+impl Clamd {
+    pub fn new(address: impl Into<String>, timeout: Duration) -> Self {
+        Self {
+            address: address.into(),
+            timeout,
+        }
+    }
+
+    async fn scan_content(
+        &self,
+        content: &mut (dyn AsyncRead + Send + Unpin),
+    ) -> Result<Verdict, io::Error> {
+        let reply = if let Some(address) = self.address.strip_prefix("tcp://") {
+            instream(TcpStream::connect(address).await?, content).await?
+        } else if let Some(socket) = self.address.strip_prefix("unix://") {
+            #[cfg(unix)]
+            {
+                instream(tokio::net::UnixStream::connect(socket).await?, content).await?
+            }
+            #[cfg(not(unix))]
+            {
+                return Err(io::Error::new(
+                    ErrorKind::Unsupported,
+                    format!("Unix sockets are not supported: {}", socket),
+                ));
+            }
+        } else {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("Unsupported clamd address: {}", self.address),
+            ));
+        };
+
+        parse_reply(&reply)
+    }
+}
+
+#[async_trait]
+impl Scanner for Clamd {
+    async fn scan(
+        &self,
+        content: &mut (dyn AsyncRead + Send + Unpin),
+    ) -> Result<Verdict, io::Error> {
+        tokio::time::timeout(self.timeout, self.scan_content(content))
+            .await
+            .map_err(|_| io::Error::new(ErrorKind::TimedOut, "clamd did not reply in time"))?
+    }
+}
+
+/// Streams the content to clamd with the INSTREAM command, returning its reply.
+async fn instream<S>(
+    mut stream: S,
+    content: &mut (dyn AsyncRead + Send + Unpin),
+) -> Result<String, io::Error>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    stream.write_all(b"zINSTREAM\0").await?;
+    let mut buffer = vec![0; CHUNK_SIZE];
+    loop {
+        let read = content.read(&mut buffer).await?;
+        // Every chunk is prefixed with its length, and a zero length ends the stream.
+        stream.write_all(&(read as u32).to_be_bytes()).await?;
+        if read == 0 {
+            break;
+        }
+        stream.write_all(&buffer[..read]).await?;
+    }
+    stream.flush().await?;
+
+    let mut reply = vec![];
+    stream.read_to_end(&mut reply).await?;
+    Ok(String::from_utf8_lossy(&reply)
+        .trim_end_matches(['\0', '\n'])
+        .to_string())
+}
+
+/// Reads replies like `stream: OK` and `stream: Eicar-Test-Signature FOUND`.
+fn parse_reply(reply: &str) -> Result<Verdict, io::Error> {
+    let result = reply.strip_prefix("stream: ").unwrap_or(reply);
+    if result == "OK" {
+        Ok(Verdict::Clean)
+    } else if let Some(signature) = result.strip_suffix(" FOUND") {
+        Ok(Verdict::Infected(signature.to_string()))
+    } else {
+        Err(io::Error::other(format!("clamd failed to scan: {}", reply)))
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum ScanError {
+    #[error("{path} is infected with {signature}")]
+    Infected { path: String, signature: String },
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct QuarantineEntry {
+    pub id: String,
+    /// Path relative to the storage root the file was uploaded to.
+    pub path: String,
+    pub signature: String,
+    pub quarantined_at: DateTime<Utc>,
+}
+
+/// The scanning stage of uploads. Staged files are scanned before they are moved into place,
+/// and infected ones are deleted or kept under the internal `quarantine` area, like the trash
+/// with an `entry.json` next to the content.
+pub struct Scanning {
+    scanner: Option<Arc<dyn Scanner>>,
+    on_infected: InfectedAction,
+    quarantine_root: PathBuf,
+    encryption: Arc<Encryption>,
+}
+
+impl Scanning {
+    pub fn new(
+        settings: &ScanningSettings,
+        storage_details: &StorageDetails,
+        encryption: Arc<Encryption>,
+    ) -> Self {
+        let scanner = settings.enabled.then(|| {
+            Arc::new(Clamd::new(
+                &settings.clamd_address,
+                Duration::from_secs(settings.timeout_seconds),
+            )) as Arc<dyn Scanner>
+        });
+        Self::with_scanner(scanner, settings.on_infected, storage_details, encryption)
+    }
+
+    pub fn with_scanner(
+        scanner: Option<Arc<dyn Scanner>>,
+        on_infected: InfectedAction,
+        storage_details: &StorageDetails,
+        encryption: Arc<Encryption>,
+    ) -> Self {
+        Self {
+            scanner,
+            on_infected,
+            quarantine_root: storage_details.internal_path("quarantine"),
+            encryption,
+        }
+    }
+
+    /// Scans a staged file headed for `relative_path`. An infected file is removed from the
+    /// staging area.
+    #[tracing::instrument(name = "Scan staged file", skip(self, staged_path))]
+    pub async fn check(&self, staged_path: &Path, relative_path: &str) -> Result<(), ScanError> {
+        let signature = match &self.scanner {
+            Some(scanner) => {
+                let mut content = self
+                    .encryption
+                    .open(staged_path)
+                    .await?
+                    .into_reader()
+                    .await?;
+                match scanner.scan(&mut content).await? {
+                    Verdict::Clean => return Ok(()),
+                    Verdict::Infected(signature) => signature,
+                }
+            }
+            None => return Ok(()),
+        };
+
+        match self.on_infected {
+            InfectedAction::Reject => fs::remove_file(staged_path).await?,
+            InfectedAction::Quarantine => {
+                let entry = QuarantineEntry {
+                    id: Uuid::new_v4().simple().to_string(),
+                    path: relative_path.to_string(),
+                    signature: signature.clone(),
+                    quarantined_at: Utc::now(),
+                };
+                let entry_directory = self.quarantine_root.join(&entry.id);
+                fs::create_dir_all(&entry_directory).await?;
+                fs::write(
+                    entry_directory.join(ENTRY_FILE_NAME),
+                    serde_json::to_vec(&entry).map_err(io::Error::from)?,
+                )
+                .await?;
+                fs::rename(staged_path, entry_directory.join(CONTENT_NAME)).await?;
+            }
+        }
+
+        tracing::warn!("Rejected {} infected with {}", relative_path, signature);
+        Err(ScanError::Infected {
+            path: relative_path.to_string(),
+            signature,
+        })
+    }
+
+    /// Lists the quarantined files.
+    pub async fn quarantined(&self) -> Result<Vec<QuarantineEntry>, io::Error> {
+        let mut entries = match fs::read_dir(&self.quarantine_root).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e),
+        };
+
+        let mut quarantined = vec![];
+        while let Some(entry) = entries.next_entry().await? {
+            let contents = fs::read(entry.path().join(ENTRY_FILE_NAME)).await?;
+            quarantined.push(serde_json::from_slice(&contents)?);
+        }
+        Ok(quarantined)
+    }
+}
@@ -1,40 +1,289 @@
-use std::{net::TcpListener, sync::Arc, time::Duration};
+use std::{
+    io,
+    net::{SocketAddr, TcpListener},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use crate::{
-    domain::StorageDetails,
-    routes::{health_check, upload},
+    archives::Archives,
+    audit::AuditLog,
+    compression::Compression,
+    configuration::{CompressionSettings, EncryptionSettings, Settings},
+    content_types::ContentPolicy,
+    domain::{StorageDetails, WriteLocks, INTERNAL_DIRECTORY},
+    encryption::{Encryption, EncryptionReport},
+    events::Events,
+    extraction::Extraction,
+    index::{Index, IndexError, ReconcileReport},
+    journal::Journal,
+    metrics::Metrics,
+    routes::{
+        audit_log, change_feed, delete_file, delta, get_archive, get_file, get_thumbnail,
+        health_check, list_files, list_trash, metrics, patch_file, post_archive, post_file,
+        purge_trash_entry, put_file, quarantine, ready, restore_trash_entry, rotate_keys, s3,
+        search, search_text, upload, webdav,
+    },
+    s3::{self, Authenticator, MultipartUploads},
+    scanning::{self, Scanning},
+    thumbnails::Thumbnails,
+    trash::{self, Trash},
+    versioning::Versioning,
+    webdav::{self, Locks},
+    webhooks::Webhooks,
 };
 use axum::{
     body::BoxBody,
+    extract::MatchedPath,
+    middleware::{self, Next},
     response::Response,
-    routing::{get, post},
+    routing::{any, delete, get, post},
     Extension, Router,
 };
-use hyper::{Body, Request};
-use tower_http::{classify::ServerErrorsFailureClass, trace::TraceLayer};
+use hyper::{Body, Method, Request};
+use opentelemetry::global;
+use opentelemetry_http::HeaderExtractor;
+use tower_http::{
+    classify::ServerErrorsFailureClass,
+    request_id::{MakeRequestId, PropagateRequestIdLayer, RequestId, SetRequestIdLayer},
+    trace::TraceLayer,
+};
 use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
 use uuid::Uuid;
 
-pub async fn app(listener: TcpListener, storage_details: StorageDetails) {
+const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Rebuilds the metadata index from the storage root, reading files through the same encryption
+/// and compression as the server.
+pub async fn reindex(settings: Settings) -> Result<ReconcileReport, IndexError> {
+    let storage_details = StorageDetails {
+        path: settings.application.storage_path,
+        min_free_space_bytes: settings.application.min_free_space_bytes,
+        write_locks: WriteLocks::default(),
+    };
+    let (_, compression) = codecs(&settings.encryption, settings.compression);
+    Index::from_settings(&settings.index, &storage_details)?
+        .with_compression(compression)
+        .reconcile()
+        .await
+}
+
+/// Encrypts the files of a storage root written before encryption was enabled, which cannot be
+/// read once it is. Internal files never written encrypted, such as the index, the journal and
+/// the descriptions of trash entries, are left as they are.
+pub async fn encrypt(settings: Settings) -> Result<EncryptionReport, io::Error> {
+    let storage_details = StorageDetails {
+        path: settings.application.storage_path,
+        min_free_space_bytes: settings.application.min_free_space_bytes,
+        write_locks: WriteLocks::default(),
+    };
+    let (encryption, _) = codecs(&settings.encryption, settings.compression);
+    let plaintext_areas = [
+        settings
+            .index
+            .path
+            .map_or_else(|| storage_details.internal_path("index"), PathBuf::from),
+        settings
+            .journal
+            .path
+            .map_or_else(|| storage_details.internal_path("journal"), PathBuf::from),
+        storage_details.internal_path("webhooks"),
+        storage_details.internal_path("staging"),
+    ];
+    let descriptors = [
+        trash::ENTRY_FILE_NAME,
+        scanning::ENTRY_FILE_NAME,
+        s3::INFO_FILE,
+    ];
+    let internal_root = Path::new(&storage_details.path).join(INTERNAL_DIRECTORY);
+
+    encryption
+        .encrypt_plaintext(Path::new(&storage_details.path), |path| {
+            // By name, so the files next to the index database are left out too.
+            let in_plaintext_area = plaintext_areas.iter().any(|area| {
+                path.to_string_lossy()
+                    .starts_with(area.to_string_lossy().as_ref())
+            });
+            let is_descriptor = path.starts_with(&internal_root)
+                && path
+                    .file_name()
+                    .is_some_and(|name| descriptors.iter().any(|descriptor| name == *descriptor));
+            in_plaintext_area || is_descriptor
+        })
+        .await
+}
+
+/// The encryption and compression stored files are written and read through.
+fn codecs(
+    encryption: &EncryptionSettings,
+    compression: CompressionSettings,
+) -> (Arc<Encryption>, Arc<Compression>) {
+    let encryption = Arc::new(Encryption::new(encryption).expect("Invalid encryption settings"));
+    let compression = Arc::new(
+        Compression::new(compression, encryption.clone())
+            .expect("Invalid content type in compression skip list"),
+    );
+    (encryption, compression)
+}
+
+pub async fn app(listener: TcpListener, settings: Settings) {
+    let storage_details = StorageDetails {
+        path: settings.application.storage_path,
+        min_free_space_bytes: settings.application.min_free_space_bytes,
+        write_locks: WriteLocks::default(),
+    };
+    let metrics_registry = Arc::new(Metrics::new().expect("Failed to create metrics"));
+    let audit = Arc::new(AuditLog::new(settings.audit));
+    let content_policy = Arc::new(
+        ContentPolicy::new(&settings.content_types)
+            .expect("Invalid content type in allow/deny list"),
+    );
+    let (encryption, compression) = codecs(&settings.encryption, settings.compression);
+    let index = Arc::new(
+        Index::from_settings(&settings.index, &storage_details)
+            .expect("Failed to open metadata index")
+            .with_compression(compression.clone()),
+    );
+    let thumbnails = Arc::new(Thumbnails::new(
+        settings.thumbnails,
+        &storage_details,
+        index.clone(),
+        compression.clone(),
+    ));
+    let scanning = Arc::new(Scanning::new(
+        &settings.scanning,
+        &storage_details,
+        encryption.clone(),
+    ));
+    let archives = Arc::new(Archives::new(
+        &storage_details,
+        index.clone(),
+        compression.clone(),
+    ));
+    let extraction = Arc::new(Extraction::new(settings.extraction, encryption.clone()));
+    let locks = Arc::new(Locks::new(&settings.webdav));
+    let auth = Arc::new(settings.auth);
+    let authenticator = Arc::new(Authenticator::new(&settings.s3, auth.clone()));
+    let uploads = Arc::new(MultipartUploads::new(
+        &settings.s3,
+        &storage_details,
+        encryption.clone(),
+    ));
+    let versioning = Arc::new(Versioning::new(
+        settings.versioning,
+        &storage_details,
+        index.clone(),
+        compression.clone(),
+    ));
+    tokio::spawn(versioning.clone().prune_periodically());
+    let trash = Arc::new(Trash::new(
+        settings.trash,
+        &storage_details,
+        index.clone(),
+        compression.clone(),
+    ));
+    tokio::spawn(trash.clone().purge_expired_periodically());
+    let webhooks = Arc::new(
+        Webhooks::new(settings.webhooks, &storage_details).expect("Invalid webhook path glob"),
+    );
+    tokio::spawn(webhooks.clone().deliver_queued());
+    let journal = Journal::open(settings.journal.path.map_or_else(
+        || {
+            storage_details
+                .internal_path("journal")
+                .join("journal.jsonl")
+        },
+        PathBuf::from,
+    ))
+    .await
+    .expect("Failed to read change journal");
+    let events = Arc::new(Events::new(
+        settings.events,
+        journal,
+        index.clone(),
+        webhooks,
+    ));
+    tokio::spawn(thumbnails.clone().follow(events.clone()));
+
     let router = Router::new()
         .route("/health_check", get(health_check))
+        .route("/ready", get(ready))
+        .route("/metrics", get(metrics))
         .route("/upload", post(upload))
-        .layer(Extension(Arc::new(storage_details)));
+        .route("/events", get(change_feed))
+        .route("/delta", get(delta))
+        .route("/files", get(list_files))
+        .route("/search", get(search))
+        .route("/search/text", get(search_text))
+        .route(
+            "/files/*path",
+            get(get_file)
+                .post(post_file)
+                .put(put_file)
+                .patch(patch_file)
+                .delete(delete_file),
+        )
+        .route("/thumbnails/*path", get(get_thumbnail))
+        .route("/archive", post(post_archive))
+        .route("/archive/*path", get(get_archive))
+        .route("/trash", get(list_trash))
+        .route("/trash/:id", delete(purge_trash_entry))
+        .route("/trash/:id/restore", post(restore_trash_entry))
+        .route("/admin/audit", get(audit_log))
+        .route("/admin/quarantine", get(quarantine))
+        .route("/admin/encryption/rotate", post(rotate_keys))
+        .route(webdav::PREFIX, any(webdav))
+        .route(&format!("{}/*path", webdav::PREFIX), any(webdav))
+        .route(s3::PREFIX, any(s3))
+        .route(&format!("{}/*path", s3::PREFIX), any(s3));
+
+    let router = add_metrics_middleware(router, metrics_registry.clone())
+        .layer(Extension(Arc::new(storage_details)))
+        .layer(Extension(metrics_registry))
+        .layer(Extension(audit))
+        .layer(Extension(versioning))
+        .layer(Extension(trash))
+        .layer(Extension(events))
+        .layer(Extension(index))
+        .layer(Extension(content_policy))
+        .layer(Extension(scanning))
+        .layer(Extension(thumbnails))
+        .layer(Extension(archives))
+        .layer(Extension(extraction))
+        .layer(Extension(locks))
+        .layer(Extension(authenticator))
+        .layer(Extension(uploads))
+        .layer(Extension(encryption))
+        .layer(Extension(compression))
+        .layer(Extension(auth));
 
     let router = add_tracing_middleware(router);
 
     axum::Server::from_tcp(listener)
         .unwrap()
-        .serve(router.into_make_service())
+        .serve(router.into_make_service_with_connect_info::<SocketAddr>())
         .await
         .unwrap();
 }
 
 fn add_tracing_middleware(router: Router) -> Router {
     let tracing_layer = TraceLayer::new_for_http()
-        .make_span_with(|_request: &Request<Body>| {
-            let request_id = Uuid::new_v4().to_string();
-            tracing::info_span!("http-request", %request_id)
+        .make_span_with(|request: &Request<Body>| {
+            let request_id = request
+                .headers()
+                .get(REQUEST_ID_HEADER)
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or_default();
+            let span = tracing::info_span!("http-request", %request_id);
+
+            // Continue the caller's trace when a W3C `traceparent` header is present.
+            let parent_context = global::get_text_map_propagator(|propagator| {
+                propagator.extract(&HeaderExtractor(request.headers()))
+            });
+            span.set_parent(parent_context);
+            span
         })
         .on_request(|request: &Request<Body>, _span: &Span| {
             tracing::info!("request: {} {}", request.method(), request.uri().path())
@@ -49,5 +298,77 @@ fn add_tracing_middleware(router: Router) -> Router {
                 tracing::error!("error: {}", error)
             },
         );
-    router.layer(tracing_layer)
+
+    // Layers added last run first: the request id is settled before the trace span is created.
+    router
+        .layer(tracing_layer)
+        .layer(PropagateRequestIdLayer::x_request_id())
+        .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+}
+
+/// Generates a request id for requests that did not bring their own `X-Request-Id`.
+#[derive(Clone, Copy)]
+struct MakeRequestUuid;
+
+impl MakeRequestId for MakeRequestUuid {
+    fn make_request_id<B>(&mut self, _request: &Request<B>) -> Option<RequestId> {
+        let request_id = Uuid::new_v4().to_string().parse().ok()?;
+        Some(RequestId::new(request_id))
+    }
+}
+
+/// Records the request count and latency of every matched route, labelled like the
+/// `response` events emitted by the tracing middleware.
+fn add_metrics_middleware(router: Router, metrics: Arc<Metrics>) -> Router {
+    router.route_layer(middleware::from_fn(
+        move |request: Request<Body>, next: Next<Body>| {
+            let metrics = metrics.clone();
+            async move {
+                let method = method_label(request.method());
+                let route = request
+                    .extensions()
+                    .get::<MatchedPath>()
+                    .map(|path| path.as_str().to_string())
+                    .unwrap_or_else(|| request.uri().path().to_string());
+
+                let start = Instant::now();
+                let response = next.run(request).await;
+                let latency = start.elapsed();
+
+                let status = response.status().as_u16().to_string();
+                let labels = [method, route.as_str(), status.as_str()];
+                metrics.http_requests_total.with_label_values(&labels).inc();
+                metrics
+                    .http_request_duration_seconds
+                    .with_label_values(&labels)
+                    .observe(latency.as_secs_f64());
+
+                response
+            }
+        },
+    ))
+}
+
+/// The `method` label of a request. Methods clients make up are counted together, so they
+/// cannot create a time series each on the routes that accept any method.
+fn method_label(method: &Method) -> &'static str {
+    match *method {
+        Method::GET => "GET",
+        Method::HEAD => "HEAD",
+        Method::POST => "POST",
+        Method::PUT => "PUT",
+        Method::PATCH => "PATCH",
+        Method::DELETE => "DELETE",
+        Method::OPTIONS => "OPTIONS",
+        _ => match method.as_str() {
+            "PROPFIND" => "PROPFIND",
+            "PROPPATCH" => "PROPPATCH",
+            "MKCOL" => "MKCOL",
+            "COPY" => "COPY",
+            "MOVE" => "MOVE",
+            "LOCK" => "LOCK",
+            "UNLOCK" => "UNLOCK",
+            _ => "other",
+        },
+    }
 }
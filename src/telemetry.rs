@@ -1,4 +1,12 @@
+use opentelemetry::{
+    global,
+    sdk::{propagation::TraceContextPropagator, trace, Resource},
+    trace::TraceError,
+    KeyValue,
+};
+use opentelemetry_otlp::WithExportConfig;
 use tracing::{subscriber::set_global_default, Subscriber};
+use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
 use tracing_log::LogTracer;
 use tracing_subscriber::{
     fmt::{format::FmtSpan, MakeWriter},
@@ -6,19 +14,72 @@ use tracing_subscriber::{
     EnvFilter, Registry,
 };
 
-pub fn get_subscriber<Sink>(default_env_filter: String, sink: Sink) -> impl Subscriber + Send + Sync
+use crate::configuration::{LogFormat, TelemetrySettings};
+
+pub fn get_subscriber<Sink>(
+    default_env_filter: String,
+    sink: Sink,
+    settings: &TelemetrySettings,
+    tracer: Option<trace::Tracer>,
+) -> impl Subscriber + Send + Sync
 where
     Sink: for<'a> MakeWriter<'a> + Send + Sync + 'static,
 {
     let env_filter = std::env::var("RUST_LOG").unwrap_or(default_env_filter);
-    Registry::default().with(EnvFilter::new(env_filter)).with(
-        tracing_subscriber::fmt::layer()
-            .with_writer(sink)
-            .with_span_events(FmtSpan::CLOSE),
-    )
+
+    let (pretty_layer, json_layer) = match settings.log_format {
+        LogFormat::Pretty => (
+            Some(
+                tracing_subscriber::fmt::layer()
+                    .with_writer(sink)
+                    .with_span_events(FmtSpan::CLOSE),
+            ),
+            None,
+        ),
+        LogFormat::Json => (
+            None,
+            Some(BunyanFormattingLayer::new(
+                settings.service_name.clone(),
+                sink,
+            )),
+        ),
+    };
+
+    Registry::default()
+        .with(EnvFilter::new(env_filter))
+        .with(json_layer.as_ref().map(|_| JsonStorageLayer))
+        .with(json_layer)
+        .with(pretty_layer)
+        .with(tracer.map(|tracer| tracing_opentelemetry::layer().with_tracer(tracer)))
 }
 
 pub fn init_subscriber(subscriber: impl Subscriber + Send + Sync) {
     LogTracer::init().expect("Failed to initialize logger");
+    global::set_text_map_propagator(TraceContextPropagator::new());
     set_global_default(subscriber).expect("Failed to set global subscriber");
 }
+
+/// Builds a tracer exporting spans over OTLP/HTTP when an endpoint is configured.
+pub fn get_otlp_tracer(settings: &TelemetrySettings) -> Result<Option<trace::Tracer>, TraceError> {
+    let endpoint = match &settings.otlp_endpoint {
+        Some(endpoint) => endpoint,
+        None => return Ok(None),
+    };
+
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .http()
+                .with_endpoint(endpoint),
+        )
+        .with_trace_config(
+            trace::config().with_resource(Resource::new(vec![KeyValue::new(
+                "service.name",
+                settings.service_name.clone(),
+            )])),
+        )
+        .install_batch(opentelemetry::runtime::Tokio)?;
+
+    Ok(Some(tracer))
+}
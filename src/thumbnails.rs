@@ -0,0 +1,256 @@
+use std::{
+    io::{self, Cursor, ErrorKind},
+    path::PathBuf,
+    sync::Arc,
+};
+
+use image::{imageops::FilterType, io::Limits, ImageFormat};
+use tokio::{fs, sync::broadcast::error::RecvError};
+use uuid::Uuid;
+
+use crate::{
+    compression::Compression,
+    configuration::ThumbnailsSettings,
+    domain::StorageDetails,
+    events::{Events, FileEvent, FileEventKind},
+    index::Index,
+};
+
+/// Largest width or height of an image decoded to make a thumbnail of.
+const MAX_IMAGE_DIMENSION: u32 = 16_384;
+/// Most memory a decoder may allocate for one image.
+const MAX_DECODER_ALLOCATION: u64 = 256 * 1024 * 1024;
+
+#[derive(thiserror::Error, Debug)]
+pub enum ThumbnailError {
+    #[error("{0} is not a supported image")]
+    Unsupported(String),
+    #[error("{0} is too large to make a thumbnail of")]
+    TooLarge(String),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+/// A generated thumbnail, with the path it is cached at.
+pub struct Thumbnail {
+    pub path: PathBuf,
+    pub content_type: &'static str,
+    pub contents: Vec<u8>,
+}
+
+/// Scaled-down copies of images, cached under the internal `thumbnails` area as
+/// `<relative path>/<size>.<extension>`. A thumbnail older than its original is stale and
+/// generated again. Thumbnails are encrypted like the images they are made from, but never
+/// compressed.
+pub struct Thumbnails {
+    settings: ThumbnailsSettings,
+    storage_root: PathBuf,
+    cache_root: PathBuf,
+    index: Arc<Index>,
+    compression: Arc<Compression>,
+}
+
+impl Thumbnails {
+    pub fn new(
+        settings: ThumbnailsSettings,
+        storage_details: &StorageDetails,
+        index: Arc<Index>,
+        compression: Arc<Compression>,
+    ) -> Self {
+        Self {
+            settings,
+            storage_root: PathBuf::from(&storage_details.path),
+            cache_root: storage_details.internal_path("thumbnails"),
+            index,
+            compression,
+        }
+    }
+
+    pub fn sizes(&self) -> &[u32] {
+        &self.settings.sizes
+    }
+
+    /// Returns the thumbnail of an image fitting within `size` pixels, generating it if it is
+    /// not cached yet.
+    pub async fn get(&self, relative_path: &str, size: u32) -> Result<Thumbnail, ThumbnailError> {
+        let original = fs::metadata(self.storage_root.join(relative_path)).await?;
+        if !original.is_file() {
+            return Err(ThumbnailError::Unsupported(relative_path.to_string()));
+        }
+        let modified = original.modified()?;
+
+        for (extension, content_type) in [("jpg", "image/jpeg"), ("png", "image/png")] {
+            let path = self.thumbnail_path(relative_path, size, extension);
+            match fs::metadata(&path).await {
+                Ok(metadata) if metadata.modified()? >= modified => {
+                    let contents = self
+                        .compression
+                        .open(&path, None)
+                        .await?
+                        .read_to_end()
+                        .await?;
+                    return Ok(Thumbnail {
+                        path,
+                        content_type,
+                        contents,
+                    });
+                }
+                Ok(_) => {}
+                Err(e) if e.kind() == ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        self.generate(relative_path, size).await
+    }
+
+    /// Generates the thumbnail of an image in one size, replacing any cached one.
+    #[tracing::instrument(name = "Generate thumbnail", skip(self))]
+    pub async fn generate(
+        &self,
+        relative_path: &str,
+        size: u32,
+    ) -> Result<Thumbnail, ThumbnailError> {
+        let codec = self
+            .index
+            .codec(relative_path)
+            .await
+            .map_err(io::Error::other)?;
+        let original = self
+            .compression
+            .open(&self.storage_root.join(relative_path), codec)
+            .await?;
+        if original.size() > self.settings.max_source_size_bytes {
+            return Err(ThumbnailError::TooLarge(relative_path.to_string()));
+        }
+        let original = original.read_to_end().await?;
+
+        let unsupported = relative_path.to_string();
+        let (format, contents) =
+            tokio::task::spawn_blocking(move || -> Result<_, ThumbnailError> {
+                let mut reader =
+                    image::io::Reader::new(Cursor::new(original)).with_guessed_format()?;
+                let mut limits = Limits::default();
+                limits.max_image_width = Some(MAX_IMAGE_DIMENSION);
+                limits.max_image_height = Some(MAX_IMAGE_DIMENSION);
+                limits.max_alloc = Some(MAX_DECODER_ALLOCATION);
+                reader.limits(limits);
+                let format = match reader.format() {
+                    Some(ImageFormat::Jpeg) => ImageFormat::Jpeg,
+                    Some(_) => ImageFormat::Png,
+                    None => return Err(ThumbnailError::Unsupported(unsupported)),
+                };
+                let image = reader
+                    .decode()
+                    .map_err(|_| ThumbnailError::Unsupported(unsupported))?;
+                // Images already small enough are only re-encoded, never enlarged.
+                let thumbnail = if image.width() > size || image.height() > size {
+                    image.resize(size, size, FilterType::Lanczos3)
+                } else {
+                    image
+                };
+                let mut contents = Cursor::new(vec![]);
+                thumbnail
+                    .write_to(&mut contents, format)
+                    .map_err(io::Error::other)?;
+                Ok((format, contents.into_inner()))
+            })
+            .await
+            .map_err(io::Error::other)??;
+
+        let (extension, content_type) = match format {
+            ImageFormat::Jpeg => ("jpg", "image/jpeg"),
+            _ => ("png", "image/png"),
+        };
+        let path = self.thumbnail_path(relative_path, size, extension);
+        fs::create_dir_all(self.cache_root.join(relative_path)).await?;
+        let temporary_path = self
+            .cache_root
+            .join(format!(".{}", Uuid::new_v4().simple()));
+        let written = self
+            .compression
+            .encryption()
+            .write(&temporary_path, &mut contents.as_slice())
+            .await;
+        if let Err(e) = written {
+            let _ = fs::remove_file(&temporary_path).await;
+            return Err(e.into());
+        }
+        fs::rename(&temporary_path, &path).await?;
+
+        Ok(Thumbnail {
+            path,
+            content_type,
+            contents,
+        })
+    }
+
+    /// Removes the cached thumbnails of a file, or of everything below a directory.
+    pub async fn invalidate(&self, relative_path: &str) -> Result<(), io::Error> {
+        match fs::remove_dir_all(self.cache_root.join(relative_path)).await {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    /// Follows published events until the process exits, invalidating the thumbnails of changed
+    /// files and generating them for uploaded images.
+    pub async fn follow(self: Arc<Self>, events: Arc<Events>) {
+        let mut live = match events.subscribe(None).await {
+            Ok(subscription) => subscription.live,
+            Err(e) => {
+                tracing::error!("Failed to follow file events: {:?}", e);
+                return;
+            }
+        };
+
+        loop {
+            match live.recv().await {
+                Ok(event) => self.apply(&event).await,
+                // Thumbnails of files changed meanwhile are stale and get generated again.
+                Err(RecvError::Lagged(missed)) => {
+                    tracing::warn!("Missed {} file events for thumbnails", missed)
+                }
+                Err(RecvError::Closed) => return,
+            }
+        }
+    }
+
+    async fn apply(&self, event: &FileEvent) {
+        let mut invalidated = vec![event.path.as_str()];
+        if let Some(from_path) = &event.from_path {
+            invalidated.push(from_path);
+        }
+        if event.kind != FileEventKind::VersionCreated {
+            for path in invalidated {
+                if let Err(e) = self.invalidate(path).await {
+                    tracing::error!("Failed to invalidate thumbnails of {}: {:?}", path, e);
+                }
+            }
+        }
+
+        let is_image = event
+            .content_type
+            .as_deref()
+            .is_some_and(|content_type| content_type.starts_with("image/"));
+        if event.kind == FileEventKind::UploadCompleted
+            && is_image
+            && self.settings.generate_on_upload
+        {
+            for &size in &self.settings.sizes {
+                match self.generate(&event.path, size).await {
+                    Ok(_) | Err(ThumbnailError::Unsupported(_) | ThumbnailError::TooLarge(_)) => {}
+                    Err(e) => {
+                        tracing::error!("Failed to generate thumbnail of {}: {:?}", event.path, e)
+                    }
+                }
+            }
+        }
+    }
+
+    fn thumbnail_path(&self, relative_path: &str, size: u32, extension: &str) -> PathBuf {
+        self.cache_root
+            .join(relative_path)
+            .join(format!("{}.{}", size, extension))
+    }
+}
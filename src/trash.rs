@@ -0,0 +1,208 @@
+use std::{
+    collections::BTreeMap,
+    io::{self, ErrorKind},
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+use uuid::Uuid;
+
+use crate::{
+    compression::{Codec, Compression},
+    configuration::TrashSettings,
+    domain::StorageDetails,
+    index::Index,
+};
+
+/// Description of an entry, written in plaintext.
+pub const ENTRY_FILE_NAME: &str = "entry.json";
+const CONTENT_NAME: &str = "content";
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TrashEntry {
+    pub id: String,
+    /// Path relative to the storage root the content was deleted from.
+    pub original_path: String,
+    pub deleted_at: DateTime<Utc>,
+    pub deleted_by: String,
+    pub is_directory: bool,
+    pub size: Option<u64>,
+    /// Codecs of the compressed files of the content, by original path relative to the
+    /// storage root.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub codecs: BTreeMap<String, Codec>,
+}
+
+/// Soft-deleted files and directories, kept under the internal `trash` area. Every entry is a
+/// directory holding the deleted content and an `entry.json` describing where it came from.
+pub struct Trash {
+    settings: TrashSettings,
+    storage_root: PathBuf,
+    trash_root: PathBuf,
+    index: Arc<Index>,
+    compression: Arc<Compression>,
+}
+
+impl Trash {
+    pub fn new(
+        settings: TrashSettings,
+        storage_details: &StorageDetails,
+        index: Arc<Index>,
+        compression: Arc<Compression>,
+    ) -> Self {
+        Self {
+            settings,
+            storage_root: PathBuf::from(&storage_details.path),
+            trash_root: storage_details.internal_path("trash"),
+            index,
+            compression,
+        }
+    }
+
+    /// Moves a file or directory into the trash.
+    #[tracing::instrument(name = "Move to trash", skip(self))]
+    pub async fn delete(
+        &self,
+        relative_path: &str,
+        deleted_by: &str,
+    ) -> Result<TrashEntry, io::Error> {
+        let path = self.storage_root.join(relative_path);
+        let metadata = fs::metadata(&path).await?;
+        let codecs = self
+            .index
+            .codecs(relative_path)
+            .await
+            .map_err(io::Error::other)?;
+        let size = match metadata.is_file() {
+            true => Some(
+                self.compression
+                    .content_size(&path, codecs.get(relative_path).copied())
+                    .await?,
+            ),
+            false => None,
+        };
+
+        let entry = TrashEntry {
+            id: Uuid::new_v4().simple().to_string(),
+            original_path: relative_path.to_string(),
+            deleted_at: Utc::now(),
+            deleted_by: deleted_by.to_string(),
+            is_directory: metadata.is_dir(),
+            size,
+            codecs,
+        };
+
+        let entry_directory = self.trash_root.join(&entry.id);
+        fs::create_dir_all(&entry_directory).await?;
+        fs::write(
+            entry_directory.join(ENTRY_FILE_NAME),
+            serde_json::to_vec(&entry)?,
+        )
+        .await?;
+        fs::rename(&path, entry_directory.join(CONTENT_NAME)).await?;
+
+        Ok(entry)
+    }
+
+    /// Lists the trash entries, most recently deleted first.
+    pub async fn list(&self) -> Result<Vec<TrashEntry>, io::Error> {
+        let mut directories = match fs::read_dir(&self.trash_root).await {
+            Ok(directories) => directories,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e),
+        };
+
+        let mut entries = vec![];
+        while let Some(directory) = directories.next_entry().await? {
+            match self.entry(&directory.file_name().to_string_lossy()).await {
+                Ok(entry) => entries.push(entry),
+                // Entries are written before their content is moved, a missing description
+                // can only belong to an interrupted delete.
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            }
+        }
+
+        entries.sort_by_key(|entry| std::cmp::Reverse(entry.deleted_at));
+        Ok(entries)
+    }
+
+    pub async fn entry(&self, id: &str) -> Result<TrashEntry, io::Error> {
+        let entry_directory = self.entry_directory(id)?;
+        let contents = fs::read(entry_directory.join(ENTRY_FILE_NAME)).await?;
+        Ok(serde_json::from_slice(&contents)?)
+    }
+
+    /// Moves an entry back to its original path, failing if that path is taken.
+    #[tracing::instrument(name = "Restore from trash", skip(self))]
+    pub async fn restore(&self, id: &str) -> Result<TrashEntry, io::Error> {
+        let entry = self.entry(id).await?;
+        let path = self.storage_root.join(&entry.original_path);
+        if fs::metadata(&path).await.is_ok() {
+            return Err(io::Error::new(
+                ErrorKind::AlreadyExists,
+                format!("{} already exists", entry.original_path),
+            ));
+        }
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        let entry_directory = self.entry_directory(id)?;
+        fs::rename(entry_directory.join(CONTENT_NAME), &path).await?;
+        fs::remove_dir_all(entry_directory).await?;
+
+        Ok(entry)
+    }
+
+    /// Permanently removes an entry.
+    #[tracing::instrument(name = "Purge from trash", skip(self))]
+    pub async fn purge(&self, id: &str) -> Result<TrashEntry, io::Error> {
+        let entry = self.entry(id).await?;
+        fs::remove_dir_all(self.entry_directory(id)?).await?;
+        Ok(entry)
+    }
+
+    /// Permanently removes the entries older than the retention period.
+    #[tracing::instrument(name = "Purge expired trash entries", skip(self))]
+    pub async fn purge_expired(&self) -> Result<Vec<TrashEntry>, io::Error> {
+        let oldest_allowed =
+            Utc::now() - chrono::Duration::days(self.settings.retention_days as i64);
+
+        let mut purged = vec![];
+        for entry in self.list().await? {
+            if entry.deleted_at < oldest_allowed {
+                purged.push(self.purge(&entry.id).await?);
+            }
+        }
+
+        Ok(purged)
+    }
+
+    /// Runs `purge_expired` on the configured interval, of at least a second, until the process
+    /// exits.
+    pub async fn purge_expired_periodically(self: Arc<Self>) {
+        let mut interval = tokio::time::interval(Duration::from_secs(
+            self.settings.purge_interval_seconds.max(1),
+        ));
+        loop {
+            interval.tick().await;
+            match self.purge_expired().await {
+                Ok(purged) if !purged.is_empty() => {
+                    tracing::info!("Purged {} expired trash entries", purged.len())
+                }
+                Ok(_) => {}
+                Err(e) => tracing::error!("Failed to purge expired trash entries: {:?}", e),
+            }
+        }
+    }
+
+    fn entry_directory(&self, id: &str) -> Result<PathBuf, io::Error> {
+        Uuid::try_parse(id).map_err(|_| io::Error::new(ErrorKind::NotFound, "Unknown entry"))?;
+        Ok(self.trash_root.join(id))
+    }
+}
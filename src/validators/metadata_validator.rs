@@ -0,0 +1,51 @@
+use crate::index::MetadataPatch;
+
+pub fn validate_tag(s: &str) -> Result<(), String> {
+    let is_empty_or_whitespace = s.trim().is_empty();
+    let is_too_long = s.len() > 64;
+    let contains_control_characters = s.chars().any(|c| c.is_control());
+
+    if is_empty_or_whitespace || is_too_long || contains_control_characters {
+        Err(format!("Invalid tag: {}", s))
+    } else {
+        Ok(())
+    }
+}
+
+pub fn validate_metadata_key(s: &str) -> Result<(), String> {
+    let is_too_long = s.len() > 128;
+    let contains_invalid_characters = !s
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+
+    if s.is_empty() || is_too_long || contains_invalid_characters {
+        Err(format!("Invalid metadata key: {}", s))
+    } else {
+        Ok(())
+    }
+}
+
+pub fn validate_metadata_value(s: &str) -> Result<(), String> {
+    let is_too_long = s.len() > 1024;
+    let contains_control_characters = s.chars().any(|c| c.is_control());
+
+    if is_too_long || contains_control_characters {
+        Err("Invalid metadata value".to_string())
+    } else {
+        Ok(())
+    }
+}
+
+pub fn validate_metadata_patch(patch: &MetadataPatch) -> Result<(), String> {
+    for (key, value) in &patch.metadata {
+        validate_metadata_key(key)?;
+        if let Some(value) = value {
+            validate_metadata_value(value)?;
+        }
+    }
+    patch
+        .add_tags
+        .iter()
+        .chain(&patch.remove_tags)
+        .try_for_each(|tag| validate_tag(tag))
+}
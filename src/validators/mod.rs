@@ -1,3 +1,7 @@
 mod file_name_validator;
+mod metadata_validator;
+mod relative_path_validator;
 
 pub use file_name_validator::*;
+pub use metadata_validator::*;
+pub use relative_path_validator::*;
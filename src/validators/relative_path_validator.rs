@@ -0,0 +1,19 @@
+use std::path::{Component, Path};
+
+use crate::domain::INTERNAL_DIRECTORY;
+
+/// Accepts paths that stay inside the storage root and outside its internal directory.
+pub fn validate_relative_path(s: &str) -> Result<(), String> {
+    let contains_control_characters = s.chars().any(|c| c.is_control());
+    let mut components = Path::new(s).components();
+    let escapes_root = components
+        .clone()
+        .any(|component| !matches!(component, Component::Normal(_) | Component::CurDir));
+    let is_internal = components.any(|component| component.as_os_str() == INTERNAL_DIRECTORY);
+
+    if contains_control_characters || escapes_root || is_internal {
+        Err(format!("Invalid path: {}", s))
+    } else {
+        Ok(())
+    }
+}
@@ -0,0 +1,303 @@
+use std::{
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time,
+};
+
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+use serde::Serialize;
+use tokio::fs;
+use uuid::Uuid;
+
+use crate::{
+    compression::{Codec, Compression},
+    configuration::VersioningSettings,
+    domain::StorageDetails,
+    index::Index,
+};
+
+const VERSION_TIMESTAMP_FORMAT: &str = "%Y%m%dT%H%M%S%.6fZ";
+
+#[derive(Serialize, Clone, Debug)]
+pub struct VersionInfo {
+    pub id: String,
+    pub size: u64,
+    pub created_at: DateTime<Utc>,
+    /// Codec the version is stored with, `None` if it is stored as is.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub codec: Option<Codec>,
+}
+
+/// Keeps previous contents of overwritten files under the internal `versions` area, in a
+/// directory per file named after its path relative to the storage root. The ids of compressed
+/// versions end in the name of their codec, as the index only knows the current content.
+pub struct Versioning {
+    settings: VersioningSettings,
+    storage_root: PathBuf,
+    versions_root: PathBuf,
+    staging_root: PathBuf,
+    index: Arc<Index>,
+    compression: Arc<Compression>,
+}
+
+impl Versioning {
+    pub fn new(
+        settings: VersioningSettings,
+        storage_details: &StorageDetails,
+        index: Arc<Index>,
+        compression: Arc<Compression>,
+    ) -> Self {
+        Self {
+            settings,
+            storage_root: PathBuf::from(&storage_details.path),
+            versions_root: storage_details.internal_path("versions"),
+            staging_root: storage_details.internal_path("staging"),
+            index,
+            compression,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.settings.enabled
+    }
+
+    /// Moves the current content of `relative_path` into a new version, if versioning is
+    /// enabled and the file exists.
+    #[tracing::instrument(name = "Preserve file version", skip(self))]
+    pub async fn preserve(&self, relative_path: &str) -> Result<Option<VersionInfo>, io::Error> {
+        if !self.is_enabled() {
+            return Ok(None);
+        }
+
+        let file_path = self.storage_root.join(relative_path);
+        match fs::metadata(&file_path).await {
+            Ok(metadata) if metadata.is_file() => {}
+            Ok(_) => return Ok(None),
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let codec = self
+            .index
+            .codec(relative_path)
+            .await
+            .map_err(io::Error::other)?;
+        let size = self.compression.content_size(&file_path, codec).await?;
+
+        let created_at = Utc::now();
+        let mut id = format!(
+            "{}-{}",
+            created_at.format(VERSION_TIMESTAMP_FORMAT),
+            &Uuid::new_v4().simple().to_string()[..8]
+        );
+        if let Some(codec) = codec {
+            id = format!("{}.{}", id, codec.as_str());
+        }
+        let version_directory = self.version_directory(relative_path);
+        fs::create_dir_all(&version_directory).await?;
+        fs::rename(&file_path, version_directory.join(&id)).await?;
+
+        self.prune(relative_path).await?;
+        Ok(Some(VersionInfo {
+            id,
+            size,
+            created_at,
+            codec,
+        }))
+    }
+
+    /// Moves a version created by `preserve` back in place of the current file.
+    pub async fn revert(
+        &self,
+        relative_path: &str,
+        version: &VersionInfo,
+    ) -> Result<(), io::Error> {
+        fs::rename(
+            self.version_directory(relative_path).join(&version.id),
+            self.storage_root.join(relative_path),
+        )
+        .await
+    }
+
+    /// Lists the versions of a file, newest first.
+    pub async fn list(&self, relative_path: &str) -> Result<Vec<VersionInfo>, io::Error> {
+        let mut entries = match fs::read_dir(self.version_directory(relative_path)).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e),
+        };
+
+        let mut versions = vec![];
+        while let Some(entry) = entries.next_entry().await? {
+            let metadata = entry.metadata().await?;
+            let id = entry.file_name().to_string_lossy().into_owned();
+            if let (true, Some((created_at, codec))) = (metadata.is_file(), parse_id(&id)) {
+                versions.push(VersionInfo {
+                    id,
+                    size: self.compression.content_size(&entry.path(), codec).await?,
+                    created_at,
+                    codec,
+                });
+            }
+        }
+
+        versions.sort_by(|a, b| b.id.cmp(&a.id));
+        Ok(versions)
+    }
+
+    /// Resolves the on-disk path of a version and the codec it is stored with, if the id is
+    /// well-formed.
+    pub fn version_path(&self, relative_path: &str, id: &str) -> Option<(PathBuf, Option<Codec>)> {
+        let (_, codec) = parse_id(id)?;
+        Some((self.version_directory(relative_path).join(id), codec))
+    }
+
+    /// Makes an old version the current content again, returning the restored version. The
+    /// replaced content is preserved as a version of its own, so a restore can be undone. The
+    /// version is copied aside first, since preserving the replaced content may prune it.
+    #[tracing::instrument(name = "Restore file version", skip(self))]
+    pub async fn restore(
+        &self,
+        relative_path: &str,
+        id: &str,
+    ) -> Result<(VersionInfo, Option<VersionInfo>), io::Error> {
+        let unknown = || io::Error::new(ErrorKind::NotFound, "Unknown version");
+        let (created_at, codec) = parse_id(id).ok_or_else(unknown)?;
+        let (version_path, _) = self.version_path(relative_path, id).ok_or_else(unknown)?;
+        let version = VersionInfo {
+            id: id.to_string(),
+            size: self.compression.content_size(&version_path, codec).await?,
+            created_at,
+            codec,
+        };
+
+        fs::create_dir_all(&self.staging_root).await?;
+        let staged_path = self
+            .staging_root
+            .join(format!("restore-{}", Uuid::new_v4().simple()));
+        fs::copy(&version_path, &staged_path).await?;
+
+        let restored = match self.preserve(relative_path).await {
+            Ok(replaced) => fs::rename(&staged_path, self.storage_root.join(relative_path))
+                .await
+                .map(|()| replaced),
+            Err(e) => Err(e),
+        };
+        if restored.is_err() {
+            let _ = fs::remove_file(&staged_path).await;
+        }
+        Ok((version, restored?))
+    }
+
+    /// Applies the retention limits by count and age to the versions of a file, returning how
+    /// many were removed.
+    async fn prune(&self, relative_path: &str) -> Result<usize, io::Error> {
+        let version_directory = self.version_directory(relative_path);
+        let mut entries = match fs::read_dir(&version_directory).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e),
+        };
+        let mut versions = vec![];
+        while let Some(entry) = entries.next_entry().await? {
+            let id = entry.file_name().to_string_lossy().into_owned();
+            if let (true, Some((created_at, _))) =
+                (entry.file_type().await?.is_file(), parse_id(&id))
+            {
+                versions.push((id, created_at));
+            }
+        }
+        versions.sort_by(|a, b| b.0.cmp(&a.0));
+
+        let oldest_allowed = self
+            .settings
+            .max_age_days
+            .map(|days| Utc::now() - Duration::days(days as i64));
+        let mut pruned = 0;
+        for (index, (id, created_at)) in versions.iter().enumerate() {
+            let too_many = self
+                .settings
+                .max_versions
+                .is_some_and(|max_versions| index >= max_versions);
+            let too_old = oldest_allowed.is_some_and(|oldest| *created_at < oldest);
+
+            if too_many || too_old {
+                match fs::remove_file(version_directory.join(id)).await {
+                    Ok(()) => pruned += 1,
+                    // Restored or pruned meanwhile.
+                    Err(e) if e.kind() == ErrorKind::NotFound => {}
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+
+        Ok(pruned)
+    }
+
+    /// Applies the retention limits to the versions of every file, returning how many were
+    /// removed.
+    #[tracing::instrument(name = "Prune all file versions", skip(self))]
+    pub async fn prune_all(&self) -> Result<usize, io::Error> {
+        let mut pruned = 0;
+        let mut pending = vec![String::new()];
+        while let Some(relative_path) = pending.pop() {
+            let mut entries = match fs::read_dir(self.version_directory(&relative_path)).await {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(e),
+            };
+            let mut has_versions = false;
+            while let Some(entry) = entries.next_entry().await? {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                if entry.file_type().await?.is_dir() {
+                    pending.push(match relative_path.is_empty() {
+                        true => name,
+                        false => format!("{}/{}", relative_path, name),
+                    });
+                } else if parse_id(&name).is_some() {
+                    has_versions = true;
+                }
+            }
+            if has_versions {
+                pruned += self.prune(&relative_path).await?;
+            }
+        }
+
+        Ok(pruned)
+    }
+
+    /// Runs `prune_all` on the configured interval, of at least a second, until the process
+    /// exits. Overwrites prune the versions of their file right away.
+    pub async fn prune_periodically(self: Arc<Self>) {
+        let period = time::Duration::from_secs(self.settings.prune_interval_seconds.max(1));
+        let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+        loop {
+            interval.tick().await;
+            match self.prune_all().await {
+                Ok(pruned) if pruned > 0 => tracing::info!("Pruned {} file versions", pruned),
+                Ok(_) => {}
+                Err(e) => tracing::error!("Failed to prune file versions: {:?}", e),
+            }
+        }
+    }
+
+    fn version_directory(&self, relative_path: &str) -> PathBuf {
+        self.versions_root.join(Path::new(relative_path))
+    }
+}
+
+/// Parses the creation time and codec out of a version id.
+fn parse_id(id: &str) -> Option<(DateTime<Utc>, Option<Codec>)> {
+    let (timestamp, suffix) = id.rsplit_once('-')?;
+    let (suffix, codec) = match suffix.split_once('.') {
+        Some((suffix, codec)) => (suffix, Some(Codec::parse(codec).ok()?)),
+        None => (suffix, None),
+    };
+    if !suffix.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    NaiveDateTime::parse_from_str(timestamp, VERSION_TIMESTAMP_FORMAT)
+        .ok()
+        .map(|created_at| (DateTime::from_utc(created_at, Utc), codec))
+}
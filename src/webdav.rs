@@ -0,0 +1,627 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime},
+};
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
+use quick_xml::{
+    escape::escape,
+    events::Event,
+    name::{Namespace, ResolveResult},
+    reader::NsReader,
+};
+use uuid::Uuid;
+
+use crate::configuration::WebDavSettings;
+
+/// Where the WebDAV tree is served, mirroring the storage root.
+pub const PREFIX: &str = "/webdav";
+const DAV_NAMESPACE: &str = "DAV:";
+const LOCK_TOKEN_SCHEME: &str = "opaquelocktoken:";
+/// Characters escaped in the path segments of hrefs.
+const SEGMENT: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}')
+    .add(b'/');
+
+/// The `Depth` of a request, `infinity` when absent.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Depth {
+    Zero,
+    One,
+    Infinity,
+}
+
+impl Depth {
+    pub fn parse(value: Option<&str>) -> Result<Self, String> {
+        match value.map(str::trim) {
+            None => Ok(Depth::Infinity),
+            Some("0") => Ok(Depth::Zero),
+            Some("1") => Ok(Depth::One),
+            Some(value) if value.eq_ignore_ascii_case("infinity") => Ok(Depth::Infinity),
+            Some(value) => Err(format!("Invalid depth: {}", value)),
+        }
+    }
+}
+
+/// A property, named by its namespace and local name.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PropertyName {
+    pub namespace: String,
+    pub name: String,
+}
+
+impl PropertyName {
+    fn is_dav(&self, name: &str) -> bool {
+        self.namespace == DAV_NAMESPACE && self.name == name
+    }
+
+    /// An empty element of the property, declaring its namespace if it is not `DAV:`.
+    fn empty_element(&self) -> String {
+        match self.namespace.as_str() {
+            DAV_NAMESPACE => format!("<D:{}/>", self.name),
+            "" => format!("<{} xmlns=\"\"/>", self.name),
+            namespace => format!("<x:{} xmlns:x=\"{}\"/>", self.name, escape(namespace)),
+        }
+    }
+}
+
+/// What a `PROPFIND` asks for.
+#[derive(Debug, PartialEq, Eq)]
+pub enum PropFind {
+    AllProp,
+    PropName,
+    Prop(Vec<PropertyName>),
+}
+
+impl PropFind {
+    /// Parses the body of a `PROPFIND`, an empty one asking for every property.
+    pub fn parse(body: &[u8]) -> Result<Self, String> {
+        if body.iter().all(u8::is_ascii_whitespace) {
+            return Ok(PropFind::AllProp);
+        }
+
+        let mut request = None;
+        let mut properties = vec![];
+        read_elements(body, |ancestors, element| {
+            match ancestors {
+                [] if !element.is_dav("propfind") => {
+                    return Err("Expected a propfind element".to_string())
+                }
+                [_] if element.is_dav("allprop") => request = Some(PropFind::AllProp),
+                [_] if element.is_dav("propname") => request = Some(PropFind::PropName),
+                [_, prop] if prop.is_dav("prop") => properties.push(element.clone()),
+                _ => {}
+            }
+            Ok(())
+        })?;
+        Ok(request.unwrap_or(PropFind::Prop(properties)))
+    }
+}
+
+/// Who asked for a lock, as sent in its `owner` element.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LockOwner {
+    Href(String),
+    Text(String),
+}
+
+/// What a `LOCK` with a body asks for.
+#[derive(Debug, PartialEq, Eq)]
+pub struct LockInfo {
+    pub exclusive: bool,
+    pub owner: Option<LockOwner>,
+}
+
+impl LockInfo {
+    pub fn parse(body: &[u8]) -> Result<Self, String> {
+        let mut exclusive = None;
+        let mut write = false;
+        let mut owner = None;
+        let mut reader = NsReader::from_reader(body);
+        reader.trim_text(true);
+        let mut ancestors: Vec<PropertyName> = vec![];
+        loop {
+            let (namespace, event) = reader
+                .read_resolved_event()
+                .map_err(|e| format!("Invalid XML: {}", e))?;
+            let (element, is_empty) = match &event {
+                Event::Start(start) => (element_name(&namespace, start.local_name())?, false),
+                Event::Empty(start) => (element_name(&namespace, start.local_name())?, true),
+                Event::Text(text) => {
+                    if ancestors.get(1).is_some_and(|owner| owner.is_dav("owner")) {
+                        let text = text.unescape().map_err(|e| format!("Invalid XML: {}", e))?;
+                        owner = Some(match (owner, ancestors.get(2)) {
+                            (_, Some(href)) if href.is_dav("href") => {
+                                LockOwner::Href(text.into_owned())
+                            }
+                            (Some(LockOwner::Text(previous)), _) => {
+                                LockOwner::Text(previous + &text)
+                            }
+                            (Some(href @ LockOwner::Href(_)), _) => href,
+                            (None, _) => LockOwner::Text(text.into_owned()),
+                        });
+                    }
+                    continue;
+                }
+                Event::End(_) => {
+                    ancestors.pop();
+                    continue;
+                }
+                Event::Eof => break,
+                _ => continue,
+            };
+
+            match ancestors.as_slice() {
+                [] if !element.is_dav("lockinfo") => {
+                    return Err("Expected a lockinfo element".to_string())
+                }
+                [_, scope] if scope.is_dav("lockscope") => {
+                    exclusive = Some(element.is_dav("exclusive"))
+                }
+                [_, kind] if kind.is_dav("locktype") => write = element.is_dav("write"),
+                _ => {}
+            }
+            if !is_empty {
+                ancestors.push(element);
+            }
+        }
+
+        if !write {
+            return Err("Only write locks are supported".to_string());
+        }
+        Ok(LockInfo {
+            exclusive: exclusive.ok_or("Expected a lock scope")?,
+            owner,
+        })
+    }
+}
+
+/// A lock on a path and, for deep locks, everything below it.
+#[derive(Clone, Debug)]
+pub struct Lock {
+    pub token: String,
+    /// Path relative to the storage root, empty for the root.
+    pub path: String,
+    pub exclusive: bool,
+    pub deep: bool,
+    pub owner: Option<LockOwner>,
+    /// Name of the identity that took the lock.
+    pub principal: String,
+    expires_at: Instant,
+}
+
+impl Lock {
+    /// Whether the lock is on `path`, directly or through a deep lock of a parent.
+    fn covers(&self, path: &str) -> bool {
+        self.path == path || (self.deep && is_within(path, &self.path))
+    }
+
+    /// The `activelock` element describing the lock.
+    pub fn active_lock(&self) -> String {
+        let owner = match &self.owner {
+            Some(LockOwner::Href(href)) => {
+                format!("<D:owner><D:href>{}</D:href></D:owner>", escape(href))
+            }
+            Some(LockOwner::Text(text)) => format!("<D:owner>{}</D:owner>", escape(text)),
+            None => String::new(),
+        };
+        let remaining = self
+            .expires_at
+            .saturating_duration_since(Instant::now())
+            .as_secs_f64()
+            .ceil() as u64;
+        format!(
+            "<D:activelock><D:locktype><D:write/></D:locktype>\
+             <D:lockscope><D:{}/></D:lockscope><D:depth>{}</D:depth>{}\
+             <D:timeout>Second-{}</D:timeout>\
+             <D:locktoken><D:href>{}</D:href></D:locktoken>\
+             <D:lockroot><D:href>{}</D:href></D:lockroot></D:activelock>",
+            if self.exclusive {
+                "exclusive"
+            } else {
+                "shared"
+            },
+            if self.deep { "infinity" } else { "0" },
+            owner,
+            remaining,
+            escape(&self.token),
+            escape(&href(&self.path, false)),
+        )
+    }
+}
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum LockError {
+    /// A conflicting lock is held on the path.
+    #[error("{0} is locked")]
+    Locked(String),
+    #[error("No lock {0} applies to the resource")]
+    NoSuchLock(String),
+    #[error("Lock {0} belongs to someone else")]
+    NotOwner(String),
+}
+
+/// A request for a new lock.
+pub struct LockRequest {
+    pub path: String,
+    pub exclusive: bool,
+    pub deep: bool,
+    pub owner: Option<LockOwner>,
+    pub principal: String,
+    pub timeout: Option<Duration>,
+}
+
+/// WebDAV write locks, held in memory. Locks guard uploads and deletions through every API, lock
+/// tokens being submitted in the `If` header, and are lost on restart, which clients handle like
+/// expired locks.
+pub struct Locks {
+    max_timeout: Duration,
+    locks: Mutex<Vec<Lock>>,
+}
+
+impl Locks {
+    pub fn new(settings: &WebDavSettings) -> Self {
+        Self {
+            max_timeout: Duration::from_secs(settings.max_lock_timeout_seconds),
+            locks: Mutex::new(vec![]),
+        }
+    }
+
+    /// Takes a new lock, unless it conflicts with a lock held on the path, a parent or, for
+    /// deep locks, anything below it. Shared locks only conflict with exclusive ones.
+    pub fn lock(&self, request: LockRequest) -> Result<Lock, LockError> {
+        let mut locks = self.active();
+        if let Some(conflict) = locks.iter().find(|lock| {
+            (lock.covers(&request.path) || (request.deep && is_within(&lock.path, &request.path)))
+                && (lock.exclusive || request.exclusive)
+        }) {
+            return Err(LockError::Locked(conflict.path.clone()));
+        }
+
+        let lock = Lock {
+            token: format!("{}{}", LOCK_TOKEN_SCHEME, Uuid::new_v4()),
+            path: request.path,
+            exclusive: request.exclusive,
+            deep: request.deep,
+            owner: request.owner,
+            principal: request.principal,
+            expires_at: Instant::now() + self.timeout(request.timeout),
+        };
+        locks.push(lock.clone());
+        Ok(lock)
+    }
+
+    /// Extends one of the locks on `path` whose token was submitted.
+    pub fn refresh(
+        &self,
+        path: &str,
+        tokens: &[String],
+        timeout: Option<Duration>,
+    ) -> Option<Lock> {
+        let expires_at = Instant::now() + self.timeout(timeout);
+        let mut locks = self.active();
+        let lock = locks
+            .iter_mut()
+            .find(|lock| lock.covers(path) && tokens.contains(&lock.token))?;
+        lock.expires_at = expires_at;
+        Some(lock.clone())
+    }
+
+    /// Releases a lock on `path`, which only the identity that took it may do.
+    pub fn unlock(&self, path: &str, token: &str, principal: &str) -> Result<(), LockError> {
+        let mut locks = self.active();
+        let position = locks
+            .iter()
+            .position(|lock| lock.token == token && lock.covers(path))
+            .ok_or_else(|| LockError::NoSuchLock(token.to_string()))?;
+        if locks[position].principal != principal {
+            return Err(LockError::NotOwner(token.to_string()));
+        }
+        locks.remove(position);
+        Ok(())
+    }
+
+    /// Checks that every lock guarding a change to `path` had its token submitted: locks on the
+    /// path or a parent, on the collection it is a member of, and on anything below it.
+    pub fn check(&self, path: &str, tokens: &[String]) -> Result<(), LockError> {
+        let parent = parent(path);
+        match self.active().iter().find(|lock| {
+            (lock.covers(path) || Some(lock.path.as_str()) == parent || is_within(&lock.path, path))
+                && !tokens.contains(&lock.token)
+        }) {
+            Some(lock) => Err(LockError::Locked(lock.path.clone())),
+            None => Ok(()),
+        }
+    }
+
+    /// The locks on `path`, directly or through a parent.
+    pub fn discover(&self, path: &str) -> Vec<Lock> {
+        self.active()
+            .iter()
+            .filter(|lock| lock.covers(path))
+            .cloned()
+            .collect()
+    }
+
+    /// Drops the locks on `path` and below it, once it was deleted or moved away.
+    pub fn release(&self, path: &str) {
+        self.active().retain(|lock| !is_within(&lock.path, path));
+    }
+
+    /// The locks, with expired ones dropped.
+    fn active(&self) -> std::sync::MutexGuard<'_, Vec<Lock>> {
+        let mut locks = self.locks.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        locks.retain(|lock| lock.expires_at > now);
+        locks
+    }
+
+    fn timeout(&self, requested: Option<Duration>) -> Duration {
+        requested.map_or(self.max_timeout, |timeout| timeout.min(self.max_timeout))
+    }
+}
+
+/// A file or collection described in a `PROPFIND` response.
+pub struct Resource {
+    /// Path relative to the storage root, empty for the root.
+    pub path: String,
+    pub is_collection: bool,
+    /// Size of the original content of files.
+    pub size: u64,
+    pub modified_at: SystemTime,
+    pub created_at: SystemTime,
+    pub etag: String,
+    pub locks: Vec<Lock>,
+}
+
+impl Resource {
+    const PROPERTIES: [&'static str; 9] = [
+        "creationdate",
+        "displayname",
+        "getcontentlength",
+        "getcontenttype",
+        "getetag",
+        "getlastmodified",
+        "resourcetype",
+        "supportedlock",
+        "lockdiscovery",
+    ];
+
+    /// The `response` element of the resource, with the properties asked for.
+    pub fn response(&self, request: &PropFind) -> String {
+        let mut found = vec![];
+        let mut missing = vec![];
+        match request {
+            PropFind::AllProp => found.extend(
+                Self::PROPERTIES
+                    .iter()
+                    .filter_map(|name| self.property(name)),
+            ),
+            PropFind::PropName => found.extend(
+                Self::PROPERTIES
+                    .iter()
+                    .filter(|name| self.property(name).is_some())
+                    .map(|name| format!("<D:{}/>", name)),
+            ),
+            PropFind::Prop(properties) => {
+                for property in properties {
+                    match property.namespace.as_str() {
+                        DAV_NAMESPACE => match self.property(&property.name) {
+                            Some(value) => found.push(value),
+                            None => missing.push(property.empty_element()),
+                        },
+                        _ => missing.push(property.empty_element()),
+                    }
+                }
+            }
+        }
+
+        let mut response = format!(
+            "<D:response><D:href>{}</D:href>",
+            escape(&href(&self.path, self.is_collection))
+        );
+        for (properties, status) in [(found, "200 OK"), (missing, "404 Not Found")] {
+            if !properties.is_empty() {
+                response.push_str(&format!(
+                    "<D:propstat><D:prop>{}</D:prop><D:status>HTTP/1.1 {}</D:status></D:propstat>",
+                    properties.concat(),
+                    status
+                ));
+            }
+        }
+        response.push_str("</D:response>");
+        response
+    }
+
+    fn property(&self, name: &str) -> Option<String> {
+        let value = match name {
+            "creationdate" => {
+                DateTime::<Utc>::from(self.created_at).to_rfc3339_opts(SecondsFormat::Secs, true)
+            }
+            "displayname" => escape(self.path.rsplit('/').next().unwrap_or_default()).into_owned(),
+            "getcontentlength" if !self.is_collection => self.size.to_string(),
+            "getcontenttype" if !self.is_collection => mime_guess::from_path(&self.path)
+                .first_or_octet_stream()
+                .essence_str()
+                .to_string(),
+            "getetag" if !self.is_collection => escape(&self.etag).into_owned(),
+            "getlastmodified" => httpdate::fmt_http_date(self.modified_at),
+            "resourcetype" if self.is_collection => "<D:collection/>".to_string(),
+            "resourcetype" => String::new(),
+            "supportedlock" => "<D:lockentry><D:lockscope><D:exclusive/></D:lockscope>\
+                                <D:locktype><D:write/></D:locktype></D:lockentry>\
+                                <D:lockentry><D:lockscope><D:shared/></D:lockscope>\
+                                <D:locktype><D:write/></D:locktype></D:lockentry>"
+                .to_string(),
+            "lockdiscovery" => self.locks.iter().map(Lock::active_lock).collect(),
+            _ => return None,
+        };
+        Some(format!(
+            "<D:{name}>{value}</D:{name}>",
+            name = name,
+            value = value
+        ))
+    }
+}
+
+/// A `multistatus` document of `PROPFIND` responses.
+pub fn multistatus(responses: &[String]) -> String {
+    format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\
+         <D:multistatus xmlns:D=\"DAV:\">{}</D:multistatus>",
+        responses.concat()
+    )
+}
+
+/// The body answering a `LOCK`.
+pub fn lock_discovery(lock: &Lock) -> String {
+    format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\
+         <D:prop xmlns:D=\"DAV:\"><D:lockdiscovery>{}</D:lockdiscovery></D:prop>",
+        lock.active_lock()
+    )
+}
+
+/// An `error` document naming the precondition a request failed.
+pub fn precondition_error(condition: &str) -> String {
+    format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\
+         <D:error xmlns:D=\"DAV:\"><D:{}/></D:error>",
+        condition
+    )
+}
+
+/// The URL path of a storage path, ending in a slash for collections.
+pub fn href(path: &str, is_collection: bool) -> String {
+    let mut href = PREFIX.to_string();
+    for segment in path.split('/').filter(|segment| !segment.is_empty()) {
+        href.push('/');
+        href.extend(utf8_percent_encode(segment, SEGMENT));
+    }
+    if is_collection || path.is_empty() {
+        href.push('/');
+    }
+    href
+}
+
+/// The storage path a `Destination` header points at, given as an absolute URL or path below
+/// the WebDAV prefix.
+pub fn destination_path(destination: &str) -> Option<String> {
+    let path = match destination.split_once("://") {
+        Some((_, rest)) => &rest[rest.find('/')?..],
+        None => destination,
+    };
+    let path = path.strip_prefix(PREFIX)?;
+    if !path.is_empty() && !path.starts_with('/') {
+        return None;
+    }
+    let path = percent_decode_str(path).decode_utf8().ok()?;
+    Some(path.trim_matches('/').to_string())
+}
+
+/// The lock tokens submitted in an `If` header. Its conditions are otherwise not evaluated.
+pub fn submitted_tokens(if_header: Option<&str>) -> Vec<String> {
+    let mut tokens = vec![];
+    let mut rest = if_header.unwrap_or_default();
+    while let Some(start) = rest.find('<') {
+        let Some(end) = rest[start..].find('>') else {
+            break;
+        };
+        let token = &rest[start + 1..start + end];
+        if token.starts_with(LOCK_TOKEN_SCHEME) {
+            tokens.push(token.to_string());
+        }
+        rest = &rest[start + end + 1..];
+    }
+    tokens
+}
+
+/// Parses a `Timeout` header, `Infinite` or `Second-<n>`, taking its first usable value.
+pub fn parse_timeout(value: Option<&str>) -> Option<Duration> {
+    value?.split(',').map(str::trim).find_map(|timeout| {
+        timeout
+            .strip_prefix("Second-")
+            .and_then(|seconds| seconds.parse().ok())
+            .map(Duration::from_secs)
+    })
+}
+
+/// Whether `path` is `ancestor` or below it, the empty path being the root.
+pub fn is_within(path: &str, ancestor: &str) -> bool {
+    ancestor.is_empty()
+        || path == ancestor
+        || path
+            .strip_prefix(ancestor)
+            .is_some_and(|rest| rest.starts_with('/'))
+}
+
+/// The path of the collection `path` is a member of.
+pub fn parent(path: &str) -> Option<&str> {
+    match path {
+        "" => None,
+        path => Some(path.rsplit_once('/').map_or("", |(parent, _)| parent)),
+    }
+}
+
+/// Calls `visit` with the ancestors and name of every element of an XML document.
+fn read_elements(
+    body: &[u8],
+    mut visit: impl FnMut(&[PropertyName], &PropertyName) -> Result<(), String>,
+) -> Result<(), String> {
+    let mut reader = NsReader::from_reader(body);
+    reader.trim_text(true);
+    let mut ancestors = vec![];
+    loop {
+        let (namespace, event) = reader
+            .read_resolved_event()
+            .map_err(|e| format!("Invalid XML: {}", e))?;
+        match event {
+            Event::Start(start) => {
+                let element = element_name(&namespace, start.local_name())?;
+                visit(&ancestors, &element)?;
+                ancestors.push(element);
+            }
+            Event::Empty(start) => {
+                let element = element_name(&namespace, start.local_name())?;
+                visit(&ancestors, &element)?;
+            }
+            Event::End(_) => {
+                ancestors.pop();
+            }
+            Event::Eof => return Ok(()),
+            _ => {}
+        }
+    }
+}
+
+fn element_name(
+    namespace: &ResolveResult,
+    local_name: quick_xml::name::LocalName,
+) -> Result<PropertyName, String> {
+    let namespace = match namespace {
+        ResolveResult::Bound(Namespace(namespace)) => {
+            String::from_utf8_lossy(namespace).into_owned()
+        }
+        ResolveResult::Unbound => String::new(),
+        ResolveResult::Unknown(prefix) => {
+            return Err(format!(
+                "Unknown namespace prefix {}",
+                String::from_utf8_lossy(prefix)
+            ))
+        }
+    };
+    Ok(PropertyName {
+        namespace,
+        name: String::from_utf8_lossy(local_name.as_ref()).into_owned(),
+    })
+}
@@ -0,0 +1,311 @@
+use std::{
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use tokio::{fs, sync::Notify};
+use uuid::Uuid;
+
+use crate::{
+    configuration::{WebhookEndpointSettings, WebhooksSettings},
+    domain::StorageDetails,
+    events::FileEvent,
+};
+
+/// `sha256=<hex>` HMAC of the request body, keyed with the endpoint's secret.
+pub const SIGNATURE_HEADER: &str = "x-crumbbox-signature";
+pub const EVENT_HEADER: &str = "x-crumbbox-event";
+/// Stays the same across retries, so receivers can drop duplicates.
+pub const DELIVERY_HEADER: &str = "x-crumbbox-delivery";
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// An event waiting to be delivered to one endpoint, stored as a JSON file in the outbox.
+#[derive(Serialize, Deserialize, Debug)]
+struct Delivery {
+    id: String,
+    /// Position of the endpoint in the configuration, endpoints sharing a URL may differ by
+    /// secret.
+    endpoint: usize,
+    url: String,
+    event: FileEvent,
+    attempts: u32,
+    next_attempt_at: DateTime<Utc>,
+}
+
+struct Endpoint {
+    settings: WebhookEndpointSettings,
+    paths: Option<GlobSet>,
+}
+
+impl Endpoint {
+    fn new(settings: WebhookEndpointSettings) -> Result<Self, globset::Error> {
+        let paths = if settings.paths.is_empty() {
+            None
+        } else {
+            let mut builder = GlobSetBuilder::new();
+            for pattern in &settings.paths {
+                builder.add(GlobBuilder::new(pattern).literal_separator(true).build()?);
+            }
+            Some(builder.build()?)
+        };
+
+        Ok(Self { settings, paths })
+    }
+
+    fn accepts(&self, event: &FileEvent) -> bool {
+        let kind_matches =
+            self.settings.events.is_empty() || self.settings.events.contains(&event.kind);
+        let path_matches = self.paths.as_ref().is_none_or(|paths| {
+            paths.is_match(&event.path)
+                || event
+                    .from_path
+                    .as_ref()
+                    .is_some_and(|from_path| paths.is_match(from_path))
+        });
+
+        kind_matches && path_matches
+    }
+}
+
+/// Delivers file events to the configured endpoints. Deliveries are written to the internal
+/// `webhooks/outbox` area before being attempted, so they survive restarts, and are retried
+/// with exponential backoff until they succeed or run out of attempts.
+pub struct Webhooks {
+    settings: WebhooksSettings,
+    endpoints: Vec<Endpoint>,
+    outbox: PathBuf,
+    failed: PathBuf,
+    client: reqwest::Client,
+    wake: Notify,
+}
+
+impl Webhooks {
+    pub fn new(
+        settings: WebhooksSettings,
+        storage_details: &StorageDetails,
+    ) -> Result<Self, globset::Error> {
+        let endpoints = settings
+            .endpoints
+            .iter()
+            .cloned()
+            .map(Endpoint::new)
+            .collect::<Result<_, _>>()?;
+        let webhooks_root = storage_details.internal_path("webhooks");
+
+        Ok(Self {
+            settings,
+            endpoints,
+            outbox: webhooks_root.join("outbox"),
+            failed: webhooks_root.join("failed"),
+            client: reqwest::Client::new(),
+            wake: Notify::new(),
+        })
+    }
+
+    /// Queues a delivery of the event for every endpoint subscribed to it.
+    pub async fn enqueue(&self, event: &FileEvent) -> Result<(), io::Error> {
+        let mut queued = false;
+        for (index, endpoint) in self
+            .endpoints
+            .iter()
+            .enumerate()
+            .filter(|(_, e)| e.accepts(event))
+        {
+            let delivery = Delivery {
+                id: Uuid::new_v4().to_string(),
+                endpoint: index,
+                url: endpoint.settings.url.clone(),
+                event: event.clone(),
+                attempts: 0,
+                next_attempt_at: Utc::now(),
+            };
+            write_delivery(&self.outbox, &delivery).await?;
+            queued = true;
+        }
+
+        if queued {
+            self.wake.notify_one();
+        }
+        Ok(())
+    }
+
+    /// Works through the outbox until the process exits. Deliveries left behind by a previous
+    /// run are attempted straight away.
+    pub async fn deliver_queued(self: Arc<Self>) {
+        let mut resuming = true;
+        loop {
+            let next_attempt_at = match self.deliver_due(resuming).await {
+                Ok(next_attempt_at) => next_attempt_at,
+                Err(e) => {
+                    tracing::error!("Failed to process webhook outbox: {:?}", e);
+                    Some(Utc::now() + self.backoff(1))
+                }
+            };
+            resuming = false;
+
+            match next_attempt_at {
+                Some(at) => {
+                    let delay = (at - Utc::now()).to_std().unwrap_or_default();
+                    tokio::select! {
+                        _ = tokio::time::sleep(delay) => {}
+                        _ = self.wake.notified() => {}
+                    }
+                }
+                None => self.wake.notified().await,
+            }
+        }
+    }
+
+    /// Attempts every due delivery, returning when the next remaining one is due.
+    async fn deliver_due(&self, all: bool) -> Result<Option<DateTime<Utc>>, io::Error> {
+        let mut entries = match fs::read_dir(&self.outbox).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        let mut next_attempt_at: Option<DateTime<Utc>> = None;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.extension().is_none_or(|extension| extension != "json") {
+                continue;
+            }
+
+            let mut delivery: Delivery = match serde_json::from_slice(&fs::read(&path).await?) {
+                Ok(delivery) => delivery,
+                Err(e) => {
+                    // Set aside, so it does not hold up the deliveries after it.
+                    tracing::error!(
+                        "Moving unreadable webhook delivery {:?} aside: {:?}",
+                        path,
+                        e
+                    );
+                    fs::create_dir_all(&self.failed).await?;
+                    fs::rename(&path, self.failed.join(entry.file_name())).await?;
+                    continue;
+                }
+            };
+            if !all && delivery.next_attempt_at > Utc::now() {
+                next_attempt_at = Some(next_attempt_at.map_or(delivery.next_attempt_at, |at| {
+                    at.min(delivery.next_attempt_at)
+                }));
+                continue;
+            }
+
+            let result = match self.endpoint(&delivery) {
+                Some(endpoint) => self.attempt(endpoint, &delivery).await,
+                None => {
+                    tracing::warn!(
+                        "Dropping webhook delivery {} to {}, the endpoint is no longer configured",
+                        delivery.id,
+                        delivery.url
+                    );
+                    fs::remove_file(&path).await?;
+                    continue;
+                }
+            };
+
+            delivery.attempts += 1;
+            match result {
+                Ok(()) => fs::remove_file(&path).await?,
+                Err(e) if delivery.attempts >= self.settings.max_attempts => {
+                    tracing::error!(
+                        "Giving up on webhook delivery {} to {} after {} attempts: {:?}",
+                        delivery.id,
+                        delivery.url,
+                        delivery.attempts,
+                        e
+                    );
+                    write_delivery(&self.failed, &delivery).await?;
+                    fs::remove_file(&path).await?;
+                }
+                Err(e) => {
+                    tracing::warn!(
+                        "Webhook delivery {} to {} failed: {:?}",
+                        delivery.id,
+                        delivery.url,
+                        e
+                    );
+                    delivery.next_attempt_at = Utc::now() + self.backoff(delivery.attempts);
+                    next_attempt_at =
+                        Some(next_attempt_at.map_or(delivery.next_attempt_at, |at| {
+                            at.min(delivery.next_attempt_at)
+                        }));
+                    write_delivery(&self.outbox, &delivery).await?;
+                }
+            }
+        }
+
+        Ok(next_attempt_at)
+    }
+
+    #[tracing::instrument(
+        name = "Deliver webhook",
+        skip(self, endpoint, delivery),
+        fields(delivery_id = %delivery.id, url = %delivery.url)
+    )]
+    async fn attempt(&self, endpoint: &Endpoint, delivery: &Delivery) -> anyhow::Result<()> {
+        let body = serde_json::to_vec(&delivery.event)?;
+        let signature = sign(&endpoint.settings.secret, &body);
+
+        self.client
+            .post(&delivery.url)
+            .timeout(REQUEST_TIMEOUT)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header(SIGNATURE_HEADER, format!("sha256={}", signature))
+            .header(EVENT_HEADER, delivery.event.kind.as_str())
+            .header(DELIVERY_HEADER, &delivery.id)
+            .body(body)
+            .send()
+            .await
+            .context("Failed to send request")?
+            .error_for_status()
+            .context("Endpoint rejected the delivery")?;
+
+        Ok(())
+    }
+
+    /// The endpoint a delivery was queued for, unless the configuration changed since.
+    fn endpoint(&self, delivery: &Delivery) -> Option<&Endpoint> {
+        self.endpoints
+            .get(delivery.endpoint)
+            .filter(|e| e.settings.url == delivery.url)
+    }
+
+    /// Delay before the attempt following the `attempts`th failed one.
+    fn backoff(&self, attempts: u32) -> chrono::Duration {
+        let factor = 2u64.saturating_pow(attempts.saturating_sub(1));
+        let delay_ms = self
+            .settings
+            .initial_backoff_ms
+            .saturating_mul(factor)
+            .min(self.settings.max_backoff_ms);
+        chrono::Duration::milliseconds(delay_ms as i64)
+    }
+}
+
+/// Hex encoded HMAC-SHA256 of `body`.
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(body);
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Writes the delivery next to its final name first, so readers never see a partial file.
+async fn write_delivery(directory: &Path, delivery: &Delivery) -> Result<(), io::Error> {
+    fs::create_dir_all(directory).await?;
+    let path = directory.join(format!("{}.json", delivery.id));
+    let temporary_path = directory.join(format!("{}.json.tmp", delivery.id));
+    fs::write(&temporary_path, serde_json::to_vec(delivery)?).await?;
+    fs::rename(temporary_path, path).await
+}
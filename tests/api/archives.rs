@@ -0,0 +1,225 @@
+use std::{
+    collections::BTreeMap,
+    io::{Cursor, Read},
+};
+
+use crumbbox::configuration::{ApiKeySettings, MasterKeySettings};
+use flate2::read::GzDecoder;
+use reqwest::{header, StatusCode};
+use uuid::Uuid;
+
+use crate::helpers::{remove, root_key, spawn_app, spawn_app_with, upload, TestApp};
+
+/// Names of the entries of an archive, with the contents of files and `None` for directories.
+type Entries = BTreeMap<String, Option<Vec<u8>>>;
+
+#[tokio::test]
+async fn directories_are_downloaded_as_zip_archives() {
+    let app = spawn_app().await;
+    let directory = create_tree(&app, &Uuid::new_v4().to_string()).await;
+
+    let response = get_archive(&app, &format!("{}?format=zip", directory)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::CONTENT_TYPE], "application/zip");
+    assert_eq!(
+        response.headers()[header::CONTENT_DISPOSITION],
+        format!("attachment; filename=\"{}.zip\"", directory).as_str()
+    );
+    let entries = read_zip(response.bytes().await.unwrap().to_vec());
+    assert_eq!(entries, expected_tree(&directory));
+
+    remove(&app, &directory);
+}
+
+#[tokio::test]
+async fn directories_are_downloaded_as_tar_gz_archives_of_their_original_content() {
+    // Stored content is encrypted and compressed, archives hold what was uploaded.
+    let app = spawn_app_with(|config| {
+        config.compression.enabled = true;
+        config.compression.min_size_bytes = 0;
+        config.encryption.enabled = true;
+        config.encryption.active_key = Some("first".to_string());
+        config.encryption.master_keys = vec![MasterKeySettings {
+            id: "first".to_string(),
+            key: "11".repeat(32),
+        }];
+    })
+    .await;
+    let directory = create_tree(&app, &Uuid::new_v4().to_string()).await;
+
+    let response = get_archive(&app, &format!("{}?format=tar.gz", directory)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::CONTENT_TYPE], "application/gzip");
+    assert_eq!(
+        response.headers()[header::CONTENT_DISPOSITION],
+        format!("attachment; filename=\"{}.tar.gz\"", directory).as_str()
+    );
+    let entries = read_tar_gz(response.bytes().await.unwrap().to_vec());
+    assert_eq!(entries, expected_tree(&directory));
+
+    remove(&app, &directory);
+}
+
+#[tokio::test]
+async fn posted_paths_are_archived_under_their_full_paths() {
+    let app = spawn_app().await;
+    let directory = create_tree(&app, &Uuid::new_v4().to_string()).await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/archive?format=tar.gz", app.addr()))
+        .json(&serde_json::json!({
+            "paths": [format!("{}/a.txt", directory), format!("{}/sub", directory)],
+        }))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()[header::CONTENT_DISPOSITION],
+        "attachment; filename=\"archive.tar.gz\""
+    );
+    let entries = read_tar_gz(response.bytes().await.unwrap().to_vec());
+    let mut expected = expected_tree(&directory);
+    expected.remove(&format!("{}/", directory));
+    assert_eq!(entries, expected);
+
+    remove(&app, &directory);
+}
+
+#[tokio::test]
+async fn archives_only_hold_the_allowed_paths_of_the_caller() {
+    let directory = Uuid::new_v4().to_string();
+    let allowed = format!("{}/sub", directory);
+    let app = spawn_app_with({
+        let allowed = allowed.clone();
+        move |config| {
+            config.auth.api_keys = vec![
+                ApiKeySettings {
+                    user: "bob".to_string(),
+                    key: "bob-key".to_string(),
+                    admin: false,
+                    allowed_paths: vec![allowed],
+                },
+                root_key(),
+            ];
+        }
+    })
+    .await;
+    create_tree(&app, &directory).await;
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/archive/{}", app.addr(), directory))
+        .bearer_auth("bob-key")
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let entries = read_zip(response.bytes().await.unwrap().to_vec());
+    let expected = expected_tree(&directory)
+        .into_iter()
+        .filter(|(name, _)| name.starts_with(&allowed))
+        .collect::<Entries>();
+    assert_eq!(entries, expected);
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/archive/{}/a.txt", app.addr(), directory))
+        .bearer_auth("bob-key")
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    remove(&app, &directory);
+}
+
+#[tokio::test]
+async fn invalid_archive_requests_are_rejected() {
+    let app = spawn_app().await;
+
+    let response = get_archive(&app, &Uuid::new_v4().to_string()).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = get_archive(&app, "%2E%2E%2Fsrc").await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = get_archive(&app, ".crumbbox").await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let directory = create_tree(&app, &Uuid::new_v4().to_string()).await;
+    let response = get_archive(&app, &format!("{}?format=rar", directory)).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    for paths in [serde_json::json!([]), serde_json::json!(["../src"])] {
+        let response = reqwest::Client::new()
+            .post(format!("{}/archive", app.addr()))
+            .json(&serde_json::json!({ "paths": paths }))
+            .send()
+            .await
+            .expect("Failed to execute request");
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", paths);
+    }
+
+    remove(&app, &directory);
+}
+
+/// Creates a directory with a file, and a subdirectory with two more, through uploads.
+async fn create_tree(app: &TestApp, directory: &str) -> String {
+    std::fs::create_dir_all(format!("{}/{}/sub", app.storage_path, directory)).unwrap();
+    upload(app, directory, "a.txt", b"first file".to_vec()).await;
+    let sub = format!("{}/sub", directory);
+    upload(app, &sub, "b.txt", b"second file".repeat(200)).await;
+    upload(app, &sub, "c.bin", (0..=255).collect::<Vec<u8>>()).await;
+    directory.to_string()
+}
+
+/// The entries of an archive of a tree made by `create_tree`, named below `name`.
+fn expected_tree(name: &str) -> Entries {
+    BTreeMap::from([
+        (format!("{}/", name), None),
+        (format!("{}/a.txt", name), Some(b"first file".to_vec())),
+        (format!("{}/sub/", name), None),
+        (
+            format!("{}/sub/b.txt", name),
+            Some(b"second file".repeat(200)),
+        ),
+        (format!("{}/sub/c.bin", name), Some((0..=255).collect())),
+    ])
+}
+
+fn read_zip(archive: Vec<u8>) -> Entries {
+    let mut archive = zip::ZipArchive::new(Cursor::new(archive)).unwrap();
+    let mut entries = Entries::new();
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i).unwrap();
+        let contents = match file.is_dir() {
+            true => None,
+            false => {
+                let mut contents = vec![];
+                file.read_to_end(&mut contents).unwrap();
+                Some(contents)
+            }
+        };
+        entries.insert(file.name().to_string(), contents);
+    }
+    entries
+}
+
+fn read_tar_gz(archive: Vec<u8>) -> Entries {
+    let mut archive = tar::Archive::new(GzDecoder::new(Cursor::new(archive)));
+    let mut entries = Entries::new();
+    for entry in archive.entries().unwrap() {
+        let mut entry = entry.unwrap();
+        let name = entry.path().unwrap().to_string_lossy().into_owned();
+        if entry.header().entry_type().is_dir() {
+            entries.insert(format!("{}/", name.trim_end_matches('/')), None);
+        } else {
+            let mut contents = vec![];
+            entry.read_to_end(&mut contents).unwrap();
+            entries.insert(name, Some(contents));
+        }
+    }
+    entries
+}
+
+async fn get_archive(app: &TestApp, path_and_query: &str) -> reqwest::Response {
+    reqwest::get(format!("{}/archive/{}", app.addr(), path_and_query))
+        .await
+        .expect("Failed to execute request")
+}
@@ -0,0 +1,179 @@
+use crate::helpers::{spawn_app, spawn_app_with, TestApp};
+use crumbbox::configuration::ApiKeySettings;
+use reqwest::{
+    multipart::{Form, Part},
+    StatusCode,
+};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+#[tokio::test]
+async fn uploads_are_recorded_in_the_audit_log() {
+    let app = spawn_app().await;
+    let file_name = Uuid::new_v4().to_string();
+
+    let response = upload(&app, &file_name, b"audited contents", None).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let audit_log = std::fs::read_to_string(&app.audit_path).unwrap();
+    let record: serde_json::Value =
+        serde_json::from_str(audit_log.lines().next().unwrap()).unwrap();
+    assert_eq!(record["action"], "upload");
+    assert_eq!(record["identity"], "anonymous");
+    assert_eq!(record["client_ip"], "127.0.0.1");
+    assert_eq!(record["path"], file_name);
+    assert_eq!(record["size"], 16);
+    assert_eq!(
+        record["checksum"],
+        hex::encode(Sha256::digest(b"audited contents"))
+    );
+    std::fs::remove_file(format!("{}/{}", app.storage_path, file_name)).unwrap();
+}
+
+#[tokio::test]
+async fn uploading_an_existing_file_is_recorded_as_an_overwrite() {
+    let app = spawn_app().await;
+    let file_name = Uuid::new_v4().to_string();
+
+    upload(&app, &file_name, b"first", None).await;
+    upload(&app, &file_name, b"second", None).await;
+
+    let records = query_audit_log(&app, "", None).await;
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[0]["action"], "upload");
+    assert_eq!(records[1]["action"], "overwrite");
+    std::fs::remove_file(format!("{}/{}", app.storage_path, file_name)).unwrap();
+}
+
+#[tokio::test]
+async fn failed_uploads_are_not_recorded() {
+    let app = spawn_app().await;
+
+    let response = upload(&app, "/", b"contents", None).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    assert!(query_audit_log(&app, "", None).await.is_empty());
+}
+
+#[tokio::test]
+async fn audit_log_can_be_filtered_by_user_and_path_prefix() {
+    let app = spawn_app_with(|config| {
+        config.auth.api_keys = vec![
+            api_key("alice", "alice-key", false),
+            api_key("root", "root-key", true),
+        ]
+    })
+    .await;
+    let alice_file = format!("alice-{}", Uuid::new_v4());
+    let root_file = format!("root-{}", Uuid::new_v4());
+
+    upload(&app, &alice_file, b"contents", Some("alice-key")).await;
+    upload(&app, &root_file, b"contents", Some("root-key")).await;
+
+    let by_user = query_audit_log(&app, "?user=alice", Some("root-key")).await;
+    assert_eq!(by_user.len(), 1);
+    assert_eq!(by_user[0]["path"], alice_file);
+
+    let by_prefix = query_audit_log(&app, "?path_prefix=root-", Some("root-key")).await;
+    assert_eq!(by_prefix.len(), 1);
+    assert_eq!(by_prefix[0]["identity"], "root");
+
+    let in_the_future = query_audit_log(&app, "?from=2999-01-01T00:00:00Z", Some("root-key")).await;
+    assert!(in_the_future.is_empty());
+
+    std::fs::remove_file(format!("{}/{}", app.storage_path, alice_file)).unwrap();
+    std::fs::remove_file(format!("{}/{}", app.storage_path, root_file)).unwrap();
+}
+
+#[tokio::test]
+async fn audit_log_requires_an_admin_when_api_keys_are_configured() {
+    let app =
+        spawn_app_with(|config| config.auth.api_keys = vec![api_key("alice", "alice-key", false)])
+            .await;
+
+    let client = reqwest::Client::new();
+    let response = client
+        .get(format!("{}/admin/audit", app.addr()))
+        .bearer_auth("alice-key")
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let response = client
+        .get(format!("{}/admin/audit", app.addr()))
+        .bearer_auth("unknown-key")
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn audit_log_is_rotated_and_still_queryable() {
+    let app = spawn_app_with(|config| {
+        config.audit.max_file_bytes = 1;
+        config.audit.max_files = 5;
+    })
+    .await;
+    let file_names = (0..3)
+        .map(|_| Uuid::new_v4().to_string())
+        .collect::<Vec<_>>();
+
+    for file_name in &file_names {
+        upload(&app, file_name, b"contents", None).await;
+    }
+
+    assert!(std::path::Path::new(&format!("{}.2", app.audit_path)).exists());
+    let records = query_audit_log(&app, "", None).await;
+    let paths = records
+        .iter()
+        .map(|record| record["path"].as_str().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(paths, file_names);
+
+    for file_name in &file_names {
+        std::fs::remove_file(format!("{}/{}", app.storage_path, file_name)).unwrap();
+    }
+}
+
+async fn upload(
+    app: &TestApp,
+    file_name: &str,
+    contents: &'static [u8],
+    api_key: Option<&str>,
+) -> reqwest::Response {
+    let part = Part::bytes(contents).file_name(file_name.to_string());
+    let mut request = reqwest::Client::new()
+        .post(format!("{}/upload", app.addr()))
+        .multipart(Form::new().text("relative_path", "").part("file", part));
+    if let Some(api_key) = api_key {
+        request = request.bearer_auth(api_key);
+    }
+
+    request.send().await.expect("Failed to execute request")
+}
+
+async fn query_audit_log(
+    app: &TestApp,
+    query: &str,
+    api_key: Option<&str>,
+) -> Vec<serde_json::Value> {
+    let mut request = reqwest::Client::new().get(format!("{}/admin/audit{}", app.addr(), query));
+    if let Some(api_key) = api_key {
+        request = request.bearer_auth(api_key);
+    }
+
+    let response = request.send().await.expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    response.json().await.unwrap()
+}
+
+fn api_key(user: &str, key: &str, admin: bool) -> ApiKeySettings {
+    ApiKeySettings {
+        user: user.to_string(),
+        key: key.to_string(),
+        admin,
+        allowed_paths: vec![],
+    }
+}
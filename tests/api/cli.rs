@@ -0,0 +1,263 @@
+use std::path::{Path, PathBuf};
+
+use crumbbox::{
+    client::{
+        plan_download, plan_sync, plan_upload, transfer_all, Client, Direction, Profile, Progress,
+    },
+    configuration::ApiKeySettings,
+};
+use uuid::Uuid;
+
+use crate::helpers::{remove, spawn_app, spawn_app_with, TestApp};
+
+#[tokio::test]
+async fn directories_are_uploaded_recursively_onto_relative_paths() {
+    let app = spawn_app().await;
+    let client = client(&app);
+    let local = local_tree(&[("notes.txt", "notes"), ("photos/2024/beach.jpg", "beach")]);
+    let remote = Uuid::new_v4().to_string();
+
+    let uploads = plan_upload(&local, &remote).unwrap();
+    let failures = transfer_all(&client, Direction::Upload, uploads, 4, &Progress::hidden())
+        .await
+        .unwrap();
+    assert!(failures.is_empty(), "{:?}", failures);
+
+    let name = local.file_name().unwrap().to_str().unwrap();
+    assert_eq!(
+        read(&app, &format!("{}/{}/photos/2024/beach.jpg", remote, name)),
+        "beach"
+    );
+    let paths = client
+        .list(&remote, true)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|file| file.path)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        paths,
+        [
+            format!("{}/{}/notes.txt", remote, name),
+            format!("{}/{}/photos/2024/beach.jpg", remote, name),
+        ]
+    );
+    std::fs::remove_dir_all(&local).unwrap();
+    remove(&app, &remote);
+}
+
+#[tokio::test]
+async fn remote_directories_are_downloaded_with_their_name() {
+    let app = spawn_app().await;
+    let client = client(&app);
+    let local = local_tree(&[("a.txt", "first"), ("deeper/b.txt", "second")]);
+    let remote = Uuid::new_v4().to_string();
+    let uploads = plan_upload(&local, &remote).unwrap();
+    transfer_all(&client, Direction::Upload, uploads, 2, &Progress::hidden())
+        .await
+        .unwrap();
+    let name = local.file_name().unwrap().to_str().unwrap().to_string();
+    let remote_directory = format!("{}/{}", remote, name);
+
+    let target = local_tree(&[]);
+    let files = client.list(&remote_directory, true).await.unwrap();
+    let downloads = plan_download(files, &remote_directory, &target);
+    let failures = transfer_all(
+        &client,
+        Direction::Download,
+        downloads,
+        2,
+        &Progress::hidden(),
+    )
+    .await
+    .unwrap();
+    assert!(failures.is_empty(), "{:?}", failures);
+
+    let downloaded = target.join(&name);
+    assert_eq!(
+        std::fs::read_to_string(downloaded.join("deeper/b.txt")).unwrap(),
+        "second"
+    );
+    assert_eq!(
+        std::fs::read_to_string(downloaded.join("a.txt")).unwrap(),
+        "first"
+    );
+    assert!(!downloaded.join("a.txt.part").exists());
+    let record = client
+        .stat(&format!("{}/a.txt", remote_directory))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(record.size, 5);
+    assert!(client
+        .stat(&format!("{}/missing.txt", remote_directory))
+        .await
+        .unwrap()
+        .is_none());
+    std::fs::remove_dir_all(&local).unwrap();
+    std::fs::remove_dir_all(&target).unwrap();
+    remove(&app, &remote);
+}
+
+#[tokio::test]
+async fn sync_uploads_only_what_changed_and_finds_remote_leftovers() {
+    let app = spawn_app().await;
+    let client = client(&app);
+    let local = local_tree(&[
+        ("same.txt", "same"),
+        ("changed.txt", "before"),
+        ("gone.txt", "gone"),
+    ]);
+    let remote = Uuid::new_v4().to_string();
+    let plan = plan_sync(&local, &remote, vec![]).unwrap();
+    assert_eq!(plan.uploads.len(), 3);
+    transfer_all(
+        &client,
+        Direction::Upload,
+        plan.uploads,
+        4,
+        &Progress::hidden(),
+    )
+    .await
+    .unwrap();
+
+    std::fs::write(local.join("changed.txt"), "after!").unwrap();
+    std::fs::remove_file(local.join("gone.txt")).unwrap();
+    std::fs::write(local.join("new.txt"), "new").unwrap();
+    let remote_files = client.list(&remote, true).await.unwrap();
+    let plan = plan_sync(&local, &remote, remote_files).unwrap();
+
+    let uploads = plan
+        .uploads
+        .iter()
+        .map(|upload| upload.remote_path.clone())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        uploads,
+        [
+            format!("{}/changed.txt", remote),
+            format!("{}/new.txt", remote)
+        ]
+    );
+    assert_eq!(plan.removals, [format!("{}/gone.txt", remote)]);
+    std::fs::remove_dir_all(&local).unwrap();
+    remove(&app, &remote);
+}
+
+#[tokio::test]
+async fn files_are_moved_and_removed() {
+    let app = spawn_app().await;
+    let client = client(&app);
+    let remote = Uuid::new_v4().to_string();
+    std::fs::create_dir(format!("{}/{}", app.storage_path, remote)).unwrap();
+    std::fs::write(
+        format!("{}/{}/old name.txt", app.storage_path, remote),
+        "moved",
+    )
+    .unwrap();
+
+    client
+        .rename(
+            &format!("{}/old name.txt", remote),
+            &format!("{}/new name.txt", remote),
+        )
+        .await
+        .unwrap();
+    assert_eq!(read(&app, &format!("{}/new name.txt", remote)), "moved");
+
+    client
+        .remove(&format!("{}/new name.txt", remote))
+        .await
+        .unwrap();
+    assert!(!Path::new(&format!("{}/{}/new name.txt", app.storage_path, remote)).exists());
+    let error = client
+        .remove(&format!("{}/new name.txt", remote))
+        .await
+        .unwrap_err();
+    assert!(error.to_string().starts_with("404"), "{}", error);
+    remove(&app, &remote);
+}
+
+#[tokio::test]
+async fn the_binary_connects_with_a_profile_of_the_profile_file() {
+    let app = spawn_app_with(|config| {
+        config.auth.api_keys = vec![ApiKeySettings {
+            user: "alice".to_string(),
+            key: "alice-key".to_string(),
+            admin: false,
+            allowed_paths: vec![],
+        }];
+    })
+    .await;
+    let local = local_tree(&[("report.txt", "report")]);
+    let profile_file = local.join("profiles.yaml");
+    std::fs::write(
+        &profile_file,
+        format!(
+            "default:\n  url: {0}\n  api_key: alice-key\nwrong:\n  url: {0}\n  api_key: wrong-key\n",
+            app.addr()
+        ),
+    )
+    .unwrap();
+    let remote = Uuid::new_v4().to_string();
+
+    let output = cli(&profile_file, &["upload", "-q"])
+        .arg(local.join("report.txt"))
+        .arg(&remote)
+        .output()
+        .await
+        .unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    assert_eq!(read(&app, &format!("{}/report.txt", remote)), "report");
+
+    let output = cli(&profile_file, &["ls", &remote]).output().await.unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let listing = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        listing.contains("6  ") && listing.contains(&format!("{}/report.txt", remote)),
+        "{}",
+        listing
+    );
+
+    let output = cli(&profile_file, &["--profile", "wrong", "ls", &remote])
+        .output()
+        .await
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8(output.stderr).unwrap().contains("401"));
+    std::fs::remove_dir_all(&local).unwrap();
+    remove(&app, &remote);
+}
+
+fn client(app: &TestApp) -> Client {
+    Client::new(&Profile {
+        url: app.addr(),
+        ..Profile::default()
+    })
+    .unwrap()
+}
+
+fn cli(profile_file: &Path, arguments: &[&str]) -> tokio::process::Command {
+    let mut command = tokio::process::Command::new(env!("CARGO_BIN_EXE_crumbbox-cli"));
+    command
+        .arg("--profile-file")
+        .arg(profile_file)
+        .args(arguments);
+    command
+}
+
+/// A local directory holding the given files.
+fn local_tree(files: &[(&str, &str)]) -> PathBuf {
+    let root = std::env::temp_dir().join(Uuid::new_v4().to_string());
+    std::fs::create_dir_all(&root).unwrap();
+    for (path, contents) in files {
+        let path = root.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+    }
+    root
+}
+
+fn read(app: &TestApp, path: &str) -> String {
+    std::fs::read_to_string(format!("{}/{}", app.storage_path, path)).unwrap()
+}
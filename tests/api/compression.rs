@@ -0,0 +1,297 @@
+use async_compression::tokio::bufread::ZstdDecoder;
+use crumbbox::configuration::{MasterKeySettings, Settings};
+use reqwest::{header, StatusCode};
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncReadExt;
+use uuid::Uuid;
+
+use crate::helpers::{spawn_app_with, upload, TestApp};
+
+fn enable(config: &mut Settings) {
+    config.compression.enabled = true;
+}
+
+/// Text repeating itself, which compresses well.
+fn compressible() -> Vec<u8> {
+    (0..2000)
+        .map(|i| format!("line {} of a log that repeats itself\n", i % 50))
+        .collect::<String>()
+        .into_bytes()
+}
+
+/// Chained SHA-256 digests, which do not compress at all.
+fn incompressible() -> Vec<u8> {
+    let mut digest = Sha256::digest(b"seed");
+    let mut contents = vec![];
+    for _ in 0..1000 {
+        contents.extend_from_slice(&digest);
+        digest = Sha256::digest(digest);
+    }
+    contents
+}
+
+#[tokio::test]
+async fn compressible_files_are_stored_compressed_and_downloaded_as_sent() {
+    let app = spawn_app_with(enable).await;
+    let file_name = format!("{}.log", Uuid::new_v4());
+    let contents = compressible();
+
+    upload(&app, "", &file_name, contents.clone()).await;
+
+    let stored = std::fs::read(format!("{}/{}", app.storage_path, file_name)).unwrap();
+    assert!(stored.starts_with(b"CBXZ"));
+    assert!(stored.len() < contents.len() / 4);
+
+    let response = reqwest::get(format!("{}/files/{}", app.addr(), file_name))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers().get(header::CONTENT_ENCODING).is_none());
+    assert_eq!(response.headers()[header::VARY], "Accept-Encoding");
+    assert_eq!(response.bytes().await.unwrap().as_ref(), contents);
+
+    let record = stat(&app, &file_name).await;
+    assert_eq!(record["codec"], "zstd");
+    assert_eq!(record["size"], contents.len());
+    assert_eq!(
+        record["checksum"],
+        hex::encode(Sha256::digest(&contents)).as_str()
+    );
+    std::fs::remove_file(format!("{}/{}", app.storage_path, file_name)).unwrap();
+}
+
+#[tokio::test]
+async fn clients_accepting_zstd_get_the_stored_frame() {
+    let app = spawn_app_with(enable).await;
+    let file_name = format!("{}.log", Uuid::new_v4());
+    let contents = compressible();
+    upload(&app, "", &file_name, contents.clone()).await;
+
+    let decoded = get_with(&app, &file_name, header::ACCEPT_ENCODING, "identity").await;
+    let response = get_with(&app, &file_name, header::ACCEPT_ENCODING, "gzip, zstd").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::CONTENT_ENCODING], "zstd");
+    assert_ne!(
+        response.headers()[header::ETAG],
+        decoded.headers()[header::ETAG]
+    );
+    let encoded = response.bytes().await.unwrap();
+    assert!(encoded.len() < contents.len() / 4);
+    assert_eq!(decode(&encoded).await, contents);
+
+    let response = get_with(&app, &file_name, header::ACCEPT_ENCODING, "zstd;q=0, *").await;
+    assert!(response.headers().get(header::CONTENT_ENCODING).is_none());
+    assert_eq!(response.bytes().await.unwrap().as_ref(), contents);
+    std::fs::remove_file(format!("{}/{}", app.storage_path, file_name)).unwrap();
+}
+
+#[tokio::test]
+async fn skipped_types_and_incompressible_files_are_stored_as_sent() {
+    let app = spawn_app_with(enable).await;
+    let png = format!("{}.png", Uuid::new_v4());
+    let random = format!("{}.bin", Uuid::new_v4());
+    let mut png_contents = b"\x89PNG\r\n\x1a\n".to_vec();
+    png_contents.resize(10_000, 0);
+    upload(&app, "", &png, png_contents.clone()).await;
+    upload(&app, "", &random, incompressible()).await;
+
+    for (file_name, contents) in [(&png, png_contents), (&random, incompressible())] {
+        let path = format!("{}/{}", app.storage_path, file_name);
+        assert_eq!(std::fs::read(&path).unwrap(), contents);
+        assert!(stat(&app, file_name).await.get("codec").unwrap().is_null());
+        std::fs::remove_file(path).unwrap();
+    }
+}
+
+#[tokio::test]
+async fn ranges_are_read_from_compressed_files() {
+    let app = spawn_app_with(enable).await;
+    let file_name = format!("{}.log", Uuid::new_v4());
+    let contents = compressible();
+    let size = contents.len();
+    upload(&app, "", &file_name, contents.clone()).await;
+
+    for (range, expected) in [
+        ("bytes=0-9", 0..10),
+        ("bytes=5000-5999", 5000..6000),
+        ("bytes=-100", size - 100..size),
+    ] {
+        // Ranges are of the original content, whatever the client accepts.
+        let response = reqwest::Client::new()
+            .get(format!("{}/files/{}", app.addr(), file_name))
+            .header(header::RANGE, range)
+            .header(header::ACCEPT_ENCODING, "zstd")
+            .send()
+            .await
+            .expect("Failed to execute request");
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert!(response.headers().get(header::CONTENT_ENCODING).is_none());
+        assert_eq!(
+            response.headers()[header::CONTENT_RANGE],
+            format!("bytes {}-{}/{}", expected.start, expected.end - 1, size).as_str()
+        );
+        assert_eq!(
+            response.bytes().await.unwrap().as_ref(),
+            &contents[expected]
+        );
+    }
+    std::fs::remove_file(format!("{}/{}", app.storage_path, file_name)).unwrap();
+}
+
+#[tokio::test]
+async fn compressed_files_are_encrypted_when_encryption_is_enabled() {
+    let app = spawn_app_with(|config| {
+        enable(config);
+        config.encryption.enabled = true;
+        config.encryption.active_key = Some("first".to_string());
+        config.encryption.master_keys = vec![MasterKeySettings {
+            id: "first".to_string(),
+            key: "11".repeat(32),
+        }];
+    })
+    .await;
+    let file_name = format!("{}.log", Uuid::new_v4());
+    let contents = compressible();
+    upload(&app, "", &file_name, contents.clone()).await;
+
+    let stored = std::fs::read(format!("{}/{}", app.storage_path, file_name)).unwrap();
+    assert!(stored.starts_with(b"CBXE"));
+    assert!(stored.len() < contents.len() / 4);
+
+    let response = reqwest::get(format!("{}/files/{}", app.addr(), file_name))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.bytes().await.unwrap().as_ref(), contents);
+
+    let response = get_with(&app, &file_name, header::ACCEPT_ENCODING, "zstd").await;
+    assert_eq!(response.headers()[header::CONTENT_ENCODING], "zstd");
+    let encoded = response.bytes().await.unwrap();
+    assert_eq!(decode(&encoded).await, contents);
+
+    assert_eq!(stat(&app, &file_name).await["codec"], "zstd");
+    std::fs::remove_file(format!("{}/{}", app.storage_path, file_name)).unwrap();
+}
+
+#[tokio::test]
+async fn plain_files_starting_like_compressed_ones_are_served_as_sent() {
+    let app = spawn_app_with(enable).await;
+    let file_name = format!("{}.bin", Uuid::new_v4());
+    let mut contents = b"CBXZ\x01".to_vec();
+    contents.extend_from_slice(&u64::MAX.to_be_bytes());
+    contents.extend_from_slice(&incompressible());
+    upload(&app, "", &file_name, contents.clone()).await;
+
+    let response = get_with(&app, &file_name, header::ACCEPT_ENCODING, "zstd").await;
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers().get(header::CONTENT_ENCODING).is_none());
+    assert_eq!(response.bytes().await.unwrap().as_ref(), contents);
+
+    let record = stat(&app, &file_name).await;
+    assert!(record["codec"].is_null());
+    assert_eq!(record["size"], contents.len());
+    std::fs::remove_file(format!("{}/{}", app.storage_path, file_name)).unwrap();
+}
+
+#[tokio::test]
+async fn versions_and_trash_entries_keep_the_codec_of_their_files() {
+    let app = spawn_app_with(|config| {
+        enable(config);
+        config.versioning.enabled = true;
+    })
+    .await;
+    let file_name = format!("{}.log", Uuid::new_v4());
+    let contents = compressible();
+    upload(&app, "", &file_name, contents.clone()).await;
+    upload(&app, "", &file_name, incompressible()).await;
+
+    let versions: Vec<serde_json::Value> =
+        reqwest::get(format!("{}/files/{}?versions", app.addr(), file_name))
+            .await
+            .expect("Failed to execute request")
+            .json()
+            .await
+            .unwrap();
+    assert_eq!(versions[0]["size"], contents.len());
+    let id = versions[0]["id"].as_str().unwrap();
+    let response = reqwest::get(format!("{}/files/{}?version={}", app.addr(), file_name, id))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.bytes().await.unwrap().as_ref(), contents);
+
+    let response = reqwest::Client::new()
+        .post(format!(
+            "{}/files/{}/restore?version={}",
+            app.addr(),
+            file_name,
+            id
+        ))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(stat(&app, &file_name).await["codec"], "zstd");
+
+    let entry: serde_json::Value = reqwest::Client::new()
+        .delete(format!("{}/files/{}", app.addr(), file_name))
+        .send()
+        .await
+        .expect("Failed to execute request")
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(entry["size"], contents.len());
+    let response = reqwest::Client::new()
+        .post(format!(
+            "{}/trash/{}/restore",
+            app.addr(),
+            entry["id"].as_str().unwrap()
+        ))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let record = stat(&app, &file_name).await;
+    assert_eq!(record["codec"], "zstd");
+    assert_eq!(
+        record["checksum"],
+        hex::encode(Sha256::digest(&contents)).as_str()
+    );
+    let response = reqwest::get(format!("{}/files/{}", app.addr(), file_name))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.bytes().await.unwrap().as_ref(), contents);
+    std::fs::remove_file(format!("{}/{}", app.storage_path, file_name)).unwrap();
+}
+
+async fn get_with(
+    app: &TestApp,
+    file_name: &str,
+    name: header::HeaderName,
+    value: &str,
+) -> reqwest::Response {
+    reqwest::Client::new()
+        .get(format!("{}/files/{}", app.addr(), file_name))
+        .header(name, value)
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+async fn decode(encoded: &[u8]) -> Vec<u8> {
+    let mut decoded = vec![];
+    ZstdDecoder::new(encoded)
+        .read_to_end(&mut decoded)
+        .await
+        .unwrap();
+    decoded
+}
+
+async fn stat(app: &TestApp, file_name: &str) -> serde_json::Value {
+    reqwest::get(format!("{}/files/{}?stat", app.addr(), file_name))
+        .await
+        .expect("Failed to execute request")
+        .json()
+        .await
+        .unwrap()
+}
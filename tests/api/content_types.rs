@@ -0,0 +1,115 @@
+use std::path::Path;
+
+use reqwest::{
+    multipart::{Form, Part},
+    StatusCode,
+};
+use uuid::Uuid;
+
+use crate::helpers::{spawn_app, spawn_app_with, TestApp};
+
+const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+
+#[tokio::test]
+async fn content_type_is_detected_from_the_content() {
+    let app = spawn_app().await;
+    let disguised = format!("{}.txt", Uuid::new_v4());
+    let json = format!("{}.json", Uuid::new_v4());
+    let binary = format!("{}.txt", Uuid::new_v4());
+
+    for (file_name, contents) in [
+        (&disguised, PNG),
+        (&json, &b"{\"key\": 1}"[..]),
+        (&binary, &b"\0\x01\x02"[..]),
+    ] {
+        let response = upload(&app, file_name, contents).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    assert_eq!(content_type(&app, &disguised).await, "image/png");
+    assert_eq!(content_type(&app, &json).await, "application/json");
+    assert_eq!(
+        content_type(&app, &binary).await,
+        "application/octet-stream"
+    );
+    for file_name in [disguised, json, binary] {
+        std::fs::remove_file(format!("{}/{}", app.storage_path, file_name)).unwrap();
+    }
+}
+
+#[tokio::test]
+async fn denied_content_type_is_rejected_and_removed() {
+    let app = spawn_app_with(|config| {
+        config.content_types.denied_mime_types = vec!["image/*".to_string()];
+    })
+    .await;
+    let file_name = format!("{}.txt", Uuid::new_v4());
+
+    let response = upload(&app, &file_name, PNG).await;
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert!(response.text().await.unwrap().contains("image/png"));
+    assert!(!Path::new(&format!("{}/{}", app.storage_path, file_name)).exists());
+}
+
+#[tokio::test]
+async fn only_allowed_content_types_are_accepted() {
+    let app = spawn_app_with(|config| {
+        config.content_types.allowed_mime_types = vec!["text/plain".to_string()];
+    })
+    .await;
+    let text = format!("{}.txt", Uuid::new_v4());
+    let image = format!("{}.png", Uuid::new_v4());
+
+    assert_eq!(upload(&app, &text, b"hello").await.status(), StatusCode::OK);
+    assert_eq!(
+        upload(&app, &image, PNG).await.status(),
+        StatusCode::BAD_REQUEST
+    );
+    assert!(!Path::new(&format!("{}/{}", app.storage_path, image)).exists());
+    std::fs::remove_file(format!("{}/{}", app.storage_path, text)).unwrap();
+}
+
+#[tokio::test]
+async fn extensions_are_checked_against_the_allow_and_deny_lists() {
+    let app = spawn_app_with(|config| {
+        config.content_types.allowed_extensions = vec![".txt".to_string(), "md".to_string()];
+        config.content_types.denied_extensions = vec!["md".to_string()];
+    })
+    .await;
+
+    for file_name in ["notes.MD", "tool.exe", "no-extension"] {
+        let file_name = format!("{}-{}", Uuid::new_v4(), file_name);
+        let response = upload(&app, &file_name, b"hello").await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", file_name);
+        assert!(!Path::new(&format!("{}/{}", app.storage_path, file_name)).exists());
+    }
+
+    let file_name = format!("{}.TXT", Uuid::new_v4());
+    assert_eq!(
+        upload(&app, &file_name, b"hello").await.status(),
+        StatusCode::OK
+    );
+    std::fs::remove_file(format!("{}/{}", app.storage_path, file_name)).unwrap();
+}
+
+async fn content_type(app: &TestApp, path: &str) -> String {
+    let response = reqwest::get(format!("{}/files/{}?stat", app.addr(), path))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let stat: serde_json::Value = response.json().await.unwrap();
+    stat["content_type"].as_str().unwrap().to_string()
+}
+
+async fn upload(app: &TestApp, file_name: &str, contents: &'static [u8]) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("{}/upload", app.addr()))
+        .multipart(Form::new().text("relative_path", "").part(
+            "file",
+            Part::bytes(contents).file_name(file_name.to_string()),
+        ))
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
@@ -0,0 +1,230 @@
+use crumbbox::configuration::{ApiKeySettings, Settings};
+use reqwest::StatusCode;
+use uuid::Uuid;
+
+use crate::helpers::{remove, root_key, spawn_app, spawn_app_with, upload, TestApp};
+
+#[tokio::test]
+async fn delta_without_a_cursor_returns_the_current_cursor() {
+    let app = spawn_app().await;
+    let file_name = Uuid::new_v4().to_string();
+    upload(&app, "", &file_name, b"contents").await;
+
+    let delta = get_delta(&app, "").await;
+
+    assert_eq!(delta["changes"].as_array().unwrap().len(), 0);
+    assert_eq!(delta["cursor"], 1);
+    remove(&app, &file_name);
+}
+
+#[tokio::test]
+async fn delta_reports_creates_modifications_and_deletes() {
+    let app = spawn_app().await;
+    let cursor = get_delta(&app, "").await["cursor"].clone();
+    let file_name = Uuid::new_v4().to_string();
+
+    upload(&app, "", &file_name, b"first").await;
+    upload(&app, "", &file_name, b"second").await;
+    let response = reqwest::Client::new()
+        .delete(format!("{}/files/{}", app.addr(), file_name))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let delta = get_delta(&app, &format!("cursor={}", cursor)).await;
+    let changes = delta["changes"].as_array().unwrap();
+    let kinds = changes
+        .iter()
+        .map(|change| change["kind"].as_str().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(kinds, ["created", "modified", "deleted"]);
+    assert!(changes
+        .iter()
+        .all(|change| change["path"] == file_name.as_str()));
+    assert_eq!(changes[1]["size"], 6);
+    assert_eq!(delta["has_more"], false);
+
+    // Nothing changed since the returned cursor.
+    let delta = get_delta(&app, &format!("cursor={}", delta["cursor"])).await;
+    assert_eq!(delta["changes"].as_array().unwrap().len(), 0);
+}
+
+#[tokio::test]
+async fn delta_is_paged_with_a_limit() {
+    let app = spawn_app().await;
+    let file_names = (0..3)
+        .map(|_| Uuid::new_v4().to_string())
+        .collect::<Vec<_>>();
+    for file_name in &file_names {
+        upload(&app, "", file_name, b"contents").await;
+    }
+
+    let first_page = get_delta(&app, "cursor=0&limit=2").await;
+    assert_eq!(first_page["changes"].as_array().unwrap().len(), 2);
+    assert_eq!(first_page["has_more"], true);
+
+    let second_page = get_delta(&app, &format!("cursor={}&limit=2", first_page["cursor"])).await;
+    let changes = second_page["changes"].as_array().unwrap();
+    assert_eq!(changes.len(), 1);
+    assert_eq!(changes[0]["path"], file_names[2].as_str());
+    assert_eq!(second_page["has_more"], false);
+
+    for file_name in &file_names {
+        remove(&app, file_name);
+    }
+}
+
+#[tokio::test]
+async fn journal_survives_a_restart() {
+    let journal_path = format!(".crumbbox/journal/{}/journal.jsonl", Uuid::new_v4());
+    let configure = |config: &mut Settings| {
+        config.journal.path = Some(journal_path.clone());
+    };
+    let app = spawn_app_with(configure).await;
+    let file_name = Uuid::new_v4().to_string();
+    upload(&app, "", &file_name, b"contents").await;
+
+    let restarted = spawn_app_with(configure).await;
+    let delta = get_delta(&restarted, "cursor=0").await;
+    assert_eq!(delta["changes"][0]["path"], file_name.as_str());
+
+    // New changes continue the sequence.
+    let other_file_name = Uuid::new_v4().to_string();
+    upload(&restarted, "", &other_file_name, b"contents").await;
+    assert_eq!(get_delta(&restarted, "").await["cursor"], 2);
+
+    remove(&app, &file_name);
+    remove(&app, &other_file_name);
+}
+
+#[tokio::test]
+async fn delta_reads_from_deep_into_a_long_journal() {
+    let journal_path = format!(".crumbbox/journal/{}/journal.jsonl", Uuid::new_v4());
+    std::fs::create_dir_all(std::path::Path::new(&journal_path).parent().unwrap()).unwrap();
+    let lines = (1..=2500)
+        .map(|sequence| {
+            format!(
+                r#"{{"id":"{}","sequence":{},"kind":"deleted","timestamp":"2024-01-01T00:00:00Z","path":"file-{}"}}"#,
+                Uuid::new_v4(),
+                sequence,
+                sequence
+            ) + "\n"
+        })
+        .collect::<String>();
+    std::fs::write(&journal_path, lines).unwrap();
+    let app = spawn_app_with(|config| config.journal.path = Some(journal_path.clone())).await;
+
+    let delta = get_delta(&app, "cursor=2300&limit=2").await;
+    let paths = delta["changes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|change| change["path"].as_str().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(paths, ["file-2301", "file-2302"]);
+    assert_eq!(delta["cursor"], 2302);
+    assert_eq!(get_delta(&app, "").await["cursor"], 2500);
+}
+
+#[tokio::test]
+async fn events_are_held_back_until_the_journal_is_written() {
+    let journal_path = format!(".crumbbox/journal/{}/journal.jsonl", Uuid::new_v4());
+    let app = spawn_app_with(|config| config.journal.path = Some(journal_path.clone())).await;
+    assert_eq!(get_delta(&app, "").await["cursor"], 0);
+    // A directory in its place, once the app opened it, makes appending to the journal fail.
+    std::fs::create_dir_all(&journal_path).unwrap();
+    let held_back = Uuid::new_v4().to_string();
+    upload(&app, "", &held_back, b"contents").await;
+    assert_eq!(get_delta(&app, "").await["cursor"], 0);
+
+    std::fs::remove_dir(&journal_path).unwrap();
+    let file_name = Uuid::new_v4().to_string();
+    upload(&app, "", &file_name, b"contents").await;
+
+    let delta = get_delta(&app, "cursor=0").await;
+    let paths = delta["changes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|change| change["path"].as_str().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(paths, [held_back.as_str(), file_name.as_str()]);
+    remove(&app, &held_back);
+    remove(&app, &file_name);
+}
+
+#[tokio::test]
+async fn cursor_ahead_of_the_journal_is_rejected() {
+    let app = spawn_app().await;
+
+    let response = reqwest::get(format!("{}/delta?cursor=100", app.addr()))
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(response.status(), StatusCode::GONE);
+}
+
+#[tokio::test]
+async fn delta_leaves_out_changes_outside_the_scope_of_the_caller() {
+    let allowed = Uuid::new_v4().to_string();
+    let app = spawn_app_with({
+        let allowed = allowed.clone();
+        move |config| {
+            config.auth.api_keys = vec![
+                ApiKeySettings {
+                    user: "bob".to_string(),
+                    key: "bob-key".to_string(),
+                    admin: false,
+                    allowed_paths: vec![allowed],
+                },
+                root_key(),
+            ];
+        }
+    })
+    .await;
+    let cursor = get_delta(&app, "").await["cursor"].clone();
+    let hidden = Uuid::new_v4().to_string();
+    upload(&app, "", &hidden, b"hidden").await;
+    std::fs::create_dir_all(format!("{}/{}", app.storage_path, allowed)).unwrap();
+    let response = reqwest::Client::new()
+        .put(format!("{}/files/{}/visible.txt", app.addr(), allowed))
+        .bearer_auth("root-key")
+        .body("visible")
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert!(response.status().is_success());
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/delta?cursor={}", app.addr(), cursor))
+        .bearer_auth("bob-key")
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let delta: serde_json::Value = response.json().await.unwrap();
+    let paths = delta["changes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|change| change["path"].as_str().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(paths, [format!("{}/visible.txt", allowed)]);
+    // The cursor still moves past the changes left out.
+    assert_eq!(delta["cursor"], cursor.as_u64().unwrap() + 2);
+
+    remove(&app, &hidden);
+    std::fs::remove_dir_all(format!("{}/{}", app.storage_path, allowed)).unwrap();
+}
+
+async fn get_delta(app: &TestApp, query: &str) -> serde_json::Value {
+    let mut request = reqwest::Client::new().get(format!("{}/delta?{}", app.addr(), query));
+    if let Some(admin_key) = &app.admin_key {
+        request = request.bearer_auth(admin_key);
+    }
+
+    let response = request.send().await.expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    response.json().await.unwrap()
+}
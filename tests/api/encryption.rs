@@ -0,0 +1,309 @@
+use std::path::Path;
+
+use crumbbox::{
+    configuration::{MasterKeySettings, Settings},
+    index::Index,
+    startup::{encrypt, reindex},
+};
+use reqwest::{header, StatusCode};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::helpers::{spawn_app, spawn_app_with, upload, TestApp};
+
+fn master_key(id: &str, byte: &str) -> MasterKeySettings {
+    MasterKeySettings {
+        id: id.to_string(),
+        key: byte.repeat(32),
+    }
+}
+
+fn enable(config: &mut Settings, active_key: &str, master_keys: Vec<MasterKeySettings>) {
+    config.encryption.enabled = true;
+    config.encryption.active_key = Some(active_key.to_string());
+    config.encryption.master_keys = master_keys;
+}
+
+#[tokio::test]
+async fn encrypted_files_are_stored_as_ciphertext_and_downloaded_as_plaintext() {
+    let app =
+        spawn_app_with(|config| enable(config, "first", vec![master_key("first", "11")])).await;
+    let file_name = Uuid::new_v4().to_string();
+    let contents = (0..200_000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+
+    upload(&app, "", &file_name, contents.clone()).await;
+
+    let stored = std::fs::read(format!("{}/{}", app.storage_path, file_name)).unwrap();
+    assert!(stored.starts_with(b"CBXE"));
+    assert!(!stored
+        .windows(64)
+        .any(|window| window == &contents[1000..1064]));
+
+    let response = reqwest::get(format!("{}/files/{}", app.addr(), file_name))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.bytes().await.unwrap().as_ref(), contents);
+
+    let record: serde_json::Value =
+        reqwest::get(format!("{}/files/{}?stat", app.addr(), file_name))
+            .await
+            .expect("Failed to execute request")
+            .json()
+            .await
+            .unwrap();
+    assert_eq!(record["size"], contents.len());
+    assert_eq!(
+        record["checksum"],
+        hex::encode(Sha256::digest(&contents)).as_str()
+    );
+    std::fs::remove_file(format!("{}/{}", app.storage_path, file_name)).unwrap();
+}
+
+#[tokio::test]
+async fn ranges_are_read_from_encrypted_and_plaintext_files() {
+    let encrypted = spawn_app_with(|config| {
+        enable(config, "first", vec![master_key("first", "11")]);
+        // Small chunks, so the ranges span several of them.
+        config.encryption.chunk_size_bytes = 16;
+    })
+    .await;
+    let plaintext = spawn_app().await;
+    let contents = (0..100u8).collect::<Vec<_>>();
+
+    for app in [encrypted, plaintext] {
+        let file_name = Uuid::new_v4().to_string();
+        upload(&app, "", &file_name, contents.clone()).await;
+
+        for (range, expected) in [
+            ("bytes=10-40", 10..41),
+            ("bytes=90-", 90..100),
+            ("bytes=-5", 95..100),
+            ("bytes=32-47", 32..48),
+            ("bytes=98-500", 98..100),
+        ] {
+            let response = get_range(&app, &file_name, range).await;
+            assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+            assert_eq!(
+                response.headers()[header::CONTENT_RANGE],
+                format!("bytes {}-{}/100", expected.start, expected.end - 1).as_str()
+            );
+            assert_eq!(
+                response.bytes().await.unwrap().as_ref(),
+                &contents[expected]
+            );
+        }
+
+        let response = get_range(&app, &file_name, "bytes=100-").await;
+        assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(response.headers()[header::CONTENT_RANGE], "bytes */100");
+        std::fs::remove_file(format!("{}/{}", app.storage_path, file_name)).unwrap();
+    }
+}
+
+#[tokio::test]
+async fn rotation_moves_files_to_the_active_key() {
+    // A storage root of its own, so the rotation only sees this test's files.
+    let storage_path = format!(".crumbbox/test-encryption/{}", Uuid::new_v4());
+    std::fs::create_dir_all(&storage_path).unwrap();
+    let file_name = Uuid::new_v4().to_string();
+
+    let old = spawn_app_with(|config| {
+        enable(config, "old", vec![master_key("old", "11")]);
+        config.application.storage_path = storage_path.clone();
+    })
+    .await;
+    upload(&old, "", &file_name, b"rotate me".to_vec()).await;
+
+    let rotating = spawn_app_with(|config| {
+        enable(
+            config,
+            "new",
+            vec![master_key("old", "11"), master_key("new", "22")],
+        );
+        config.application.storage_path = storage_path.clone();
+    })
+    .await;
+    let response = reqwest::Client::new()
+        .post(format!("{}/admin/encryption/rotate", rotating.addr()))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["rewrapped"], 1);
+    assert_eq!(report["failed"], 0);
+
+    let new = spawn_app_with(|config| {
+        enable(config, "new", vec![master_key("new", "22")]);
+        config.application.storage_path = storage_path.clone();
+    })
+    .await;
+    let response = reqwest::get(format!("{}/files/{}", new.addr(), file_name))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.bytes().await.unwrap().as_ref(), b"rotate me");
+
+    std::fs::remove_dir_all(storage_path).unwrap();
+}
+
+#[tokio::test]
+async fn plaintext_files_starting_like_encrypted_ones_are_served_as_sent() {
+    let app = spawn_app().await;
+    let file_name = Uuid::new_v4().to_string();
+    let mut contents = b"CBXE\x01".to_vec();
+    contents.resize(1000, 0);
+    upload(&app, "", &file_name, contents.clone()).await;
+
+    let response = reqwest::get(format!("{}/files/{}", app.addr(), file_name))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.bytes().await.unwrap().as_ref(), contents);
+    std::fs::remove_file(format!("{}/{}", app.storage_path, file_name)).unwrap();
+}
+
+#[tokio::test]
+async fn tampered_content_is_not_served() {
+    let app =
+        spawn_app_with(|config| enable(config, "first", vec![master_key("first", "11")])).await;
+    let file_name = Uuid::new_v4().to_string();
+    upload(&app, "", &file_name, b"do not touch".to_vec()).await;
+
+    let path = format!("{}/{}", app.storage_path, file_name);
+    let mut stored = std::fs::read(&path).unwrap();
+    let last = stored.len() - 1;
+    stored[last] ^= 1;
+    std::fs::write(&path, stored).unwrap();
+
+    // The download fails up front, or is cut off before or after the headers were sent.
+    let downloaded = match reqwest::get(format!("{}/files/{}", app.addr(), file_name)).await {
+        Ok(response) if response.status().is_success() => response.bytes().await.ok(),
+        _ => None,
+    };
+    assert!(downloaded.is_none());
+    std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn enabling_encryption_on_a_populated_root_encrypts_its_files() {
+    let storage_path = format!(".crumbbox/test-encrypt/{}", Uuid::new_v4());
+    std::fs::create_dir_all(&storage_path).unwrap();
+    let plaintext = spawn_app_with(|config| {
+        config.application.storage_path = storage_path.clone();
+        config.versioning.enabled = true;
+    })
+    .await;
+    upload(&plaintext, "", "kept.txt", b"first".to_vec()).await;
+    upload(&plaintext, "", "kept.txt", b"second".to_vec()).await;
+    upload(&plaintext, "", "deleted.txt", b"deleted".to_vec()).await;
+    let entry: serde_json::Value = reqwest::Client::new()
+        .delete(format!("{}/files/deleted.txt", plaintext.addr()))
+        .send()
+        .await
+        .expect("Failed to execute request")
+        .json()
+        .await
+        .unwrap();
+
+    let configure = |config: &mut Settings| {
+        config.application.storage_path = storage_path.clone();
+        config.versioning.enabled = true;
+        enable(config, "first", vec![master_key("first", "11")]);
+    };
+    let mut config = Settings::get_configuration().expect("Failed to get configuration");
+    configure(&mut config);
+    let report = encrypt(config).await.unwrap();
+    // The file, its version and the trashed file, not the description of the trash entry.
+    assert_eq!((report.encrypted, report.current, report.failed), (3, 0, 0));
+    let stored = std::fs::read(format!("{}/kept.txt", storage_path)).unwrap();
+    assert!(stored.starts_with(b"CBXE"));
+
+    let mut config = Settings::get_configuration().expect("Failed to get configuration");
+    configure(&mut config);
+    let report = encrypt(config).await.unwrap();
+    assert_eq!((report.encrypted, report.current, report.failed), (0, 3, 0));
+
+    let app = spawn_app_with(configure).await;
+    let get = |path: String| async move {
+        let response = reqwest::get(path).await.expect("Failed to execute request");
+        assert_eq!(response.status(), StatusCode::OK);
+        response.bytes().await.unwrap()
+    };
+    assert_eq!(
+        get(format!("{}/files/kept.txt", app.addr())).await.as_ref(),
+        b"second"
+    );
+    let versions: Vec<serde_json::Value> =
+        serde_json::from_slice(&get(format!("{}/files/kept.txt?versions", app.addr())).await)
+            .unwrap();
+    let version = get(format!(
+        "{}/files/kept.txt?version={}",
+        app.addr(),
+        versions[0]["id"].as_str().unwrap()
+    ))
+    .await;
+    assert_eq!(version.as_ref(), b"first");
+    let response = reqwest::Client::new()
+        .post(format!(
+            "{}/trash/{}/restore",
+            app.addr(),
+            entry["id"].as_str().unwrap()
+        ))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        get(format!("{}/files/deleted.txt", app.addr()))
+            .await
+            .as_ref(),
+        b"deleted"
+    );
+
+    std::fs::remove_dir_all(storage_path).unwrap();
+}
+
+#[tokio::test]
+async fn reindexing_an_encrypted_root_records_the_plaintext() {
+    let storage_path = format!(".crumbbox/test-reindex/{}", Uuid::new_v4());
+    std::fs::create_dir_all(&storage_path).unwrap();
+    let configure = |config: &mut Settings| {
+        config.application.storage_path = storage_path.clone();
+        enable(config, "first", vec![master_key("first", "11")]);
+    };
+    let app = spawn_app_with(configure).await;
+    let contents = b"{\"encrypted\": true}".to_vec();
+    upload(&app, "", "data.json", contents.clone()).await;
+
+    let mut config = Settings::get_configuration().expect("Failed to get configuration");
+    configure(&mut config);
+    let index_path = format!("{}/.crumbbox/index/reindexed.sqlite", storage_path);
+    config.index.path = Some(index_path.clone());
+    let report = reindex(config).await.unwrap();
+
+    assert_eq!(report.indexed, 1);
+    let record = Index::open(Path::new(&index_path), &storage_path)
+        .unwrap()
+        .get("data.json")
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(record.size, contents.len() as u64);
+    assert_eq!(
+        record.checksum.as_deref(),
+        Some(hex::encode(Sha256::digest(&contents)).as_str())
+    );
+    assert_eq!(record.content_type.as_deref(), Some("application/json"));
+    std::fs::remove_dir_all(storage_path).unwrap();
+}
+
+async fn get_range(app: &TestApp, file_name: &str, range: &str) -> reqwest::Response {
+    reqwest::Client::new()
+        .get(format!("{}/files/{}", app.addr(), file_name))
+        .header(header::RANGE, range)
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
@@ -0,0 +1,132 @@
+use std::time::Duration;
+
+use reqwest::StatusCode;
+use uuid::Uuid;
+
+use crate::helpers::{create_directory, spawn_app, spawn_app_with, upload, TestApp};
+
+#[tokio::test]
+async fn subscribers_receive_events_under_their_prefix() {
+    let app = spawn_app().await;
+    let directory = create_directory(&app);
+    let outside = Uuid::new_v4().to_string();
+    let mut feed = EventFeed::open(&app, &format!("prefix={}", directory), None).await;
+
+    upload(&app, "", &outside, b"contents").await;
+    upload(&app, &directory, "inside.txt", b"contents").await;
+
+    let (_, event) = feed.next().await;
+    assert_eq!(event["kind"], "upload_completed");
+    assert_eq!(event["path"], format!("{}/inside.txt", directory));
+
+    std::fs::remove_file(format!("{}/{}", app.storage_path, outside)).unwrap();
+    std::fs::remove_dir_all(format!("{}/{}", app.storage_path, directory)).unwrap();
+}
+
+#[tokio::test]
+async fn reconnecting_subscriber_resumes_after_its_last_event() {
+    let app = spawn_app().await;
+    let directory = create_directory(&app);
+    let query = format!("prefix={}", directory);
+    let mut feed = EventFeed::open(&app, &query, None).await;
+
+    upload(&app, &directory, "first.txt", b"contents").await;
+    upload(&app, &directory, "second.txt", b"contents").await;
+    let (first_sequence, _) = feed.next().await;
+    let (second_sequence, _) = feed.next().await;
+    drop(feed);
+
+    let mut feed = EventFeed::open(&app, &query, Some(first_sequence)).await;
+    let (sequence, event) = feed.next().await;
+    assert_eq!(sequence, second_sequence);
+    assert_eq!(event["path"], format!("{}/second.txt", directory));
+
+    std::fs::remove_dir_all(format!("{}/{}", app.storage_path, directory)).unwrap();
+}
+
+#[tokio::test]
+async fn cursor_older_than_the_buffer_is_served_from_the_journal() {
+    let app = spawn_app_with(|config| config.events.buffer_size = 1).await;
+    let directory = create_directory(&app);
+    upload(&app, &directory, "first.txt", b"contents").await;
+    upload(&app, &directory, "second.txt", b"contents").await;
+
+    let mut feed = EventFeed::open(&app, "", Some(0)).await;
+    let (sequence, event) = feed.next().await;
+    assert_eq!(sequence, 1);
+    assert_eq!(event["path"], format!("{}/first.txt", directory));
+
+    std::fs::remove_dir_all(format!("{}/{}", app.storage_path, directory)).unwrap();
+}
+
+#[tokio::test]
+async fn cursor_ahead_of_the_journal_is_rejected() {
+    let app = spawn_app().await;
+
+    let response = reqwest::get(format!("{}/events?cursor=100", app.addr()))
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(response.status(), StatusCode::GONE);
+}
+
+#[tokio::test]
+async fn prefix_outside_the_storage_root_is_rejected() {
+    let app = spawn_app().await;
+
+    let response = reqwest::get(format!("{}/events?prefix=../", app.addr()))
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+/// A minimal Server-Sent Events reader.
+struct EventFeed {
+    response: reqwest::Response,
+    buffer: String,
+}
+
+impl EventFeed {
+    async fn open(app: &TestApp, query: &str, last_event_id: Option<u64>) -> Self {
+        let mut request = reqwest::Client::new().get(format!("{}/events?{}", app.addr(), query));
+        if let Some(id) = last_event_id {
+            request = request.header("Last-Event-ID", id.to_string());
+        }
+        let response = request.send().await.expect("Failed to execute request");
+        assert_eq!(response.status(), StatusCode::OK);
+
+        Self {
+            response,
+            buffer: String::new(),
+        }
+    }
+
+    /// Returns the id and data of the next event, skipping keep-alive comments.
+    async fn next(&mut self) -> (u64, serde_json::Value) {
+        loop {
+            if let Some(end) = self.buffer.find("\n\n") {
+                let block = self.buffer[..end].to_string();
+                self.buffer.drain(..end + 2);
+
+                let field = |name: &str| {
+                    block.lines().find_map(|line| {
+                        line.strip_prefix(name)
+                            .map(|value| value.trim_start().to_string())
+                    })
+                };
+                if let (Some(id), Some(data)) = (field("id:"), field("data:")) {
+                    return (id.parse().unwrap(), serde_json::from_str(&data).unwrap());
+                }
+                continue;
+            }
+
+            let chunk = tokio::time::timeout(Duration::from_secs(5), self.response.chunk())
+                .await
+                .expect("Timed out waiting for an event")
+                .expect("Failed to read the event stream")
+                .expect("Event stream ended");
+            self.buffer.push_str(std::str::from_utf8(&chunk).unwrap());
+        }
+    }
+}
@@ -0,0 +1,285 @@
+use std::io::{Cursor, Write};
+
+use crumbbox::configuration::MasterKeySettings;
+use flate2::{write::GzEncoder, Compression};
+use reqwest::{
+    multipart::{Form, Part},
+    StatusCode,
+};
+use zip::{write::SimpleFileOptions, ZipWriter};
+
+use crate::helpers::{create_directory, remove, spawn_app, spawn_app_with, TestApp};
+
+/// Entries of a test archive: names ending in a slash are directories, the others files.
+type Entries<'a> = &'a [(&'a str, &'a [u8])];
+
+const TREE: Entries = &[
+    ("readme.txt", b"top level file"),
+    ("docs/", b""),
+    ("docs/guide/intro.txt", b"nested file"),
+    ("empty/", b""),
+];
+
+#[tokio::test]
+async fn zip_archives_are_extracted_into_the_target_directory() {
+    let app = spawn_app().await;
+    let directory = create_directory(&app);
+
+    let response = upload(&app, &directory, "tree.zip", zip_archive(TREE)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    assert_tree(&app, &directory).await;
+    assert!(!exists(&app, &format!("{}/tree.zip", directory)));
+    remove(&app, &directory);
+}
+
+#[tokio::test]
+async fn tar_gz_archives_are_extracted_and_encrypted_like_uploads() {
+    let app = spawn_app_with(|config| {
+        config.encryption.enabled = true;
+        config.encryption.active_key = Some("first".to_string());
+        config.encryption.master_keys = vec![MasterKeySettings {
+            id: "first".to_string(),
+            key: "11".repeat(32),
+        }];
+    })
+    .await;
+    let directory = create_directory(&app);
+
+    let response = upload(&app, &directory, "tree.tar.gz", tar_gz_archive(TREE)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    assert_tree(&app, &directory).await;
+    let stored = std::fs::read(format!("{}/{}/readme.txt", app.storage_path, directory)).unwrap();
+    assert!(stored.starts_with(b"CBXE"));
+    remove(&app, &directory);
+}
+
+#[tokio::test]
+async fn entries_escaping_the_target_directory_are_rejected() {
+    let app = spawn_app().await;
+    let directory = create_directory(&app);
+
+    for name in [
+        "../escaped.txt",
+        "docs/../../escaped.txt",
+        "/escaped.txt",
+        "docs\\..\\escaped.txt",
+    ] {
+        let archive = zip_archive(&[("readme.txt", b"fine"), (name, b"escaped")]);
+        let response = upload(&app, &directory, "slip.zip", archive).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", name);
+    }
+
+    let archive = zip_archive(&[(".crumbbox/escaped.txt", b"internal")]);
+    let response = upload(&app, "", "internal.zip", archive).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    assert!(is_empty(&app, &directory));
+    assert!(!exists(&app, "escaped.txt"));
+    remove(&app, &directory);
+}
+
+#[tokio::test]
+async fn links_and_conflicting_entries_are_rejected() {
+    let app = spawn_app().await;
+    let directory = create_directory(&app);
+
+    let mut zip = ZipWriter::new(Cursor::new(vec![]));
+    zip.add_symlink("link", "/etc/passwd", SimpleFileOptions::default())
+        .unwrap();
+    let archive = zip.finish().unwrap().into_inner();
+    let response = upload(&app, &directory, "link.zip", archive).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let mut tar = tar::Builder::new(vec![]);
+    let mut header = tar::Header::new_gnu();
+    header.set_entry_type(tar::EntryType::Symlink);
+    header.set_size(0);
+    tar.append_link(&mut header, "link", "/etc/passwd").unwrap();
+    let response = upload(&app, &directory, "link.tar", tar.into_inner().unwrap()).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let archive = zip_archive(&[("a", b"file"), ("a/b.txt", b"below a file")]);
+    let response = upload(&app, &directory, "conflict.zip", archive).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    assert!(is_empty(&app, &directory));
+    remove(&app, &directory);
+}
+
+#[tokio::test]
+async fn archives_unpacking_past_the_limits_are_rejected() {
+    let app = spawn_app_with(|config| {
+        config.extraction.max_entries = 3;
+        config.extraction.max_total_size_bytes = 100_000;
+    })
+    .await;
+    let directory = create_directory(&app);
+
+    // Far more than a hundred times its compressed size.
+    let zeros = vec![0; 50_000];
+    let response = upload(
+        &app,
+        &directory,
+        "bomb.zip",
+        zip_archive(&[("zeros", &zeros)]),
+    )
+    .await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let large = (0..120_000u32)
+        .map(|i| (i * 7919 % 251) as u8)
+        .collect::<Vec<_>>();
+    let archive = tar_gz_archive(&[("large.bin", &large)]);
+    let response = upload(&app, &directory, "large.tar.gz", archive).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let archive = zip_archive(&[("1", b"1"), ("2", b"2"), ("3", b"3"), ("4", b"4")]);
+    let response = upload(&app, &directory, "many.zip", archive).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    assert!(is_empty(&app, &directory));
+    remove(&app, &directory);
+}
+
+#[tokio::test]
+async fn files_that_are_not_archives_are_rejected() {
+    let app = spawn_app().await;
+    let directory = create_directory(&app);
+
+    let response = upload(&app, &directory, "notes.txt", b"plain text".to_vec()).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let mut corrupt = zip_archive(TREE);
+    corrupt.truncate(corrupt.len() - 30);
+    let response = upload(&app, &directory, "corrupt.zip", corrupt).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    assert!(is_empty(&app, &directory));
+    remove(&app, &directory);
+}
+
+#[tokio::test]
+async fn failed_extractions_leave_nothing_behind() {
+    let app = spawn_app().await;
+    let directory = create_directory(&app);
+    // A file cannot be moved onto this directory, which fails the upload part way through.
+    std::fs::create_dir(format!("{}/{}/taken", app.storage_path, directory)).unwrap();
+
+    let archive = zip_archive(&[
+        ("new/nested/first.txt", b"first"),
+        ("second.txt", b"second"),
+        ("taken", b"conflicts with a directory"),
+    ]);
+    let response = upload(&app, &directory, "partial.zip", archive).await;
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+    let mut left = std::fs::read_dir(format!("{}/{}", app.storage_path, directory))
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect::<Vec<_>>();
+    left.sort();
+    assert_eq!(left, ["taken"]);
+    remove(&app, &directory);
+}
+
+#[tokio::test]
+async fn archives_are_stored_as_sent_without_extraction() {
+    let app = spawn_app().await;
+    let directory = create_directory(&app);
+    let archive = zip_archive(TREE);
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/upload", app.addr()))
+        .multipart(form(&directory, "tree.zip", archive.clone()))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let stored = std::fs::read(format!("{}/{}/tree.zip", app.storage_path, directory)).unwrap();
+    assert_eq!(stored, archive);
+    assert!(!exists(&app, &format!("{}/docs", directory)));
+    remove(&app, &directory);
+}
+
+/// Checks the content of `TREE` was extracted into `directory`, through downloads.
+async fn assert_tree(app: &TestApp, directory: &str) {
+    for (name, contents) in TREE {
+        let path = format!("{}/{}", directory, name);
+        if name.ends_with('/') {
+            assert!(std::fs::metadata(format!("{}/{}", app.storage_path, path))
+                .unwrap()
+                .is_dir());
+            continue;
+        }
+        let response = reqwest::get(format!("{}/files/{}", app.addr(), path))
+            .await
+            .expect("Failed to execute request");
+        assert_eq!(response.status(), StatusCode::OK, "{}", path);
+        assert_eq!(response.bytes().await.unwrap().as_ref(), *contents);
+    }
+}
+
+fn zip_archive(entries: Entries) -> Vec<u8> {
+    let mut zip = ZipWriter::new(Cursor::new(vec![]));
+    for (name, contents) in entries {
+        match name.strip_suffix('/') {
+            Some(directory) => zip.add_directory(directory, SimpleFileOptions::default()),
+            None => zip.start_file(*name, SimpleFileOptions::default()),
+        }
+        .unwrap();
+        zip.write_all(contents).unwrap();
+    }
+    zip.finish().unwrap().into_inner()
+}
+
+fn tar_gz_archive(entries: Entries) -> Vec<u8> {
+    let mut tar = tar::Builder::new(GzEncoder::new(vec![], Compression::default()));
+    for (name, contents) in entries {
+        let mut header = tar::Header::new_gnu();
+        let entry_type = match name.ends_with('/') {
+            true => tar::EntryType::Directory,
+            false => tar::EntryType::Regular,
+        };
+        header.set_entry_type(entry_type);
+        header.set_mode(0o644);
+        header.set_size(contents.len() as u64);
+        tar.append_data(&mut header, name, *contents).unwrap();
+    }
+    tar.into_inner().unwrap().finish().unwrap()
+}
+
+fn form(directory: &str, file_name: &str, contents: Vec<u8>) -> Form {
+    Form::new()
+        .text("relative_path", directory.to_string())
+        .part(
+            "file",
+            Part::bytes(contents).file_name(file_name.to_string()),
+        )
+}
+
+async fn upload(
+    app: &TestApp,
+    directory: &str,
+    file_name: &str,
+    contents: Vec<u8>,
+) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("{}/upload?extract=true", app.addr()))
+        .multipart(form(directory, file_name, contents))
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+fn exists(app: &TestApp, path: &str) -> bool {
+    std::fs::metadata(format!("{}/{}", app.storage_path, path)).is_ok()
+}
+
+fn is_empty(app: &TestApp, directory: &str) -> bool {
+    std::fs::read_dir(format!("{}/{}", app.storage_path, directory))
+        .unwrap()
+        .next()
+        .is_none()
+}
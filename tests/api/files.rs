@@ -0,0 +1,438 @@
+use crate::helpers::{
+    create_directory, remove, root_key, spawn_app, spawn_app_with, upload, TestApp,
+};
+use chrono::Utc;
+use crumbbox::configuration::ApiKeySettings;
+use reqwest::{
+    multipart::{Form, Part},
+    StatusCode,
+};
+use uuid::Uuid;
+
+#[tokio::test]
+async fn uploaded_file_can_be_downloaded() {
+    let app = spawn_app().await;
+    let file_name = Uuid::new_v4().to_string();
+    upload(&app, "directed_path_folder", &file_name, b"download me").await;
+
+    let response = reqwest::get(format!(
+        "{}/files/directed_path_folder/{}",
+        app.addr(),
+        file_name
+    ))
+    .await
+    .expect("Failed to execute request");
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.bytes().await.unwrap().as_ref(), b"download me");
+    std::fs::remove_file(format!(
+        "{}/directed_path_folder/{}",
+        app.storage_path, file_name
+    ))
+    .unwrap();
+}
+
+#[tokio::test]
+async fn downloading_a_missing_file_returns_404() {
+    let app = spawn_app().await;
+
+    let response = reqwest::get(format!("{}/files/{}", app.addr(), Uuid::new_v4()))
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn paths_outside_the_storage_root_are_rejected() {
+    let app = spawn_app().await;
+
+    let response = reqwest::get(format!("{}/files/%2E%2E%2FCargo.toml", app.addr()))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let response = reqwest::get(format!("{}/files/.crumbbox/versions", app.addr()))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/upload", app.addr()))
+        .multipart(
+            Form::new()
+                .text("relative_path", "../")
+                .part("file", Part::bytes(b"x".to_vec()).file_name("escape.txt")),
+        )
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn overwritten_files_keep_their_previous_version() {
+    let app = spawn_app_with(|config| config.versioning.enabled = true).await;
+    let file_name = Uuid::new_v4().to_string();
+
+    upload(&app, "", &file_name, b"first").await;
+    upload(&app, "", &file_name, b"second").await;
+
+    let versions = list_versions(&app, &file_name).await;
+    assert_eq!(versions.len(), 1);
+    assert_eq!(versions[0]["size"], 5);
+
+    let response = reqwest::get(format!(
+        "{}/files/{}?version={}",
+        app.addr(),
+        file_name,
+        versions[0]["id"].as_str().unwrap()
+    ))
+    .await
+    .expect("Failed to execute request");
+    assert_eq!(response.bytes().await.unwrap().as_ref(), b"first");
+
+    cleanup(&app, &file_name);
+}
+
+#[tokio::test]
+async fn old_version_can_be_restored() {
+    let app = spawn_app_with(|config| config.versioning.enabled = true).await;
+    let file_name = Uuid::new_v4().to_string();
+    upload(&app, "", &file_name, b"first").await;
+    upload(&app, "", &file_name, b"second").await;
+    let versions = list_versions(&app, &file_name).await;
+
+    let response = reqwest::Client::new()
+        .post(format!(
+            "{}/files/{}/restore?version={}",
+            app.addr(),
+            file_name,
+            versions[0]["id"].as_str().unwrap()
+        ))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let current = std::fs::read(format!("{}/{}", app.storage_path, file_name)).unwrap();
+    assert_eq!(current, b"first");
+    // The content replaced by the restore is kept as well.
+    assert_eq!(list_versions(&app, &file_name).await.len(), 2);
+
+    cleanup(&app, &file_name);
+}
+
+#[tokio::test]
+async fn restoring_an_unknown_version_returns_404() {
+    let app = spawn_app_with(|config| config.versioning.enabled = true).await;
+    let file_name = Uuid::new_v4().to_string();
+
+    let response = reqwest::Client::new()
+        .post(format!(
+            "{}/files/{}/restore?version=20200101T000000.000000Z-abcdef01",
+            app.addr(),
+            file_name
+        ))
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn versions_are_not_kept_when_versioning_is_disabled() {
+    let app = spawn_app().await;
+    let file_name = Uuid::new_v4().to_string();
+
+    upload(&app, "", &file_name, b"first").await;
+    upload(&app, "", &file_name, b"second").await;
+
+    assert!(list_versions(&app, &file_name).await.is_empty());
+    std::fs::remove_file(format!("{}/{}", app.storage_path, file_name)).unwrap();
+}
+
+#[tokio::test]
+async fn versions_beyond_the_retention_count_are_removed() {
+    let app = spawn_app_with(|config| {
+        config.versioning.enabled = true;
+        config.versioning.max_versions = Some(2);
+    })
+    .await;
+    let file_name = Uuid::new_v4().to_string();
+
+    for contents in [b"first", b"secnd", b"third", b"forth"] {
+        upload(&app, "", &file_name, contents).await;
+    }
+
+    let versions = list_versions(&app, &file_name).await;
+    assert_eq!(versions.len(), 2);
+    let newest = reqwest::get(format!(
+        "{}/files/{}?version={}",
+        app.addr(),
+        file_name,
+        versions[0]["id"].as_str().unwrap()
+    ))
+    .await
+    .unwrap()
+    .bytes()
+    .await
+    .unwrap();
+    assert_eq!(newest.as_ref(), b"third");
+
+    cleanup(&app, &file_name);
+}
+
+#[tokio::test]
+async fn versions_of_files_not_overwritten_again_expire() {
+    // Left by an earlier run, the file is not overwritten while the app runs.
+    let directory = Uuid::new_v4().to_string();
+    let versions = format!(".crumbbox/test/.crumbbox/versions/{}/file.txt", directory);
+    std::fs::create_dir_all(&versions).unwrap();
+    let expired = "20000101T000000.000000Z-0123abcd";
+    let recent = format!("{}-4567abcd", Utc::now().format("%Y%m%dT%H%M%S%.6fZ"));
+    std::fs::write(format!("{}/{}", versions, expired), b"old").unwrap();
+    std::fs::write(format!("{}/{}", versions, recent), b"recent").unwrap();
+    let app = spawn_app_with(|config| {
+        config.versioning.enabled = true;
+        config.versioning.max_versions = None;
+        config.versioning.max_age_days = Some(1);
+        config.versioning.prune_interval_seconds = 1;
+    })
+    .await;
+    let path = format!("{}/file.txt", directory);
+    assert_eq!(list_versions(&app, &path).await.len(), 2);
+
+    let mut remaining = vec![];
+    for _ in 0..30 {
+        remaining = list_versions(&app, &path).await;
+        if remaining.len() == 1 {
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    assert_eq!(remaining.len(), 1);
+    assert_eq!(remaining[0]["id"], recent);
+
+    remove(&app, &format!(".crumbbox/versions/{}", directory));
+}
+
+#[tokio::test]
+async fn the_oldest_version_can_be_restored_at_the_retention_count() {
+    let app = spawn_app_with(|config| {
+        config.versioning.enabled = true;
+        config.versioning.max_versions = Some(2);
+    })
+    .await;
+    let file_name = Uuid::new_v4().to_string();
+    for contents in [b"first", b"secnd", b"third"] {
+        upload(&app, "", &file_name, contents).await;
+    }
+    let versions = list_versions(&app, &file_name).await;
+    assert_eq!(versions.len(), 2);
+
+    let response = reqwest::Client::new()
+        .post(format!(
+            "{}/files/{}/restore?version={}",
+            app.addr(),
+            file_name,
+            versions[1]["id"].as_str().unwrap()
+        ))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let current = std::fs::read(format!("{}/{}", app.storage_path, file_name)).unwrap();
+    assert_eq!(current, b"first");
+    // Preserving the replaced content pruned the restored version.
+    let ids = list_versions(&app, &file_name)
+        .await
+        .iter()
+        .map(|version| version["id"].as_str().unwrap().to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(ids.len(), 2);
+    assert!(!ids.contains(&versions[1]["id"].as_str().unwrap().to_string()));
+
+    cleanup(&app, &file_name);
+}
+
+#[tokio::test]
+async fn failed_overwrite_puts_the_previous_content_back() {
+    let app = spawn_app_with(|config| config.versioning.enabled = true).await;
+    let file_name = Uuid::new_v4().to_string();
+    upload(&app, "", &file_name, b"original").await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/upload", app.addr()))
+        .multipart(
+            Form::new()
+                .text("relative_path", "")
+                .part(
+                    "file",
+                    Part::bytes(b"replacement".to_vec()).file_name(file_name.clone()),
+                )
+                .part("file", Part::bytes(b"x".to_vec()).file_name("/")),
+        )
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let current = std::fs::read(format!("{}/{}", app.storage_path, file_name)).unwrap();
+    assert_eq!(current, b"original");
+    assert!(list_versions(&app, &file_name).await.is_empty());
+    // The upload failed while staging, so the original was never moved aside.
+    assert!(!std::path::Path::new(&format!(
+        "{}/.crumbbox/versions/{}",
+        app.storage_path, file_name
+    ))
+    .exists());
+
+    std::fs::remove_file(format!("{}/{}", app.storage_path, file_name)).unwrap();
+}
+
+#[tokio::test]
+async fn anonymous_requests_are_rejected_once_api_keys_are_configured() {
+    let allowed = Uuid::new_v4().to_string();
+    let app = spawn_app_with({
+        let allowed = allowed.clone();
+        move |config| {
+            config.auth.api_keys = vec![
+                ApiKeySettings {
+                    user: "bob".to_string(),
+                    key: "bob-key".to_string(),
+                    admin: false,
+                    allowed_paths: vec![allowed],
+                },
+                root_key(),
+            ];
+        }
+    })
+    .await;
+    let other = create_directory(&app);
+    upload(&app, &other, "file.txt", b"hidden").await;
+    let client = reqwest::Client::new();
+
+    let outside = format!("{}/files/{}/file.txt", app.addr(), other);
+    let requests = [
+        client.get(format!("{}/files?prefix={}", app.addr(), other)),
+        client.get(&outside),
+        client.put(&outside).body("changed"),
+        client.delete(&outside),
+        client.get(format!("{}/search?q=file", app.addr())),
+        client.post(format!("{}/upload", app.addr())).multipart(
+            Form::new()
+                .text("relative_path", other.clone())
+                .part("file", Part::bytes(&b"sneaked"[..]).file_name("new.txt")),
+        ),
+        client.request(
+            reqwest::Method::from_bytes(b"PROPFIND").unwrap(),
+            format!("{}/webdav/{}", app.addr(), other),
+        ),
+        client
+            .get(&outside)
+            .header(reqwest::header::AUTHORIZATION, "Token bob-key"),
+    ];
+    for request in requests {
+        let response = request.send().await.expect("Failed to execute request");
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    // S3 answers unsigned requests the way S3 does.
+    let response = client
+        .get(format!("{}/s3/{}/file.txt", app.addr(), other))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let contents = std::fs::read(format!("{}/{}/file.txt", app.storage_path, other)).unwrap();
+    assert_eq!(contents, b"hidden");
+    assert!(!std::path::Path::new(&format!("{}/{}/new.txt", app.storage_path, other)).exists());
+    remove(&app, &other);
+}
+
+#[tokio::test]
+async fn scoped_keys_cannot_reach_paths_outside_their_scope() {
+    let allowed = Uuid::new_v4().to_string();
+    let app = spawn_app_with({
+        let allowed = allowed.clone();
+        move |config| {
+            config.auth.api_keys = vec![
+                ApiKeySettings {
+                    user: "bob".to_string(),
+                    key: "bob-key".to_string(),
+                    admin: false,
+                    allowed_paths: vec![allowed],
+                },
+                root_key(),
+            ];
+        }
+    })
+    .await;
+    let other = Uuid::new_v4().to_string();
+    std::fs::create_dir_all(format!("{}/{}", app.storage_path, allowed)).unwrap();
+    std::fs::create_dir_all(format!("{}/{}", app.storage_path, other)).unwrap();
+    upload(&app, &other, "file.txt", b"hidden").await;
+    let client = reqwest::Client::new();
+
+    let outside = format!("{}/files/{}/file.txt", app.addr(), other);
+    let requests = [
+        client.get(&outside),
+        client.get(format!("{}?stat", outside)),
+        client.put(&outside).body("changed"),
+        client.post(format!("{}/restore?version=1", outside)),
+        client
+            .patch(format!("{}/metadata", outside))
+            .json(&serde_json::json!({})),
+        client.delete(&outside),
+        client.get(format!("{}/thumbnails/{}/file.txt", app.addr(), other)),
+        client.post(format!("{}/upload", app.addr())).multipart(
+            Form::new()
+                .text("relative_path", other.clone())
+                .part("file", Part::bytes(&b"sneaked"[..]).file_name("new.txt")),
+        ),
+    ];
+    for request in requests {
+        let response = request
+            .bearer_auth("bob-key")
+            .send()
+            .await
+            .expect("Failed to execute request");
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+    let contents = std::fs::read(format!("{}/{}/file.txt", app.storage_path, other)).unwrap();
+    assert_eq!(contents, b"hidden");
+    assert!(!std::path::Path::new(&format!("{}/{}/new.txt", app.storage_path, other)).exists());
+
+    let response = client
+        .put(format!("{}/files/{}/file.txt", app.addr(), allowed))
+        .bearer_auth("bob-key")
+        .body("mine")
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert!(response.status().is_success());
+
+    std::fs::remove_dir_all(format!("{}/{}", app.storage_path, allowed)).unwrap();
+    std::fs::remove_dir_all(format!("{}/{}", app.storage_path, other)).unwrap();
+}
+
+async fn list_versions(app: &TestApp, file_name: &str) -> Vec<serde_json::Value> {
+    let response = reqwest::get(format!("{}/files/{}?versions", app.addr(), file_name))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    response.json().await.unwrap()
+}
+
+fn cleanup(app: &TestApp, file_name: &str) {
+    std::fs::remove_file(format!("{}/{}", app.storage_path, file_name)).unwrap();
+    std::fs::remove_dir_all(format!(
+        "{}/.crumbbox/versions/{}",
+        app.storage_path, file_name
+    ))
+    .unwrap();
+}
@@ -0,0 +1,137 @@
+use std::time::Duration;
+
+use crumbbox::configuration::Settings;
+use reqwest::StatusCode;
+
+use crate::helpers::{create_directory, remove, spawn_app, spawn_app_with, upload, TestApp};
+
+fn enable_full_text(config: &mut Settings) {
+    config.index.full_text.enabled = true;
+}
+
+#[tokio::test]
+async fn uploaded_text_files_are_ranked_with_highlighted_snippets() {
+    let app = spawn_app_with(enable_full_text).await;
+    let directory = create_directory(&app);
+    upload(
+        &app,
+        &directory,
+        "report.md",
+        b"# Outage report\n\nThe database failover took four minutes.",
+    )
+    .await;
+    upload(
+        &app,
+        &directory,
+        "failover.log",
+        b"failover started\nfailover completed\nfailover verified",
+    )
+    .await;
+    upload(&app, &directory, "image.png", b"failover").await;
+
+    let results = wait_for_matches(&app, "failover", 2).await;
+    let matches = results["matches"].as_array().unwrap();
+    let paths = matches
+        .iter()
+        .map(|found| found["path"].as_str().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        paths,
+        [
+            format!("{}/failover.log", directory),
+            format!("{}/report.md", directory)
+        ]
+    );
+    assert!(matches[0]["score"].as_f64().unwrap() > matches[1]["score"].as_f64().unwrap());
+    assert!(matches[1]["snippet"]
+        .as_str()
+        .unwrap()
+        .contains("database <mark>failover</mark> took"));
+    assert_eq!(results["has_more"], false);
+    remove(&app, &directory);
+}
+
+#[tokio::test]
+async fn every_word_must_match_and_query_syntax_is_taken_literally() {
+    let app = spawn_app_with(enable_full_text).await;
+    let directory = create_directory(&app);
+    upload(&app, &directory, "notes.txt", b"alpha beta").await;
+    upload(&app, &directory, "data.json", br#"{"alpha": "gamma"}"#).await;
+
+    wait_for_matches(&app, "gamma", 1).await;
+    let results = wait_for_matches(&app, "alpha beta", 1).await;
+    assert_eq!(
+        results["matches"][0]["path"],
+        format!("{}/notes.txt", directory)
+    );
+
+    let results = search_text(&app, "alpha AND \"gamma").await;
+    assert_eq!(results["matches"].as_array().unwrap().len(), 0);
+    remove(&app, &directory);
+}
+
+#[tokio::test]
+async fn overwritten_and_deleted_files_leave_the_text_index() {
+    let app = spawn_app_with(enable_full_text).await;
+    let directory = create_directory(&app);
+    upload(&app, &directory, "draft.txt", b"original wording").await;
+    upload(&app, &directory, "draft.txt", b"revised wording").await;
+
+    wait_for_matches(&app, "revised", 1).await;
+    assert_eq!(
+        search_text(&app, "original").await["matches"]
+            .as_array()
+            .unwrap()
+            .len(),
+        0
+    );
+
+    let response = reqwest::Client::new()
+        .delete(format!("{}/files/{}/draft.txt", app.addr(), directory))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        search_text(&app, "revised").await["matches"]
+            .as_array()
+            .unwrap()
+            .len(),
+        0
+    );
+    remove(&app, &directory);
+}
+
+#[tokio::test]
+async fn full_text_search_is_not_found_when_disabled() {
+    let app = spawn_app().await;
+
+    let response = reqwest::get(format!("{}/search/text?q=anything", app.addr()))
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+async fn search_text(app: &TestApp, query: &str) -> serde_json::Value {
+    let response = reqwest::Client::new()
+        .get(format!("{}/search/text", app.addr()))
+        .query(&[("q", query)])
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    response.json().await.unwrap()
+}
+
+/// Searches until `query` has `expected` matches, texts being extracted in the background.
+async fn wait_for_matches(app: &TestApp, query: &str, expected: usize) -> serde_json::Value {
+    for _ in 0..50 {
+        let results = search_text(app, query).await;
+        if results["matches"].as_array().unwrap().len() == expected {
+            return results;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("{} never had {} matches", query, expected);
+}
@@ -1,20 +1,81 @@
+use axum::{body::Bytes, routing::post, Extension, Router};
 use crumbbox::{
-    configuration::Settings,
-    domain::StorageDetails,
+    configuration::{ApiKeySettings, Settings, TelemetrySettings},
     startup::app,
-    telemetry::{get_subscriber, init_subscriber},
+    telemetry::{get_otlp_tracer, get_subscriber, init_subscriber},
 };
 use once_cell::sync::Lazy;
-use std::net::{SocketAddr, TcpListener};
+use reqwest::{
+    multipart::{Form, Part},
+    StatusCode,
+};
+use std::{
+    net::{SocketAddr, TcpListener},
+    path::Path,
+    sync::{Arc, Mutex},
+};
+use uuid::Uuid;
+
+/// In-process stand-in for an OTLP collector, recording the raw body of every export request.
+pub struct OtlpCollector {
+    pub address: SocketAddr,
+    pub exports: Arc<Mutex<Vec<Bytes>>>,
+}
+
+/// Runtime shared by the collector and the span exporter, which both outlive any single test.
+static TELEMETRY_RUNTIME: Lazy<tokio::runtime::Runtime> = Lazy::new(|| {
+    tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(1)
+        .enable_all()
+        .build()
+        .unwrap()
+});
+
+pub static OTLP_COLLECTOR: Lazy<OtlpCollector> = Lazy::new(|| {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let exports = Arc::new(Mutex::new(vec![]));
+
+    let router = Router::new()
+        .route(
+            "/v1/traces",
+            post(
+                |exports: Extension<Arc<Mutex<Vec<Bytes>>>>, body: Bytes| async move {
+                    exports.lock().unwrap().push(body);
+                },
+            ),
+        )
+        .layer(Extension(exports.clone()));
+
+    let _guard = TELEMETRY_RUNTIME.enter();
+    TELEMETRY_RUNTIME.spawn(
+        axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(router.into_make_service()),
+    );
+
+    OtlpCollector { address, exports }
+});
 
 static TRACING: Lazy<()> = Lazy::new(|| {
     let default_filter = String::from("info");
 
+    std::env::set_var("OTEL_BSP_SCHEDULE_DELAY", "100");
+    let telemetry_settings = TelemetrySettings {
+        otlp_endpoint: Some(format!("http://{}/v1/traces", OTLP_COLLECTOR.address)),
+        ..TelemetrySettings::default()
+    };
+    let tracer = {
+        let _guard = TELEMETRY_RUNTIME.enter();
+        get_otlp_tracer(&telemetry_settings).expect("Failed to create OTLP tracer")
+    };
+
     if std::env::var("TEST_LOG").is_ok() {
-        let subscriber = get_subscriber(default_filter, std::io::stdout);
+        let subscriber =
+            get_subscriber(default_filter, std::io::stdout, &telemetry_settings, tracer);
         init_subscriber(subscriber);
     } else {
-        let subscriber = get_subscriber(default_filter, std::io::sink);
+        let subscriber = get_subscriber(default_filter, std::io::sink, &telemetry_settings, tracer);
         init_subscriber(subscriber);
     };
 });
@@ -22,6 +83,11 @@ static TRACING: Lazy<()> = Lazy::new(|| {
 pub struct TestApp {
     pub address: SocketAddr,
     pub storage_path: String,
+    pub audit_path: String,
+    pub index_path: String,
+    /// Key of the first admin configured, sent by the helpers setting up files once anonymous
+    /// requests are rejected.
+    pub admin_key: Option<String>,
 }
 
 impl TestApp {
@@ -31,12 +97,23 @@ impl TestApp {
 }
 
 pub async fn spawn_app() -> TestApp {
+    spawn_app_with(|_| {}).await
+}
+
+pub async fn spawn_app_with(configure: impl FnOnce(&mut Settings)) -> TestApp {
     Lazy::force(&TRACING);
 
     let config = {
         let mut config = Settings::get_configuration().expect("Failed to get configuration");
         config.application.port = 0;
         config.application.storage_path = ".crumbbox/test/".to_string();
+        config.audit.path = format!(".crumbbox/audit/{}/audit.jsonl", Uuid::new_v4());
+        config.journal.path = Some(format!(
+            ".crumbbox/journal/{}/journal.jsonl",
+            Uuid::new_v4()
+        ));
+        config.index.path = Some(format!(".crumbbox/index/{}/index.sqlite", Uuid::new_v4()));
+        configure(&mut config);
         config
     };
 
@@ -47,14 +124,75 @@ pub async fn spawn_app() -> TestApp {
     )
     .unwrap();
     let address = listener.local_addr().unwrap();
-    let storage_details = StorageDetails {
-        path: config.application.storage_path.clone(),
-    };
+    let storage_path = config.application.storage_path.clone();
+    let audit_path = config.audit.path.clone();
+    let index_path = config.index.path.clone().unwrap();
+    let admin_key = config
+        .auth
+        .api_keys
+        .iter()
+        .find(|api_key| api_key.admin)
+        .map(|api_key| api_key.key.clone());
 
-    let _ = tokio::spawn(app(listener, storage_details));
+    tokio::spawn(app(listener, config));
 
     TestApp {
         address,
-        storage_path: config.application.storage_path,
+        storage_path,
+        audit_path,
+        index_path,
+        admin_key,
+    }
+}
+
+/// An admin key, for tests of scoped keys to set up files outside their scope.
+pub fn root_key() -> ApiKeySettings {
+    ApiKeySettings {
+        user: "root".to_string(),
+        key: "root-key".to_string(),
+        admin: true,
+        allowed_paths: vec![],
+    }
+}
+
+/// Uploads a file into the directory at `relative_path`, empty for the storage root.
+pub async fn upload(
+    app: &TestApp,
+    relative_path: &str,
+    file_name: &str,
+    contents: impl Into<Vec<u8>>,
+) {
+    let mut request = reqwest::Client::new()
+        .post(format!("{}/upload", app.addr()))
+        .multipart(
+            Form::new()
+                .text("relative_path", relative_path.to_string())
+                .part(
+                    "file",
+                    Part::bytes(contents.into()).file_name(file_name.to_string()),
+                ),
+        );
+    if let Some(admin_key) = &app.admin_key {
+        request = request.bearer_auth(admin_key);
+    }
+
+    let response = request.send().await.expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+/// Creates a directory of a unique name under the storage root, shared by every test.
+pub fn create_directory(app: &TestApp) -> String {
+    let directory = Uuid::new_v4().to_string();
+    std::fs::create_dir_all(format!("{}/{}", app.storage_path, directory)).unwrap();
+    directory
+}
+
+/// Removes a file or directory left under the storage root by a test.
+pub fn remove(app: &TestApp, relative_path: &str) {
+    let path = Path::new(&app.storage_path).join(relative_path);
+    if path.is_dir() {
+        std::fs::remove_dir_all(path).unwrap();
+    } else {
+        std::fs::remove_file(path).unwrap();
     }
 }
@@ -0,0 +1,140 @@
+use std::path::Path;
+
+use crumbbox::{
+    configuration::ApiKeySettings,
+    index::{FileRecord, Index},
+};
+use reqwest::{
+    multipart::{Form, Part},
+    StatusCode,
+};
+use uuid::Uuid;
+
+use crate::helpers::{remove, spawn_app, spawn_app_with, TestApp};
+
+#[tokio::test]
+async fn uploaded_file_is_indexed() {
+    let app = spawn_app_with(|config| {
+        config.auth.api_keys = vec![ApiKeySettings {
+            user: "alice".to_string(),
+            key: "alice-key".to_string(),
+            admin: false,
+            allowed_paths: vec![],
+        }];
+    })
+    .await;
+    let file_name = format!("{}.json", Uuid::new_v4());
+
+    upload(&app, &file_name, b"{}", Some("alice-key")).await;
+
+    let record = get_record(&app, &file_name)
+        .await
+        .expect("File not indexed");
+    assert_eq!(record.size, 2);
+    assert_eq!(
+        record.checksum.as_deref(),
+        Some("44136fa355b3678a1146ad16f7e8649e94fb4fc21fe77e8310c060f61caaff8a")
+    );
+    assert_eq!(record.content_type.as_deref(), Some("application/json"));
+    assert_eq!(record.owner.as_deref(), Some("alice"));
+    remove(&app, &file_name);
+}
+
+#[tokio::test]
+async fn overwrite_updates_the_content_but_keeps_owner_and_creation_time() {
+    let app = spawn_app().await;
+    let file_name = Uuid::new_v4().to_string();
+    upload(&app, &file_name, b"first", None).await;
+    let first = get_record(&app, &file_name).await.unwrap();
+
+    upload(&app, &file_name, b"second", None).await;
+
+    let second = get_record(&app, &file_name).await.unwrap();
+    assert_eq!(second.size, 6);
+    assert_ne!(second.checksum, first.checksum);
+    assert_eq!(second.owner.as_deref(), Some("anonymous"));
+    assert_eq!(second.created_at, first.created_at);
+    assert!(second.modified_at > first.modified_at);
+    remove(&app, &file_name);
+}
+
+#[tokio::test]
+async fn deleted_file_leaves_the_index_and_returns_with_a_restore() {
+    let app = spawn_app().await;
+    let file_name = Uuid::new_v4().to_string();
+    upload(&app, &file_name, b"contents", None).await;
+
+    let response = reqwest::Client::new()
+        .delete(format!("{}/files/{}", app.addr(), file_name))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    let entry: serde_json::Value = response.json().await.unwrap();
+    assert!(get_record(&app, &file_name).await.is_none());
+
+    let response = reqwest::Client::new()
+        .post(format!(
+            "{}/trash/{}/restore",
+            app.addr(),
+            entry["id"].as_str().unwrap()
+        ))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let record = get_record(&app, &file_name)
+        .await
+        .expect("File not indexed");
+    assert_eq!(record.size, 8);
+    remove(&app, &file_name);
+}
+
+#[tokio::test]
+async fn reconcile_rebuilds_the_index_from_the_storage_root() {
+    let storage_path = format!(".crumbbox/test-index/{}", Uuid::new_v4());
+    std::fs::create_dir_all(format!("{}/nested", storage_path)).unwrap();
+    std::fs::write(format!("{}/nested/on-disk.txt", storage_path), b"on disk").unwrap();
+    std::fs::write(format!("{}/gone.txt", storage_path), b"gone").unwrap();
+    let index = Index::open(
+        Path::new(&format!("{}/.crumbbox/index/index.sqlite", storage_path)),
+        &storage_path,
+    )
+    .unwrap();
+
+    let report = index.reconcile().await.unwrap();
+    assert_eq!(report.indexed, 2);
+    let record = index.get("nested/on-disk.txt").await.unwrap().unwrap();
+    assert_eq!(record.size, 7);
+    assert_eq!(record.content_type.as_deref(), Some("text/plain"));
+
+    std::fs::remove_file(format!("{}/gone.txt", storage_path)).unwrap();
+    let report = index.reconcile().await.unwrap();
+    assert_eq!(report.indexed, 1);
+    assert_eq!(report.removed, 1);
+    assert!(index.get("gone.txt").await.unwrap().is_none());
+
+    std::fs::remove_dir_all(storage_path).unwrap();
+}
+
+async fn get_record(app: &TestApp, path: &str) -> Option<FileRecord> {
+    Index::open(Path::new(&app.index_path), &app.storage_path)
+        .unwrap()
+        .get(path)
+        .await
+        .unwrap()
+}
+
+async fn upload(app: &TestApp, file_name: &str, contents: &'static [u8], api_key: Option<&str>) {
+    let mut request = reqwest::Client::new()
+        .post(format!("{}/upload", app.addr()))
+        .multipart(Form::new().text("relative_path", "").part(
+            "file",
+            Part::bytes(contents).file_name(file_name.to_string()),
+        ));
+    if let Some(api_key) = api_key {
+        request = request.bearer_auth(api_key);
+    }
+
+    let response = request.send().await.expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+}
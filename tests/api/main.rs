@@ -1,3 +1,31 @@
+mod archives;
+mod audit;
+mod cli;
+mod compression;
+mod content_types;
+mod delta;
+mod encryption;
+mod events;
+mod extraction;
+mod files;
+mod full_text;
+// Written before clippy flagged borrowed `format!` arguments.
+#[allow(clippy::needless_borrows_for_generic_args)]
 mod health_check;
 mod helpers;
+mod index;
+mod metadata;
+mod metrics;
+mod put;
+mod ready;
+mod s3;
+mod scanning;
+mod search;
+mod telemetry;
+mod thumbnails;
+mod trash;
+// Written before clippy flagged borrowed `format!` arguments.
+#[allow(clippy::needless_borrows_for_generic_args)]
 mod upload;
+mod webdav;
+mod webhooks;
@@ -0,0 +1,241 @@
+use reqwest::{
+    multipart::{Form, Part},
+    StatusCode,
+};
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::helpers::{create_directory, remove, spawn_app, upload, TestApp};
+
+#[tokio::test]
+async fn upload_fields_become_tags_and_metadata() {
+    let app = spawn_app().await;
+    let file_name = Uuid::new_v4().to_string();
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/upload", app.addr()))
+        .multipart(
+            Form::new()
+                .text("relative_path", "")
+                .text("tag", "invoice")
+                .text("metadata.customer", "acme")
+                .part(
+                    "file",
+                    Part::bytes(&b"contents"[..]).file_name(file_name.clone()),
+                ),
+        )
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let stat = stat(&app, &file_name).await;
+    assert_eq!(stat["size"], 8);
+    assert_eq!(stat["tags"], json!(["invoice"]));
+    assert_eq!(stat["metadata"], json!({ "customer": "acme" }));
+    remove(&app, &file_name);
+}
+
+#[tokio::test]
+async fn upload_with_a_too_long_metadata_key_is_rejected() {
+    let app = spawn_app().await;
+    let file_name = Uuid::new_v4().to_string();
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/upload", app.addr()))
+        .multipart(
+            Form::new()
+                .text("relative_path", "")
+                .text(format!("metadata.{}", "k".repeat(200)), "value")
+                .part(
+                    "file",
+                    Part::bytes(&b"contents"[..]).file_name(file_name.clone()),
+                ),
+        )
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert!(!std::path::Path::new(&format!("{}/{}", app.storage_path, file_name)).exists());
+}
+
+#[tokio::test]
+async fn patch_sets_and_removes_metadata_and_tags() {
+    let app = spawn_app().await;
+    let file_name = Uuid::new_v4().to_string();
+    upload(&app, "", &file_name, b"contents").await;
+
+    let record = patch(
+        &app,
+        &file_name,
+        json!({ "metadata": { "project": "apollo", "stage": "draft" }, "add_tags": ["a", "b"] }),
+    )
+    .await;
+    assert_eq!(
+        record["metadata"],
+        json!({ "project": "apollo", "stage": "draft" })
+    );
+    assert_eq!(record["tags"], json!(["a", "b"]));
+
+    let record = patch(
+        &app,
+        &file_name,
+        json!({ "metadata": { "stage": null }, "remove_tags": ["a"] }),
+    )
+    .await;
+    assert_eq!(record["metadata"], json!({ "project": "apollo" }));
+    assert_eq!(record["tags"], json!(["b"]));
+    assert_eq!(stat(&app, &file_name).await, record);
+    remove(&app, &file_name);
+}
+
+#[tokio::test]
+async fn patch_rejects_missing_files_and_invalid_tags() {
+    let app = spawn_app().await;
+    let client = reqwest::Client::new();
+
+    let response = client
+        .patch(format!("{}/files/{}/metadata", app.addr(), Uuid::new_v4()))
+        .json(&json!({ "add_tags": ["a"] }))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let file_name = Uuid::new_v4().to_string();
+    upload(&app, "", &file_name, b"contents").await;
+    let response = client
+        .patch(format!("{}/files/{}/metadata", app.addr(), file_name))
+        .json(&json!({ "add_tags": [" "] }))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    remove(&app, &file_name);
+}
+
+#[tokio::test]
+async fn listing_filters_by_prefix_tag_and_metadata() {
+    let app = spawn_app().await;
+    let directory = Uuid::new_v4().to_string();
+    std::fs::create_dir_all(format!("{}/{}/nested", app.storage_path, directory)).unwrap();
+    upload(&app, &directory, "top", b"contents").await;
+    upload(&app, &format!("{}/nested", directory), "deep", b"contents").await;
+    patch(
+        &app,
+        &format!("{}/top", directory),
+        json!({ "metadata": { "color": "red" }, "add_tags": ["keep"] }),
+    )
+    .await;
+    patch(
+        &app,
+        &format!("{}/nested/deep", directory),
+        json!({ "metadata": { "color": "blue" }, "add_tags": ["keep"] }),
+    )
+    .await;
+
+    let paths = list(&app, &format!("prefix={}", directory)).await;
+    assert_eq!(paths, [format!("{}/top", directory)]);
+
+    let paths = list(
+        &app,
+        &format!("prefix={}&recursive=true&tag=keep", directory),
+    )
+    .await;
+    assert_eq!(
+        paths,
+        [
+            format!("{}/nested/deep", directory),
+            format!("{}/top", directory)
+        ]
+    );
+
+    let paths = list(
+        &app,
+        &format!("prefix={}&recursive=true&metadata.color=blue", directory),
+    )
+    .await;
+    assert_eq!(paths, [format!("{}/nested/deep", directory)]);
+
+    let response = reqwest::get(format!("{}/files?unknown=1", app.addr()))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    std::fs::remove_dir_all(format!("{}/{}", app.storage_path, directory)).unwrap();
+}
+
+#[tokio::test]
+async fn listing_pages_on_from_a_cursor() {
+    let app = spawn_app().await;
+    let directory = create_directory(&app);
+    for file_name in ["a", "b", "c"] {
+        upload(&app, &directory, file_name, b"contents").await;
+    }
+
+    let query = format!("prefix={}&limit=2", directory);
+    let paths = list(&app, &query).await;
+    assert_eq!(
+        paths,
+        [format!("{}/a", directory), format!("{}/b", directory)]
+    );
+    let paths = list(&app, &format!("{}&cursor={}", query, paths[1])).await;
+    assert_eq!(paths, [format!("{}/c", directory)]);
+
+    std::fs::remove_dir_all(format!("{}/{}", app.storage_path, directory)).unwrap();
+}
+
+#[tokio::test]
+async fn listing_non_recursively_stops_at_non_ascii_directories() {
+    let app = spawn_app().await;
+    let root = create_directory(&app);
+    let directory = format!("{}/日本", root);
+    std::fs::create_dir_all(format!("{}/{}/a/b", app.storage_path, directory)).unwrap();
+    upload(&app, &directory, "top", b"contents").await;
+    upload(&app, &format!("{}/a/b", directory), "deep", b"contents").await;
+
+    let paths = list(&app, &format!("prefix={}", directory)).await;
+    assert_eq!(paths, [format!("{}/top", directory)]);
+
+    let paths = list(&app, &format!("prefix={}&recursive=true", directory)).await;
+    assert_eq!(
+        paths,
+        [
+            format!("{}/a/b/deep", directory),
+            format!("{}/top", directory)
+        ]
+    );
+    remove(&app, &root);
+}
+
+async fn stat(app: &TestApp, path: &str) -> serde_json::Value {
+    let response = reqwest::get(format!("{}/files/{}?stat", app.addr(), path))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    response.json().await.unwrap()
+}
+
+async fn patch(app: &TestApp, path: &str, body: serde_json::Value) -> serde_json::Value {
+    let response = reqwest::Client::new()
+        .patch(format!("{}/files/{}/metadata", app.addr(), path))
+        .json(&body)
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    response.json().await.unwrap()
+}
+
+async fn list(app: &TestApp, query: &str) -> Vec<String> {
+    let response = reqwest::get(format!("{}/files?{}", app.addr(), query))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let files: Vec<serde_json::Value> = response.json().await.unwrap();
+    files
+        .iter()
+        .map(|file| file["path"].as_str().unwrap().to_string())
+        .collect()
+}
@@ -0,0 +1,105 @@
+use crate::helpers::spawn_app;
+use reqwest::{
+    multipart::{Form, Part},
+    StatusCode,
+};
+use uuid::Uuid;
+
+#[tokio::test]
+async fn metrics_endpoint_reports_request_counts_per_route() {
+    let app = spawn_app().await;
+
+    let client = reqwest::Client::new();
+    client
+        .get(format!("{}/health_check", app.addr()))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    let response = client
+        .get(format!("{}/metrics", app.addr()))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = response.text().await.unwrap();
+    assert!(body.contains(
+        r#"crumbbox_http_requests_total{method="GET",route="/health_check",status="200"} 1"#
+    ));
+    assert!(body.contains("crumbbox_http_request_duration_seconds_bucket"));
+    assert!(body.contains("crumbbox_storage_free_bytes"));
+    assert!(body.contains("crumbbox_storage_used_bytes"));
+}
+
+#[tokio::test]
+async fn made_up_methods_are_counted_together() {
+    let app = spawn_app().await;
+
+    let client = reqwest::Client::new();
+    for method in ["FROBNICATE", "TWIDDLE"] {
+        client
+            .request(
+                reqwest::Method::from_bytes(method.as_bytes()).unwrap(),
+                format!("{}/webdav/", app.addr()),
+            )
+            .send()
+            .await
+            .expect("Failed to execute request.");
+    }
+
+    let body = client
+        .get(format!("{}/metrics", app.addr()))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+        .text()
+        .await
+        .unwrap();
+    assert!(
+        body.contains(r#"method="other",route="/webdav/*path""#),
+        "{}",
+        body
+    );
+    assert!(!body.contains("FROBNICATE"));
+}
+
+#[tokio::test]
+async fn metrics_endpoint_reports_uploaded_bytes_and_validation_rejections() {
+    let app = spawn_app().await;
+    let contents = b"metrics test".to_vec();
+    let content_length = contents.len();
+    let file_name = Uuid::new_v4().to_string();
+
+    let client = reqwest::Client::new();
+    client
+        .post(format!("{}/upload", app.addr()))
+        .multipart(
+            Form::new()
+                .text("relative_path", "")
+                .part("file", Part::bytes(contents).file_name(file_name.clone())),
+        )
+        .send()
+        .await
+        .expect("Failed to execute request");
+    client
+        .post(format!("{}/upload", app.addr()))
+        .multipart(Form::new().text("relative_path", ""))
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    let body = client
+        .get(format!("{}/metrics", app.addr()))
+        .send()
+        .await
+        .expect("Failed to execute request.")
+        .text()
+        .await
+        .unwrap();
+
+    assert!(body.contains(&format!("crumbbox_uploaded_bytes_total {}", content_length)));
+    assert!(body.contains(r#"crumbbox_validation_rejections_total{reason="no_files"} 1"#));
+    assert!(body.contains("crumbbox_active_uploads 0"));
+    std::fs::remove_file(format!("{}/{}", app.storage_path, file_name)).unwrap();
+}
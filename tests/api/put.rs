@@ -0,0 +1,216 @@
+use reqwest::{header, StatusCode};
+use uuid::Uuid;
+
+use crate::helpers::{create_directory, remove, spawn_app, spawn_app_with, TestApp};
+
+#[tokio::test]
+async fn put_stores_the_raw_body() {
+    let app = spawn_app().await;
+    let path = format!("{}.txt", Uuid::new_v4());
+
+    let response = put(&app, &path, "raw body").send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let created_etag = response.headers()[header::ETAG].clone();
+
+    let response = reqwest::get(format!("{}/files/{}", app.addr(), path))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()[header::ETAG], created_etag);
+    assert_eq!(response.text().await.unwrap(), "raw body");
+
+    let response = put(&app, &path, "replaced").send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert_ne!(response.headers()[header::ETAG], created_etag);
+    assert_eq!(read(&app, &path), "replaced");
+    remove(&app, &path);
+}
+
+#[tokio::test]
+async fn if_none_match_star_only_creates_files() {
+    let app = spawn_app().await;
+    let path = format!("{}.txt", Uuid::new_v4());
+
+    let response = put(&app, &path, "first")
+        .header(header::IF_NONE_MATCH, "*")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let response = put(&app, &path, "second")
+        .header(header::IF_NONE_MATCH, "*")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+    assert_eq!(read(&app, &path), "first");
+    remove(&app, &path);
+}
+
+#[tokio::test]
+async fn if_match_replaces_only_the_expected_content() {
+    let app = spawn_app().await;
+    let path = format!("{}.txt", Uuid::new_v4());
+
+    let response = put(&app, &path, "first")
+        .header(header::IF_MATCH, "*")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+    assert!(!exists(&app, &path));
+
+    let response = put(&app, &path, "first").send().await.unwrap();
+    let first_etag = response.headers()[header::ETAG].clone();
+    let response = put(&app, &path, "second")
+        .header(header::IF_MATCH, first_etag.clone())
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    // Someone else's change went in first, so the stale tag no longer matches.
+    let response = put(&app, &path, "third")
+        .header(header::IF_MATCH, first_etag)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+    assert_eq!(read(&app, &path), "second");
+    remove(&app, &path);
+}
+
+#[tokio::test]
+async fn concurrent_conditional_puts_let_only_one_through() {
+    let app = spawn_app().await;
+    let path = format!("{}.txt", Uuid::new_v4());
+
+    let created = put_concurrently(&app, &path, header::IF_NONE_MATCH, "*").await;
+    assert_eq!(created.len(), 1, "Several create-only puts went through");
+    assert_eq!(read(&app, &path), created[0]);
+
+    let response = reqwest::get(format!("{}/files/{}", app.addr(), path))
+        .await
+        .unwrap();
+    let etag = response.headers()[header::ETAG]
+        .to_str()
+        .unwrap()
+        .to_string();
+    let replaced = put_concurrently(&app, &path, header::IF_MATCH, &etag).await;
+    assert_eq!(replaced.len(), 1, "Several puts replaced the same version");
+    assert_eq!(read(&app, &path), replaced[0]);
+    remove(&app, &path);
+}
+
+#[tokio::test]
+async fn declared_content_types_are_checked_and_recorded() {
+    let app = spawn_app_with(|config| {
+        config.content_types.denied_mime_types = vec!["application/x-sh".to_string()];
+    })
+    .await;
+    let path = format!("{}.dat", Uuid::new_v4());
+
+    let response = put(&app, &path, "echo hello")
+        .header(header::CONTENT_TYPE, "application/x-sh")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert!(!exists(&app, &path));
+
+    let response = reqwest::Client::new()
+        .put(format!("{}/files/{}", app.addr(), path))
+        .header(header::CONTENT_TYPE, "application/vnd.crumbbox.test")
+        .body(vec![0u8, 1, 2, 3])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let record = reqwest::get(format!("{}/files/{}?stat", app.addr(), path))
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    assert_eq!(record["content_type"], "application/vnd.crumbbox.test");
+    remove(&app, &path);
+}
+
+#[tokio::test]
+async fn invalid_put_requests_are_rejected() {
+    let app = spawn_app().await;
+    let directory = create_directory(&app);
+
+    let response = put(&app, &directory, "onto a directory")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+
+    let response = put(&app, ".crumbbox/internal.txt", "internal")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let response = put(&app, &format!("{}/bad\u{1}name.txt", directory), "bad name")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let response = put(&app, &format!("{}/notes.txt", directory), "text")
+        .header(header::CONTENT_TYPE, "not a type")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let mut left = std::fs::read_dir(format!("{}/{}", app.storage_path, directory)).unwrap();
+    assert!(left.next().is_none());
+    std::fs::remove_dir(format!("{}/{}", app.storage_path, directory)).unwrap();
+}
+
+fn put(app: &TestApp, path: &str, body: &'static str) -> reqwest::RequestBuilder {
+    reqwest::Client::new()
+        .put(format!("{}/files/{}", app.addr(), path))
+        .body(body)
+}
+
+/// Sends many conditional puts of the same file at once, returning the bodies of those that
+/// succeeded, any other having failed its precondition.
+async fn put_concurrently(
+    app: &TestApp,
+    path: &str,
+    condition: header::HeaderName,
+    value: &str,
+) -> Vec<String> {
+    let bodies = (0..32).map(|i| i.to_string()).collect::<Vec<_>>();
+    let responses = futures::future::join_all(bodies.iter().map(|body| {
+        reqwest::Client::new()
+            .put(format!("{}/files/{}", app.addr(), path))
+            .header(condition.clone(), value)
+            .body(body.clone())
+            .send()
+    }))
+    .await;
+
+    bodies
+        .into_iter()
+        .zip(responses)
+        .filter_map(|(body, response)| {
+            let status = response.unwrap().status();
+            assert!(status.is_success() || status == StatusCode::PRECONDITION_FAILED);
+            status.is_success().then_some(body)
+        })
+        .collect()
+}
+
+fn read(app: &TestApp, path: &str) -> String {
+    std::fs::read_to_string(format!("{}/{}", app.storage_path, path)).unwrap()
+}
+
+fn exists(app: &TestApp, path: &str) -> bool {
+    std::fs::metadata(format!("{}/{}", app.storage_path, path)).is_ok()
+}
@@ -0,0 +1,73 @@
+use crate::helpers::spawn_app_with;
+use reqwest::StatusCode;
+
+#[tokio::test]
+async fn ready_returns_200_when_storage_is_healthy() {
+    let app = spawn_app_with(|config| config.application.min_free_space_bytes = 0).await;
+
+    let client = reqwest::Client::new();
+    let response = client
+        .get(format!("{}/ready", app.addr()))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["ready"], true);
+    assert_eq!(body["checks"]["storage_exists"]["ok"], true);
+    assert_eq!(body["checks"]["storage_writable"]["ok"], true);
+    assert_eq!(body["checks"]["free_space"]["ok"], true);
+}
+
+#[tokio::test]
+async fn ready_returns_503_when_storage_path_is_missing() {
+    let app = spawn_app_with(|config| {
+        config.application.storage_path = ".crumbbox/does-not-exist/".to_string()
+    })
+    .await;
+
+    let client = reqwest::Client::new();
+    let response = client
+        .get(format!("{}/ready", app.addr()))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["ready"], false);
+    assert_eq!(body["checks"]["storage_exists"]["ok"], false);
+    assert_eq!(body["checks"]["storage_writable"]["ok"], false);
+}
+
+#[tokio::test]
+async fn ready_returns_503_when_free_space_is_below_threshold() {
+    let app = spawn_app_with(|config| config.application.min_free_space_bytes = u64::MAX).await;
+
+    let client = reqwest::Client::new();
+    let response = client
+        .get(format!("{}/ready", app.addr()))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["checks"]["storage_writable"]["ok"], true);
+    assert_eq!(body["checks"]["free_space"]["ok"], false);
+}
+
+#[tokio::test]
+async fn health_check_stays_healthy_when_storage_is_not_ready() {
+    let app = spawn_app_with(|config| config.application.min_free_space_bytes = u64::MAX).await;
+
+    let client = reqwest::Client::new();
+    let response = client
+        .get(format!("{}/health_check", app.addr()))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(response.status(), StatusCode::OK);
+}
@@ -0,0 +1,192 @@
+use std::{
+    net::SocketAddr,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use crumbbox::configuration::{InfectedAction, Settings};
+use reqwest::{
+    multipart::{Form, Part},
+    StatusCode,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+use uuid::Uuid;
+
+use crate::helpers::{spawn_app_with, TestApp};
+
+const INFECTED: &[u8] = b"X5O!P%@AP[4\\PZX54(P^)7CC)7}$EICAR-STANDARD-ANTIVIRUS-TEST-FILE!$H+H*";
+
+/// A clamd stand-in answering INSTREAM requests, recording the content it was sent.
+struct FakeClamd {
+    address: SocketAddr,
+    scanned: Arc<Mutex<Vec<Vec<u8>>>>,
+}
+
+impl FakeClamd {
+    async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let scanned = Arc::new(Mutex::new(vec![]));
+
+        let recorded = scanned.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(answer(stream, recorded.clone()));
+            }
+        });
+
+        Self { address, scanned }
+    }
+
+    fn configure(&self, config: &mut Settings) {
+        config.scanning.enabled = true;
+        config.scanning.clamd_address = format!("tcp://{}", self.address);
+    }
+
+    fn scanned(&self) -> Vec<Vec<u8>> {
+        self.scanned.lock().unwrap().clone()
+    }
+}
+
+async fn answer(mut stream: TcpStream, recorded: Arc<Mutex<Vec<Vec<u8>>>>) {
+    let mut command = [0; 10];
+    stream.read_exact(&mut command).await.unwrap();
+    assert_eq!(&command, b"zINSTREAM\0");
+
+    let mut content = vec![];
+    loop {
+        let length = stream.read_u32().await.unwrap() as usize;
+        if length == 0 {
+            break;
+        }
+        let mut chunk = vec![0; length];
+        stream.read_exact(&mut chunk).await.unwrap();
+        content.extend(chunk);
+    }
+
+    let reply: &[u8] = if content.windows(5).any(|window| window == b"EICAR") {
+        b"stream: Eicar-Test-Signature FOUND\0"
+    } else {
+        b"stream: OK\0"
+    };
+    // Recorded before replying, so the upload cannot finish first.
+    recorded.lock().unwrap().push(content);
+    stream.write_all(reply).await.unwrap();
+}
+
+#[tokio::test]
+async fn clean_files_are_scanned_and_stored() {
+    let clamd = FakeClamd::start().await;
+    let app = spawn_app_with(|config| clamd.configure(config)).await;
+    let file_name = Uuid::new_v4().to_string();
+
+    let response = upload(&app, &[(&file_name, b"harmless")]).await;
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(clamd.scanned(), [b"harmless".to_vec()]);
+    assert_eq!(read(&app, &file_name).unwrap(), b"harmless");
+    std::fs::remove_file(format!("{}/{}", app.storage_path, file_name)).unwrap();
+}
+
+#[tokio::test]
+async fn infected_upload_is_rejected_with_none_of_its_files_stored() {
+    let clamd = FakeClamd::start().await;
+    let app = spawn_app_with(|config| clamd.configure(config)).await;
+    let clean = Uuid::new_v4().to_string();
+    let infected = Uuid::new_v4().to_string();
+
+    let response = upload(&app, &[(&clean, b"harmless"), (&infected, INFECTED)]).await;
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert!(response
+        .text()
+        .await
+        .unwrap()
+        .contains("Eicar-Test-Signature"));
+    assert!(read(&app, &clean).is_none());
+    assert!(read(&app, &infected).is_none());
+}
+
+#[tokio::test]
+async fn infected_files_can_be_quarantined() {
+    // A storage root of its own, so the quarantine only holds this test's file.
+    let storage_path = format!(".crumbbox/test-scanning/{}", Uuid::new_v4());
+    std::fs::create_dir_all(&storage_path).unwrap();
+    let clamd = FakeClamd::start().await;
+    let app = spawn_app_with(|config| {
+        clamd.configure(config);
+        config.scanning.on_infected = InfectedAction::Quarantine;
+        config.application.storage_path = storage_path.clone();
+    })
+    .await;
+    let file_name = Uuid::new_v4().to_string();
+
+    let response = upload(&app, &[(&file_name, INFECTED)]).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert!(read(&app, &file_name).is_none());
+
+    let response = reqwest::get(format!("{}/admin/quarantine", app.addr()))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let entries: Vec<serde_json::Value> = response.json().await.unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0]["path"], file_name.as_str());
+    assert_eq!(entries[0]["signature"], "Eicar-Test-Signature");
+    let quarantined = std::fs::read(format!(
+        "{}/.crumbbox/quarantine/{}/content",
+        storage_path,
+        entries[0]["id"].as_str().unwrap()
+    ))
+    .unwrap();
+    assert_eq!(quarantined, INFECTED);
+
+    std::fs::remove_dir_all(storage_path).unwrap();
+}
+
+#[tokio::test]
+async fn upload_fails_when_the_scanner_is_unreachable() {
+    // Nothing listens on a port released right after binding it.
+    let address = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let app = spawn_app_with(|config| {
+        config.scanning.enabled = true;
+        config.scanning.clamd_address = format!("tcp://{}", address);
+    })
+    .await;
+    let file_name = Uuid::new_v4().to_string();
+
+    let response = upload(&app, &[(&file_name, b"harmless")]).await;
+
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert!(read(&app, &file_name).is_none());
+}
+
+async fn upload(app: &TestApp, files: &[(&str, &'static [u8])]) -> reqwest::Response {
+    let mut form = Form::new().text("relative_path", "");
+    for (file_name, contents) in files {
+        form = form.part(
+            "file",
+            Part::bytes(*contents).file_name(file_name.to_string()),
+        );
+    }
+
+    reqwest::Client::new()
+        .post(format!("{}/upload", app.addr()))
+        .multipart(form)
+        .send()
+        .await
+        .expect("Failed to execute request")
+}
+
+fn read(app: &TestApp, file_name: &str) -> Option<Vec<u8>> {
+    let path = format!("{}/{}", app.storage_path, file_name);
+    Path::new(&path)
+        .exists()
+        .then(|| std::fs::read(path).unwrap())
+}
@@ -0,0 +1,195 @@
+use crumbbox::configuration::ApiKeySettings;
+use reqwest::StatusCode;
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::helpers::{
+    create_directory, remove, root_key, spawn_app, spawn_app_with, upload, TestApp,
+};
+
+#[tokio::test]
+async fn search_matches_name_substrings_and_globs() {
+    let app = spawn_app().await;
+    let marker = Uuid::new_v4().simple().to_string();
+    let directory = create_directory(&app);
+    upload(&app, &directory, &format!("Report-{}.PDF", marker), b"pdf").await;
+    upload(&app, &directory, &format!("notes-{}.txt", marker), b"notes").await;
+
+    let found = search(&app, &format!("q=report-{}", marker)).await;
+    assert_eq!(
+        paths(&found),
+        [format!("{}/Report-{}.PDF", directory, marker)]
+    );
+
+    let found = search(&app, &format!("q=*-{}.txt", marker)).await;
+    assert_eq!(
+        paths(&found),
+        [format!("{}/notes-{}.txt", directory, marker)]
+    );
+
+    // Directory names are not matched.
+    let found = search(&app, &format!("q={}", directory)).await;
+    assert!(paths(&found).is_empty());
+    remove(&app, &directory);
+}
+
+#[tokio::test]
+async fn search_filters_by_size_content_type_date_and_tag() {
+    let app = spawn_app().await;
+    let marker = Uuid::new_v4().simple().to_string();
+    let directory = create_directory(&app);
+    let started = chrono::Utc::now();
+    upload(&app, &directory, &format!("{}-small.txt", marker), b"12").await;
+    upload(
+        &app,
+        &directory,
+        &format!("{}-large.json", marker),
+        b"123456",
+    )
+    .await;
+    let large = format!("{}/{}-large.json", directory, marker);
+    let small = format!("{}/{}-small.txt", directory, marker);
+
+    let found = search(&app, &format!("q={}&min_size=3", marker)).await;
+    assert_eq!(paths(&found), [large.as_str()]);
+    let found = search(&app, &format!("q={}&max_size=2", marker)).await;
+    assert_eq!(paths(&found), [small.as_str()]);
+    let found = search(&app, &format!("q={}&content_type=text/*", marker)).await;
+    assert_eq!(paths(&found), [small.as_str()]);
+    let found = search(&app, &format!("q={}&content_type=application/json", marker)).await;
+    assert_eq!(paths(&found), [large.as_str()]);
+
+    let found = search(
+        &app,
+        &format!(
+            "q={}&modified_after={}",
+            marker,
+            urlencode(&started.to_rfc3339())
+        ),
+    )
+    .await;
+    assert_eq!(found["files"].as_array().unwrap().len(), 2);
+    let found = search(
+        &app,
+        &format!(
+            "q={}&modified_before={}",
+            marker,
+            urlencode(&started.to_rfc3339())
+        ),
+    )
+    .await;
+    assert!(paths(&found).is_empty());
+
+    let response = reqwest::Client::new()
+        .patch(format!("{}/files/{}/metadata", app.addr(), small))
+        .json(&json!({ "add_tags": ["urgent"], "metadata": { "owner": "ops" } }))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let found = search(&app, &format!("q={}&tag=urgent&metadata.owner=ops", marker)).await;
+    assert_eq!(paths(&found), [small]);
+    remove(&app, &directory);
+}
+
+#[tokio::test]
+async fn search_is_paginated_with_a_cursor() {
+    let app = spawn_app().await;
+    let marker = Uuid::new_v4().simple().to_string();
+    let directory = create_directory(&app);
+    for number in 0..3 {
+        upload(&app, &directory, &format!("{}-{}", marker, number), b"data").await;
+    }
+
+    let first_page = search(&app, &format!("q={}&limit=2", marker)).await;
+    assert_eq!(first_page["files"].as_array().unwrap().len(), 2);
+    assert_eq!(first_page["has_more"], true);
+
+    let second_page = search(
+        &app,
+        &format!(
+            "q={}&limit=2&cursor={}",
+            marker,
+            urlencode(first_page["cursor"].as_str().unwrap())
+        ),
+    )
+    .await;
+    assert_eq!(paths(&second_page), [format!("{}/{}-2", directory, marker)]);
+    assert_eq!(second_page["has_more"], false);
+    assert!(second_page.get("cursor").is_none());
+    remove(&app, &directory);
+}
+
+#[tokio::test]
+async fn search_is_scoped_to_the_allowed_paths_of_the_caller() {
+    let allowed = Uuid::new_v4().to_string();
+    let app = spawn_app_with({
+        let allowed = allowed.clone();
+        move |config| {
+            config.auth.api_keys = vec![
+                ApiKeySettings {
+                    user: "bob".to_string(),
+                    key: "bob-key".to_string(),
+                    admin: false,
+                    allowed_paths: vec![allowed.clone()],
+                },
+                root_key(),
+            ];
+        }
+    })
+    .await;
+    let marker = Uuid::new_v4().simple().to_string();
+    std::fs::create_dir_all(format!("{}/{}", app.storage_path, allowed)).unwrap();
+    let other = create_directory(&app);
+    upload(&app, &allowed, &format!("{}-visible", marker), b"data").await;
+    upload(&app, &other, &format!("{}-hidden", marker), b"data").await;
+
+    let response = reqwest::Client::new()
+        .get(format!("{}/search?q={}", app.addr(), marker))
+        .bearer_auth("bob-key")
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let found: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(paths(&found), [format!("{}/{}-visible", allowed, marker)]);
+
+    remove(&app, &allowed);
+    remove(&app, &other);
+}
+
+#[tokio::test]
+async fn search_rejects_invalid_parameters() {
+    let app = spawn_app().await;
+
+    for query in ["min_size=big", "modified_after=yesterday", "unknown=1"] {
+        let response = reqwest::get(format!("{}/search?{}", app.addr(), query))
+            .await
+            .expect("Failed to execute request");
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", query);
+    }
+}
+
+async fn search(app: &TestApp, query: &str) -> serde_json::Value {
+    let response = reqwest::get(format!("{}/search?{}", app.addr(), query))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    response.json().await.unwrap()
+}
+
+fn paths(found: &serde_json::Value) -> Vec<String> {
+    found["files"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|file| file["path"].as_str().unwrap().to_string())
+        .collect()
+}
+
+fn urlencode(value: &str) -> String {
+    value
+        .replace('+', "%2B")
+        .replace(':', "%3A")
+        .replace('/', "%2F")
+}
@@ -0,0 +1,133 @@
+use std::{
+    io::Write,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use crate::helpers::{spawn_app, OTLP_COLLECTOR};
+use crumbbox::{
+    configuration::{LogFormat, TelemetrySettings},
+    telemetry::get_subscriber,
+};
+use reqwest::StatusCode;
+use tracing_subscriber::fmt::MakeWriter;
+use uuid::Uuid;
+
+#[tokio::test]
+async fn request_spans_are_exported_as_part_of_the_incoming_trace() {
+    let app = spawn_app().await;
+    let trace_id = Uuid::new_v4();
+
+    let client = reqwest::Client::new();
+    let response = client
+        .get(format!("{}/health_check", app.addr()))
+        .header(
+            "traceparent",
+            format!("00-{}-00f067aa0ba902b7-01", trace_id.simple()),
+        )
+        .send()
+        .await
+        .expect("Failed to execute request.");
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // Trace ids are encoded as raw bytes in the protobuf payload.
+    let trace_id_bytes = trace_id.as_bytes();
+    let mut exported = false;
+    for _ in 0..100 {
+        exported =
+            OTLP_COLLECTOR.exports.lock().unwrap().iter().any(|export| {
+                contains(export, trace_id_bytes) && contains(export, b"http-request")
+            });
+        if exported {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+
+    assert!(exported, "No span with trace id {} was exported", trace_id);
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack
+        .windows(needle.len())
+        .any(|window| window == needle)
+}
+
+#[tokio::test]
+async fn generated_request_id_is_returned_to_the_client() {
+    let app = spawn_app().await;
+
+    let client = reqwest::Client::new();
+    let response = client
+        .get(format!("{}/health_check", app.addr()))
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    let request_id = response
+        .headers()
+        .get("x-request-id")
+        .expect("No request id in response");
+    assert!(Uuid::parse_str(request_id.to_str().unwrap()).is_ok());
+}
+
+#[tokio::test]
+async fn incoming_request_id_is_echoed_back() {
+    let app = spawn_app().await;
+
+    let client = reqwest::Client::new();
+    let response = client
+        .get(format!("{}/health_check", app.addr()))
+        .header("x-request-id", "support-ticket-1234")
+        .send()
+        .await
+        .expect("Failed to execute request.");
+
+    assert_eq!(response.headers()["x-request-id"], "support-ticket-1234");
+}
+
+#[test]
+fn json_log_format_emits_bunyan_records() {
+    let buffer = SharedBuffer::default();
+    let settings = TelemetrySettings {
+        log_format: LogFormat::Json,
+        ..TelemetrySettings::default()
+    };
+    let subscriber = get_subscriber("info".to_string(), buffer.clone(), &settings, None);
+
+    tracing::subscriber::with_default(subscriber, || {
+        let span = tracing::info_span!("http-request", request_id = "abc");
+        let _entered = span.enter();
+        tracing::info!("A health check was requested");
+    });
+
+    let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+    let record = output
+        .lines()
+        .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+        .find(|record| record["msg"] == "[HTTP-REQUEST - EVENT] A health check was requested")
+        .expect("Event was not logged");
+    assert_eq!(record["name"], "crumbbox");
+    assert_eq!(record["request_id"], "abc");
+}
+
+#[derive(Clone, Default)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> MakeWriter<'a> for SharedBuffer {
+    type Writer = SharedBuffer;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.clone()
+    }
+}
@@ -0,0 +1,169 @@
+use std::{io::Cursor, path::Path, time::Duration};
+
+use image::{DynamicImage, GenericImageView, ImageFormat};
+use reqwest::StatusCode;
+use uuid::Uuid;
+
+use crate::helpers::{spawn_app, spawn_app_with, upload, TestApp};
+
+#[tokio::test]
+async fn uploaded_images_get_thumbnails_in_every_size() {
+    let app = spawn_app().await;
+    let file_name = format!("{}.png", Uuid::new_v4());
+
+    upload(&app, "", &file_name, encode(600, 300, ImageFormat::Png)).await;
+
+    let cache_path = cache_path(&app, &file_name);
+    let generated = wait_for(|| {
+        Path::new(&format!("{}/128.png", cache_path)).exists()
+            && Path::new(&format!("{}/512.png", cache_path)).exists()
+    })
+    .await;
+    assert!(generated, "Thumbnails were not generated on upload");
+
+    cleanup(&app, &file_name);
+}
+
+#[tokio::test]
+async fn thumbnails_are_generated_on_demand_within_the_requested_size() {
+    let app = spawn_app_with(|config| config.thumbnails.generate_on_upload = false).await;
+    let png = format!("{}.png", Uuid::new_v4());
+    let jpeg = format!("{}.jpg", Uuid::new_v4());
+    upload(&app, "", &png, encode(600, 300, ImageFormat::Png)).await;
+    upload(&app, "", &jpeg, encode(100, 400, ImageFormat::Jpeg)).await;
+    assert!(!Path::new(&cache_path(&app, &png)).exists());
+
+    let (content_type, thumbnail) = get_thumbnail(&app, &png, Some(512)).await;
+    assert_eq!(content_type, "image/png");
+    assert_eq!(thumbnail.dimensions(), (512, 256));
+
+    let (content_type, thumbnail) = get_thumbnail(&app, &png, None).await;
+    assert_eq!(content_type, "image/png");
+    assert_eq!(thumbnail.dimensions(), (128, 64));
+    assert!(Path::new(&format!("{}/128.png", cache_path(&app, &png))).exists());
+
+    let (content_type, thumbnail) = get_thumbnail(&app, &jpeg, Some(128)).await;
+    assert_eq!(content_type, "image/jpeg");
+    assert_eq!(thumbnail.dimensions(), (32, 128));
+
+    // Images smaller than the requested size are not enlarged.
+    let (_, thumbnail) = get_thumbnail(&app, &jpeg, Some(512)).await;
+    assert_eq!(thumbnail.dimensions(), (100, 400));
+
+    cleanup(&app, &png);
+    cleanup(&app, &jpeg);
+}
+
+#[tokio::test]
+async fn unsupported_sizes_and_files_are_rejected() {
+    let app = spawn_app().await;
+    let image = format!("{}.png", Uuid::new_v4());
+    let text = format!("{}.txt", Uuid::new_v4());
+    upload(&app, "", &image, encode(20, 20, ImageFormat::Png)).await;
+    upload(&app, "", &text, b"not an image".to_vec()).await;
+
+    let response = request(&app, &format!("{}?size=100", image)).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = request(&app, &text).await;
+    assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    let response = request(&app, &format!("{}.png", Uuid::new_v4())).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = request(&app, "%2E%2E%2FCargo.toml").await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    cleanup(&app, &image);
+    std::fs::remove_file(format!("{}/{}", app.storage_path, text)).unwrap();
+}
+
+#[tokio::test]
+async fn thumbnails_follow_overwritten_and_deleted_images() {
+    let app = spawn_app_with(|config| config.thumbnails.generate_on_upload = false).await;
+    let file_name = format!("{}.png", Uuid::new_v4());
+    upload(&app, "", &file_name, encode(600, 300, ImageFormat::Png)).await;
+    let (_, thumbnail) = get_thumbnail(&app, &file_name, Some(128)).await;
+    assert_eq!(thumbnail.dimensions(), (128, 64));
+
+    upload(&app, "", &file_name, encode(300, 600, ImageFormat::Png)).await;
+    let (_, thumbnail) = get_thumbnail(&app, &file_name, Some(128)).await;
+    assert_eq!(thumbnail.dimensions(), (64, 128));
+
+    let response = reqwest::Client::new()
+        .delete(format!("{}/files/{}", app.addr(), file_name))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let cache_path = cache_path(&app, &file_name);
+    let invalidated = wait_for(|| !Path::new(&cache_path).exists()).await;
+    assert!(invalidated, "Thumbnails of a deleted image were kept");
+    let response = request(&app, &format!("{}?size=128", file_name)).await;
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn images_larger_than_the_source_limit_get_no_thumbnails() {
+    let contents = encode(600, 300, ImageFormat::Png);
+    let limit = contents.len() as u64 - 1;
+    let app = spawn_app_with(|config| config.thumbnails.max_source_size_bytes = limit).await;
+    let file_name = format!("{}.png", Uuid::new_v4());
+    upload(&app, "", &file_name, contents).await;
+
+    let response = request(&app, &format!("{}?size=128", file_name)).await;
+    assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    assert!(!Path::new(&cache_path(&app, &file_name)).exists());
+
+    cleanup(&app, &file_name);
+}
+
+fn encode(width: u32, height: u32, format: ImageFormat) -> Vec<u8> {
+    let mut contents = Cursor::new(vec![]);
+    DynamicImage::new_rgb8(width, height)
+        .write_to(&mut contents, format)
+        .unwrap();
+    contents.into_inner()
+}
+
+async fn request(app: &TestApp, path_and_query: &str) -> reqwest::Response {
+    reqwest::get(format!("{}/thumbnails/{}", app.addr(), path_and_query))
+        .await
+        .expect("Failed to execute request")
+}
+
+async fn get_thumbnail(
+    app: &TestApp,
+    file_name: &str,
+    size: Option<u32>,
+) -> (String, DynamicImage) {
+    let query = size
+        .map(|size| format!("?size={}", size))
+        .unwrap_or_default();
+    let response = request(app, &format!("{}{}", file_name, query)).await;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let content_type = response.headers()["content-type"]
+        .to_str()
+        .unwrap()
+        .to_string();
+    let thumbnail = image::load_from_memory(&response.bytes().await.unwrap()).unwrap();
+    (content_type, thumbnail)
+}
+
+async fn wait_for(condition: impl Fn() -> bool) -> bool {
+    for _ in 0..50 {
+        if condition() {
+            return true;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    false
+}
+
+fn cache_path(app: &TestApp, file_name: &str) -> String {
+    format!("{}/.crumbbox/thumbnails/{}", app.storage_path, file_name)
+}
+
+fn cleanup(app: &TestApp, file_name: &str) {
+    std::fs::remove_file(format!("{}/{}", app.storage_path, file_name)).unwrap();
+    let _ = std::fs::remove_dir_all(cache_path(app, file_name));
+}
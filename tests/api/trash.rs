@@ -0,0 +1,279 @@
+use std::path::Path;
+
+use crate::helpers::{create_directory, root_key, spawn_app, spawn_app_with, upload, TestApp};
+use crumbbox::configuration::ApiKeySettings;
+use reqwest::StatusCode;
+use uuid::Uuid;
+
+#[tokio::test]
+async fn deleted_file_is_moved_to_the_trash() {
+    let app = spawn_app().await;
+    let file_name = Uuid::new_v4().to_string();
+    upload(&app, "", &file_name, b"delete me").await;
+
+    let entry = delete(&app, &file_name).await;
+
+    assert_eq!(entry["original_path"], file_name.as_str());
+    assert_eq!(entry["size"], 9);
+    assert!(!Path::new(&format!("{}/{}", app.storage_path, file_name)).exists());
+    let response = reqwest::get(format!("{}/files/{}", app.addr(), file_name))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let id = entry["id"].as_str().unwrap();
+    assert!(list_trash(&app)
+        .await
+        .iter()
+        .any(|listed| listed["id"] == id));
+
+    purge(&app, id).await;
+}
+
+#[tokio::test]
+async fn deleting_a_missing_file_returns_404() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .delete(format!("{}/files/{}", app.addr(), Uuid::new_v4()))
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn deleted_directory_can_be_restored() {
+    let app = spawn_app().await;
+    let directory = create_directory(&app);
+    upload(&app, &directory, "a.txt", b"a").await;
+    upload(&app, &directory, "b.txt", b"b").await;
+
+    let entry = delete(&app, &directory).await;
+    assert_eq!(entry["is_directory"], true);
+    assert!(!Path::new(&format!("{}/{}", app.storage_path, directory)).exists());
+
+    let response = reqwest::Client::new()
+        .post(format!(
+            "{}/trash/{}/restore",
+            app.addr(),
+            entry["id"].as_str().unwrap()
+        ))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let restored = std::fs::read(format!("{}/{}/b.txt", app.storage_path, directory)).unwrap();
+    assert_eq!(restored, b"b");
+    assert!(!list_trash(&app)
+        .await
+        .iter()
+        .any(|listed| listed["id"] == entry["id"]));
+
+    std::fs::remove_dir_all(format!("{}/{}", app.storage_path, directory)).unwrap();
+}
+
+#[tokio::test]
+async fn restore_does_not_overwrite_a_new_file() {
+    let app = spawn_app().await;
+    let file_name = Uuid::new_v4().to_string();
+    upload(&app, "", &file_name, b"old").await;
+    let entry = delete(&app, &file_name).await;
+    upload(&app, "", &file_name, b"new").await;
+
+    let response = reqwest::Client::new()
+        .post(format!(
+            "{}/trash/{}/restore",
+            app.addr(),
+            entry["id"].as_str().unwrap()
+        ))
+        .send()
+        .await
+        .expect("Failed to execute request");
+
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    let current = std::fs::read(format!("{}/{}", app.storage_path, file_name)).unwrap();
+    assert_eq!(current, b"new");
+
+    purge(&app, entry["id"].as_str().unwrap()).await;
+    std::fs::remove_file(format!("{}/{}", app.storage_path, file_name)).unwrap();
+}
+
+#[tokio::test]
+async fn purged_entries_cannot_be_restored() {
+    let app = spawn_app().await;
+    let file_name = Uuid::new_v4().to_string();
+    upload(&app, "", &file_name, b"gone").await;
+    let entry = delete(&app, &file_name).await;
+    let id = entry["id"].as_str().unwrap();
+
+    purge(&app, id).await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/trash/{}/restore", app.addr(), id))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn expired_entries_are_purged_in_the_background() {
+    // A storage root of its own, so the purge leaves the other tests' entries alone.
+    let storage_path = format!(".crumbbox/test-trash/{}/", Uuid::new_v4());
+    std::fs::create_dir_all(&storage_path).unwrap();
+    let app = spawn_app_with(|config| {
+        config.application.storage_path = storage_path.clone();
+        config.trash.retention_days = 0;
+        // Runs every second, the shortest interval.
+        config.trash.purge_interval_seconds = 0;
+    })
+    .await;
+    let file_name = Uuid::new_v4().to_string();
+    upload(&app, "", &file_name, b"expiring").await;
+    let entry = delete(&app, &file_name).await;
+    let trash_path = format!(
+        "{}/.crumbbox/trash/{}",
+        app.storage_path,
+        entry["id"].as_str().unwrap()
+    );
+
+    let mut purged = false;
+    for _ in 0..30 {
+        if !Path::new(&trash_path).exists() {
+            purged = true;
+            break;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    }
+    assert!(purged, "Expired trash entry was not purged");
+
+    std::fs::remove_dir_all(storage_path).unwrap();
+}
+
+#[tokio::test]
+async fn only_admins_purge_entries() {
+    let app = spawn_app_with(|config| {
+        config.auth.api_keys = vec![
+            ApiKeySettings {
+                user: "alice".to_string(),
+                key: "alice-key".to_string(),
+                admin: false,
+                allowed_paths: vec![],
+            },
+            ApiKeySettings {
+                user: "root".to_string(),
+                key: "root-key".to_string(),
+                admin: true,
+                allowed_paths: vec![],
+            },
+        ];
+    })
+    .await;
+    let file_name = Uuid::new_v4().to_string();
+    upload(&app, "", &file_name, b"kept").await;
+    let entry = delete(&app, &file_name).await;
+    let url = format!("{}/trash/{}", app.addr(), entry["id"].as_str().unwrap());
+
+    let client = reqwest::Client::new();
+    let response = client
+        .delete(&url)
+        .bearer_auth("alice-key")
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = client
+        .delete(&url)
+        .bearer_auth("root-key")
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn scoped_keys_only_see_and_restore_entries_from_their_scope() {
+    let allowed = Uuid::new_v4().to_string();
+    let app = spawn_app_with({
+        let allowed = allowed.clone();
+        move |config| {
+            config.auth.api_keys = vec![
+                ApiKeySettings {
+                    user: "bob".to_string(),
+                    key: "bob-key".to_string(),
+                    admin: false,
+                    allowed_paths: vec![allowed],
+                },
+                root_key(),
+            ];
+        }
+    })
+    .await;
+    let other = Uuid::new_v4().to_string();
+    std::fs::create_dir_all(format!("{}/{}", app.storage_path, allowed)).unwrap();
+    std::fs::create_dir_all(format!("{}/{}", app.storage_path, other)).unwrap();
+    upload(&app, &allowed, "visible.txt", b"visible").await;
+    upload(&app, &other, "hidden.txt", b"hidden").await;
+    let visible = delete(&app, &format!("{}/visible.txt", allowed)).await;
+    let hidden = delete(&app, &format!("{}/hidden.txt", other)).await;
+    let client = reqwest::Client::new();
+
+    let response = client
+        .get(format!("{}/trash", app.addr()))
+        .bearer_auth("bob-key")
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    let entries: Vec<serde_json::Value> = response.json().await.unwrap();
+    assert!(entries.iter().any(|entry| entry["id"] == visible["id"]));
+    assert!(!entries.iter().any(|entry| entry["id"] == hidden["id"]));
+
+    let response = client
+        .post(format!(
+            "{}/trash/{}/restore",
+            app.addr(),
+            hidden["id"].as_str().unwrap()
+        ))
+        .bearer_auth("bob-key")
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert!(!std::path::Path::new(&format!("{}/{}/hidden.txt", app.storage_path, other)).exists());
+
+    std::fs::remove_dir_all(format!("{}/{}", app.storage_path, allowed)).unwrap();
+    std::fs::remove_dir_all(format!("{}/{}", app.storage_path, other)).unwrap();
+}
+
+async fn delete(app: &TestApp, relative_path: &str) -> serde_json::Value {
+    let mut request =
+        reqwest::Client::new().delete(format!("{}/files/{}", app.addr(), relative_path));
+    if let Some(admin_key) = &app.admin_key {
+        request = request.bearer_auth(admin_key);
+    }
+
+    let response = request.send().await.expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    response.json().await.unwrap()
+}
+
+async fn list_trash(app: &TestApp) -> Vec<serde_json::Value> {
+    let response = reqwest::get(format!("{}/trash", app.addr()))
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+    response.json().await.unwrap()
+}
+
+async fn purge(app: &TestApp, id: &str) {
+    let response = reqwest::Client::new()
+        .delete(format!("{}/trash/{}", app.addr(), id))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+}
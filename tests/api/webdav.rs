@@ -0,0 +1,423 @@
+use crumbbox::configuration::{ApiKeySettings, MasterKeySettings};
+use reqwest::{
+    header,
+    multipart::{Form, Part},
+    Method, StatusCode,
+};
+use uuid::Uuid;
+
+use crate::helpers::{create_directory, remove, root_key, spawn_app, spawn_app_with, TestApp};
+
+const LOCK_BODY: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<D:lockinfo xmlns:D="DAV:">
+  <D:lockscope><D:exclusive/></D:lockscope>
+  <D:locktype><D:write/></D:locktype>
+  <D:owner><D:href>mailto:alice@example.com</D:href></D:owner>
+</D:lockinfo>"#;
+
+#[tokio::test]
+async fn options_advertises_class_2_compliance() {
+    let app = spawn_app().await;
+
+    let response = dav(&app, "OPTIONS", "").send().await.unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["dav"], "1, 2");
+    let allow = response.headers()[header::ALLOW].to_str().unwrap();
+    for method in ["PROPFIND", "MKCOL", "COPY", "MOVE", "LOCK", "UNLOCK"] {
+        assert!(allow.contains(method), "{}", allow);
+    }
+}
+
+#[tokio::test]
+async fn files_put_over_webdav_are_stored_like_uploads() {
+    let app = spawn_app_with(|config| {
+        config.encryption.enabled = true;
+        config.encryption.active_key = Some("first".to_string());
+        config.encryption.master_keys = vec![MasterKeySettings {
+            id: "first".to_string(),
+            key: "22".repeat(32),
+        }];
+    })
+    .await;
+    let directory = create_directory(&app);
+    let path = format!("{}/notes.txt", directory);
+
+    let response = dav(&app, "PUT", &path).body("first").send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let response = dav(&app, "PUT", &path).body("second").send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let response = dav(&app, "GET", &path).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers().contains_key(header::ETAG));
+    assert_eq!(response.text().await.unwrap(), "second");
+    let stored = std::fs::read(format!("{}/{}", app.storage_path, path)).unwrap();
+    assert!(stored.starts_with(b"CBXE"));
+
+    let response = dav(&app, "PUT", &format!("{}/missing/notes.txt", directory))
+        .body("orphan")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    remove(&app, &directory);
+}
+
+#[tokio::test]
+async fn propfind_lists_a_collection_and_its_members() {
+    let app = spawn_app().await;
+    let directory = create_directory(&app);
+    put(&app, &format!("{}/a.txt", directory), "abc").await;
+    let response = dav(&app, "MKCOL", &format!("{}/sub", directory))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let response = dav(&app, "PROPFIND", &directory)
+        .header("Depth", "0")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::MULTI_STATUS);
+    let body = response.text().await.unwrap();
+    assert_eq!(body.matches("<D:response>").count(), 1);
+    assert!(body.contains("<D:collection/>"));
+
+    let response = dav(&app, "PROPFIND", &directory)
+        .header("Depth", "1")
+        .body(
+            r#"<?xml version="1.0"?><D:propfind xmlns:D="DAV:"><D:prop><D:getcontentlength/></D:prop></D:propfind>"#,
+        )
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::MULTI_STATUS);
+    let body = response.text().await.unwrap();
+    assert_eq!(body.matches("<D:response>").count(), 3);
+    assert!(body.contains(&format!("/webdav/{}/a.txt</D:href>", directory)));
+    assert!(body.contains(&format!("/webdav/{}/sub/</D:href>", directory)));
+    assert!(body.contains("<D:getcontentlength>3</D:getcontentlength>"));
+
+    let response = dav(&app, "PROPFIND", &directory)
+        .header("Depth", "infinity")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let response = dav(&app, "PROPFIND", &format!("{}/missing", directory))
+        .header("Depth", "0")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    remove(&app, &directory);
+}
+
+#[tokio::test]
+async fn move_and_copy_honour_the_overwrite_header() {
+    let app = spawn_app().await;
+    let directory = create_directory(&app);
+    put(&app, &format!("{}/a.txt", directory), "a").await;
+    put(&app, &format!("{}/b.txt", directory), "b").await;
+
+    let response = dav(&app, "COPY", &format!("{}/a.txt", directory))
+        .header("Destination", destination(&app, &directory, "c.txt"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    assert_eq!(read(&app, &format!("{}/c.txt", directory)), "a");
+
+    let response = dav(&app, "MOVE", &format!("{}/a.txt", directory))
+        .header("Destination", destination(&app, &directory, "b.txt"))
+        .header("Overwrite", "F")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+    assert_eq!(read(&app, &format!("{}/b.txt", directory)), "b");
+
+    let response = dav(&app, "MOVE", &format!("{}/a.txt", directory))
+        .header("Destination", destination(&app, &directory, "b.txt"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert_eq!(read(&app, &format!("{}/b.txt", directory)), "a");
+    assert!(!exists(&app, &format!("{}/a.txt", directory)));
+
+    let response = dav(&app, "MOVE", &directory)
+        .header("Destination", destination(&app, &directory, "inner"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    remove(&app, &directory);
+}
+
+#[tokio::test]
+async fn deleted_resources_go_to_the_trash() {
+    let app = spawn_app().await;
+    let directory = create_directory(&app);
+    let path = format!("{}/a.txt", directory);
+    put(&app, &path, "a").await;
+
+    let response = dav(&app, "DELETE", &path).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    assert!(!exists(&app, &path));
+
+    let trash = reqwest::get(format!("{}/trash", app.addr()))
+        .await
+        .unwrap()
+        .json::<serde_json::Value>()
+        .await
+        .unwrap();
+    let entry = trash
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|entry| entry["original_path"] == path.as_str())
+        .expect("Not in the trash");
+    reqwest::Client::new()
+        .delete(format!(
+            "{}/trash/{}",
+            app.addr(),
+            entry["id"].as_str().unwrap()
+        ))
+        .send()
+        .await
+        .unwrap();
+
+    let response = dav(&app, "DELETE", "").send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    remove(&app, &directory);
+}
+
+#[tokio::test]
+async fn locked_resources_require_the_lock_token() {
+    let app = spawn_app().await;
+    let directory = create_directory(&app);
+    let path = format!("{}/locked.txt", directory);
+    put(&app, &path, "v1").await;
+
+    let response = dav(&app, "LOCK", &path)
+        .header("Timeout", "Second-600")
+        .body(LOCK_BODY)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let token = response.headers()["lock-token"]
+        .to_str()
+        .unwrap()
+        .to_string();
+    let body = response.text().await.unwrap();
+    assert!(body.contains("<D:timeout>Second-600</D:timeout>"));
+    assert!(body.contains("mailto:alice@example.com"));
+
+    let response = dav(&app, "PUT", &path).body("v2").send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::LOCKED);
+    let response = dav(&app, "LOCK", &path)
+        .body(LOCK_BODY)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::LOCKED);
+
+    let response = dav(&app, "PUT", &path)
+        .header("If", format!("({})", token))
+        .body("v2")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+    let response = dav(&app, "UNLOCK", &path)
+        .header("Lock-Token", &token)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let response = dav(&app, "DELETE", &path).send().await.unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    remove(&app, &directory);
+}
+
+#[tokio::test]
+async fn locks_guard_uploads_and_deletions_through_every_api() {
+    let app = spawn_app().await;
+    let directory = create_directory(&app);
+    let path = format!("{}/locked.txt", directory);
+    put(&app, &path, "v1").await;
+    let response = dav(&app, "LOCK", &path)
+        .body(LOCK_BODY)
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let token = response.headers()["lock-token"]
+        .to_str()
+        .unwrap()
+        .to_string();
+
+    let client = reqwest::Client::new();
+    let files_url = format!("{}/files/{}", app.addr(), path);
+    let s3_url = format!("{}/s3/{}", app.addr(), path);
+    let form = Form::new()
+        .text("relative_path", directory.clone())
+        .part("file", Part::bytes(b"v2".to_vec()).file_name("locked.txt"));
+    let responses = [
+        client.put(&files_url).body("v2").send().await.unwrap(),
+        client.delete(&files_url).send().await.unwrap(),
+        client
+            .post(format!("{}/upload", app.addr()))
+            .multipart(form)
+            .send()
+            .await
+            .unwrap(),
+        client.put(&s3_url).body("v2").send().await.unwrap(),
+        client.delete(&s3_url).send().await.unwrap(),
+    ];
+    for response in responses {
+        assert_eq!(response.status(), StatusCode::LOCKED);
+    }
+    assert_eq!(read(&app, &path), "v1");
+
+    let response = client
+        .put(&files_url)
+        .header("If", format!("({})", token))
+        .body("v2")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    let response = client
+        .delete(&files_url)
+        .header("If", format!("({})", token))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    // Deleting the file released its lock.
+    put(&app, &path, "v3").await;
+    remove(&app, &directory);
+}
+
+#[tokio::test]
+async fn basic_credentials_carry_the_api_key() {
+    let app = spawn_app_with(|config| {
+        config.auth.api_keys = vec![ApiKeySettings {
+            user: "alice".to_string(),
+            key: "alice-key".to_string(),
+            admin: false,
+            allowed_paths: vec![],
+        }];
+    })
+    .await;
+
+    let response = dav(&app, "PROPFIND", "")
+        .header("Depth", "0")
+        .basic_auth("alice", Some("wrong-key"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let response = dav(&app, "PROPFIND", "")
+        .header("Depth", "0")
+        .basic_auth("alice", Some("alice-key"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::MULTI_STATUS);
+}
+
+#[tokio::test]
+async fn scoped_keys_cannot_change_or_move_into_paths_outside_their_scope() {
+    let allowed = Uuid::new_v4().to_string();
+    let app = spawn_app_with({
+        let allowed = allowed.clone();
+        move |config| {
+            config.auth.api_keys = vec![
+                ApiKeySettings {
+                    user: "bob".to_string(),
+                    key: "bob-key".to_string(),
+                    admin: false,
+                    allowed_paths: vec![allowed],
+                },
+                root_key(),
+            ];
+        }
+    })
+    .await;
+    std::fs::create_dir(format!("{}/{}", app.storage_path, allowed)).unwrap();
+    let other = create_directory(&app);
+    put(&app, &format!("{}/mine.txt", allowed), "mine").await;
+    put(&app, &format!("{}/theirs.txt", other), "theirs").await;
+
+    for method in ["GET", "PUT", "DELETE", "PROPFIND", "LOCK"] {
+        let response = dav(&app, method, &format!("{}/theirs.txt", other))
+            .basic_auth("bob", Some("bob-key"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN, "{}", method);
+    }
+    let response = dav(&app, "MOVE", &format!("{}/mine.txt", allowed))
+        .header("Destination", destination(&app, &other, "mine.txt"))
+        .basic_auth("bob", Some("bob-key"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert_eq!(read(&app, &format!("{}/theirs.txt", other)), "theirs");
+    assert!(exists(&app, &format!("{}/mine.txt", allowed)));
+    assert!(!exists(&app, &format!("{}/mine.txt", other)));
+
+    // The root leads to the allowed path, it can be listed but only shows that path.
+    let response = dav(&app, "PROPFIND", "")
+        .header("Depth", "1")
+        .basic_auth("bob", Some("bob-key"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::MULTI_STATUS);
+    let listing = response.text().await.unwrap();
+    assert!(listing.contains(&allowed));
+    assert!(!listing.contains(&other));
+
+    remove(&app, &allowed);
+    remove(&app, &other);
+}
+
+fn dav(app: &TestApp, method: &str, path: &str) -> reqwest::RequestBuilder {
+    reqwest::Client::new().request(
+        Method::from_bytes(method.as_bytes()).unwrap(),
+        format!("{}/webdav/{}", app.addr(), path),
+    )
+}
+
+async fn put(app: &TestApp, path: &str, contents: &'static str) {
+    let mut request = dav(app, "PUT", path).body(contents);
+    if let Some(admin_key) = &app.admin_key {
+        request = request.bearer_auth(admin_key);
+    }
+
+    let response = request.send().await.unwrap();
+    assert!(response.status().is_success());
+}
+
+fn destination(app: &TestApp, directory: &str, name: &str) -> String {
+    format!("{}/webdav/{}/{}", app.addr(), directory, name)
+}
+
+fn read(app: &TestApp, path: &str) -> String {
+    std::fs::read_to_string(format!("{}/{}", app.storage_path, path)).unwrap()
+}
+
+fn exists(app: &TestApp, path: &str) -> bool {
+    std::fs::metadata(format!("{}/{}", app.storage_path, path)).is_ok()
+}
@@ -0,0 +1,344 @@
+use std::{
+    net::{SocketAddr, TcpListener},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use axum::{
+    body::Bytes,
+    http::{HeaderMap, StatusCode},
+    routing::post,
+    Extension, Router,
+};
+use crumbbox::{
+    configuration::{Settings, WebhookEndpointSettings},
+    events::FileEventKind,
+};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use uuid::Uuid;
+
+use crate::helpers::{spawn_app_with, upload, TestApp};
+
+#[tokio::test]
+async fn completed_upload_is_delivered_with_a_signature() {
+    let receiver = spawn_receiver(0);
+    let storage_path = isolated_storage_path();
+    let app = spawn_app_with(|config| {
+        configure(config, &storage_path, vec![endpoint(&receiver)]);
+    })
+    .await;
+
+    upload(&app, "", "report.txt", b"contents").await;
+
+    let requests = receiver.wait_for(1).await;
+    let request = &requests[0];
+    assert_eq!(request.event, "upload_completed");
+    let event: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+    assert_eq!(event["kind"], "upload_completed");
+    assert_eq!(event["path"], "report.txt");
+    assert_eq!(event["size"], 8);
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(b"secret").unwrap();
+    mac.update(&request.body);
+    let expected = format!("sha256={}", hex::encode(mac.finalize().into_bytes()));
+    assert_eq!(request.signature, expected);
+
+    std::fs::remove_dir_all(storage_path).unwrap();
+}
+
+#[tokio::test]
+async fn endpoints_only_receive_matching_events() {
+    let receiver = spawn_receiver(0);
+    let storage_path = isolated_storage_path();
+    let app = spawn_app_with(|config| {
+        configure(
+            config,
+            &storage_path,
+            vec![WebhookEndpointSettings {
+                events: vec![FileEventKind::Deleted],
+                paths: vec!["*.txt".to_string()],
+                ..endpoint(&receiver)
+            }],
+        );
+    })
+    .await;
+
+    upload(&app, "", "image.png", b"png").await;
+    upload(&app, "", "notes.txt", b"txt").await;
+    delete(&app, "image.png").await;
+    delete(&app, "notes.txt").await;
+
+    receiver.wait_for(1).await;
+    // Give deliveries that should not happen a chance to show up.
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    let requests = receiver.requests.lock().unwrap().clone();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(requests[0].event, "deleted");
+    let event: serde_json::Value = serde_json::from_slice(&requests[0].body).unwrap();
+    assert_eq!(event["path"], "notes.txt");
+
+    std::fs::remove_dir_all(storage_path).unwrap();
+}
+
+#[tokio::test]
+async fn overwrite_with_versioning_reports_the_created_version() {
+    let receiver = spawn_receiver(0);
+    let storage_path = isolated_storage_path();
+    let app = spawn_app_with(|config| {
+        configure(config, &storage_path, vec![endpoint(&receiver)]);
+        config.versioning.enabled = true;
+    })
+    .await;
+
+    upload(&app, "", "data.csv", b"first").await;
+    receiver.wait_for(1).await;
+    upload(&app, "", "data.csv", b"second").await;
+
+    let requests = receiver.wait_for(3).await;
+    let mut kinds = requests
+        .iter()
+        .map(|request| request.event.as_str())
+        .collect::<Vec<_>>();
+    kinds.sort();
+    assert_eq!(
+        kinds,
+        ["upload_completed", "upload_completed", "version_created"]
+    );
+
+    std::fs::remove_dir_all(storage_path).unwrap();
+}
+
+#[tokio::test]
+async fn failed_deliveries_are_retried() {
+    let receiver = spawn_receiver(2);
+    let storage_path = isolated_storage_path();
+    let app = spawn_app_with(|config| {
+        configure(config, &storage_path, vec![endpoint(&receiver)]);
+        config.webhooks.initial_backoff_ms = 50;
+    })
+    .await;
+
+    upload(&app, "", "retry.txt", b"retry").await;
+
+    let requests = receiver.wait_for(3).await;
+    assert!(requests
+        .iter()
+        .all(|request| request.delivery == requests[0].delivery));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(outbox_size(&storage_path), 0);
+
+    std::fs::remove_dir_all(storage_path).unwrap();
+}
+
+#[tokio::test]
+async fn queued_deliveries_survive_a_restart() {
+    let receiver = spawn_receiver(1);
+    let storage_path = isolated_storage_path();
+    let configure_app = |config: &mut Settings| {
+        configure(config, &storage_path, vec![endpoint(&receiver)]);
+        config.webhooks.initial_backoff_ms = 60_000;
+    };
+    let app = spawn_app_with(configure_app).await;
+
+    upload(&app, "", "restart.txt", b"restart").await;
+    receiver.wait_for(1).await;
+    // The retry is a minute away, only a new instance picking up the outbox delivers it.
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(outbox_size(&storage_path), 1);
+
+    spawn_app_with(configure_app).await;
+
+    let requests = receiver.wait_for(2).await;
+    assert_eq!(requests[0].delivery, requests[1].delivery);
+
+    std::fs::remove_dir_all(storage_path).unwrap();
+}
+
+#[tokio::test]
+async fn unreadable_deliveries_are_set_aside_without_holding_up_others() {
+    let receiver = spawn_receiver(0);
+    let storage_path = isolated_storage_path();
+    let outbox = format!("{}/.crumbbox/webhooks/outbox", storage_path);
+    std::fs::create_dir_all(&outbox).unwrap();
+    std::fs::write(format!("{}/corrupt.json", outbox), b"{\"id\": ").unwrap();
+    let app = spawn_app_with(|config| {
+        configure(config, &storage_path, vec![endpoint(&receiver)]);
+    })
+    .await;
+
+    upload(&app, "", "after.txt", b"after").await;
+
+    let requests = receiver.wait_for(1).await;
+    let event: serde_json::Value = serde_json::from_slice(&requests[0].body).unwrap();
+    assert_eq!(event["path"], "after.txt");
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert_eq!(outbox_size(&storage_path), 0);
+    assert!(std::path::Path::new(&format!(
+        "{}/.crumbbox/webhooks/failed/corrupt.json",
+        storage_path
+    ))
+    .exists());
+
+    std::fs::remove_dir_all(storage_path).unwrap();
+}
+
+#[tokio::test]
+async fn endpoints_sharing_a_url_sign_with_their_own_secret() {
+    let receiver = spawn_receiver(0);
+    let storage_path = isolated_storage_path();
+    let app = spawn_app_with(|config| {
+        configure(
+            config,
+            &storage_path,
+            vec![
+                WebhookEndpointSettings {
+                    secret: "first".to_string(),
+                    paths: vec!["*.txt".to_string()],
+                    ..endpoint(&receiver)
+                },
+                WebhookEndpointSettings {
+                    secret: "second".to_string(),
+                    paths: vec!["*.csv".to_string()],
+                    ..endpoint(&receiver)
+                },
+            ],
+        );
+    })
+    .await;
+
+    upload(&app, "", "notes.txt", b"txt").await;
+    upload(&app, "", "data.csv", b"csv").await;
+
+    let requests = receiver.wait_for(2).await;
+    for request in requests {
+        let event: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+        let secret = match event["path"].as_str().unwrap() {
+            "notes.txt" => "first",
+            _ => "second",
+        };
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+        mac.update(&request.body);
+        let expected = format!("sha256={}", hex::encode(mac.finalize().into_bytes()));
+        assert_eq!(request.signature, expected);
+    }
+
+    std::fs::remove_dir_all(storage_path).unwrap();
+}
+
+#[derive(Clone)]
+struct ReceivedRequest {
+    signature: String,
+    event: String,
+    delivery: String,
+    body: Bytes,
+}
+
+struct Receiver {
+    address: SocketAddr,
+    requests: Arc<Mutex<Vec<ReceivedRequest>>>,
+}
+
+impl Receiver {
+    async fn wait_for(&self, count: usize) -> Vec<ReceivedRequest> {
+        for _ in 0..50 {
+            let requests = self.requests.lock().unwrap().clone();
+            if requests.len() >= count {
+                return requests;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        panic!("Expected {} webhook requests", count);
+    }
+}
+
+/// Spawns a webhook receiver answering the first `failures` requests with a 500.
+fn spawn_receiver(failures: usize) -> Receiver {
+    let requests = Arc::new(Mutex::new(vec![]));
+    let remaining_failures = Arc::new(AtomicUsize::new(failures));
+
+    let router = Router::new()
+        .route(
+            "/hook",
+            post(
+                |headers: HeaderMap,
+                 body: Bytes,
+                 requests: Extension<Arc<Mutex<Vec<ReceivedRequest>>>>,
+                 remaining_failures: Extension<Arc<AtomicUsize>>| async move {
+                    let header = |name: &str| {
+                        headers
+                            .get(name)
+                            .and_then(|value| value.to_str().ok())
+                            .unwrap_or_default()
+                            .to_string()
+                    };
+                    requests.lock().unwrap().push(ReceivedRequest {
+                        signature: header("x-crumbbox-signature"),
+                        event: header("x-crumbbox-event"),
+                        delivery: header("x-crumbbox-delivery"),
+                        body,
+                    });
+
+                    let failing = remaining_failures
+                        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+                        .is_ok();
+                    if failing {
+                        StatusCode::INTERNAL_SERVER_ERROR
+                    } else {
+                        StatusCode::OK
+                    }
+                },
+            ),
+        )
+        .layer(Extension(requests.clone()))
+        .layer(Extension(remaining_failures));
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(
+        axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(router.into_make_service()),
+    );
+
+    Receiver { address, requests }
+}
+
+fn endpoint(receiver: &Receiver) -> WebhookEndpointSettings {
+    WebhookEndpointSettings {
+        url: format!("http://{}/hook", receiver.address),
+        secret: "secret".to_string(),
+        events: vec![],
+        paths: vec![],
+    }
+}
+
+/// A storage root of its own, so every test has its own outbox.
+fn isolated_storage_path() -> String {
+    let storage_path = format!(".crumbbox/test-webhooks/{}/", Uuid::new_v4());
+    std::fs::create_dir_all(&storage_path).unwrap();
+    storage_path
+}
+
+fn configure(config: &mut Settings, storage_path: &str, endpoints: Vec<WebhookEndpointSettings>) {
+    config.application.storage_path = storage_path.to_string();
+    config.webhooks.endpoints = endpoints;
+}
+
+fn outbox_size(storage_path: &str) -> usize {
+    std::fs::read_dir(format!("{}/.crumbbox/webhooks/outbox", storage_path))
+        .map(|entries| entries.count())
+        .unwrap_or(0)
+}
+
+async fn delete(app: &TestApp, relative_path: &str) {
+    let response = reqwest::Client::new()
+        .delete(format!("{}/files/{}", app.addr(), relative_path))
+        .send()
+        .await
+        .expect("Failed to execute request");
+    assert_eq!(response.status(), StatusCode::OK);
+}